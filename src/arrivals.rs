@@ -0,0 +1,647 @@
+/**
+ * ARRIVALS
+ *
+ * Stochastic arrival processes of the vehicles, so the density of the grid can
+ * change during a run instead of being kept at the maximum number of OBUs.
+ * The vehicles arrive at the entry points of the grid, the first cell of every
+ * street, following a Poisson process with a constant rate, a Poisson process
+ * with a rate profile over the rounds (a rush hour curve), or a fixed
+ * schedule. The arrivals are split among the entry points by their weights. A
+ * vehicle waits at its entry point until the entry cell is free and the
+ * maximum number of OBUs, if any, allows it to enter.
+ */
+use crate::grid::{Coordinate, Grid};
+use crate::obu_manager::OnBoardUnitManager;
+use crate::scenario::ScenarioError;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Largest mean drawn at once by the Poisson sampler, exp(-mean) must not underflow
+const MAX_POISSON_MEAN: f64 = 500.0;
+
+/**
+ * ArrivalProcess represents the processes of the vehicle arrivals.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrivalProcess {
+    Poisson,  // Poisson arrivals with a constant rate
+    Profile,  // Poisson arrivals with a rate varying over the rounds
+    Schedule, // A fixed number of arrivals in given rounds
+}
+
+/**
+ * EntryWeight represents the weight of an entry point of the grid. The
+ * arrivals are split among the entry points in proportion to their weights.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntryWeight {
+    pub x: u32,
+    pub y: u32,
+    pub weight: f64,
+}
+
+/**
+ * ArrivalParams represents the arrival process of the vehicles and how the
+ * arrivals are split among the entry points.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArrivalParams {
+    pub process: ArrivalProcess,
+    #[serde(default)]
+    pub rate: Option<f64>, // Poisson: mean arrivals per round over all the entry points
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profile: Vec<(u32, f64)>, // Profile: (round, rate) points, linearly interpolated
+    #[serde(default)]
+    pub period: Option<u32>, // Profile: rounds after which the profile repeats, none if it doesn't
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<(u32, u32)>, // Schedule: (round, number of arrivals) entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weights: Vec<EntryWeight>, // Weights of the entry points, 1 for the ones not listed
+}
+
+/**
+ * Arrivals draws the vehicle arrivals of every round and adds their OBUs to
+ * the grid.
+ */
+pub struct Arrivals {
+    params: ArrivalParams,
+    schedule: BTreeMap<u32, u32>, // Number of arrivals of each scheduled round
+    entry_points: Vec<Coordinate>,
+    weights: Vec<f64>, // Share of the arrivals of each entry point, they add up to 1
+    waiting: Vec<u32>, // Vehicles waiting to enter the grid at each entry point
+    rng: StdRng,       // Random number generator of the arrivals
+}
+
+/**
+ * ArrivalProcess implementation.
+ */
+impl ArrivalProcess {
+    /**
+     * Return the name of the process, as in the scenario.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArrivalProcess::Poisson => "poisson",
+            ArrivalProcess::Profile => "profile",
+            ArrivalProcess::Schedule => "schedule",
+        }
+    }
+}
+
+/**
+ * ArrivalParams implementation.
+ */
+impl ArrivalParams {
+    /**
+     * Check that the process is fully described and that the weights are
+     * given for entry points of the grid.
+     */
+    pub fn validate(&self, entry_points: &[Coordinate]) -> Result<(), ScenarioError> {
+        // Each process only uses its own fields
+        let fields = [
+            ("rate", ArrivalProcess::Poisson, self.rate.is_some()),
+            ("profile", ArrivalProcess::Profile, !self.profile.is_empty()),
+            ("period", ArrivalProcess::Profile, self.period.is_some()),
+            (
+                "schedule",
+                ArrivalProcess::Schedule,
+                !self.schedule.is_empty(),
+            ),
+        ];
+        for (name, process, is_set) in fields {
+            if is_set && process != self.process {
+                return Err(ScenarioError::Invalid(format!(
+                    "arrivals.{} is not used by the {} process",
+                    name,
+                    self.process.name()
+                )));
+            }
+        }
+
+        match self.process {
+            ArrivalProcess::Poisson => match self.rate {
+                Some(rate) => validate_rate("arrivals.rate", rate)?,
+                None => {
+                    return Err(ScenarioError::Invalid(
+                        "arrivals.rate must be set for the poisson process".to_string(),
+                    ))
+                }
+            },
+            ArrivalProcess::Profile => {
+                if self.profile.is_empty() {
+                    return Err(ScenarioError::Invalid(
+                        "arrivals.profile must be set for the profile process".to_string(),
+                    ));
+                }
+                for (round, rate) in &self.profile {
+                    validate_rate(&format!("arrivals.profile rate of round {}", round), *rate)?;
+                }
+                if self
+                    .profile
+                    .windows(2)
+                    .any(|points| points[0].0 >= points[1].0)
+                {
+                    return Err(ScenarioError::Invalid(
+                        "arrivals.profile rounds must be increasing".to_string(),
+                    ));
+                }
+
+                let last_round = self.profile[self.profile.len() - 1].0;
+                if self.period.is_some_and(|period| period <= last_round) {
+                    return Err(ScenarioError::Invalid(format!(
+                        "arrivals.period must be greater than the last profile round ({})",
+                        last_round
+                    )));
+                }
+            }
+            ArrivalProcess::Schedule => {
+                if self.schedule.is_empty() {
+                    return Err(ScenarioError::Invalid(
+                        "arrivals.schedule must be set for the schedule process".to_string(),
+                    ));
+                }
+            }
+        }
+
+        // The weights are given for distinct entry points
+        for (index, entry_weight) in self.weights.iter().enumerate() {
+            let coordinate = Coordinate {
+                x: entry_weight.x,
+                y: entry_weight.y,
+            };
+            if !entry_points.contains(&coordinate) {
+                return Err(ScenarioError::Invalid(format!(
+                    "arrivals.weights: ({}, {}) is not an entry point of the grid",
+                    coordinate.x, coordinate.y
+                )));
+            }
+            if self.weights[..index]
+                .iter()
+                .any(|other| (other.x, other.y) == (coordinate.x, coordinate.y))
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "arrivals.weights: ({}, {}) has more than one weight",
+                    coordinate.x, coordinate.y
+                )));
+            }
+            validate_rate(
+                &format!("arrivals.weights of ({}, {})", coordinate.x, coordinate.y),
+                entry_weight.weight,
+            )?;
+        }
+
+        if entry_weights(&self.weights, entry_points)
+            .iter()
+            .sum::<f64>()
+            <= 0.0
+        {
+            return Err(ScenarioError::Invalid(
+                "arrivals.weights must give a positive weight to an entry point".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Arrivals implementation.
+ */
+impl Arrivals {
+    /**
+     * Create the arrivals of the given params at the entry points of the grid.
+     * The params must be valid for these entry points.
+     */
+    pub fn new(params: &ArrivalParams, entry_points: Vec<Coordinate>, rng: StdRng) -> Arrivals {
+        // Normalize the weights, so they give the share of each entry point
+        let mut weights = entry_weights(&params.weights, &entry_points);
+        let total_weight: f64 = weights.iter().sum();
+        weights
+            .iter_mut()
+            .for_each(|weight| *weight /= total_weight);
+
+        // Add up the arrivals scheduled in the same round
+        let mut schedule = BTreeMap::new();
+        for (round, count) in &params.schedule {
+            *schedule.entry(*round).or_insert(0) += count;
+        }
+
+        Arrivals {
+            params: params.clone(),
+            schedule,
+            waiting: vec![0; entry_points.len()],
+            entry_points,
+            weights,
+            rng,
+        }
+    }
+
+    /**
+     * Return the mean number of arrivals of a round over all the entry points.
+     */
+    pub fn get_rate(&self, round: u32) -> f64 {
+        match self.params.process {
+            ArrivalProcess::Poisson => self.params.rate.unwrap_or(0.0),
+            ArrivalProcess::Profile => {
+                let profile = &self.params.profile;
+                let round = match self.params.period {
+                    Some(period) => round % period,
+                    None => round,
+                };
+
+                // Constant before the first point and after the last one
+                match profile
+                    .iter()
+                    .position(|(point_round, _)| *point_round > round)
+                {
+                    Some(0) => profile[0].1,
+                    Some(next) => {
+                        let (round0, rate0) = profile[next - 1];
+                        let (round1, rate1) = profile[next];
+                        let t = (round - round0) as f64 / (round1 - round0) as f64;
+                        rate0 + t * (rate1 - rate0)
+                    }
+                    None => profile[profile.len() - 1].1,
+                }
+            }
+            ArrivalProcess::Schedule => self.schedule.get(&round).copied().unwrap_or(0) as f64,
+        }
+    }
+
+    /**
+     * Return the number of vehicles waiting to enter the grid.
+     */
+    pub fn get_waiting(&self) -> u32 {
+        self.waiting.iter().sum()
+    }
+
+    /**
+     * Draw the arrivals of a round and add the OBUs of the waiting vehicles
+     * to the free entry cells, as long as the maximum number of OBUs allows
+     * it. At most one vehicle enters at each entry point per round. Return the
+     * number of added OBUs.
+     */
+    pub fn add_obus(
+        &mut self,
+        round: u32,
+        grid: &mut Grid,
+        obu_manager: &mut OnBoardUnitManager,
+    ) -> u32 {
+        self.draw_arrivals(round);
+
+        let mut added_obus = 0;
+        for (index, coordinate) in self.entry_points.iter().enumerate() {
+            if self.waiting[index] == 0 {
+                continue;
+            }
+            if obu_manager.is_full() {
+                break;
+            }
+
+            // The vehicle waits until the entry cell is free
+            if grid.insert_obu_at(*coordinate, obu_manager.get_next_id())
+                && obu_manager.create_obu(*coordinate).is_some()
+            {
+                self.waiting[index] -= 1;
+                added_obus += 1;
+            }
+        }
+
+        added_obus
+    }
+
+    /**
+     * Draw the arrivals of a round at each entry point.
+     */
+    fn draw_arrivals(&mut self, round: u32) {
+        match self.params.process {
+            ArrivalProcess::Poisson | ArrivalProcess::Profile => {
+                // Each entry point gets a Poisson process with its share of the rate
+                let rate = self.get_rate(round);
+                for (waiting, weight) in self.waiting.iter_mut().zip(&self.weights) {
+                    *waiting += sample_poisson(&mut self.rng, rate * weight);
+                }
+            }
+            ArrivalProcess::Schedule => {
+                let count = self.schedule.get(&round).copied().unwrap_or(0);
+                if count == 0 {
+                    return;
+                }
+
+                // Each vehicle picks its entry point by weight
+                let entry = WeightedIndex::new(&self.weights).expect("Invalid entry weights");
+                for _ in 0..count {
+                    self.waiting[entry.sample(&mut self.rng)] += 1;
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Check that a rate or weight is a finite number not less than 0.
+ */
+fn validate_rate(name: &str, rate: f64) -> Result<(), ScenarioError> {
+    if !(rate.is_finite() && rate >= 0.0) {
+        return Err(ScenarioError::Invalid(format!(
+            "{} must be a number not less than 0, got {}",
+            name, rate
+        )));
+    }
+
+    Ok(())
+}
+
+/**
+ * Return the weight of each entry point, 1 for the ones not listed.
+ */
+fn entry_weights(weights: &[EntryWeight], entry_points: &[Coordinate]) -> Vec<f64> {
+    entry_points
+        .iter()
+        .map(|coordinate| {
+            weights
+                .iter()
+                .find(|entry_weight| {
+                    (entry_weight.x, entry_weight.y) == (coordinate.x, coordinate.y)
+                })
+                .map_or(1.0, |entry_weight| entry_weight.weight)
+        })
+        .collect()
+}
+
+/**
+ * Draw a number of events of a Poisson distribution with the given mean, by
+ * multiplying uniform numbers until their product falls below exp(-mean).
+ * Large means are drawn as a sum of smaller ones.
+ */
+fn sample_poisson(rng: &mut StdRng, mean: f64) -> u32 {
+    let mut count = 0;
+    let mut remaining = mean;
+
+    while remaining > 0.0 {
+        let chunk = remaining.min(MAX_POISSON_MEAN);
+        remaining -= chunk;
+
+        let limit = (-chunk).exp();
+        let mut product: f64 = rng.gen();
+        while product > limit {
+            count += 1;
+            product *= rng.gen::<f64>();
+        }
+    }
+
+    count
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grid::GridParams;
+    use crate::obu_manager::ObuManagerParams;
+    use crate::scenario::Scenario;
+    use rand::SeedableRng;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 100
+
+        [simulator]
+        seed = 3
+
+        [grid]
+        blocks_per_street = 4
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = false
+
+        [obu_manager]
+        comms_range = 4
+        tx_base_failure_rate = 0.02
+        tx_faulty_obu_failure_rate = 0.5
+        gps_failure_rate = 0.02
+        gps_faulty_obu_failure_rate = 0.05
+        faulty_obus = 3
+
+        [arrivals]
+        process = "schedule"
+        schedule = [[0, 10], [40, 8], [40, 4]]
+        weights = [{ x = 0, y = 0, weight = 0.0 }, { x = 12, y = 3, weight = 2.0 }]
+    "#;
+
+    /**
+     * Return the params of a process with the given fields.
+     */
+    fn arrival_params(process: ArrivalProcess) -> ArrivalParams {
+        ArrivalParams {
+            process,
+            rate: None,
+            profile: Vec::new(),
+            period: None,
+            schedule: Vec::new(),
+            weights: Vec::new(),
+        }
+    }
+
+    /**
+     * Return the entry points of the grid of the tests.
+     */
+    fn entry_points() -> Vec<Coordinate> {
+        Grid::new(GridParams {
+            blocks_per_street: 4,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        })
+        .get_entry_points()
+    }
+
+    /**
+     * Test the validation of the params.
+     */
+    #[test]
+    fn test_validate() {
+        let entry_points = entry_points();
+        assert_eq!(entry_points.len(), 9);
+
+        let mut params = arrival_params(ArrivalProcess::Poisson);
+        assert!(params.validate(&entry_points).is_err());
+        params.rate = Some(-1.0);
+        assert!(params.validate(&entry_points).is_err());
+        params.rate = Some(2.5);
+        assert!(params.validate(&entry_points).is_ok());
+
+        // the fields of the other processes are rejected
+        params.schedule = vec![(0, 1)];
+        assert!(params.validate(&entry_points).is_err());
+        params.schedule.clear();
+
+        // the weights are for distinct entry points, (1, 0) is a street cell
+        params.weights = vec![EntryWeight {
+            x: 1,
+            y: 0,
+            weight: 1.0,
+        }];
+        assert!(params.validate(&entry_points).is_err());
+        params.weights = vec![
+            EntryWeight {
+                x: 0,
+                y: 0,
+                weight: 1.0,
+            },
+            EntryWeight {
+                x: 0,
+                y: 0,
+                weight: 2.0,
+            },
+        ];
+        assert!(params.validate(&entry_points).is_err());
+
+        // at least one entry point gets vehicles
+        params.weights = entry_points
+            .iter()
+            .map(|coordinate| EntryWeight {
+                x: coordinate.x,
+                y: coordinate.y,
+                weight: 0.0,
+            })
+            .collect();
+        assert!(params.validate(&entry_points).is_err());
+        params.weights[3].weight = 0.5;
+        assert!(params.validate(&entry_points).is_ok());
+
+        let mut params = arrival_params(ArrivalProcess::Profile);
+        params.profile = vec![(0, 1.0), (50, 4.0), (50, 1.0)];
+        assert!(params.validate(&entry_points).is_err());
+        params.profile.pop();
+        params.period = Some(50);
+        assert!(params.validate(&entry_points).is_err());
+        params.period = Some(100);
+        assert!(params.validate(&entry_points).is_ok());
+    }
+
+    /**
+     * Test the interpolation of the rate profile.
+     */
+    #[test]
+    fn test_rate_profile() {
+        let mut params = arrival_params(ArrivalProcess::Profile);
+        params.profile = vec![(10, 1.0), (30, 5.0), (40, 0.0)];
+        let arrivals = Arrivals::new(&params, entry_points(), StdRng::seed_from_u64(0));
+
+        assert_eq!(arrivals.get_rate(0), 1.0);
+        assert_eq!(arrivals.get_rate(10), 1.0);
+        assert_eq!(arrivals.get_rate(20), 3.0);
+        assert_eq!(arrivals.get_rate(35), 2.5);
+        assert_eq!(arrivals.get_rate(90), 0.0);
+
+        // the profile repeats every period
+        params.period = Some(60);
+        let arrivals = Arrivals::new(&params, entry_points(), StdRng::seed_from_u64(0));
+        assert_eq!(arrivals.get_rate(80), 3.0);
+        assert_eq!(arrivals.get_rate(65), 1.0);
+    }
+
+    /**
+     * Test the Poisson arrivals and their split among the entry points.
+     */
+    #[test]
+    fn test_poisson_arrivals() {
+        let entry_points = entry_points();
+        let mut params = arrival_params(ArrivalProcess::Poisson);
+        params.rate = Some(3.0);
+        params.weights = vec![
+            EntryWeight {
+                x: entry_points[0].x,
+                y: entry_points[0].y,
+                weight: 0.0,
+            },
+            EntryWeight {
+                x: entry_points[1].x,
+                y: entry_points[1].y,
+                weight: 8.0,
+            },
+        ];
+        let mut arrivals = Arrivals::new(&params, entry_points, StdRng::seed_from_u64(1));
+
+        // the entry point 1 gets half of the arrivals, the entry point 0 none
+        for round in 0..2000 {
+            arrivals.draw_arrivals(round);
+        }
+        let mean = arrivals.get_waiting() as f64 / 2000.0;
+        assert!((mean - 3.0).abs() < 0.15, "mean arrivals {}", mean);
+        assert_eq!(arrivals.waiting[0], 0);
+        let share = arrivals.waiting[1] as f64 / arrivals.get_waiting() as f64;
+        assert!((share - 0.5).abs() < 0.03, "share {}", share);
+
+        // large means are drawn too
+        let mut rng = StdRng::seed_from_u64(2);
+        let count = sample_poisson(&mut rng, 2000.0);
+        assert!((1800..2200).contains(&count), "count {}", count);
+        assert_eq!(sample_poisson(&mut rng, 0.0), 0);
+    }
+
+    /**
+     * Test the scheduled arrivals entering the grid.
+     */
+    #[test]
+    fn test_scheduled_arrivals() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        let mut grid = Grid::new(scenario.grid.clone());
+        let mut obu_manager = OnBoardUnitManager::new(
+            ObuManagerParams {
+                max_obus: Some(5),
+                ..scenario.obu_manager.clone()
+            },
+            grid.get_width(),
+            grid.get_height(),
+            StdRng::seed_from_u64(0),
+        );
+        let params = scenario.arrivals.as_ref().unwrap();
+        let mut arrivals = Arrivals::new(params, grid.get_entry_points(), StdRng::seed_from_u64(0));
+        assert_eq!(arrivals.get_rate(40), 12.0);
+
+        // one vehicle enters at each entry point, up to the cap
+        assert_eq!(arrivals.add_obus(0, &mut grid, &mut obu_manager), 5);
+        assert_eq!(arrivals.get_waiting(), 5);
+        assert_eq!(arrivals.waiting[0], 0);
+        assert_eq!(arrivals.add_obus(1, &mut grid, &mut obu_manager), 0);
+        assert_eq!(arrivals.get_waiting(), 5);
+
+        // the simulated vehicles all enter the grid, no OBU arrives after the schedule
+        let mut simulator = scenario.create_simulator().unwrap();
+        simulator.set_quiet(true);
+        simulator.set_collect_metrics(true);
+        simulator.init();
+        let report = simulator.run(scenario.rounds);
+
+        let added: Vec<u32> = report.round_metrics.iter().map(|m| m.obus_added).collect();
+        assert_eq!(added.iter().sum::<u32>(), 22);
+        assert_eq!(added[..40].iter().sum::<u32>(), 10);
+        assert_eq!(report.round_metrics[99].obus_waiting, 0);
+        assert_eq!(report.confusion_matrix.total(), 22);
+
+        // without a cap the first vehicles are faulty, and so are the replacements of the
+        // faulty ones that left
+        assert!(report.verdicts[..3].iter().all(|v| v.is_faulty));
+        assert!(report.verdicts.iter().filter(|v| v.is_faulty).count() >= 3);
+    }
+}
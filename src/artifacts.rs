@@ -0,0 +1,295 @@
+/**
+ * ARTIFACTS
+ *
+ * The reporting layer writes the artifacts of the runs, so the simulation and
+ * the detection stay free of side effects. Every run gets its own directory in
+ * the output directory, named after the kind of run and a sequence number
+ * (run-0001, batch-0002, sweep-0003), so successive runs never clobber each
+ * other. The replications of a batch get their own rep-NNNN directories, in
+ * the point-NNNN directory of their combination for a sweep.
+ */
+use crate::metrics;
+use crate::report::{ConfusionMatrix, DetectionRates, ObuVerdict, SimulationReport};
+use crate::scenario::Scenario;
+use crate::trace::TraceWriter;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const REPUTATION_FILE: &str = "reputation.csv";
+pub const CONFUSION_MATRIX_FILE: &str = "confusion_matrix.json";
+pub const SCENARIO_FILE: &str = "scenario.toml";
+pub const SEED_FILE: &str = "seed.txt";
+pub const TRACE_FILE: &str = "trace.jsonl";
+
+/**
+ * ConfusionMatrixArtifact represents the contents of the confusion matrix file.
+ */
+#[derive(Serialize)]
+struct ConfusionMatrixArtifact<'a> {
+    confusion_matrix: &'a ConfusionMatrix,
+    rates: &'a DetectionRates,
+}
+
+/**
+ * Create the next free directory named `<kind>-NNNN` in the parent directory.
+ * The directory is created atomically, so concurrent runs get distinct
+ * directories.
+ */
+pub fn create_run_dir(parent: &Path, kind: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(parent)?;
+
+    let mut number = 1;
+    loop {
+        let dir = parent.join(format!("{}-{:04}", kind, number));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/**
+ * Return the name of the directory of a replication.
+ */
+pub fn replication_dir_name(replication: u32) -> String {
+    format!("rep-{:04}", replication)
+}
+
+/**
+ * Return the name of the directory of a sweep combination.
+ */
+pub fn point_dir_name(point: usize) -> String {
+    format!("point-{:04}", point)
+}
+
+/**
+ * Create the trace writer of a run in its directory, if the scenario enables
+ * the artifacts and the trace. The trace is written while the run goes, so
+ * the directory must exist before the run starts.
+ */
+pub fn create_trace(dir: &Path, scenario: &Scenario) -> io::Result<Option<TraceWriter>> {
+    if !scenario.output.artifacts || !scenario.output.trace {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(dir)?;
+    TraceWriter::create(&dir.join(TRACE_FILE)).map(Some)
+}
+
+/**
+ * Write the artifacts of a run to the given directory: the reputation table,
+ * the confusion matrix, a copy of the scenario, the seed and the per-round
+ * metrics if enabled. The scenario copy has the seed of the report, so it
+ * reproduces the run.
+ */
+pub fn write_run_artifacts(
+    dir: &Path,
+    scenario: &Scenario,
+    report: &SimulationReport,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    write_reputation_csv(&dir.join(REPUTATION_FILE), &report.verdicts)?;
+
+    // Write the confusion matrix and its rates
+    let confusion_matrix = ConfusionMatrixArtifact {
+        confusion_matrix: &report.confusion_matrix,
+        rates: &report.rates,
+    };
+    fs::write(
+        dir.join(CONFUSION_MATRIX_FILE),
+        serde_json::to_string_pretty(&confusion_matrix)? + "\n",
+    )?;
+
+    // The copy describes this run only, not the sweep it may be part of
+    let mut scenario = scenario.clone();
+    scenario.simulator.seed = report.seed;
+    scenario.sweep = None;
+    write_scenario(&dir.join(SCENARIO_FILE), &scenario)?;
+
+    fs::write(dir.join(SEED_FILE), format!("{}\n", report.seed))?;
+
+    if let Some(format) = scenario.output.metrics {
+        metrics::write_metrics(&dir.join(format.file_name()), &report.round_metrics, format)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Write the scenario in TOML format.
+ */
+pub fn write_scenario(path: &Path, scenario: &Scenario) -> io::Result<()> {
+    let mut value = toml::Value::try_from(scenario).map_err(io::Error::other)?;
+    shorten_floats(&mut value);
+
+    let contents = toml::to_string(&value).map_err(io::Error::other)?;
+    fs::write(path, contents)
+}
+
+/**
+ * Write the floats holding an f32 with their shortest f32 representation, so
+ * a rate of 0.02 is written as 0.02 instead of 0.019999999552965164. The
+ * shortened value is read back as the same f32.
+ */
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(float) if (*float as f32) as f64 == *float => {
+            *float = (*float as f32).to_string().parse().unwrap_or(*float);
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(shorten_floats),
+        toml::Value::Table(table) => table
+            .iter_mut()
+            .for_each(|(_, value)| shorten_floats(value)),
+        _ => {}
+    }
+}
+
+/**
+ * Write the reputation of every OBU observed by the RSUs to a CSV file.
+ */
+pub fn write_reputation_csv(path: &Path, verdicts: &[ObuVerdict]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+
+    writeln!(file, "OBU #,TX Error,TX Rep,GPS Error,GPS Rep,Reputation")?;
+    for (obu_id, detector) in verdicts
+        .iter()
+        .filter_map(|verdict| Some((verdict.obu_id, verdict.detector.as_ref()?)))
+    {
+        writeln!(
+            file,
+            "{},{},{},{},{},{}",
+            obu_id,
+            detector.tx_error_rate,
+            detector.tx_reputation as u8,
+            detector.gps_error_rate,
+            detector.gps_reputation as u8,
+            detector.reputation as u8
+        )?;
+    }
+
+    Ok(())
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 20
+
+        [simulator]
+        seed = 5
+
+        [grid]
+        blocks_per_street = 4
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = false
+
+        [obu_manager]
+        max_obus = 10
+        comms_range = 4
+        tx_base_failure_rate = 0.02
+        tx_faulty_obu_failure_rate = 0.05
+        gps_failure_rate = 0.02
+        gps_faulty_obu_failure_rate = 0.05
+        faulty_obus = 2
+    "#;
+
+    /**
+     * Return an empty directory for a test.
+     */
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vanet4j_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /**
+     * Test the naming of the run directories.
+     */
+    #[test]
+    fn test_create_run_dir() {
+        let parent = test_dir("test_create_run_dir");
+
+        assert_eq!(
+            create_run_dir(&parent, "run").unwrap(),
+            parent.join("run-0001")
+        );
+        assert_eq!(
+            create_run_dir(&parent, "run").unwrap(),
+            parent.join("run-0002")
+        );
+        assert_eq!(
+            create_run_dir(&parent, "batch").unwrap(),
+            parent.join("batch-0001")
+        );
+
+        assert_eq!(replication_dir_name(3), "rep-0003");
+        assert_eq!(point_dir_name(12), "point-0012");
+
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    /**
+     * Test the artifacts of a run.
+     */
+    #[test]
+    fn test_write_run_artifacts() {
+        let dir = test_dir("test_write_run_artifacts");
+        let mut scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        let mut simulator = scenario.create_simulator().unwrap();
+        simulator.set_quiet(true);
+        simulator.init();
+        let report = simulator.run(scenario.rounds);
+
+        // the copy gets the seed of the report
+        scenario.simulator.seed = 0;
+        write_run_artifacts(&dir, &scenario, &report).unwrap();
+
+        // one line for each observed OBU
+        let reputation = fs::read_to_string(dir.join(REPUTATION_FILE)).unwrap();
+        let observed = report
+            .verdicts
+            .iter()
+            .filter(|verdict| verdict.detector.is_some())
+            .count();
+        assert_eq!(reputation.lines().count(), observed + 1);
+        assert!(reputation.starts_with("OBU #,TX Error"));
+
+        let confusion_matrix: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join(CONFUSION_MATRIX_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(
+            confusion_matrix["confusion_matrix"]["true_positive"],
+            report.confusion_matrix.true_positive
+        );
+
+        let copy = Scenario::from_file(dir.join(SCENARIO_FILE)).unwrap();
+        assert_eq!(copy.simulator.seed, 5);
+        assert_eq!(copy.obu_manager.max_obus, Some(10));
+        assert_eq!(copy.obu_manager.tx_base_failure_rate, 0.02);
+
+        // the rates are written as in the scenario
+        let contents = fs::read_to_string(dir.join(SCENARIO_FILE)).unwrap();
+        assert!(contents.contains("tx_base_failure_rate = 0.02\n"));
+
+        let seed = fs::read_to_string(dir.join(SEED_FILE)).unwrap();
+        assert_eq!(seed.trim(), "5");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
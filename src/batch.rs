@@ -0,0 +1,463 @@
+/**
+ * BATCH
+ *
+ * The batch runner runs independent replications of a scenario, each one with
+ * its own seed derived from the scenario seed, and aggregates their results.
+ * Replications run in parallel on the rayon thread pool; as each one only
+ * depends on its seed, the results don't depend on the scheduling order.
+ */
+use crate::artifacts;
+use crate::report::{ConfusionMatrix, DetectionRates, SimulationReport};
+use crate::scenario::{Scenario, ScenarioError};
+use crate::trace::TraceWriter;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/**
+ * ReplicationResult represents the results of a single replication.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplicationResult {
+    pub replication: u32,
+    pub seed: u64,
+    pub confusion_matrix: ConfusionMatrix,
+    pub rates: DetectionRates,
+}
+
+/**
+ * MetricSummary represents the aggregated values of a metric over the
+ * replications. Replications where the metric is undefined (NaN) are ignored.
+ */
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct MetricSummary {
+    pub samples: u32,   // Number of replications where the metric is defined
+    pub mean: f64,      // Sample mean
+    pub std_dev: f64,   // Sample standard deviation
+    pub ci95_low: f64,  // Lower bound of the 95% confidence interval of the mean
+    pub ci95_high: f64, // Upper bound of the 95% confidence interval of the mean
+}
+
+/**
+ * BatchSummary represents the results of all the replications and their
+ * aggregated metrics.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchSummary {
+    pub base_seed: u64,
+    pub replications: Vec<ReplicationResult>,
+    pub true_positive: MetricSummary,
+    pub false_positive: MetricSummary,
+    pub true_negative: MetricSummary,
+    pub false_negative: MetricSummary,
+    pub detection_rate: MetricSummary,
+    pub false_positive_rate: MetricSummary,
+    pub false_negative_rate: MetricSummary,
+}
+
+/**
+ * ReplicationResult implementation.
+ */
+impl ReplicationResult {
+    /**
+     * Header of the CSV records of the replications.
+     */
+    pub const CSV_HEADER: &'static str = "replication,seed,true_positive,false_positive,true_negative,false_negative,detection_rate,false_positive_rate,false_negative_rate";
+
+    /**
+     * Format the replication as a CSV record.
+     */
+    pub fn to_csv_record(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.replication,
+            self.seed,
+            self.confusion_matrix.true_positive,
+            self.confusion_matrix.false_positive,
+            self.confusion_matrix.true_negative,
+            self.confusion_matrix.false_negative,
+            self.rates.detection_rate,
+            self.rates.false_positive_rate,
+            self.rates.false_negative_rate
+        )
+    }
+}
+
+/**
+ * Derive the seed of a replication from the base seed, using the SplitMix64
+ * finalizer so consecutive replications get unrelated seeds. The seed is kept
+ * within 63 bits, as TOML integers are signed, so the scenario of any
+ * replication can be written to a file.
+ */
+pub fn derive_seed(base_seed: u64, replication: u32) -> u64 {
+    let mut z =
+        base_seed.wrapping_add((replication as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) >> 1
+}
+
+/**
+ * Run a single replication of the scenario with the given seed, traced if a
+ * trace writer is given.
+ */
+pub fn run_replication(
+    scenario: &Scenario,
+    seed: u64,
+    trace: Option<TraceWriter>,
+) -> Result<SimulationReport, ScenarioError> {
+    let mut scenario = scenario.clone();
+    scenario.simulator.seed = seed;
+
+    let mut simulator = scenario.create_simulator()?;
+    simulator.set_quiet(true);
+    simulator.set_trace(trace);
+    simulator.init();
+    Ok(simulator.run(scenario.rounds))
+}
+
+/**
+ * Run the given number of replications of the scenario and aggregate their
+ * results. The replication seeds are derived from the scenario seed. When an
+ * artifacts directory is given, the artifacts of each replication are written
+ * to its own rep-NNNN directory in it.
+ */
+pub fn run_batch(
+    scenario: &Scenario,
+    replications: u32,
+    artifacts_dir: Option<&Path>,
+) -> io::Result<BatchSummary> {
+    let base_seed = scenario.simulator.seed;
+
+    // The results are collected in the replications order
+    let results = (0..replications)
+        .into_par_iter()
+        .map(|replication| {
+            let seed = derive_seed(base_seed, replication);
+            let dir =
+                artifacts_dir.map(|dir| dir.join(artifacts::replication_dir_name(replication)));
+
+            // The trace is written during the replication
+            let trace = match &dir {
+                Some(dir) => artifacts::create_trace(dir, scenario)?,
+                None => None,
+            };
+            let report = run_replication(scenario, seed, trace).map_err(io::Error::other)?;
+
+            if let Some(dir) = &dir {
+                artifacts::write_run_artifacts(dir, scenario, &report)?;
+            }
+
+            Ok(ReplicationResult {
+                replication,
+                seed,
+                confusion_matrix: report.confusion_matrix,
+                rates: report.rates,
+            })
+        })
+        .collect::<io::Result<Vec<ReplicationResult>>>()?;
+
+    Ok(BatchSummary::new(base_seed, results))
+}
+
+/**
+ * Return the two-sided 95% critical value of the Student t distribution for
+ * the given degrees of freedom. Values between the tabulated degrees of
+ * freedom use the next lower entry, which is conservative.
+ */
+fn t_critical_95(degrees_of_freedom: u32) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+
+    match degrees_of_freedom {
+        0 => f64::NAN,
+        1..=30 => TABLE[degrees_of_freedom as usize - 1],
+        31..=39 => 2.042,
+        40..=59 => 2.021,
+        60..=119 => 2.000,
+        120..=999 => 1.980,
+        _ => 1.960,
+    }
+}
+
+/**
+ * MetricSummary implementation.
+ */
+impl MetricSummary {
+    /**
+     * Summarize the given values, ignoring the NaN ones.
+     */
+    pub fn from_values(values: &[f64]) -> MetricSummary {
+        let values: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+        let n = values.len() as f64;
+
+        // Calculate the sample mean
+        let mean = values.iter().sum::<f64>() / n;
+
+        // Calculate the sample standard deviation
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let std_dev = variance.sqrt();
+
+        // Calculate the half width of the confidence interval
+        let half_width = t_critical_95(values.len().saturating_sub(1) as u32) * std_dev / n.sqrt();
+
+        MetricSummary {
+            samples: values.len() as u32,
+            mean,
+            std_dev,
+            ci95_low: mean - half_width,
+            ci95_high: mean + half_width,
+        }
+    }
+}
+
+/**
+ * BatchSummary implementation.
+ */
+impl BatchSummary {
+    /**
+     * Aggregate the results of the replications.
+     */
+    pub fn new(base_seed: u64, replications: Vec<ReplicationResult>) -> BatchSummary {
+        // Summarize a metric extracted from each replication
+        let summarize = |metric: &dyn Fn(&ReplicationResult) -> f64| {
+            let values: Vec<f64> = replications.iter().map(metric).collect();
+            MetricSummary::from_values(&values)
+        };
+
+        BatchSummary {
+            base_seed,
+            true_positive: summarize(&|r| r.confusion_matrix.true_positive as f64),
+            false_positive: summarize(&|r| r.confusion_matrix.false_positive as f64),
+            true_negative: summarize(&|r| r.confusion_matrix.true_negative as f64),
+            false_negative: summarize(&|r| r.confusion_matrix.false_negative as f64),
+            detection_rate: summarize(&|r| r.rates.detection_rate as f64),
+            false_positive_rate: summarize(&|r| r.rates.false_positive_rate as f64),
+            false_negative_rate: summarize(&|r| r.rates.false_negative_rate as f64),
+            replications,
+        }
+    }
+
+    /**
+     * Return the name and the summary of each metric.
+     */
+    pub fn metrics(&self) -> [(&'static str, &MetricSummary); 7] {
+        [
+            ("true_positive", &self.true_positive),
+            ("false_positive", &self.false_positive),
+            ("true_negative", &self.true_negative),
+            ("false_negative", &self.false_negative),
+            ("detection_rate", &self.detection_rate),
+            ("false_positive_rate", &self.false_positive_rate),
+            ("false_negative_rate", &self.false_negative_rate),
+        ]
+    }
+
+    /**
+     * Print the summary table to the console.
+     */
+    pub fn print(&self) {
+        println!("--- BATCH SUMMARY ---");
+        println!(
+            "Replications: {} (base seed {})",
+            self.replications.len(),
+            self.base_seed
+        );
+        println!(
+            "{:<20} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            "Metric", "Samples", "Mean", "Std Dev", "CI95 Low", "CI95 High"
+        );
+        for (name, summary) in self.metrics() {
+            println!(
+                "{:<20} {:>7} {:>10.4} {:>10.4} {:>10.4} {:>10.4}",
+                name,
+                summary.samples,
+                summary.mean,
+                summary.std_dev,
+                summary.ci95_low,
+                summary.ci95_high
+            );
+        }
+    }
+
+    /**
+     * Write the results of each replication to a CSV file.
+     */
+    pub fn write_replications_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+
+        writeln!(file, "{}", ReplicationResult::CSV_HEADER)?;
+        for result in &self.replications {
+            writeln!(file, "{}", result.to_csv_record())?;
+        }
+
+        Ok(())
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 20
+
+        [simulator]
+        seed = 5
+
+        [grid]
+        blocks_per_street = 4
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = false
+
+        [obu_manager]
+        max_obus = 10
+        comms_range = 4
+        tx_base_failure_rate = 0.02
+        tx_faulty_obu_failure_rate = 0.05
+        gps_failure_rate = 0.02
+        gps_faulty_obu_failure_rate = 0.05
+        faulty_obus = 2
+    "#;
+
+    /**
+     * Test the seed derivation.
+     */
+    #[test]
+    fn test_derive_seed() {
+        // the derivation is deterministic
+        assert_eq!(derive_seed(42, 0), derive_seed(42, 0));
+
+        // each replication gets its own seed
+        let seeds: Vec<u64> = (0..100).map(|i| derive_seed(42, i)).collect();
+        for i in 0..seeds.len() {
+            for j in (i + 1)..seeds.len() {
+                assert_ne!(seeds[i], seeds[j]);
+            }
+        }
+
+        // other base seeds give other seeds
+        assert_ne!(derive_seed(42, 0), derive_seed(43, 0));
+
+        // the seeds fit in a TOML integer
+        assert!(seeds.iter().all(|seed| *seed <= i64::MAX as u64));
+    }
+
+    /**
+     * Test the summary of a metric.
+     */
+    #[test]
+    fn test_metric_summary() {
+        let summary = MetricSummary::from_values(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+
+        assert_eq!(summary.samples, 8);
+        assert!((summary.mean - 5.0).abs() < 0.0001);
+        assert!((summary.std_dev - 2.13809).abs() < 0.0001);
+
+        // t(7) = 2.365
+        let half_width = 2.365 * 2.13809 / 8.0_f64.sqrt();
+        assert!((summary.ci95_low - (5.0 - half_width)).abs() < 0.0001);
+        assert!((summary.ci95_high - (5.0 + half_width)).abs() < 0.0001);
+
+        // undefined values are ignored
+        let summary = MetricSummary::from_values(&[1.0, f64::NAN, 3.0]);
+        assert_eq!(summary.samples, 2);
+        assert!((summary.mean - 2.0).abs() < 0.0001);
+
+        // a single value has no deviation
+        let summary = MetricSummary::from_values(&[1.0]);
+        assert_eq!(summary.samples, 1);
+        assert!(summary.std_dev.is_nan());
+    }
+
+    /**
+     * Test a batch of replications.
+     */
+    #[test]
+    fn test_run_batch() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        let summary = run_batch(&scenario, 3, None).unwrap();
+
+        assert_eq!(summary.replications.len(), 3);
+        assert_eq!(summary.detection_rate.samples, 3);
+        for (i, result) in summary.replications.iter().enumerate() {
+            assert_eq!(result.replication, i as u32);
+            assert_eq!(result.seed, derive_seed(scenario.simulator.seed, i as u32));
+            // the obus that left the grid are evaluated too
+            assert!(result.confusion_matrix.total() >= 10);
+        }
+
+        // the same batch gives the same results
+        let summary_2 = run_batch(&scenario, 3, None).unwrap();
+        for (result, result_2) in summary.replications.iter().zip(&summary_2.replications) {
+            assert_eq!(result.confusion_matrix, result_2.confusion_matrix);
+        }
+    }
+
+    /**
+     * Test the artifacts of the replications.
+     */
+    #[test]
+    fn test_run_batch_artifacts() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "vanet4j_test_run_batch_artifacts_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let summary = run_batch(&scenario, 2, Some(&dir)).unwrap();
+
+        // each replication has its own directory with its own seed
+        for result in &summary.replications {
+            let seed = std::fs::read_to_string(
+                dir.join(artifacts::replication_dir_name(result.replication))
+                    .join(artifacts::SEED_FILE),
+            )
+            .unwrap();
+            assert_eq!(seed.trim(), result.seed.to_string());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /**
+     * Test that the results don't depend on the number of threads.
+     */
+    #[test]
+    fn test_run_batch_threads() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        // Run the batch on a pool with the given number of threads
+        let run = |threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| run_batch(&scenario, 8, None).unwrap())
+        };
+
+        let sequential = run(1);
+        let parallel = run(4);
+        for (result, result_2) in sequential.replications.iter().zip(&parallel.replications) {
+            assert_eq!(result.replication, result_2.replication);
+            assert_eq!(result.seed, result_2.seed);
+            assert_eq!(result.confusion_matrix, result_2.confusion_matrix);
+        }
+    }
+}
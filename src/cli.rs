@@ -0,0 +1,836 @@
+/**
+ * CLI
+ *
+ * Command line interface of the simulator binary. Every scenario field can be
+ * overridden from the command line, so experiments can be scripted without
+ * editing scenario files.
+ */
+use crate::artifacts;
+use crate::batch;
+use crate::metrics::MetricsFormat;
+use crate::mobility::MobilityParams;
+use crate::osm::OsmParams;
+use crate::scenario::{Scenario, ScenarioError};
+use crate::sweep::{self, SweepMode, SweepParam, SweepParams, SweepValues};
+use crate::trace::TraceReader;
+use clap::{Args, Parser, Subcommand};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/**
+ * Cli represents the command line of the simulator.
+ */
+#[derive(Debug, Parser)]
+#[command(
+    name = "vanet4j",
+    about = "Communication anomalies in VANET Simulator",
+    long_about = None
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Number of threads running replications, all the CPU cores by default
+    #[arg(short, long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+}
+
+/**
+ * Command represents the subcommands of the simulator.
+ */
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a simulation
+    Run(RunArgs),
+    /// Replay a recorded trace, e.g. with other detector settings
+    Replay(ReplayArgs),
+    /// Run replications for each combination of values of scenario fields
+    Sweep(SweepArgs),
+    /// Run independent replications of a simulation and aggregate the results
+    Batch(BatchArgs),
+    /// Print the grid and the RSUs placement without running a simulation
+    InspectGrid(InspectGridArgs),
+}
+
+/**
+ * RunArgs represents the arguments of the run subcommand.
+ */
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+
+    /// Only print the final stats
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Print the simulation report as JSON instead of the final stats
+    #[arg(long)]
+    pub json: bool,
+}
+
+/**
+ * ReplayArgs represents the arguments of the replay subcommand.
+ */
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Trace file written by a run with --trace
+    #[arg(value_name = "TRACE")]
+    pub trace_file: PathBuf,
+
+    /// The scenario file defaults to the scenario.toml next to the trace
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+
+    /// Only print the final stats
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Print the simulation report as JSON instead of the final stats
+    #[arg(long)]
+    pub json: bool,
+}
+
+/**
+ * ReplayArgs implementation.
+ */
+impl ReplayArgs {
+    /**
+     * Load the scenario of the replay, the scenario of the recorded run if no
+     * scenario file is given.
+     */
+    pub fn load_scenario(&self) -> Result<Scenario, ScenarioError> {
+        let recorded = self.trace_file.with_file_name(artifacts::SCENARIO_FILE);
+        if self.scenario.scenario.is_none() && recorded.is_file() {
+            let mut scenario = Scenario::from_file(&recorded)?;
+            self.scenario.apply(&mut scenario)?;
+            scenario.validate()?;
+            return Ok(scenario);
+        }
+
+        self.scenario.load()
+    }
+}
+
+/**
+ * SweepArgs represents the arguments of the sweep subcommand.
+ */
+#[derive(Debug, Args)]
+pub struct SweepArgs {
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+
+    /// Swept field and its values, as a comma separated list or a start:stop:step
+    /// range, e.g. --param faulty_obus=10,20,30 (can be repeated, replaces the
+    /// params of the scenario sweep section)
+    #[arg(long = "param", value_name = "FIELD=VALUES")]
+    pub params: Vec<String>,
+
+    /// Combine the values of the params one by one instead of in every combination
+    #[arg(long)]
+    pub zip: bool,
+
+    /// Number of replications for each combination of values
+    #[arg(short = 'n', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub replications: Option<u32>,
+
+    /// File, in the run directory, where the results of each replication are written
+    #[arg(long, default_value = "sweep.csv")]
+    pub results_file: PathBuf,
+
+    /// Only print the results of the sweep
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+/**
+ * SweepArgs implementation.
+ */
+impl SweepArgs {
+    /**
+     * Build the sweep from the scenario sweep section and the command line.
+     */
+    pub fn sweep(&self, scenario: &Scenario) -> Result<SweepParams, ScenarioError> {
+        let mut sweep = scenario.sweep.clone().unwrap_or(SweepParams {
+            mode: SweepMode::Cartesian,
+            replications: 1,
+            params: Vec::new(),
+        });
+
+        // The params of the command line replace the ones of the scenario
+        if !self.params.is_empty() {
+            sweep.params = Vec::new();
+            for param in &self.params {
+                match param.split_once('=') {
+                    Some((name, values)) => sweep.params.push(SweepParam {
+                        name: name.trim().to_string(),
+                        values: SweepValues::parse(values)?,
+                    }),
+                    None => {
+                        return Err(ScenarioError::Invalid(format!(
+                            "expected FIELD=VALUES, got {}",
+                            param
+                        )))
+                    }
+                }
+            }
+        }
+
+        if self.zip {
+            sweep.mode = SweepMode::Zip;
+        }
+        if let Some(replications) = self.replications {
+            sweep.replications = replications;
+        }
+
+        Ok(sweep)
+    }
+}
+
+/**
+ * BatchArgs represents the arguments of the batch subcommand.
+ */
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+
+    /// Number of replications, each one with a seed derived from the scenario seed
+    #[arg(short = 'n', long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+    pub replications: u32,
+
+    /// File, in the run directory, where the results of each replication are written
+    #[arg(long, default_value = "replications.csv")]
+    pub replications_file: PathBuf,
+
+    /// Print the batch summary as JSON instead of the summary table
+    #[arg(long)]
+    pub json: bool,
+}
+
+/**
+ * InspectGridArgs represents the arguments of the inspect-grid subcommand.
+ */
+#[derive(Debug, Args)]
+pub struct InspectGridArgs {
+    #[command(flatten)]
+    pub scenario: ScenarioArgs,
+}
+
+/**
+ * ScenarioArgs represents the scenario file and the overrides of its fields.
+ */
+#[derive(Debug, Args)]
+pub struct ScenarioArgs {
+    /// Scenario file (.toml or .json), the default scenario is used if omitted
+    pub scenario: Option<PathBuf>,
+
+    /// Number of rounds to simulate
+    #[arg(long)]
+    pub rounds: Option<usize>,
+
+    /// Seed of the random number generators
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Directory where the run directories are created
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Export the per-round metrics of each run in this format
+    #[arg(long, value_enum)]
+    pub metrics: Option<MetricsFormat>,
+
+    /// Write the trace of each run: the OBUs and the receivers of their messages
+    #[arg(long)]
+    pub trace: bool,
+
+    /// Drive the OBUs from a SUMO FCD (.xml) or time,vehicle_id,x,y (.csv) trace
+    #[arg(long, value_name = "FILE")]
+    pub mobility: Option<PathBuf>,
+
+    /// Number of blocks per street
+    #[arg(long, help_heading = "Grid")]
+    pub blocks_per_street: Option<u32>,
+
+    /// Size of each block
+    #[arg(long, help_heading = "Grid")]
+    pub block_size: Option<u32>,
+
+    /// ASCII map of the roads ('.') and buildings ('#'), instead of the blocks
+    #[arg(long, value_name = "FILE", help_heading = "Grid")]
+    pub map: Option<PathBuf>,
+
+    /// OpenStreetMap XML extract of the roads, instead of the blocks
+    #[arg(long, value_name = "FILE", help_heading = "Grid")]
+    pub osm: Option<PathBuf>,
+
+    /// Make the streets two-way, instead of one-way in alternating directions
+    #[arg(long, help_heading = "Grid")]
+    pub two_way: Option<bool>,
+
+    /// Lanes per direction of the streets
+    #[arg(long, help_heading = "Grid")]
+    pub lanes: Option<u32>,
+
+    /// RSU transmission range
+    #[arg(long, help_heading = "RSU manager")]
+    pub tx_range: Option<u32>,
+
+    /// RSU reception range, defines the spacing between RSUs
+    #[arg(long, help_heading = "RSU manager")]
+    pub rx_range: Option<u32>,
+
+    /// Detect OBU TX failures
+    #[arg(long, help_heading = "RSU manager")]
+    pub detect_obu_tx_failure: Option<bool>,
+
+    /// Detect OBU GPS failures
+    #[arg(long, help_heading = "RSU manager")]
+    pub detect_obu_gps_failure: Option<bool>,
+
+    /// Maximum number of OBUs in the grid
+    #[arg(long, help_heading = "OBU manager")]
+    pub max_obus: Option<u32>,
+
+    /// OBU communication range
+    #[arg(long, help_heading = "OBU manager")]
+    pub comms_range: Option<u32>,
+
+    /// TX failure rate of the normal OBUs
+    #[arg(long, help_heading = "OBU manager")]
+    pub tx_base_failure_rate: Option<f32>,
+
+    /// TX failure rate of the faulty OBUs
+    #[arg(long, help_heading = "OBU manager")]
+    pub tx_faulty_obu_failure_rate: Option<f32>,
+
+    /// GPS failure rate of the normal OBUs
+    #[arg(long, help_heading = "OBU manager")]
+    pub gps_failure_rate: Option<f32>,
+
+    /// GPS failure rate of the faulty OBUs
+    #[arg(long, help_heading = "OBU manager")]
+    pub gps_faulty_obu_failure_rate: Option<f32>,
+
+    /// Number of faulty OBUs
+    #[arg(long, help_heading = "OBU manager")]
+    pub faulty_obus: Option<u32>,
+
+    /// Set any scenario field, e.g. --set grid.block_size=4 (can be repeated)
+    #[arg(long = "set", value_name = "FIELD=VALUE")]
+    pub fields: Vec<String>,
+}
+
+/**
+ * ScenarioArgs implementation.
+ */
+impl ScenarioArgs {
+    /**
+     * Load the scenario and apply the command line overrides.
+     */
+    pub fn load(&self) -> Result<Scenario, ScenarioError> {
+        // Load the scenario file or use the default scenario
+        let mut scenario = match &self.scenario {
+            Some(path) => Scenario::from_file(path)?,
+            None => Scenario::default(),
+        };
+
+        self.apply(&mut scenario)?;
+
+        // Overrides may produce an invalid scenario
+        scenario.validate()?;
+
+        Ok(scenario)
+    }
+
+    /**
+     * Apply the command line overrides to the scenario.
+     */
+    fn apply(&self, scenario: &mut Scenario) -> Result<(), ScenarioError> {
+        if let Some(rounds) = self.rounds {
+            scenario.rounds = rounds;
+        }
+        if let Some(seed) = self.seed {
+            scenario.simulator.seed = seed;
+        }
+        if let Some(output_dir) = &self.output_dir {
+            scenario.output.dir = output_dir.clone();
+        }
+        if let Some(format) = self.metrics {
+            scenario.output.metrics = Some(format);
+        }
+        if self.trace {
+            scenario.output.trace = true;
+        }
+        if let Some(file) = &self.mobility {
+            // Keep the mapping of the scenario mobility section, if any
+            match &mut scenario.mobility {
+                Some(mobility) => mobility.file = file.clone(),
+                None => scenario.mobility = Some(MobilityParams::new(file.clone())),
+            }
+        }
+
+        // Grid
+        if let Some(blocks_per_street) = self.blocks_per_street {
+            scenario.grid.blocks_per_street = blocks_per_street;
+        }
+        if let Some(block_size) = self.block_size {
+            scenario.grid.block_size = block_size;
+        }
+        if let Some(path) = &self.map {
+            // The map is copied to the scenario, so its copy reproduces the run
+            let map = fs::read_to_string(path).map_err(|e| {
+                ScenarioError::Invalid(format!("can't read map file {}: {}", path.display(), e))
+            })?;
+            scenario.grid.map = Some(map);
+        }
+        if let Some(file) = &self.osm {
+            // Keep the rasterization of the scenario OSM section, if any
+            match &mut scenario.grid.osm {
+                Some(osm) => osm.file = file.clone(),
+                None => scenario.grid.osm = Some(OsmParams::new(file.clone())),
+            }
+        }
+        if let Some(two_way) = self.two_way {
+            scenario.grid.two_way = two_way;
+        }
+        if let Some(lanes) = self.lanes {
+            scenario.grid.lanes = lanes;
+        }
+
+        // RSU manager
+        if let Some(tx_range) = self.tx_range {
+            scenario.rsu_manager.tx_range = tx_range;
+        }
+        if let Some(rx_range) = self.rx_range {
+            scenario.rsu_manager.rx_range = rx_range;
+        }
+        if let Some(detect_obu_tx_failure) = self.detect_obu_tx_failure {
+            scenario.rsu_manager.detect_obu_tx_failure = detect_obu_tx_failure;
+        }
+        if let Some(detect_obu_gps_failure) = self.detect_obu_gps_failure {
+            scenario.rsu_manager.detect_obu_gps_failure = detect_obu_gps_failure;
+        }
+
+        // OBU manager
+        if let Some(max_obus) = self.max_obus {
+            scenario.obu_manager.max_obus = Some(max_obus);
+        }
+        if let Some(comms_range) = self.comms_range {
+            scenario.obu_manager.comms_range = comms_range;
+        }
+        if let Some(rate) = self.tx_base_failure_rate {
+            scenario.obu_manager.tx_base_failure_rate = rate;
+        }
+        if let Some(rate) = self.tx_faulty_obu_failure_rate {
+            scenario.obu_manager.tx_faulty_obu_failure_rate = rate;
+        }
+        if let Some(rate) = self.gps_failure_rate {
+            scenario.obu_manager.gps_failure_rate = rate;
+        }
+        if let Some(rate) = self.gps_faulty_obu_failure_rate {
+            scenario.obu_manager.gps_faulty_obu_failure_rate = rate;
+        }
+        if let Some(faulty_obus) = self.faulty_obus {
+            scenario.obu_manager.faulty_obus = faulty_obus;
+        }
+
+        // Generic overrides
+        for field in &self.fields {
+            match field.split_once('=') {
+                Some((name, value)) => scenario.set_field(name.trim(), value.trim())?,
+                None => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "expected FIELD=VALUE, got {}",
+                        field
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Execute the given command line.
+ */
+pub fn execute(cli: Cli) -> Result<(), Box<dyn Error>> {
+    // Size the thread pool used by the batch and sweep subcommands
+    if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs as usize)
+            .build_global()?;
+    }
+
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Replay(args) => replay(args),
+        Command::Sweep(args) => sweep(args),
+        Command::Batch(args) => batch(args),
+        Command::InspectGrid(args) => inspect_grid(args),
+    }
+}
+
+/**
+ * Run a single simulation.
+ */
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = args.scenario.load()?;
+
+    // The run directory is created first, as the trace is written during the run
+    let dir = if scenario.output.artifacts {
+        Some(artifacts::create_run_dir(&scenario.output.dir, "run")?)
+    } else {
+        None
+    };
+
+    let mut simulator = scenario.create_simulator()?;
+    simulator.set_quiet(args.quiet || args.json);
+    if let Some(dir) = &dir {
+        simulator.set_trace(artifacts::create_trace(dir, &scenario)?);
+    }
+    simulator.init();
+    let report = simulator.run(scenario.rounds);
+
+    if args.json {
+        println!("{}", report.to_json());
+    } else {
+        report.print();
+    }
+
+    // Write the artifacts to the run directory
+    if let Some(dir) = dir {
+        artifacts::write_run_artifacts(&dir, &scenario, &report)?;
+
+        if !args.quiet && !args.json {
+            println!("Artifacts written to {}", dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Replay a recorded trace.
+ */
+fn replay(args: ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = args.load_scenario()?;
+    let trace = TraceReader::open(&args.trace_file)
+        .map_err(|e| format!("can't read trace file {}: {}", args.trace_file.display(), e))?;
+
+    let mut simulator = scenario.create_simulator()?;
+    simulator.set_quiet(args.quiet || args.json);
+    simulator.init();
+    let report = simulator.replay(trace)?;
+
+    if args.json {
+        println!("{}", report.to_json());
+    } else {
+        report.print();
+    }
+
+    // Write the artifacts to a new replay directory
+    if scenario.output.artifacts {
+        let dir = artifacts::create_run_dir(&scenario.output.dir, "replay")?;
+        artifacts::write_run_artifacts(&dir, &scenario, &report)?;
+
+        if !args.quiet && !args.json {
+            println!("Artifacts written to {}", dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Run replications for each combination of values of the swept fields.
+ */
+fn sweep(args: SweepArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = args.scenario.load()?;
+    let sweep = args.sweep(&scenario)?;
+
+    // Check all the combinations before running any simulation
+    let combinations = sweep.combinations()?.len();
+    sweep.scenarios(&scenario)?;
+
+    if !args.quiet {
+        println!(
+            "Sweeping {} combinations with {} replications each",
+            combinations, sweep.replications
+        );
+    }
+
+    // Keep a copy of the swept scenario with the results
+    let dir = artifacts::create_run_dir(&scenario.output.dir, "sweep")?;
+    let mut swept_scenario = scenario.clone();
+    swept_scenario.sweep = Some(sweep.clone());
+    artifacts::write_scenario(&dir.join(artifacts::SCENARIO_FILE), &swept_scenario)?;
+
+    let artifacts_dir = scenario.output.artifacts.then_some(dir.as_path());
+    let results = sweep::run_sweep(&scenario, &sweep, artifacts_dir)?;
+    results.write_csv(&dir.join(&args.results_file))?;
+    results.print();
+
+    if !args.quiet {
+        println!("Results written to {}", dir.display());
+    }
+
+    Ok(())
+}
+
+/**
+ * Run independent replications of a simulation.
+ */
+fn batch(args: BatchArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = args.scenario.load()?;
+
+    // Keep a copy of the scenario with the results
+    let dir = artifacts::create_run_dir(&scenario.output.dir, "batch")?;
+    artifacts::write_scenario(&dir.join(artifacts::SCENARIO_FILE), &scenario)?;
+
+    let artifacts_dir = scenario.output.artifacts.then_some(dir.as_path());
+    let summary = batch::run_batch(&scenario, args.replications, artifacts_dir)?;
+    summary.write_replications_csv(&dir.join(&args.replications_file))?;
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&summary).expect("Failed to serialize batch summary")
+        );
+    } else {
+        summary.print();
+        println!("Results written to {}", dir.display());
+    }
+
+    Ok(())
+}
+
+/**
+ * Print the grid and the RSUs placement.
+ */
+fn inspect_grid(args: InspectGridArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = args.scenario.load()?;
+
+    let mut simulator = scenario.create_simulator()?;
+    simulator.set_quiet(true);
+    simulator.init();
+    simulator.print_layout();
+
+    Ok(())
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Test the overrides of the run subcommand.
+     */
+    #[test]
+    fn test_run_overrides() {
+        let cli = Cli::try_parse_from([
+            "vanet4j",
+            "run",
+            "--rounds",
+            "10",
+            "--seed",
+            "3",
+            "--faulty-obus",
+            "5",
+            "--detect-obu-gps-failure",
+            "true",
+            "--set",
+            "grid.block_size=4",
+            "--lanes",
+            "2",
+            "--trace",
+            "--quiet",
+        ])
+        .unwrap();
+
+        let args = match cli.command {
+            Command::Run(args) => args,
+            _ => panic!("expected the run subcommand"),
+        };
+        assert!(args.quiet);
+
+        let scenario = args.scenario.load().unwrap();
+        assert_eq!(scenario.rounds, 10);
+        assert_eq!(scenario.simulator.seed, 3);
+        assert_eq!(scenario.obu_manager.faulty_obus, 5);
+        assert!(scenario.rsu_manager.detect_obu_gps_failure);
+        assert_eq!(scenario.grid.block_size, 4);
+        assert_eq!(scenario.grid.lanes, 2);
+        assert!(!scenario.grid.two_way);
+        assert!(scenario.output.trace);
+
+        // the other fields come from the default scenario
+        assert_eq!(scenario.obu_manager.max_obus, Some(120));
+    }
+
+    /**
+     * Test reading the grid from a map file.
+     */
+    #[test]
+    fn test_map_override() {
+        let path = std::env::temp_dir().join(format!(
+            "vanet4j_test_map_override_{}.txt",
+            std::process::id()
+        ));
+        fs::write(&path, ".....\n.#.#.\n.....\n").unwrap();
+
+        let cli = Cli::try_parse_from(["vanet4j", "inspect-grid", "--map", path.to_str().unwrap()])
+            .unwrap();
+        let args = match cli.command {
+            Command::InspectGrid(args) => args,
+            _ => panic!("expected the inspect-grid subcommand"),
+        };
+        let scenario = args.scenario.load().unwrap();
+        assert_eq!(scenario.grid.map.as_deref(), Some(".....\n.#.#.\n.....\n"));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(args.scenario.load().is_err());
+    }
+
+    /**
+     * Test reading the grid from an OpenStreetMap extract.
+     */
+    #[test]
+    fn test_osm_override() {
+        let cli =
+            Cli::try_parse_from(["vanet4j", "inspect-grid", "--osm", "scenarios/village.osm"])
+                .unwrap();
+        let args = match cli.command {
+            Command::InspectGrid(args) => args,
+            _ => panic!("expected the inspect-grid subcommand"),
+        };
+        let scenario = args.scenario.load().unwrap();
+        let osm = scenario.grid.osm.unwrap();
+        assert_eq!(osm.file, PathBuf::from("scenarios/village.osm"));
+        assert_eq!(osm.cell_size, 7.5);
+    }
+
+    /**
+     * Test that invalid overrides are reported.
+     */
+    #[test]
+    fn test_invalid_overrides() {
+        let cli = Cli::try_parse_from(["vanet4j", "run", "--faulty-obus", "500"]).unwrap();
+        let args = match cli.command {
+            Command::Run(args) => args,
+            _ => panic!("expected the run subcommand"),
+        };
+        assert!(args.scenario.load().is_err());
+
+        let cli = Cli::try_parse_from(["vanet4j", "run", "--set", "grid.block_size"]).unwrap();
+        let args = match cli.command {
+            Command::Run(args) => args,
+            _ => panic!("expected the run subcommand"),
+        };
+        assert!(args.scenario.load().is_err());
+    }
+
+    /**
+     * Test the arguments of the replay subcommand.
+     */
+    #[test]
+    fn test_replay_args() {
+        let cli = Cli::try_parse_from([
+            "vanet4j",
+            "replay",
+            "results/run-0001/trace.jsonl",
+            "--detect-obu-gps-failure",
+            "false",
+        ])
+        .unwrap();
+
+        let args = match cli.command {
+            Command::Replay(args) => args,
+            _ => panic!("expected the replay subcommand"),
+        };
+        assert_eq!(
+            args.trace_file,
+            PathBuf::from("results/run-0001/trace.jsonl")
+        );
+        assert_eq!(args.scenario.scenario, None);
+
+        // without a recorded scenario the default one is used
+        let scenario = args.load_scenario().unwrap();
+        assert!(!scenario.rsu_manager.detect_obu_gps_failure);
+        assert_eq!(scenario.obu_manager.max_obus, Some(120));
+
+        // the trace is required
+        assert!(Cli::try_parse_from(["vanet4j", "replay"]).is_err());
+    }
+
+    /**
+     * Test the arguments of the sweep subcommand.
+     */
+    #[test]
+    fn test_sweep_args() {
+        let cli = Cli::try_parse_from([
+            "vanet4j",
+            "sweep",
+            "scenario.toml",
+            "--param",
+            "faulty_obus=10,20,30",
+            "--param",
+            "tx_faulty_obu_failure_rate=0.05:0.15:0.05",
+            "--zip",
+            "-n",
+            "4",
+        ])
+        .unwrap();
+
+        let args = match cli.command {
+            Command::Sweep(args) => args,
+            _ => panic!("expected the sweep subcommand"),
+        };
+        assert_eq!(args.scenario.scenario, Some(PathBuf::from("scenario.toml")));
+
+        let sweep = args.sweep(&Scenario::default()).unwrap();
+        assert_eq!(sweep.mode, SweepMode::Zip);
+        assert_eq!(sweep.replications, 4);
+        assert_eq!(sweep.params[0].name, "faulty_obus");
+        assert_eq!(
+            sweep.combinations().unwrap(),
+            vec![vec!["10", "0.05"], vec!["20", "0.1"], vec!["30", "0.15"]]
+        );
+
+        // a param needs its values
+        let cli = Cli::try_parse_from(["vanet4j", "sweep", "--param", "faulty_obus"]).unwrap();
+        let args = match cli.command {
+            Command::Sweep(args) => args,
+            _ => panic!("expected the sweep subcommand"),
+        };
+        assert!(args.sweep(&Scenario::default()).is_err());
+    }
+
+    /**
+     * Test the arguments of the batch subcommand.
+     */
+    #[test]
+    fn test_batch_args() {
+        let cli =
+            Cli::try_parse_from(["vanet4j", "batch", "-n", "5", "--seed", "9", "-j", "2"]).unwrap();
+        assert_eq!(cli.jobs, Some(2));
+
+        let args = match cli.command {
+            Command::Batch(args) => args,
+            _ => panic!("expected the batch subcommand"),
+        };
+        assert_eq!(args.replications, 5);
+        assert_eq!(args.replications_file, PathBuf::from("replications.csv"));
+        assert_eq!(args.scenario.load().unwrap().simulator.seed, 9);
+
+        // at least one replication is needed
+        assert!(Cli::try_parse_from(["vanet4j", "batch", "-n", "0"]).is_err());
+    }
+}
@@ -1,6 +1,11 @@
 use crate::grid::Coordinate;
 use crate::grid::SquareCoords;
+use crate::interference::Interference;
+use crate::latency::Latency;
+use crate::mac::{Mac, MacStats};
+use crate::propagation::Propagation;
 use crate::simulator::NodeType;
+use std::collections::{BTreeMap, BTreeSet};
 
 /**
  * NeighborEntry represents a neighbor of a node
@@ -8,6 +13,7 @@ use crate::simulator::NodeType;
 pub struct NeighborEntry {
     pub id: u32,                // ID of the neighbor
     pub coordinate: Coordinate, // Coordinate of the neighbor
+    pub send_round: u32,        // Round the message of the neighbor was sent for
 }
 
 /**
@@ -22,13 +28,36 @@ pub struct Message {
     pub phy_coord: Coordinate,  // Physical coordinate of the node that sent the message
     pub phy_range: u32,         // Physical communication range of the node that sent the message
     pub phy_area: SquareCoords, // Physical area covered by transmission of the message
+    pub slot: u32,              // Slot the transmission starts in, 0 without a MAC layer
+    pub send_round: u32,        // Round the message is delivered in without latency
 }
 
+/**
+ * Messages received with a latency, with their arrival round, by receiver
+ */
+type InTransit = BTreeMap<(NodeType, u32), Vec<(u32, Message)>>;
+
+/**
+ * Type, id of the sender and send round of a message, which identify it
+ */
+type MessageId = (NodeType, u32, u32);
+
 /**
  * Ether represents the communication medium between nodes
  */
 pub struct Ether {
-    messages: Vec<Message>, // Messages in the Ether "while in transit"
+    messages: Vec<Message>,             // Messages in the Ether "while in transit"
+    propagation: Propagation,           // Propagation of the messages, a unit disc by default
+    interference: Option<Interference>, // Interference of the concurrent messages, none by default
+    collision_count: u32,               // Messages lost to the interference at the receivers
+    mac: Option<Mac>,                   // Channel access of the nodes, all at once by default
+    mac_loss_count: u32,                // Messages lost without getting the channel
+    latency: Option<Latency>,           // Delays of the messages, none by default
+    delays: Vec<Option<u32>>,           // Delay of each message, None if it expires
+    in_transit: InTransit,              // Received messages arriving later, by receiver
+    round: u32,                         // Round the messages are delivered in
+    expired_count: u32,                 // Messages delayed past the deadline
+    late_messages: BTreeSet<MessageId>, // Messages delivered after the round they were sent for
 }
 
 /**
@@ -57,6 +86,8 @@ impl Message {
                 x2: 0,
                 y2: 0,
             },
+            slot: 0,
+            send_round: 0,
         }
     }
 
@@ -64,7 +95,7 @@ impl Message {
      * Get the coordinate of the Message
      */
     pub fn get_coordinate(&self) -> Coordinate {
-        self.coordinate.clone()
+        self.coordinate
     }
 }
 
@@ -78,9 +109,51 @@ impl Ether {
     pub fn new() -> Ether {
         Ether {
             messages: Vec::new(),
+            propagation: Propagation::default(),
+            interference: None,
+            collision_count: 0,
+            mac: None,
+            mac_loss_count: 0,
+            latency: None,
+            delays: Vec::new(),
+            in_transit: BTreeMap::new(),
+            round: 0,
+            expired_count: 0,
+            late_messages: BTreeSet::new(),
         }
     }
 
+    /**
+     * Set the propagation of the messages to the receivers.
+     */
+    pub fn set_propagation(&mut self, propagation: Propagation) {
+        self.propagation = propagation;
+    }
+
+    /**
+     * Set the interference of the concurrent messages, so a message is only
+     * received above the SINR threshold.
+     */
+    pub fn set_interference(&mut self, interference: Option<Interference>) {
+        self.interference = interference;
+    }
+
+    /**
+     * Set the channel access of the nodes, so the messages of a round are
+     * sent in time slots instead of all at once.
+     */
+    pub fn set_mac(&mut self, mac: Option<Mac>) {
+        self.mac = mac;
+    }
+
+    /**
+     * Set the latency of the messages, so they can arrive several rounds
+     * after they are sent.
+     */
+    pub fn set_latency(&mut self, latency: Option<Latency>) {
+        self.latency = latency;
+    }
+
     /**
      * Add a new message to the Ether
      */
@@ -93,6 +166,61 @@ impl Ether {
      */
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.delays.clear();
+        self.collision_count = 0;
+        self.mac_loss_count = 0;
+        self.expired_count = 0;
+        self.late_messages.clear();
+    }
+
+    /**
+     * Give the nodes of the messages access to the channel, if there is a MAC
+     * layer. Each message gets the slot its transmission starts in, and the
+     * messages that don't get the channel are lost.
+     */
+    pub fn access_channel(&mut self) {
+        let Some(mac) = self.mac.as_mut() else {
+            return;
+        };
+
+        let slots = mac.access_channel(&self.messages, &mut self.propagation);
+        let messages = std::mem::take(&mut self.messages);
+        for (mut message, slot) in messages.into_iter().zip(slots) {
+            match slot {
+                Some(slot) => {
+                    message.slot = slot;
+                    self.messages.push(message);
+                }
+                None => self.mac_loss_count += 1,
+            }
+        }
+    }
+
+    /**
+     * Draw the delay of the messages sent in the round, if there is a
+     * latency. The messages delayed past the deadline expire, they still
+     * take the channel but are never delivered.
+     */
+    pub fn delay_messages(&mut self) {
+        let Some(latency) = self.latency.as_mut() else {
+            return;
+        };
+
+        self.delays = self.messages.iter().map(|_| latency.next_delay()).collect();
+        self.expired_count += self.delays.iter().filter(|delay| delay.is_none()).count() as u32;
+    }
+
+    /**
+     * Start the delivery of the messages of the given round. The messages in
+     * transit that should have arrived before are dropped, their receivers
+     * left.
+     */
+    pub fn start_delivery(&mut self, round: u32) {
+        self.round = round;
+        for messages in self.in_transit.values_mut() {
+            messages.retain(|(arrival_round, _)| *arrival_round >= round);
+        }
+        self.in_transit.retain(|_, messages| !messages.is_empty());
     }
 
     /**
@@ -103,7 +231,152 @@ impl Ether {
     }
 
     /**
-     * Check if a transmission from a transmitter to a receiver is possible.
+     * Get the number of messages sent since the Ether was cleared that
+     * expired before being delivered.
+     */
+    pub fn get_expired_count(&self) -> u32 {
+        self.expired_count
+    }
+
+    /**
+     * Get the number of messages delivered after the round they were sent
+     * for since the Ether was cleared, each message once whatever its number
+     * of receivers.
+     */
+    pub fn get_late_count(&self) -> u32 {
+        self.late_messages.len() as u32
+    }
+
+    /**
+     * Get the number of messages lost to the interference at the receivers
+     * since the Ether was cleared.
+     */
+    pub fn get_collision_count(&self) -> u32 {
+        self.collision_count
+    }
+
+    /**
+     * Get the number of messages lost without getting the channel since the
+     * Ether was cleared.
+     */
+    pub fn get_mac_loss_count(&self) -> u32 {
+        self.mac_loss_count
+    }
+
+    /**
+     * Get the channel access of an OBU, None without a MAC layer.
+     */
+    pub fn get_mac_stats(&self, obu_id: u32) -> Option<MacStats> {
+        self.mac.as_ref().map(|mac| mac.get_stats(obu_id))
+    }
+
+    /**
+     * Return the messages arriving at a receiver in the round. The receiver
+     * gets the messages of the Ether at its coordinate as they are sent;
+     * with a latency, they arrive after their delay, along with the late
+     * messages received in the previous rounds, in the order they were sent.
+     */
+    pub fn receive_messages(
+        &mut self,
+        receiver_type: NodeType,
+        receiver_id: u32,
+        receiver_coordinate: Coordinate,
+    ) -> Vec<Message> {
+        let received =
+            self.get_received_messages(receiver_type.clone(), receiver_id, receiver_coordinate);
+        if self.latency.is_none() {
+            return received
+                .into_iter()
+                .map(|index| self.messages[index].clone())
+                .collect();
+        }
+
+        // The expired messages are received but never arrive
+        let in_transit = self
+            .in_transit
+            .entry((receiver_type, receiver_id))
+            .or_default();
+        for index in received {
+            if let Some(delay) = self.delays[index] {
+                let message = &self.messages[index];
+                in_transit.push((message.send_round + delay, message.clone()));
+            }
+        }
+
+        let (arriving, later): (Vec<_>, Vec<_>) = std::mem::take(in_transit)
+            .into_iter()
+            .partition(|(arrival_round, _)| *arrival_round <= self.round);
+        *in_transit = later;
+
+        let arriving: Vec<Message> = arriving.into_iter().map(|(_, message)| message).collect();
+        self.late_messages.extend(
+            arriving
+                .iter()
+                .filter(|message| message.send_round < self.round)
+                .map(|message| {
+                    (
+                        message.origin_type.clone(),
+                        message.origin_id,
+                        message.send_round,
+                    )
+                }),
+        );
+        arriving
+    }
+
+    /**
+     * Return the indices of the messages of the Ether a receiver gets, given
+     * the propagation of the messages. Without interference every message
+     * received above the sensitivity is delivered, with it the messages must
+     * also reach the SINR threshold over the others on the channel at the
+     * same time. The propagation may be random, receiving the messages again
+     * can give another result.
+     */
+    pub fn get_received_messages(
+        &mut self,
+        receiver_type: NodeType,
+        receiver_id: u32,
+        receiver_coordinate: Coordinate,
+    ) -> Vec<usize> {
+        // The power of every message at the receiver, in the order they were sent
+        let messages = &self.messages;
+        let mut powers = Vec::with_capacity(messages.len());
+        for message in messages {
+            powers.push(self.propagation.get_received_power(
+                message.phy_coord,
+                message.phy_range,
+                receiver_coordinate,
+            ));
+        }
+        let sensitivity = self.propagation.get_sensitivity();
+
+        let Some(interference) = &self.interference else {
+            return (0..powers.len())
+                .filter(|index| powers[*index] >= sensitivity)
+                .collect();
+        };
+
+        // The receiver does not interfere with its own message
+        let own = messages.iter().position(|message| {
+            message.origin_type == receiver_type && message.origin_id == receiver_id
+        });
+        let slots: Vec<u32> = messages.iter().map(|message| message.slot).collect();
+        let frame_slots = self.mac.as_ref().map_or(1, Mac::get_frame_slots);
+        let received = interference.get_decoded(&powers, &slots, frame_slots, sensitivity, own);
+
+        let detected = powers
+            .iter()
+            .enumerate()
+            .filter(|(index, power)| Some(*index) != own && **power >= sensitivity)
+            .count();
+        self.collision_count += (detected - received.len()) as u32;
+
+        received
+    }
+
+    /**
+     * Check if a transmission from a transmitter to a receiver is possible
+     * within the range of the transmitter.
      */
     pub fn is_transmission_possible(
         transmitter_coordinate: Coordinate,
@@ -128,6 +401,10 @@ impl Ether {
 mod tests {
 
     use super::*;
+    use crate::interference::InterferenceParams;
+    use crate::latency::{LatencyDistribution, LatencyParams};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     // This function tests the `send_message()` and `get_messages()` methods of the `Ether` struct.
@@ -186,16 +463,190 @@ mod tests {
         let receiver_coordinate_out_of_range = Coordinate { x: 10, y: 10 };
         let transmitter_range = 5;
 
-        assert_eq!(
+        assert!(
             Ether::is_transmission_possible(transmitter_coordinate, transmitter_range, receiver_coordinate_in_range),
-            true,
             "The receiver is within the transmitter's range, so the transmission should be possible."
         );
 
-        assert_eq!(
-            Ether::is_transmission_possible(transmitter_coordinate, transmitter_range, receiver_coordinate_out_of_range),
-            false,
+        assert!(
+            !Ether::is_transmission_possible(transmitter_coordinate, transmitter_range, receiver_coordinate_out_of_range),
             "The receiver is outside the transmitter's range, so the transmission should not be possible."
         );
     }
+
+    /**
+     * This function tests the `get_received_messages()` method of the `Ether` struct, with and
+     * without the interference of the concurrent messages.
+     */
+    #[test]
+    fn test_get_received_messages() {
+        let mut ether = Ether::new();
+        for (id, x) in [(0, 0), (1, 2), (2, 9)] {
+            let coordinate = Coordinate { x, y: 0 };
+            ether.send_message(Message::new(id, NodeType::OBU, coordinate, coordinate, 3));
+        }
+        let receiver_coordinate = Coordinate { x: 1, y: 0 };
+
+        // Without interference, every message in range is received.
+        assert_eq!(
+            ether.get_received_messages(NodeType::RSU, 0, receiver_coordinate),
+            vec![0, 1]
+        );
+        assert_eq!(ether.get_collision_count(), 0);
+
+        // The two messages in range collide, the one out of range doesn't interfere.
+        ether.set_interference(Some(Interference::new(&InterferenceParams {
+            sinr_threshold: 5.0,
+            noise_power: -99.0,
+            capture: true,
+        })));
+        assert!(ether
+            .get_received_messages(NodeType::RSU, 0, receiver_coordinate)
+            .is_empty());
+        assert_eq!(ether.get_collision_count(), 2);
+
+        // An OBU doesn't interfere with the messages it receives.
+        assert_eq!(
+            ether.get_received_messages(NodeType::OBU, 1, Coordinate { x: 2, y: 0 }),
+            vec![0]
+        );
+        assert_eq!(ether.get_collision_count(), 2);
+
+        // Clearing the Ether resets the collisions.
+        ether.clear();
+        assert_eq!(ether.get_collision_count(), 0);
+    }
+
+    /**
+     * Return a latency with delays drawn uniformly up to 3 rounds, expiring
+     * past 2 rounds.
+     */
+    fn latency() -> Latency {
+        Latency::new(
+            &LatencyParams {
+                distribution: LatencyDistribution::Uniform,
+                delay: None,
+                min_delay: Some(0),
+                max_delay: Some(3),
+                mean_delay: None,
+                deadline: Some(2),
+            },
+            StdRng::seed_from_u64(1),
+        )
+    }
+
+    /**
+     * Send the messages of OBUs at the given coordinates for a round, and start
+     * the delivery of the round.
+     */
+    fn send_round(ether: &mut Ether, round: u32, xs: &[u32]) {
+        ether.clear();
+        for (id, x) in xs.iter().enumerate() {
+            let coordinate = Coordinate { x: *x, y: 0 };
+            let mut message = Message::new(id as u32, NodeType::OBU, coordinate, coordinate, 3);
+            message.send_round = round;
+            ether.send_message(message);
+        }
+        ether.delay_messages();
+        ether.start_delivery(round);
+    }
+
+    /**
+     * This function tests the `receive_messages()` method of the `Ether` struct with a latency:
+     * the messages arrive late and in the order they were sent, or expire past the deadline.
+     */
+    #[test]
+    fn test_receive_messages_latency() {
+        let receiver_coordinate = Coordinate { x: 0, y: 0 };
+
+        // Without latency, the messages are delivered in the round they are sent for.
+        let mut ether = Ether::new();
+        send_round(&mut ether, 0, &[0; 20]);
+        let received = ether.receive_messages(NodeType::RSU, 0, receiver_coordinate);
+        assert_eq!(received.len(), 20);
+        assert_eq!(ether.get_expired_count(), 0);
+
+        // With latency, the messages arrive up to the deadline after they are sent.
+        ether.set_latency(Some(latency()));
+        send_round(&mut ether, 1, &[0; 20]);
+        let expired = ether.get_expired_count();
+        assert!(expired > 0 && expired < 20);
+        let mut received = ether.receive_messages(NodeType::RSU, 0, receiver_coordinate);
+        ether.receive_messages(NodeType::RSU, 1, receiver_coordinate);
+        assert_eq!(ether.get_late_count(), 0);
+
+        // The late messages of the previous round arrive with the new ones,
+        // and are counted once for both receivers.
+        send_round(&mut ether, 2, &[0; 20]);
+        let arriving = ether.receive_messages(NodeType::RSU, 0, receiver_coordinate);
+        ether.receive_messages(NodeType::RSU, 1, receiver_coordinate);
+        let send_rounds: Vec<u32> = arriving.iter().map(|m| m.send_round).collect();
+        assert!(send_rounds.contains(&1) && send_rounds.contains(&2));
+        assert!(send_rounds.windows(2).all(|rounds| rounds[0] <= rounds[1]));
+        assert_eq!(
+            ether.get_late_count(),
+            send_rounds.iter().filter(|round| **round == 1).count() as u32
+        );
+        received.extend(arriving);
+
+        // Nothing is left in transit after the deadline of the last messages.
+        for round in 3..=5 {
+            send_round(&mut ether, round, &[]);
+            received.extend(ether.receive_messages(NodeType::RSU, 0, receiver_coordinate));
+        }
+        let sent_for_1 = received.iter().filter(|m| m.send_round == 1).count() as u32;
+        assert_eq!(sent_for_1 + expired, 20);
+        assert!(ether
+            .in_transit
+            .values()
+            .all(|messages| messages.is_empty()));
+
+        // The messages in transit to a receiver that left are dropped.
+        send_round(&mut ether, 6, &[0; 20]);
+        ether.receive_messages(NodeType::RSU, 0, receiver_coordinate);
+        for round in 7..=9 {
+            send_round(&mut ether, round, &[]);
+        }
+        assert!(ether.in_transit.is_empty());
+    }
+
+    /**
+     * This function tests that a late message is received when it is sent: it neither collides
+     * with nor is captured by the messages of a later round arriving with it.
+     */
+    #[test]
+    fn test_receive_messages_late_interference() {
+        let mut ether = Ether::new();
+        ether.set_interference(Some(Interference::new(&InterferenceParams {
+            sinr_threshold: 5.0,
+            noise_power: -99.0,
+            capture: true,
+        })));
+        ether.set_latency(Some(latency()));
+        let receiver_coordinate = Coordinate { x: 1, y: 0 };
+
+        // The message of the first round is delayed by a round.
+        send_round(&mut ether, 1, &[0]);
+        ether.delays = vec![Some(1)];
+        assert!(ether
+            .receive_messages(NodeType::RSU, 0, receiver_coordinate)
+            .is_empty());
+
+        // The message of the next round, of the same power, arrives with it.
+        send_round(&mut ether, 2, &[2]);
+        ether.delays = vec![Some(0)];
+        let received = ether.receive_messages(NodeType::RSU, 0, receiver_coordinate);
+        let send_rounds: Vec<u32> = received.iter().map(|m| m.send_round).collect();
+        assert_eq!(send_rounds, vec![1, 2]);
+        assert_eq!(ether.get_collision_count(), 0);
+        assert_eq!(ether.get_late_count(), 1);
+
+        // The same messages sent in the same round collide.
+        send_round(&mut ether, 3, &[0, 2]);
+        ether.delays = vec![Some(0), Some(0)];
+        assert!(ether
+            .receive_messages(NodeType::RSU, 0, receiver_coordinate)
+            .is_empty());
+        assert_eq!(ether.get_collision_count(), 2);
+    }
 }
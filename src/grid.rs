@@ -4,6 +4,11 @@
  * The grid is a 2d array of cells, each cell is a position on the simulated
  * environment. The grid is used to store the state of the environment.
  */
+use crate::osm::OsmParams;
+use crate::road_map::RoadMap;
+use crate::scenario::ScenarioError;
+use crate::traffic_lights::TrafficLights;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
 
 /**
@@ -20,7 +25,7 @@ pub struct SquareCoords {
 /**
  * Coordinate represents the coordinates of a cell.
  */
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Coordinate {
     pub x: u32,
     pub y: u32,
@@ -35,6 +40,47 @@ enum FlowDirection {
     ToZero,   // right -> left / bottom -> top
 }
 
+/**
+ * Heading represents the direction the vehicles drive in along a street. The
+ * column streets run along the y axis and the row streets along the x axis.
+ * The headings are ordered with the column ones first.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Heading {
+    South, // Column street, towards the last row
+    North, // Column street, towards the row 0
+    East,  // Row street, towards the last column
+    West,  // Row street, towards the column 0
+}
+
+/**
+ * Lane represents a lane of a street, by its heading and its index among the
+ * lanes with the same heading.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Lane {
+    pub heading: Heading,
+    pub index: u32, // From 0 to the number of lanes with the heading - 1
+}
+
+/**
+ * Position represents the lane of a cell where an OBU is.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub coordinate: Coordinate,
+    pub lane: Lane,
+}
+
+/**
+ * LaneState represents the state of a lane in a cell.
+ */
+#[derive(Clone, Debug)]
+struct LaneState {
+    lane: Lane,
+    obu_id: Option<u32>, // If this lane is occupied by an OBU, what is its ID?
+}
+
 /**
  * CellState represents the state of a cell.
  */
@@ -42,27 +88,252 @@ enum FlowDirection {
 pub struct CellState {
     pub id: u32,                       // unique id
     is_street: bool,                   // Is this cell a street?
-    pub obu_id: Option<u32>,           // If this cell is occupied by an OBU, what is its ID?
+    lanes: Vec<LaneState>,             // Lanes of the streets of this cell, in heading order
     next_coordinates: Vec<Coordinate>, // If this cell is a street, what are the next possible coordinates?
 }
 
+/**
+ * StreetParams represents the layout of a street that differs from the
+ * default layout of the grid. The street is either a column or a row, given
+ * by its x or y coordinate.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreetParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>, // x coordinate of a column street
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<u32>, // y coordinate of a row street
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_way: Option<bool>, // The street is two-way, the grid default if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lanes: Option<u32>, // Lanes per direction, the grid default if unset
+}
+
 /**
  * GridParams represents the parameters used to create a new grid.
  */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct GridParams {
     pub blocks_per_street: u32,
     pub block_size: u32,
+    #[serde(default)]
+    pub block_widths: Vec<u32>, // Width of each column of blocks from x = 0, instead of the block size
+    #[serde(default)]
+    pub block_heights: Vec<u32>, // Height of each row of blocks from y = 0, instead of the block size
+    #[serde(default)]
+    pub map: Option<String>, // ASCII map of the roads ('.') and buildings ('#'), instead of the blocks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osm: Option<OsmParams>, // OpenStreetMap extract of the roads, instead of the blocks
+    #[serde(default)]
+    pub removed_segments: Vec<Coordinate>, // A cell of each street segment removed from the roads
+    #[serde(default)]
+    pub two_way: bool, // Streets are two-way, instead of one-way in alternating directions
+    #[serde(default = "GridParams::default_lanes")]
+    pub lanes: u32, // Lanes per direction of the streets
+    #[serde(default)]
+    pub streets: Vec<StreetParams>, // Streets with a layout other than the default one
+}
+
+/**
+ * GridStats represents the stats of a grid.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct GridStats {
+    pub width: u32,
+    pub height: u32,
+    pub street_cells: u32,
+    pub lane_cells: u32, // Cells of all the lanes, an intersection counts once per lane
 }
 
 /**
  * Grid represents the simulated environment.
  */
 pub struct Grid {
-    block_size: u32,            // The size of each block
-    blocks_per_street: u32,     // The number of blocks per street
-    cells: Vec<Vec<CellState>>, // The grid itself
-    dimension: u32,             // The dimension of the grid
-    street_cells: u32,          // The number of street cells
+    cells: Vec<Vec<CellState>>,            // The grid itself
+    width: u32,                            // The number of cells along the x axis
+    height: u32,                           // The number of cells along the y axis
+    street_cells: u32,                     // The number of street cells
+    two_way: bool,                         // Streets are two-way by default
+    lanes: u32,                            // Lanes per direction of the streets by default
+    streets: Vec<StreetParams>,            // Streets with their own layout
+    traffic_lights: Option<TrafficLights>, // Signals of the intersections, none if unsignalized
+}
+
+/**
+ * Heading implementation.
+ */
+impl Heading {
+    /**
+     * Return true if the heading is the one of a column street.
+     */
+    pub fn is_column(&self) -> bool {
+        matches!(self, Heading::South | Heading::North)
+    }
+
+    /**
+     * Return the opposite heading.
+     */
+    pub fn opposite(&self) -> Heading {
+        match self {
+            Heading::South => Heading::North,
+            Heading::North => Heading::South,
+            Heading::East => Heading::West,
+            Heading::West => Heading::East,
+        }
+    }
+}
+
+/**
+ * CellState implementation.
+ */
+impl CellState {
+    /**
+     * Return the ids of the OBUs in the cell.
+     */
+    pub fn get_obu_ids(&self) -> Vec<u32> {
+        self.lanes
+            .iter()
+            .filter_map(|lane_state| lane_state.obu_id)
+            .collect()
+    }
+}
+
+/**
+ * GridParams implementation.
+ */
+impl GridParams {
+    /**
+     * The streets have one lane per direction by default.
+     */
+    fn default_lanes() -> u32 {
+        1
+    }
+
+    /**
+     * Build the road map of the grid: the ASCII map or the OpenStreetMap
+     * extract if any, or else the blocks, with the removed segments.
+     */
+    pub fn get_road_map(&self) -> Result<RoadMap, ScenarioError> {
+        let mut road_map = match (&self.map, &self.osm) {
+            (Some(_), Some(_)) => {
+                return Err(ScenarioError::Invalid(
+                    "grid.map can't be used with grid.osm".to_string(),
+                ));
+            }
+            (None, Some(osm)) => {
+                if !self.block_widths.is_empty() || !self.block_heights.is_empty() {
+                    return Err(ScenarioError::Invalid(
+                        "grid.osm can't be used with grid.block_widths or grid.block_heights"
+                            .to_string(),
+                    ));
+                }
+
+                osm.validate()?;
+                osm.load()?
+            }
+            (Some(map), None) => {
+                if !self.block_widths.is_empty() || !self.block_heights.is_empty() {
+                    return Err(ScenarioError::Invalid(
+                        "grid.map can't be used with grid.block_widths or grid.block_heights"
+                            .to_string(),
+                    ));
+                }
+
+                RoadMap::parse(map)
+                    .map_err(|e| ScenarioError::Invalid(format!("grid.map: {}", e)))?
+            }
+            (None, None) => {
+                let default_blocks = vec![self.block_size; self.blocks_per_street as usize];
+                let block_widths = if self.block_widths.is_empty() {
+                    &default_blocks
+                } else {
+                    &self.block_widths
+                };
+                let block_heights = if self.block_heights.is_empty() {
+                    &default_blocks
+                } else {
+                    &self.block_heights
+                };
+                if block_widths.contains(&0) || block_heights.contains(&0) {
+                    return Err(ScenarioError::Invalid(
+                        "grid.block_widths and grid.block_heights must be greater than 0"
+                            .to_string(),
+                    ));
+                }
+
+                RoadMap::from_blocks(block_widths, block_heights)
+            }
+        };
+
+        for coordinate in &self.removed_segments {
+            road_map
+                .remove_segment(*coordinate)
+                .map_err(|e| ScenarioError::Invalid(format!("grid.removed_segments: {}", e)))?;
+        }
+
+        Ok(road_map)
+    }
+
+    /**
+     * Check that the road map can be built and has entry points, that the
+     * streets have lanes and that the street layouts are given for distinct
+     * streets of the grid. Return the grid, so it is only built once.
+     */
+    pub fn validate(&self) -> Result<Grid, ScenarioError> {
+        let road_map = self.get_road_map()?;
+
+        if self.lanes == 0 || self.streets.iter().any(|street| street.lanes == Some(0)) {
+            return Err(ScenarioError::Invalid(
+                "grid.lanes must be greater than 0".to_string(),
+            ));
+        }
+
+        for (index, street) in self.streets.iter().enumerate() {
+            let (street_id, is_street) = match (street.column, street.row) {
+                (Some(x), None) => (
+                    x,
+                    (0..road_map.get_height())
+                        .any(|y| road_map.has_column_street(Coordinate { x, y })),
+                ),
+                (None, Some(y)) => (
+                    y,
+                    (0..road_map.get_width()).any(|x| road_map.has_row_street(Coordinate { x, y })),
+                ),
+                _ => {
+                    return Err(ScenarioError::Invalid(
+                        "grid.streets: set either the column or the row of each street".to_string(),
+                    ))
+                }
+            };
+            if !is_street {
+                return Err(ScenarioError::Invalid(format!(
+                    "grid.streets: {} is not a street of the grid",
+                    street_id
+                )));
+            }
+            if self.streets[..index]
+                .iter()
+                .any(|other| (other.column, other.row) == (street.column, street.row))
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "grid.streets: street {} has more than one layout",
+                    street_id
+                )));
+            }
+        }
+
+        // The vehicles enter the grid at its border
+        let grid = Grid::from_road_map(&road_map, self.clone());
+        if grid.get_entry_points().is_empty() {
+            return Err(ScenarioError::Invalid(
+                "grid: no street starts at the border of the grid".to_string(),
+            ));
+        }
+
+        Ok(grid)
+    }
 }
 
 /**
@@ -70,38 +341,43 @@ pub struct Grid {
  */
 impl Grid {
     /**
-     * Create a new grid with the given number of blocks per street and the size of
-     * each block. The blocks and the grid are always a square. And the blocks have
-     * streets on all sides.
+     * Create a new grid from its params. By default the grid is a square of
+     * blocks_per_street blocks of block_size per side, with streets on all
+     * sides of the blocks. The params must be valid.
      */
     pub fn new(params: GridParams) -> Grid {
-        // Get the block size and calculate blocks per street from the parameters.
-        let block_size = params.block_size;
-        let blocks_per_street = params.blocks_per_street;
-
-        // Calculate the dimension of the grid based on the block size and blocks per street.
-        let dimension: u32 = blocks_per_street * block_size + blocks_per_street + 1;
+        let road_map = params.get_road_map().expect("Invalid grid params");
+        Grid::from_road_map(&road_map, params)
+    }
 
-        // Calculate the number of street cells.
-        let street_cells = (dimension * dimension) as u32
-            - (blocks_per_street * blocks_per_street * block_size * block_size) as u32;
+    /**
+     * Create a new grid with the roads of a road map. The lanes of the
+     * streets come from the params, their blocks, map and removed segments
+     * are not used.
+     */
+    pub fn from_road_map(road_map: &RoadMap, params: GridParams) -> Grid {
+        let width = road_map.get_width();
+        let height = road_map.get_height();
 
         // Initialize the cell id counter.
         let mut id = 0;
 
-        // Create the grid vector of cells, marking the streets.
+        // Create the grid vector of cells, marking and counting the streets.
         let mut cells = Vec::new();
-        for i in 0..dimension {
-            let mut row = Vec::new();
-            for j in 0..dimension {
-                // Determine if the cell is part of a street.
-                let is_street = i % (block_size + 1) == 0 || j % (block_size + 1) == 0;
-
-                // Create a new `CellState` instance and add it to the row vector.
-                row.push(CellState {
+        let mut street_cells = 0;
+        for x in 0..width {
+            let mut column = Vec::new();
+            for y in 0..height {
+                let is_street = road_map.is_road(Coordinate { x, y });
+                if is_street {
+                    street_cells += 1;
+                }
+
+                // Create a new `CellState` instance and add it to the column vector.
+                column.push(CellState {
                     id,
                     is_street,
-                    obu_id: None,
+                    lanes: Vec::new(),
                     next_coordinates: Vec::new(),
                 });
 
@@ -109,32 +385,163 @@ impl Grid {
                 id += 1;
             }
 
-            // Add the row to the grid vector.
-            cells.push(row);
+            // Add the column to the grid vector.
+            cells.push(column);
         }
 
-        // Create and returns a new `Grid` instance with the calculated values.
-        Grid {
-            block_size,
-            blocks_per_street,
+        // Create a new `Grid` instance with the calculated values.
+        let mut grid = Grid {
             cells,
-            dimension,
+            width,
+            height,
             street_cells,
+            two_way: params.two_way,
+            lanes: params.lanes,
+            streets: params.streets,
+            traffic_lights: None,
+        };
+
+        // Add the lanes of the streets to their cells, the column ones first.
+        for x in 0..width {
+            for y in 0..height {
+                let coordinate = Coordinate { x, y };
+                let mut lanes = Vec::new();
+                let given_headings = road_map.get_headings(coordinate);
+                for (street_id, is_column) in [(x, true), (y, false)] {
+                    let is_street = if is_column {
+                        road_map.has_column_street(coordinate)
+                    } else {
+                        road_map.has_row_street(coordinate)
+                    };
+                    let (mut headings, lane_count) = grid.get_street_layout(street_id, is_column);
+
+                    // The headings given by the road map replace the street ones
+                    if !given_headings.is_empty() {
+                        headings = given_headings
+                            .iter()
+                            .copied()
+                            .filter(|heading| heading.is_column() == is_column)
+                            .collect();
+                    } else if !is_street {
+                        continue;
+                    }
+
+                    for heading in headings {
+                        for index in 0..lane_count {
+                            lanes.push(LaneState {
+                                lane: Lane { heading, index },
+                                obu_id: None,
+                            });
+                        }
+                    }
+                }
+                grid.cells[x as usize][y as usize].lanes = lanes;
+            }
         }
+
+        grid
+    }
+
+    /**
+     * Get the grid width, its number of cells along the x axis.
+     */
+    pub fn get_width(&self) -> u32 {
+        self.width
     }
 
     /**
-     * Get the grid dimension.
+     * Get the grid height, its number of cells along the y axis.
      */
-    pub fn get_dimension(&self) -> u32 {
-        self.dimension
+    pub fn get_height(&self) -> u32 {
+        self.height
     }
 
     /**
-     * Check if the given row or column is a street.
+     * Check if a cell is a street.
      */
-    fn is_street(&self, i: u32) -> bool {
-        i % (self.block_size + 1) == 0
+    pub fn is_street(&self, coordinate: Coordinate) -> bool {
+        self.cells[coordinate.x as usize][coordinate.y as usize].is_street
+    }
+
+    /**
+     * Check if a square of the grid has a street cell.
+     */
+    pub fn has_street_in(&self, square: SquareCoords) -> bool {
+        (square.x1..=square.x2)
+            .any(|x| (square.y1..=square.y2).any(|y| self.is_street(Coordinate { x, y })))
+    }
+
+    /**
+     * Return the street cell nearest to a position given in cells, or None if
+     * the position is outside the grid. A position in a block moves to the
+     * nearest street cell in its row or column, or else to the nearest
+     * street cell.
+     */
+    pub fn snap_to_street(&self, x: f64, y: f64) -> Option<Coordinate> {
+        let max_x = (self.width - 1) as f64;
+        let max_y = (self.height - 1) as f64;
+        if !(-0.5..max_x + 0.5).contains(&x) || !(-0.5..max_y + 0.5).contains(&y) {
+            return None;
+        }
+
+        let cell = Coordinate {
+            x: x.round().clamp(0.0, max_x) as u32,
+            y: y.round().clamp(0.0, max_y) as u32,
+        };
+        if self.is_street(cell) {
+            return Some(cell);
+        }
+
+        // Inside a block, move to the nearest street cell on each side of it
+        let is_street = |coordinate: &Coordinate| self.is_street(*coordinate);
+        let mut candidates: Vec<(f64, Coordinate)> = [
+            (0..cell.x)
+                .rev()
+                .map(|x| Coordinate { x, y: cell.y })
+                .find(is_street),
+            (cell.x + 1..self.width)
+                .map(|x| Coordinate { x, y: cell.y })
+                .find(is_street),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|street| ((x - street.x as f64).abs(), street))
+        .chain(
+            [
+                (0..cell.y)
+                    .rev()
+                    .map(|y| Coordinate { x: cell.x, y })
+                    .find(is_street),
+                (cell.y + 1..self.height)
+                    .map(|y| Coordinate { x: cell.x, y })
+                    .find(is_street),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|street| ((y - street.y as f64).abs(), street)),
+        )
+        .collect();
+
+        // With no street in its row and column, move to the nearest street cell
+        if candidates.is_empty() {
+            for street_x in 0..self.width {
+                for street_y in 0..self.height {
+                    let street = Coordinate {
+                        x: street_x,
+                        y: street_y,
+                    };
+                    if self.is_street(street) {
+                        let distance = (x - street_x as f64).hypot(y - street_y as f64);
+                        candidates.push((distance, street));
+                    }
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, coordinate)| coordinate)
     }
 
     /**
@@ -147,10 +554,130 @@ impl Grid {
         }
     }
 
+    /**
+     * Get the headings and the number of lanes per heading of a street. A
+     * one-way street flows in its flow direction, a two-way street in both.
+     */
+    fn get_street_layout(&self, street_id: u32, is_column: bool) -> (Vec<Heading>, u32) {
+        let layout = self.streets.iter().find(|street| {
+            if is_column {
+                street.column == Some(street_id)
+            } else {
+                street.row == Some(street_id)
+            }
+        });
+        let two_way = layout
+            .and_then(|street| street.two_way)
+            .unwrap_or(self.two_way);
+        let lanes = layout.and_then(|street| street.lanes).unwrap_or(self.lanes);
+
+        let (from_zero, to_zero) = if is_column {
+            (Heading::South, Heading::North)
+        } else {
+            (Heading::East, Heading::West)
+        };
+        let headings = if two_way {
+            vec![from_zero, to_zero]
+        } else {
+            match self.get_flow_direction(street_id, is_column) {
+                FlowDirection::FromZero => vec![from_zero],
+                FlowDirection::ToZero => vec![to_zero],
+            }
+        };
+
+        (headings, lanes)
+    }
+
+    /**
+     * Get the coordinate next to the given one in a heading, none if it is out
+     * of the grid.
+     */
+    fn step(&self, coordinate: Coordinate, heading: Heading) -> Option<Coordinate> {
+        let Coordinate { x, y } = coordinate;
+        let next = match heading {
+            Heading::South => Coordinate { x, y: y + 1 },
+            Heading::North => Coordinate {
+                x,
+                y: y.checked_sub(1)?,
+            },
+            Heading::East => Coordinate { x: x + 1, y },
+            Heading::West => Coordinate {
+                x: x.checked_sub(1)?,
+                y,
+            },
+        };
+
+        (next.x < self.width && next.y < self.height).then_some(next)
+    }
+
+    /**
+     * Get the headings of the lanes of a cell, in heading order.
+     */
+    pub fn get_headings(&self, coordinate: Coordinate) -> Vec<Heading> {
+        let mut headings: Vec<Heading> = self.cells[coordinate.x as usize][coordinate.y as usize]
+            .lanes
+            .iter()
+            .map(|lane_state| lane_state.lane.heading)
+            .collect();
+        headings.dedup();
+        headings
+    }
+
+    /**
+     * Get the number of lanes of a cell with the given heading.
+     */
+    fn get_lane_count(&self, coordinate: Coordinate, heading: Heading) -> u32 {
+        self.cells[coordinate.x as usize][coordinate.y as usize]
+            .lanes
+            .iter()
+            .filter(|lane_state| lane_state.lane.heading == heading)
+            .count() as u32
+    }
+
+    /**
+     * Get the headings a vehicle can drive in from a position, ahead in its
+     * street and, at an intersection, into the crossing street. Vehicles
+     * only make U-turns at the dead end of a two-way street.
+     */
+    fn get_next_headings(&self, position: Position) -> Vec<Heading> {
+        let (u_turn, headings): (Vec<Heading>, Vec<Heading>) = self
+            .get_headings(position.coordinate)
+            .into_iter()
+            .filter(|heading| {
+                self.step(position.coordinate, *heading)
+                    .is_some_and(|next| self.get_lane_count(next, *heading) > 0)
+            })
+            .partition(|heading| *heading == position.lane.heading.opposite());
+
+        if headings.is_empty() {
+            u_turn
+        } else {
+            headings
+        }
+    }
+
+    /**
+     * Check if a lane of a cell is free. The intersections are shared by the
+     * crossing streets, so a lane of an intersection is only free if no OBU
+     * is in the lanes of the crossing street either.
+     */
+    fn is_lane_free(&self, position: Position) -> bool {
+        let cell = &self.cells[position.coordinate.x as usize][position.coordinate.y as usize];
+
+        cell.lanes
+            .iter()
+            .any(|lane_state| lane_state.lane == position.lane)
+            && cell.lanes.iter().all(|lane_state| {
+                lane_state.obu_id.is_none()
+                    || (lane_state.lane != position.lane
+                        && lane_state.lane.heading.is_column() == position.lane.heading.is_column())
+            })
+    }
+
     /**
      * Get the next possible coordinates from a given coordinate.
      */
-    fn get_next_coordinates(&mut self, coordinate: Coordinate) -> Vec<Coordinate> {
+    pub fn get_next_coordinates(&mut self, coordinate: Coordinate) -> Vec<Coordinate> {
         // Check if the next coordinates were already calculated for any cell
         if self.cells[coordinate.x as usize][coordinate.y as usize]
             .next_coordinates
@@ -166,61 +693,15 @@ impl Grid {
     }
 
     /**
-     * Calculate the next possible coordinates from a given coordinate.
+     * Calculate the next possible coordinates from a given coordinate, in
+     * every heading of its lanes.
      * Do not call this method directly, use grid.get_next_coordinates() instead.
      */
     fn calculate_next_coordinates(&self, coordinate: Coordinate) -> Vec<Coordinate> {
-        let mut next_coordinates = Vec::new();
-
-        // check if column is a street
-        if self.is_street(coordinate.x) {
-            match self.get_flow_direction(coordinate.x, true) {
-                FlowDirection::FromZero => {
-                    // check if there is at least one cell ahead
-                    if coordinate.y < self.dimension - 1 {
-                        next_coordinates.push(Coordinate {
-                            x: coordinate.x,
-                            y: coordinate.y + 1,
-                        });
-                    }
-                }
-                FlowDirection::ToZero => {
-                    // check if there is at least one cell ahead
-                    if coordinate.y > 0 {
-                        next_coordinates.push(Coordinate {
-                            x: coordinate.x,
-                            y: coordinate.y - 1,
-                        });
-                    }
-                }
-            }
-        }
-
-        // check if row is a street
-        if self.is_street(coordinate.y) {
-            match self.get_flow_direction(coordinate.y, false) {
-                FlowDirection::FromZero => {
-                    // check if there is at least one cell ahead
-                    if coordinate.x < self.dimension - 1 {
-                        next_coordinates.push(Coordinate {
-                            x: coordinate.x + 1,
-                            y: coordinate.y,
-                        });
-                    }
-                }
-                FlowDirection::ToZero => {
-                    // check if there is at least one cell ahead
-                    if coordinate.x > 0 {
-                        next_coordinates.push(Coordinate {
-                            x: coordinate.x - 1,
-                            y: coordinate.y,
-                        });
-                    }
-                }
-            }
-        }
-
-        next_coordinates
+        self.get_headings(coordinate)
+            .into_iter()
+            .filter_map(|heading| self.step(coordinate, heading))
+            .collect()
     }
 
     /**
@@ -228,8 +709,8 @@ impl Grid {
      */
     pub fn update_next_coordinates(&mut self) {
         // iterate over the grid
-        for i in 0..self.dimension {
-            for j in 0..self.dimension {
+        for i in 0..self.width {
+            for j in 0..self.height {
                 // get the current cell
                 let cell = &self.cells[i as usize][j as usize];
 
@@ -256,52 +737,237 @@ impl Grid {
     }
 
     /**
-     * Get possible moves from a given coordinate.
-     * A move is possible if the next cell is not occupied.
+     * Get the position of an OBU in the cell of the given coordinate.
      */
-    pub fn get_possible_moves(&mut self, coordinate: Coordinate) -> Vec<Coordinate> {
-        // get the next coordinates
-        let possible_moves = self.get_next_coordinates(coordinate);
+    pub fn get_position(&self, coordinate: Coordinate, obu_id: u32) -> Option<Position> {
+        self.cells[coordinate.x as usize][coordinate.y as usize]
+            .lanes
+            .iter()
+            .find(|lane_state| lane_state.obu_id == Some(obu_id))
+            .map(|lane_state| Position {
+                coordinate,
+                lane: lane_state.lane,
+            })
+    }
+
+    /**
+     * Get possible moves from a given position, at most one for each heading.
+     * A move is possible if the next lane is not occupied and, at a signalized
+     * intersection, its signal is not red for the heading. The vehicle keeps
+     * its lane, or the closest one when turning into a street with less
+     * lanes, and changes to an adjacent lane if its lane is taken.
+     */
+    pub fn get_possible_moves(&self, position: Position) -> Vec<Position> {
+        let mut possible_moves = Vec::new();
+
+        for heading in self.get_next_headings(position) {
+            let Some(next_coordinate) = self.step(position.coordinate, heading) else {
+                continue;
+            };
+            // the vehicles wait at the red before entering an intersection
+            if self.is_red(next_coordinate, heading) {
+                continue;
+            }
+
+            let lanes = self.get_lane_count(next_coordinate, heading);
+            let index = position.lane.index.min(lanes - 1);
+
+            // try the lane of the vehicle first, then the adjacent ones
+            let next_move = [Some(index), index.checked_sub(1), Some(index + 1)]
+                .into_iter()
+                .flatten()
+                .filter(|index| *index < lanes)
+                .map(|index| Position {
+                    coordinate: next_coordinate,
+                    lane: Lane { heading, index },
+                })
+                .find(|next_position| self.is_lane_free(*next_position));
+
+            possible_moves.extend(next_move);
+        }
 
-        // filter the next coordinates to get only the possible moves
         possible_moves
+    }
+
+    /**
+     * Get the cells a vehicle can drive to from a position, one for each
+     * heading, whether their lanes are free or not, with the heading and
+     * whether their signal is red for the vehicle.
+     */
+    pub fn get_next_cells(&self, position: Position) -> Vec<(Coordinate, Heading, bool)> {
+        self.get_next_headings(position)
             .into_iter()
-            .filter(|next_coordinate| {
-                // get the next cell state
-                let next_cell_state = self.get_cell_state(next_coordinate.clone());
+            .filter_map(|heading| {
+                self.step(position.coordinate, heading)
+                    .map(|next| (next, heading, self.is_red(next, heading)))
+            })
+            .collect()
+    }
+
+    /**
+     * Check if the signal of a cell is red for the vehicles entering it in a
+     * heading, never if the cell is not signalized.
+     */
+    fn is_red(&self, coordinate: Coordinate, heading: Heading) -> bool {
+        self.traffic_lights
+            .as_ref()
+            .is_some_and(|traffic_lights| traffic_lights.is_red(coordinate, heading))
+    }
 
-                // check if the next cell is not occupied
-                next_cell_state.obu_id.is_none()
+    /**
+     * Get the headings the vehicles can enter a cell in: the headings of its
+     * lanes that continue a lane of the cell before it.
+     */
+    fn get_approach_headings(&self, coordinate: Coordinate) -> Vec<Heading> {
+        self.get_headings(coordinate)
+            .into_iter()
+            .filter(|heading| {
+                self.step(coordinate, heading.opposite())
+                    .is_some_and(|previous| self.get_lane_count(previous, *heading) > 0)
             })
             .collect()
     }
 
     /**
-     * Move obu_id from the current coordinate to the next coordinate.
+     * Get the intersections of the grid, the cells the vehicles can enter
+     * from both a column and a row street. They are listed by column and row
+     * from 0.
+     */
+    pub fn get_intersections(&self) -> Vec<Coordinate> {
+        let mut intersections = Vec::new();
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let coordinate = Coordinate { x, y };
+                let headings = self.get_approach_headings(coordinate);
+                if headings.iter().any(Heading::is_column)
+                    && headings.iter().any(|heading| !heading.is_column())
+                {
+                    intersections.push(coordinate);
+                }
+            }
+        }
+        intersections
+    }
+
+    /**
+     * Check if an OBU approaches a cell along the column or the row streets:
+     * it is in the cell before it, in a lane heading to it.
+     */
+    pub fn has_approaching_obu(&self, coordinate: Coordinate, is_column: bool) -> bool {
+        self.get_approach_headings(coordinate)
+            .into_iter()
+            .filter(|heading| heading.is_column() == is_column)
+            .any(|heading| {
+                self.step(coordinate, heading.opposite())
+                    .is_some_and(|previous| {
+                        self.cells[previous.x as usize][previous.y as usize]
+                            .lanes
+                            .iter()
+                            .any(|lane_state| {
+                                lane_state.lane.heading == heading && lane_state.obu_id.is_some()
+                            })
+                    })
+            })
+    }
+
+    /**
+     * Set the signals of the intersections, the grid has none by default.
      */
-    pub fn move_obu(
-        &mut self,
-        current_coordinate: Coordinate,
-        next_coordinate: Coordinate,
-    ) -> Coordinate {
-        // get the current cell state
-        let mut current_cell_state = self.get_cell_state(current_coordinate);
+    pub fn set_traffic_lights(&mut self, traffic_lights: Option<TrafficLights>) {
+        self.traffic_lights = traffic_lights;
+    }
 
-        // get the next cell state
-        let mut next_cell_state = self.get_cell_state(next_coordinate);
+    /**
+     * Get the signals of the intersections, none if the grid has none.
+     */
+    pub fn get_traffic_lights(&self) -> Option<&TrafficLights> {
+        self.traffic_lights.as_ref()
+    }
 
-        // move the obu_id from the current cell to the next cell
-        next_cell_state.obu_id = current_cell_state.obu_id;
-        current_cell_state.obu_id = None;
+    /**
+     * Move the signals of the intersections to the next round.
+     */
+    pub fn update_traffic_lights(&mut self) {
+        if let Some(mut traffic_lights) = self.traffic_lights.take() {
+            traffic_lights.update(self);
+            self.traffic_lights = Some(traffic_lights);
+        }
+    }
 
-        // update the current cell state
-        self.cells[current_coordinate.x as usize][current_coordinate.y as usize] =
-            current_cell_state;
+    /**
+     * Check if a position is an exit of the grid, where the vehicles leave
+     * it: the end of a two-way street at the border, or a lane with no next
+     * lane. The one-way streets at the border turn into the border streets.
+     */
+    pub fn is_exit(&self, position: Position) -> bool {
+        let Position { coordinate, lane } = position;
+        let is_two_way = self.get_lane_count(coordinate, lane.heading.opposite()) > 0;
 
-        // update the next cell state
-        self.cells[next_coordinate.x as usize][next_coordinate.y as usize] = next_cell_state;
+        (is_two_way && self.step(coordinate, lane.heading).is_none())
+            || self.get_next_headings(position).is_empty()
+    }
 
-        next_coordinate.clone()
+    /**
+     * Get the exits of the grid, the cells with a lane where the vehicles
+     * leave it. They are listed by column and row from 0.
+     */
+    pub fn get_exits(&self) -> Vec<Coordinate> {
+        let mut exits = Vec::new();
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let coordinate = Coordinate { x, y };
+                if self.cells[x as usize][y as usize]
+                    .lanes
+                    .iter()
+                    .any(|lane_state| {
+                        self.is_exit(Position {
+                            coordinate,
+                            lane: lane_state.lane,
+                        })
+                    })
+                {
+                    exits.push(coordinate);
+                }
+            }
+        }
+        exits
+    }
+
+    /**
+     * Remove the obu_id of the given position.
+     */
+    pub fn remove_obu(&mut self, position: Position) {
+        if let Some(lane_state) = self.get_lane_state_mut(position) {
+            lane_state.obu_id = None;
+        }
+    }
+
+    /**
+     * Move obu_id from the current position to the next position.
+     */
+    pub fn move_obu(&mut self, current_position: Position, next_position: Position) -> Coordinate {
+        // take the obu_id from the current lane
+        let obu_id = self
+            .get_lane_state_mut(current_position)
+            .and_then(|lane_state| lane_state.obu_id.take());
+
+        // put it in the next lane
+        if let Some(lane_state) = self.get_lane_state_mut(next_position) {
+            lane_state.obu_id = obu_id;
+        }
+
+        next_position.coordinate
+    }
+
+    /**
+     * Get the state of the lane of a position, none if the cell has no such
+     * lane.
+     */
+    fn get_lane_state_mut(&mut self, position: Position) -> Option<&mut LaneState> {
+        self.cells[position.coordinate.x as usize][position.coordinate.y as usize]
+            .lanes
+            .iter_mut()
+            .find(|lane_state| lane_state.lane == position.lane)
     }
 
     /**
@@ -314,11 +980,11 @@ impl Grid {
         let y1 = std::cmp::max(0, coordinate.y.saturating_sub(range));
 
         let x2 = min(
-            self.dimension.saturating_sub(1),
+            self.width.saturating_sub(1),
             coordinate.x.saturating_add(range),
         );
         let y2 = min(
-            self.dimension.saturating_sub(1),
+            self.height.saturating_sub(1),
             coordinate.y.saturating_add(range),
         );
 
@@ -343,8 +1009,8 @@ impl Grid {
      * Print the grid to the console, showing the cells' ids and the streets.
      */
     pub fn print_grid_with_cells_ids(&self) {
-        for i in 0..self.dimension {
-            for j in 0..self.dimension {
+        for j in 0..self.height {
+            for i in 0..self.width {
                 let cell = &self.cells[i as usize][j as usize];
                 if cell.is_street {
                     print!("|");
@@ -361,8 +1027,8 @@ impl Grid {
      * Print the grid to the console, showing the cells' coordinates and the id.
      */
     pub fn print_grid_with_cells_coordinates(&self) {
-        for i in 0..self.dimension {
-            for j in 0..self.dimension {
+        for j in 0..self.height {
+            for i in 0..self.width {
                 let cell = &self.cells[i as usize][j as usize];
                 if cell.is_street {
                     print!("|");
@@ -375,6 +1041,26 @@ impl Grid {
         }
     }
 
+    /**
+     * Print a map of the grid to the console, one line per row. Streets are
+     * shown as '.', blocks as '#' and the marked coordinates as 'R'.
+     */
+    pub fn print_map(&self, marks: &[Coordinate]) {
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let cell = &self.cells[i as usize][j as usize];
+                if marks.iter().any(|mark| mark.x == i && mark.y == j) {
+                    print!("R");
+                } else if cell.is_street {
+                    print!(".");
+                } else {
+                    print!("#");
+                }
+            }
+            println!();
+        }
+    }
+
     /**
      * Print next possible coordinates for a cell
      */
@@ -389,56 +1075,95 @@ impl Grid {
     }
 
     /**
-     * Insert a new obu in the grid. Choose first street with first cell available,
-     * considering the flow direction.
+     * Get the entry points of the grid, the cells at its border where a lane
+     * starts. They are listed by column and row from 0: for each street id,
+     * the column entries come before the row ones. A corner cell starting two
+     * streets is listed once.
      */
-    pub fn insert_obu(&mut self, obu_id: u32) -> Option<Coordinate> {
-        let mut street_id = 0;
+    pub fn get_entry_points(&self) -> Vec<Coordinate> {
+        let mut entry_points = Vec::new();
+        let is_entry = |coordinate: Coordinate, is_column: bool| {
+            self.cells[coordinate.x as usize][coordinate.y as usize]
+                .lanes
+                .iter()
+                .any(|lane_state| {
+                    lane_state.lane.heading.is_column() == is_column
+                        && self
+                            .step(coordinate, lane_state.lane.heading.opposite())
+                            .is_none()
+                })
+        };
 
         // iterate over the streets
-        while street_id < self.dimension {
-            // if this id is a street (row/column)
-            if self.is_street(street_id) {
-                // check column first
-                let start = match self.get_flow_direction(street_id, true) {
-                    FlowDirection::FromZero => 0,
-                    FlowDirection::ToZero => self.dimension - 1,
-                };
-
-                // check if cell is empty
-                if self.cells[street_id as usize][start as usize]
-                    .obu_id
-                    .is_none()
-                {
-                    // insert obu
-                    self.cells[street_id as usize][start as usize].obu_id = Some(obu_id);
-                    return Some(Coordinate {
-                        x: street_id,
-                        y: start,
-                    });
+        for street_id in 0..self.width.max(self.height) {
+            let column = (0..self.height)
+                .filter(|_| street_id < self.width)
+                .map(|y| (Coordinate { x: street_id, y }, true));
+            let row = (0..self.width)
+                .filter(|_| street_id < self.height)
+                .map(|x| (Coordinate { x, y: street_id }, false));
+            for (entry, is_column) in column.chain(row) {
+                if is_entry(entry, is_column) && !entry_points.contains(&entry) {
+                    entry_points.push(entry);
                 }
+            }
+        }
 
-                // then check row
-                let start: usize = match self.get_flow_direction(street_id, false) {
-                    FlowDirection::FromZero => 0,
-                    FlowDirection::ToZero => (self.dimension - 1) as usize,
-                };
+        entry_points
+    }
 
-                // check if cell is empty
-                if self.cells[start][street_id as usize].obu_id.is_none() {
-                    // insert obu
-                    self.cells[start][street_id as usize].obu_id = Some(obu_id);
-                    return Some(Coordinate {
-                        x: start as u32,
-                        y: street_id,
-                    });
-                }
-            }
+    /**
+     * Insert a new obu in the grid. Choose the first entry point available.
+     */
+    pub fn insert_obu(&mut self, obu_id: u32) -> Option<Coordinate> {
+        self.get_entry_points()
+            .into_iter()
+            .find(|coordinate| self.insert_obu_at(*coordinate, obu_id))
+    }
 
-            street_id += self.block_size + 1;
+    /**
+     * Insert an obu in the first free entry lane of the given cell, the lanes
+     * starting at the border of the grid. Return true if the obu was
+     * inserted.
+     */
+    pub fn insert_obu_at(&mut self, coordinate: Coordinate, obu_id: u32) -> bool {
+        let entry_lane = self.cells[coordinate.x as usize][coordinate.y as usize]
+            .lanes
+            .iter()
+            .map(|lane_state| Position {
+                coordinate,
+                lane: lane_state.lane,
+            })
+            .find(|position| {
+                self.step(coordinate, position.lane.heading.opposite())
+                    .is_none()
+                    && self.is_lane_free(*position)
+            });
+
+        match entry_lane.and_then(|position| self.get_lane_state_mut(position)) {
+            Some(lane_state) => {
+                lane_state.obu_id = Some(obu_id);
+                true
+            }
+            None => false,
         }
+    }
 
-        None
+    /**
+     * Get grid stats
+     */
+    pub fn get_stats(&self) -> GridStats {
+        GridStats {
+            width: self.width,
+            height: self.height,
+            street_cells: self.street_cells,
+            lane_cells: self
+                .cells
+                .iter()
+                .flatten()
+                .map(|cell| cell.lanes.len() as u32)
+                .sum(),
+        }
     }
 
     /**
@@ -446,10 +1171,9 @@ impl Grid {
      */
     pub fn print_stats(&self, max_obus: Option<u32>) {
         println!("--- Grid Stats ---");
-        println!("Block size: {}", self.block_size);
-        println!("Blocks per street: {}", self.blocks_per_street);
-        println!("Dimension: {}x{}", self.dimension, self.dimension);
+        println!("Dimension: {}x{}", self.width, self.height);
         println!("Number of street cells: {}", self.street_cells);
+        println!("Number of lane cells: {}", self.get_stats().lane_cells);
         // if max_obus is not None, print percentage of occupied street cells
         if let Some(max_obus) = max_obus {
             println!(
@@ -468,8 +1192,9 @@ impl Grid {
 mod tests {
 
     use super::*;
-    use crate::grid::{Coordinate, FlowDirection, Grid};
     use crate::obu::OnBoardUnit;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     /**
      * Test grid creation
@@ -479,35 +1204,43 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let grid = Grid::new(params);
 
         // check grid dimension
-        assert_eq!(grid.dimension, 10);
+        assert_eq!((grid.width, grid.height), (10, 10));
 
         // check all the cells of the first street
         for i in 0..10 {
-            assert_eq!(grid.cells[0][i].is_street, true);
+            assert!(grid.cells[0][i].is_street);
         }
 
         // check all the cells of the first block
         for i in 1..=2 {
             for j in 1..=2 {
-                assert_eq!(grid.cells[i][j].is_street, false);
+                assert!(!grid.cells[i][j].is_street);
             }
         }
 
         // check all the cells of the last block
         for i in 7..=8 {
             for j in 7..=8 {
-                assert_eq!(grid.cells[i][j].is_street, false);
+                assert!(!grid.cells[i][j].is_street);
             }
         }
 
         // check all the cells of the last vertical street
         for i in 1..=8 {
-            assert_eq!(grid.cells[i][9].is_street, true);
+            assert!(grid.cells[i][9].is_street);
         }
 
         // check street cells
@@ -522,21 +1255,31 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let grid = Grid::new(params);
 
+        let is_street = |x, y| grid.is_street(Coordinate { x, y });
+
         // check if row/column 0 is a street
-        assert_eq!(grid.is_street(0), true);
+        assert!(is_street(0, 1) && is_street(1, 0));
 
         // check if row/column 1 is not a street
-        assert_eq!(grid.is_street(1), false);
+        assert!(!is_street(1, 2) && !is_street(2, 1));
 
         // check if row/column 2 is not a street
-        assert_eq!(grid.is_street(2), false);
+        assert!(!is_street(2, 2));
 
         // check if row/column 3 is a street
-        assert_eq!(grid.is_street(3), true);
+        assert!(is_street(3, 1) && is_street(1, 3));
     }
 
     /**
@@ -547,6 +1290,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let grid = Grid::new(params);
@@ -572,6 +1323,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let grid = Grid::new(params);
@@ -615,6 +1374,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let mut grid = Grid::new(params);
@@ -660,6 +1427,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let mut grid = Grid::new(params);
@@ -703,6 +1478,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let mut grid = Grid::new(params);
@@ -714,7 +1497,17 @@ mod tests {
         for _i in 0..8 {
             match grid.insert_obu(obu_id) {
                 Some(coordinate) => {
-                    obus.push(OnBoardUnit::new(obu_id, coordinate, 0, 0.0, 0.0, false, 0));
+                    obus.push(OnBoardUnit::new(
+                        obu_id,
+                        coordinate,
+                        0,
+                        0.0,
+                        0.0,
+                        false,
+                        0,
+                        0,
+                        StdRng::seed_from_u64(0),
+                    ));
                     obu_id += 1;
                 }
                 None => {
@@ -772,25 +1565,45 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let mut grid = Grid::new(params);
 
-        let possible_moves = grid.get_possible_moves(Coordinate { x: 3, y: 0 });
+        let position = Position {
+            coordinate: Coordinate { x: 3, y: 0 },
+            lane: Lane {
+                heading: Heading::South,
+                index: 0,
+            },
+        };
+        let possible_moves = grid.get_possible_moves(position);
         assert_eq!(possible_moves.len(), 2);
-        assert_eq!(possible_moves[0].x, 3);
-        assert_eq!(possible_moves[0].y, 1);
-        assert_eq!(possible_moves[1].x, 4);
-        assert_eq!(possible_moves[1].y, 0);
+        assert_eq!(possible_moves[0].coordinate, Coordinate { x: 3, y: 1 });
+        assert_eq!(possible_moves[1].coordinate, Coordinate { x: 4, y: 0 });
+        assert_eq!(possible_moves[1].lane.heading, Heading::East);
 
-        grid.cells[3][1].obu_id = Some(0);
-        let possible_moves = grid.get_possible_moves(Coordinate { x: 3, y: 0 });
+        grid.cells[3][1].lanes[0].obu_id = Some(0);
+        let possible_moves = grid.get_possible_moves(position);
         assert_eq!(possible_moves.len(), 1);
-        assert_eq!(possible_moves[0].x, 4);
-        assert_eq!(possible_moves[0].y, 0);
-
-        grid.cells[1][0].obu_id = Some(1);
-        let possible_moves = grid.get_possible_moves(Coordinate { x: 0, y: 0 });
+        assert_eq!(possible_moves[0].coordinate, Coordinate { x: 4, y: 0 });
+
+        grid.cells[1][0].lanes[0].obu_id = Some(1);
+        let position = Position {
+            coordinate: Coordinate { x: 0, y: 0 },
+            lane: Lane {
+                heading: Heading::East,
+                index: 0,
+            },
+        };
+        let possible_moves = grid.get_possible_moves(position);
         assert_eq!(possible_moves.len(), 0);
     }
 
@@ -802,6 +1615,14 @@ mod tests {
         let params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let grid = Grid::new(params);
@@ -842,7 +1663,7 @@ mod tests {
             x2: 7,
             y2: 7,
         };
-        assert_eq!(Grid::check_overlapping_squares(square_1, square_2), true);
+        assert!(Grid::check_overlapping_squares(square_1, square_2));
 
         let square_1 = SquareCoords {
             x1: 10,
@@ -856,7 +1677,7 @@ mod tests {
             x2: 12,
             y2: 11,
         };
-        assert_eq!(Grid::check_overlapping_squares(square_1, square_2), true);
+        assert!(Grid::check_overlapping_squares(square_1, square_2));
     }
 
     /**
@@ -876,7 +1697,7 @@ mod tests {
             x2: 9,
             y2: 9,
         };
-        assert_eq!(Grid::check_overlapping_squares(square_1, square_2), false);
+        assert!(!Grid::check_overlapping_squares(square_1, square_2));
 
         let square_1 = SquareCoords {
             x1: 15,
@@ -890,6 +1711,423 @@ mod tests {
             x2: 14,
             y2: 14,
         };
-        assert_eq!(Grid::check_overlapping_squares(square_1, square_2), false);
+        assert!(!Grid::check_overlapping_squares(square_1, square_2));
+    }
+
+    /**
+     * Test snapping positions to the street cells.
+     */
+    #[test]
+    fn test_snap_to_street() {
+        let grid_params = GridParams {
+            blocks_per_street: 3,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        };
+        let grid = Grid::new(grid_params);
+
+        // street cells are only rounded
+        assert_eq!(
+            grid.snap_to_street(2.6, 0.2),
+            Some(Coordinate { x: 3, y: 0 })
+        );
+        assert_eq!(
+            grid.snap_to_street(-0.4, 9.4),
+            Some(Coordinate { x: 0, y: 9 })
+        );
+
+        // block cells go to the nearest street
+        assert_eq!(
+            grid.snap_to_street(4.2, 4.4),
+            Some(Coordinate { x: 3, y: 4 })
+        );
+        assert_eq!(
+            grid.snap_to_street(4.8, 5.4),
+            Some(Coordinate { x: 5, y: 6 })
+        );
+
+        // positions outside the grid
+        assert_eq!(grid.snap_to_street(-0.6, 0.0), None);
+        assert_eq!(grid.snap_to_street(3.0, 9.5), None);
+        assert_eq!(grid.snap_to_street(f64::NAN, 0.0), None);
+    }
+
+    /**
+     * Return the positions of every lane of the grid.
+     */
+    fn all_positions(grid: &Grid) -> Vec<Position> {
+        let mut positions = Vec::new();
+        for x in 0..grid.width {
+            for y in 0..grid.height {
+                for lane_state in &grid.cells[x as usize][y as usize].lanes {
+                    positions.push(Position {
+                        coordinate: Coordinate { x, y },
+                        lane: lane_state.lane,
+                    });
+                }
+            }
+        }
+        positions
+    }
+
+    /**
+     * Test the exits of the grid.
+     */
+    #[test]
+    fn test_is_exit() {
+        let grid_params = GridParams {
+            blocks_per_street: 2,
+            block_size: 3,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        };
+        let grid = Grid::new(grid_params);
+
+        // all the streets are even: the columns flow to y = 0, the rows to x = 8
+        let exits: Vec<Position> = all_positions(&grid)
+            .into_iter()
+            .filter(|position| grid.is_exit(*position))
+            .collect();
+        assert_eq!(exits.len(), 2);
+        assert!(exits
+            .iter()
+            .all(|exit| exit.coordinate == Coordinate { x: 8, y: 0 }));
+
+        // the vehicles at the end of a street turn into the crossing one
+        let position = Position {
+            coordinate: Coordinate { x: 4, y: 0 },
+            lane: Lane {
+                heading: Heading::North,
+                index: 0,
+            },
+        };
+        assert!(!grid.is_exit(position));
+    }
+
+    /**
+     * Test the two-way streets.
+     */
+    #[test]
+    fn test_two_way_streets() {
+        let grid_params = GridParams {
+            blocks_per_street: 2,
+            block_size: 3,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: true,
+            lanes: 1,
+            streets: vec![StreetParams {
+                column: None,
+                row: Some(4),
+                two_way: Some(false),
+                lanes: None,
+            }],
+        };
+        let mut grid = Grid::new(grid_params);
+
+        // the column streets flow both ways, the row 4 keeps its flow direction
+        assert_eq!(
+            grid.get_headings(Coordinate { x: 4, y: 4 }),
+            vec![Heading::South, Heading::North, Heading::East]
+        );
+        assert_eq!(grid.get_stats().lane_cells, 3 * 9 * 2 + 2 * 9 * 2 + 9);
+
+        // the two-way streets start at both borders
+        let entry_points = grid.get_entry_points();
+        assert_eq!(entry_points.len(), 7);
+        assert!(entry_points.contains(&Coordinate { x: 0, y: 4 }));
+        assert!(!entry_points.contains(&Coordinate { x: 8, y: 4 }));
+
+        // the vehicles enter the lanes starting at the border, an intersection only
+        // holds the vehicles of one of its streets
+        let corner = Coordinate { x: 0, y: 8 };
+        assert!(grid.insert_obu_at(corner, 0));
+        assert_eq!(
+            grid.get_position(corner, 0).unwrap().lane.heading,
+            Heading::North
+        );
+        assert!(!grid.insert_obu_at(corner, 1));
+
+        // the vehicles turn both ways at an intersection, but don't make U-turns
+        let position = Position {
+            coordinate: Coordinate { x: 4, y: 4 },
+            lane: Lane {
+                heading: Heading::North,
+                index: 0,
+            },
+        };
+        let headings: Vec<Heading> = grid
+            .get_possible_moves(position)
+            .iter()
+            .map(|next_position| next_position.lane.heading)
+            .collect();
+        assert_eq!(headings, vec![Heading::North, Heading::East]);
+
+        // the two-way streets end at the border, the row 4 turns into the column 8
+        let exits: Vec<Position> = all_positions(&grid)
+            .into_iter()
+            .filter(|position| grid.is_exit(*position))
+            .collect();
+        assert_eq!(exits.len(), 10);
+        assert!(exits
+            .iter()
+            .all(|exit| exit.coordinate != Coordinate { x: 8, y: 4 }));
+    }
+
+    /**
+     * Test the lane changes on a street with several lanes.
+     */
+    #[test]
+    fn test_lane_changes() {
+        let grid_params = GridParams {
+            blocks_per_street: 2,
+            block_size: 3,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: vec![StreetParams {
+                column: Some(4),
+                row: None,
+                two_way: None,
+                lanes: Some(3),
+            }],
+        };
+        let mut grid = Grid::new(grid_params);
+
+        // the column 4 flows north, to y = 0
+        let lane = |index| Lane {
+            heading: Heading::North,
+            index,
+        };
+        let position = Position {
+            coordinate: Coordinate { x: 4, y: 7 },
+            lane: lane(1),
+        };
+        grid.get_lane_state_mut(position).unwrap().obu_id = Some(0);
+
+        // the vehicle keeps its lane while it is free
+        let ahead = Coordinate { x: 4, y: 6 };
+        assert_eq!(grid.get_possible_moves(position)[0].lane, lane(1));
+
+        // and changes to an adjacent free lane when it is taken
+        grid.insert_obu_at(Coordinate { x: 4, y: 8 }, 1);
+        grid.move_obu(
+            grid.get_position(Coordinate { x: 4, y: 8 }, 1).unwrap(),
+            Position {
+                coordinate: ahead,
+                lane: lane(1),
+            },
+        );
+        assert_eq!(grid.get_possible_moves(position)[0].lane, lane(0));
+        grid.get_lane_state_mut(Position {
+            coordinate: ahead,
+            lane: lane(0),
+        })
+        .unwrap()
+        .obu_id = Some(2);
+        assert_eq!(grid.get_possible_moves(position)[0].lane, lane(2));
+        grid.get_lane_state_mut(Position {
+            coordinate: ahead,
+            lane: lane(2),
+        })
+        .unwrap()
+        .obu_id = Some(3);
+        assert!(grid.get_possible_moves(position).is_empty());
+
+        // the lanes of a cell hold a vehicle each
+        assert_eq!(grid.get_cell_state(ahead).get_obu_ids(), vec![2, 1, 3]);
+
+        // a vehicle turning into a street with less lanes takes the closest one
+        let position = Position {
+            coordinate: Coordinate { x: 4, y: 4 },
+            lane: lane(2),
+        };
+        let turn = grid.get_possible_moves(position)[1];
+        assert_eq!(turn.lane.heading, Heading::East);
+        assert_eq!(turn.lane.index, 0);
+    }
+
+    /**
+     * Test the validation of the grid params.
+     */
+    #[test]
+    fn test_validate_grid_params() {
+        let street = |column, row| StreetParams {
+            column,
+            row,
+            two_way: Some(true),
+            lanes: None,
+        };
+        let mut grid_params = GridParams {
+            blocks_per_street: 2,
+            block_size: 3,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 2,
+            streets: vec![street(Some(4), None), street(None, Some(4))],
+        };
+        assert!(grid_params.validate().is_ok());
+
+        grid_params.lanes = 0;
+        assert!(grid_params.validate().is_err());
+        grid_params.lanes = 1;
+
+        // a street is a column or a row of the grid, with a single layout
+        for streets in [
+            vec![street(Some(3), None)],
+            vec![street(Some(12), None)],
+            vec![street(Some(4), Some(4))],
+            vec![street(None, Some(8)), street(None, Some(8))],
+        ] {
+            grid_params.streets = streets;
+            assert!(grid_params.validate().is_err());
+        }
+    }
+
+    /**
+     * Test a rectangular grid with blocks of different sizes and a removed
+     * street segment.
+     */
+    #[test]
+    fn test_irregular_grid() {
+        let grid_params = GridParams {
+            blocks_per_street: 0,
+            block_size: 0,
+            block_widths: vec![1, 3],
+            block_heights: vec![2],
+            map: None,
+            osm: None,
+            removed_segments: vec![Coordinate { x: 2, y: 1 }],
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        };
+        assert!(grid_params.validate().is_ok());
+        let grid = Grid::new(grid_params);
+
+        // the column 2 is removed between the rows 0 and 3
+        assert_eq!((grid.get_width(), grid.get_height()), (7, 4));
+        assert_eq!(grid.get_stats().street_cells, 18);
+        assert!(!grid.is_street(Coordinate { x: 2, y: 1 }));
+        assert_eq!(
+            grid.get_headings(Coordinate { x: 2, y: 0 }),
+            vec![Heading::East]
+        );
+
+        // the streets start at the border, the columns flow north
+        assert_eq!(
+            grid.get_entry_points(),
+            vec![
+                Coordinate { x: 0, y: 3 },
+                Coordinate { x: 0, y: 0 },
+                Coordinate { x: 6, y: 3 }
+            ]
+        );
+        let exits: Vec<Position> = all_positions(&grid)
+            .into_iter()
+            .filter(|position| grid.is_exit(*position))
+            .collect();
+        assert_eq!(exits.len(), 2);
+        assert!(exits
+            .iter()
+            .all(|exit| exit.coordinate == Coordinate { x: 6, y: 0 }));
+
+        // the squares and the positions are bound by the width and the height
+        let square = grid.get_square_coords(Coordinate { x: 5, y: 2 }, 3);
+        assert_eq!((square.x2, square.y2), (6, 3));
+        assert_eq!(
+            grid.snap_to_street(3.0, 1.6),
+            Some(Coordinate { x: 3, y: 3 })
+        );
+        assert_eq!(
+            grid.snap_to_street(6.4, 3.4),
+            Some(Coordinate { x: 6, y: 3 })
+        );
+        assert_eq!(grid.snap_to_street(3.0, 3.6), None);
+    }
+
+    /**
+     * Test a grid read from an ASCII map, with a dead end.
+     */
+    #[test]
+    fn test_map_grid() {
+        let mut grid_params = GridParams {
+            blocks_per_street: 0,
+            block_size: 0,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: Some(".......\n.##.##.\n.#####.\n.......\n".to_string()),
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        };
+        assert!(grid_params.validate().is_ok());
+        let grid = Grid::new(grid_params.clone());
+        assert_eq!((grid.get_width(), grid.get_height()), (7, 4));
+
+        // the vehicles of the row 0 turn into the column 3, a dead end
+        let lane = |heading| Lane { heading, index: 0 };
+        let before_dead_end = Position {
+            coordinate: Coordinate { x: 3, y: 0 },
+            lane: lane(Heading::East),
+        };
+        let dead_end = Position {
+            coordinate: Coordinate { x: 3, y: 1 },
+            lane: lane(Heading::South),
+        };
+        assert_eq!(grid.get_possible_moves(before_dead_end)[0], dead_end);
+
+        // the vehicles leave the grid at a one-way dead end
+        assert!(grid.is_exit(dead_end));
+
+        // and make a U-turn at a two-way one
+        grid_params.two_way = true;
+        let grid = Grid::new(grid_params.clone());
+        assert!(!grid.is_exit(dead_end));
+        assert_eq!(
+            grid.get_possible_moves(dead_end),
+            vec![Position {
+                coordinate: Coordinate { x: 3, y: 0 },
+                lane: lane(Heading::North),
+            }]
+        );
+
+        // invalid maps and maps with no street starting at the border
+        for map in ["...\n.#\n", "..R\n", "###\n#.#\n###\n"] {
+            grid_params.map = Some(map.to_string());
+            assert!(grid_params.validate().is_err());
+        }
+
+        // the blocks sizes don't apply to a map
+        grid_params.map = Some("...\n".to_string());
+        assert!(grid_params.validate().is_ok());
+        grid_params.block_widths = vec![3];
+        assert!(grid_params.validate().is_err());
     }
 }
@@ -0,0 +1,288 @@
+/**
+ * INTERFERENCE
+ *
+ * Interference of the concurrent messages of the Ether. All the messages of a
+ * round are sent at the same time, so at a receiver the power of the other
+ * messages adds up to the noise, and a message is only decoded when its
+ * signal to interference and noise ratio (SINR) reaches a threshold. With the
+ * capture effect the receiver locks on the strongest message when it stands
+ * out from the others, without it any other message received above the
+ * sensitivity collides with it. The messages lost to the interference are
+ * counted as collisions, which grow with the density of the traffic.
+ */
+use crate::scenario::ScenarioError;
+use serde::{Deserialize, Serialize};
+
+/**
+ * InterferenceParams represents the reception of the concurrent messages.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterferenceParams {
+    #[serde(default = "InterferenceParams::default_sinr_threshold")]
+    pub sinr_threshold: f64, // Lowest SINR a message is decoded with, in dB
+    #[serde(default = "InterferenceParams::default_noise_power")]
+    pub noise_power: f64, // Power of the thermal noise of the receivers, in dBm
+    #[serde(default = "InterferenceParams::default_capture")]
+    pub capture: bool, // Decode the strongest of the concurrent messages, or none of them
+}
+
+/**
+ * Interference decides which of the concurrent messages a receiver decodes.
+ */
+pub struct Interference {
+    sinr_threshold: f64, // Lowest SINR a message is decoded with, as a power ratio
+    noise_power: f64,    // Power of the thermal noise of the receivers, in mW
+    capture: bool,       // Decode the strongest of the concurrent messages, or none of them
+}
+
+/**
+ * InterferenceParams implementation.
+ */
+impl InterferenceParams {
+    /**
+     * By default a message is decoded 5 dB over the interference and the
+     * noise, the SINR of the 6 Mbps rate of the vehicular networks.
+     */
+    fn default_sinr_threshold() -> f64 {
+        5.0
+    }
+
+    /**
+     * By default the noise is the thermal noise of a 10 MHz channel with a
+     * noise figure of 5 dB.
+     */
+    fn default_noise_power() -> f64 {
+        -99.0
+    }
+
+    /**
+     * By default the receivers capture the strongest message.
+     */
+    fn default_capture() -> bool {
+        true
+    }
+
+    /**
+     * Check that the threshold and the noise are finite numbers.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let values = [
+            ("sinr_threshold", self.sinr_threshold),
+            ("noise_power", self.noise_power),
+        ];
+        for (name, value) in values {
+            if !value.is_finite() {
+                return Err(ScenarioError::Invalid(format!(
+                    "interference.{} must be a finite number, got {}",
+                    name, value
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Interference implementation.
+ */
+impl Interference {
+    /**
+     * Create the interference of the given params, which must be valid.
+     */
+    pub fn new(params: &InterferenceParams) -> Interference {
+        Interference {
+            sinr_threshold: to_linear(params.sinr_threshold),
+            noise_power: to_linear(params.noise_power),
+            capture: params.capture,
+        }
+    }
+
+    /**
+     * Return the indices of the messages a receiver decodes, given the power
+     * in dBm it gets each concurrent message with and its sensitivity. The
+     * messages interfere when they are on the channel at the same time: each
+     * one starts in its slot and takes the given number of slots. The message
+     * of the receiver itself, if any, neither interferes nor is decoded.
+     */
+    pub fn get_decoded(
+        &self,
+        powers: &[f64],
+        slots: &[u32],
+        frame_slots: u32,
+        sensitivity: f64,
+        own: Option<usize>,
+    ) -> Vec<usize> {
+        // The power and the number of detected messages starting in each slot,
+        // summed over the slots before
+        let slot_count = slots.iter().max().map_or(0, |slot| *slot as usize + 1);
+        let mut total_powers = vec![0.0; slot_count + 1];
+        let mut detected = vec![0; slot_count + 1];
+        for (index, power) in powers.iter().enumerate() {
+            if Some(index) != own {
+                let slot = slots[index] as usize;
+                total_powers[slot + 1] += to_linear(*power);
+                detected[slot + 1] += (*power >= sensitivity) as u32;
+            }
+        }
+        for slot in 1..=slot_count {
+            total_powers[slot] += total_powers[slot - 1];
+            detected[slot] += detected[slot - 1];
+        }
+
+        (0..powers.len())
+            .filter(|index| Some(*index) != own && powers[*index] >= sensitivity)
+            .filter(|index| {
+                // The messages starting less than a frame before or after
+                let first = (slots[*index] + 1).saturating_sub(frame_slots) as usize;
+                let last = (slots[*index] as usize + frame_slots as usize).min(slot_count);
+                let signal = to_linear(powers[*index]);
+
+                // Without the capture effect another detected message collides
+                if !self.capture && detected[last] - detected[first] > 1 {
+                    return false;
+                }
+
+                let interference = (total_powers[last] - total_powers[first] - signal).max(0.0);
+                signal >= self.sinr_threshold * (self.noise_power + interference)
+            })
+            .collect()
+    }
+}
+
+/**
+ * Return the power in mW of a power in dBm, or the ratio of a gain in dB.
+ */
+pub fn to_linear(power: f64) -> f64 {
+    10f64.powf(power / 10.0)
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Return the interference with the default threshold and noise.
+     */
+    fn interference(capture: bool) -> Interference {
+        Interference::new(&InterferenceParams {
+            sinr_threshold: InterferenceParams::default_sinr_threshold(),
+            noise_power: InterferenceParams::default_noise_power(),
+            capture,
+        })
+    }
+
+    /**
+     * Test the validation of the params.
+     */
+    #[test]
+    fn test_validate() {
+        let mut params = InterferenceParams {
+            sinr_threshold: 5.0,
+            noise_power: -99.0,
+            capture: true,
+        };
+        assert!(params.validate().is_ok());
+        params.sinr_threshold = -3.0;
+        assert!(params.validate().is_ok());
+        params.noise_power = f64::NEG_INFINITY;
+        assert!(params.validate().is_err());
+        params.noise_power = -99.0;
+        params.sinr_threshold = f64::NAN;
+        assert!(params.validate().is_err());
+    }
+
+    /**
+     * Test the SINR of the concurrent messages, with and without the capture
+     * effect.
+     */
+    #[test]
+    fn test_get_decoded() {
+        let interference = interference(true);
+        let sensitivity = -85.0;
+        let slots = [0; 5];
+
+        // a lone message is decoded above the sensitivity and the noise
+        assert_eq!(
+            interference.get_decoded(&[-80.0], &[0], 1, sensitivity, None),
+            vec![0]
+        );
+        assert!(interference
+            .get_decoded(&[-90.0], &[0], 1, sensitivity, None)
+            .is_empty());
+        assert!(interference
+            .get_decoded(&[-96.0], &[0], 1, -100.0, None)
+            .is_empty());
+
+        // two messages of the same power collide, the far ones don't interfere
+        let powers = [-60.0, -60.0, f64::NEG_INFINITY];
+        assert!(interference
+            .get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None)
+            .is_empty());
+        assert_eq!(
+            interference.get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, Some(1)),
+            vec![0]
+        );
+
+        // the strongest message is captured 5 dB over the others
+        let powers = [-60.0, -66.0, -90.0, -90.0];
+        assert_eq!(
+            interference.get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None),
+            vec![0]
+        );
+        let powers = [-60.0, -64.0];
+        assert!(interference
+            .get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None)
+            .is_empty());
+
+        // the messages under the sensitivity add up to a collision
+        let powers = [-80.0, -90.0, -90.0, -90.0, -90.0];
+        assert!(interference
+            .get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None)
+            .is_empty());
+        assert_eq!(
+            interference.get_decoded(&powers[..2], &slots[..2], 1, sensitivity, None),
+            vec![0]
+        );
+
+        // without the capture effect a second detected message collides
+        let interference = self::interference(false);
+        let powers = [-60.0, -80.0];
+        assert!(interference
+            .get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None)
+            .is_empty());
+        let powers = [-60.0, -90.0];
+        assert_eq!(
+            interference.get_decoded(&powers, &slots[..powers.len()], 1, sensitivity, None),
+            vec![0]
+        );
+    }
+
+    /**
+     * Test the messages on the channel at different times, which only
+     * interfere when their frames overlap.
+     */
+    #[test]
+    fn test_get_decoded_slots() {
+        let interference = interference(true);
+        let powers = [-60.0, -60.0, -60.0];
+
+        // the frames of 4 slots overlap when they start less than 4 slots apart
+        assert_eq!(
+            interference.get_decoded(&powers, &[0, 4, 8], 4, -85.0, None),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            interference.get_decoded(&powers, &[0, 3, 8], 4, -85.0, None),
+            vec![2]
+        );
+        assert!(interference
+            .get_decoded(&powers, &[2, 5, 8], 4, -85.0, None)
+            .is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+/**
+ * LATENCY
+ *
+ * Latency of the messages of the Ether. Without it, a message sent at the end
+ * of a round is delivered at the start of the next one. With it, each message
+ * draws a delay from a distribution, the number of extra rounds it stays in
+ * transit, so the messages of a node can arrive late and out of order. A
+ * message delayed past the deadline expires and is never delivered.
+ */
+use crate::scenario::ScenarioError;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/**
+ * LatencyDistribution represents the distributions of the delays of the
+ * messages.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyDistribution {
+    Constant,  // Every message has the same delay
+    Uniform,   // Delays drawn uniformly between a min and a max
+    Geometric, // Delays drawn from a geometric distribution, mostly short with a long tail
+}
+
+/**
+ * LatencyParams represents the distribution of the delays of the messages, in
+ * rounds on top of the round every message takes.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LatencyParams {
+    pub distribution: LatencyDistribution,
+    #[serde(default)]
+    pub delay: Option<u32>, // Constant: delay of every message
+    #[serde(default)]
+    pub min_delay: Option<u32>, // Uniform: lowest delay
+    #[serde(default)]
+    pub max_delay: Option<u32>, // Uniform: highest delay
+    #[serde(default)]
+    pub mean_delay: Option<f64>, // Geometric: mean of the delays
+    #[serde(default)]
+    pub deadline: Option<u32>, // Longest delay of a delivered message, the later ones expire; none if unset
+}
+
+/**
+ * Latency draws the delay of every message.
+ */
+pub struct Latency {
+    params: LatencyParams,
+    rng: StdRng, // Random number generator of the delays
+}
+
+/**
+ * LatencyDistribution implementation.
+ */
+impl LatencyDistribution {
+    /**
+     * Return the name of the distribution, as in the scenario.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyDistribution::Constant => "constant",
+            LatencyDistribution::Uniform => "uniform",
+            LatencyDistribution::Geometric => "geometric",
+        }
+    }
+}
+
+/**
+ * LatencyParams implementation.
+ */
+impl LatencyParams {
+    /**
+     * Check that the distribution is fully described.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        // Each distribution only uses its own fields
+        let fields = [
+            ("delay", LatencyDistribution::Constant, self.delay.is_some()),
+            (
+                "min_delay",
+                LatencyDistribution::Uniform,
+                self.min_delay.is_some(),
+            ),
+            (
+                "max_delay",
+                LatencyDistribution::Uniform,
+                self.max_delay.is_some(),
+            ),
+            (
+                "mean_delay",
+                LatencyDistribution::Geometric,
+                self.mean_delay.is_some(),
+            ),
+        ];
+        for (name, distribution, is_set) in fields {
+            if is_set && distribution != self.distribution {
+                return Err(ScenarioError::Invalid(format!(
+                    "latency.{} is not used by the {} distribution",
+                    name,
+                    self.distribution.name()
+                )));
+            }
+        }
+
+        match self.distribution {
+            LatencyDistribution::Constant => {
+                if self.delay.is_none() {
+                    return Err(ScenarioError::Invalid(
+                        "latency.delay must be set for the constant distribution".to_string(),
+                    ));
+                }
+            }
+            LatencyDistribution::Uniform => match (self.min_delay, self.max_delay) {
+                (Some(min_delay), Some(max_delay)) if min_delay <= max_delay => {}
+                _ => {
+                    return Err(ScenarioError::Invalid(
+                        "latency.min_delay and latency.max_delay must be set, the min not greater than the max, for the uniform distribution"
+                            .to_string(),
+                    ))
+                }
+            },
+            LatencyDistribution::Geometric => match self.mean_delay {
+                Some(mean_delay) if mean_delay.is_finite() && mean_delay >= 0.0 => {}
+                _ => {
+                    return Err(ScenarioError::Invalid(
+                        "latency.mean_delay must be set not less than 0 for the geometric distribution"
+                            .to_string(),
+                    ))
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Latency implementation.
+ */
+impl Latency {
+    /**
+     * Create the latency of the given params, which must be valid.
+     */
+    pub fn new(params: &LatencyParams, rng: StdRng) -> Latency {
+        Latency {
+            params: params.clone(),
+            rng,
+        }
+    }
+
+    /**
+     * Return the delay of a message in rounds, or None if it expires before
+     * being delivered.
+     */
+    pub fn next_delay(&mut self) -> Option<u32> {
+        let delay = match self.params.distribution {
+            LatencyDistribution::Constant => self.params.delay.unwrap_or(0),
+            LatencyDistribution::Uniform => self
+                .rng
+                .gen_range(self.params.min_delay.unwrap_or(0)..=self.params.max_delay.unwrap_or(0)),
+            LatencyDistribution::Geometric => {
+                // The number of failures before a success of probability
+                // 1 / (1 + mean), by inversion
+                let mean_delay = self.params.mean_delay.unwrap_or(0.0);
+                if mean_delay > 0.0 {
+                    let u: f64 = 1.0 - self.rng.gen::<f64>();
+                    (u.ln() / (mean_delay / (1.0 + mean_delay)).ln()).floor() as u32
+                } else {
+                    0
+                }
+            }
+        };
+
+        match self.params.deadline {
+            Some(deadline) if delay > deadline => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+
+    /**
+     * Return the params of a distribution without its fields.
+     */
+    fn latency_params(distribution: LatencyDistribution) -> LatencyParams {
+        LatencyParams {
+            distribution,
+            delay: None,
+            min_delay: None,
+            max_delay: None,
+            mean_delay: None,
+            deadline: None,
+        }
+    }
+
+    /**
+     * Test the validation of the distributions.
+     */
+    #[test]
+    fn test_validate() {
+        let mut params = latency_params(LatencyDistribution::Constant);
+        assert!(params.validate().is_err());
+        params.delay = Some(2);
+        assert!(params.validate().is_ok());
+        params.mean_delay = Some(1.0);
+        assert!(params.validate().is_err());
+
+        let mut params = latency_params(LatencyDistribution::Uniform);
+        params.min_delay = Some(3);
+        params.max_delay = Some(1);
+        assert!(params.validate().is_err());
+        params.max_delay = Some(3);
+        assert!(params.validate().is_ok());
+
+        let mut params = latency_params(LatencyDistribution::Geometric);
+        params.mean_delay = Some(-1.0);
+        assert!(params.validate().is_err());
+        params.mean_delay = Some(0.5);
+        params.deadline = Some(0);
+        assert!(params.validate().is_ok());
+    }
+
+    /**
+     * Test the delays drawn from the distributions, and the ones past the
+     * deadline.
+     */
+    #[test]
+    fn test_next_delay() {
+        let mut params = latency_params(LatencyDistribution::Constant);
+        params.delay = Some(2);
+        let mut latency = Latency::new(&params, StdRng::seed_from_u64(1));
+        assert_eq!(latency.next_delay(), Some(2));
+        params.deadline = Some(1);
+        let mut latency = Latency::new(&params, StdRng::seed_from_u64(1));
+        assert_eq!(latency.next_delay(), None);
+
+        let mut params = latency_params(LatencyDistribution::Uniform);
+        params.min_delay = Some(1);
+        params.max_delay = Some(3);
+        let mut latency = Latency::new(&params, StdRng::seed_from_u64(1));
+        for _ in 0..100 {
+            assert!((1..=3).contains(&latency.next_delay().unwrap()));
+        }
+
+        // the geometric delays have the given mean, and expire past the deadline
+        let mut params = latency_params(LatencyDistribution::Geometric);
+        params.mean_delay = Some(1.5);
+        let mut latency = Latency::new(&params, StdRng::seed_from_u64(1));
+        let delays: Vec<u32> = (0..20000).map(|_| latency.next_delay().unwrap()).collect();
+        let mean = delays.iter().sum::<u32>() as f64 / delays.len() as f64;
+        assert!((mean - 1.5).abs() < 0.05, "mean {}", mean);
+        let zeros = delays.iter().filter(|delay| **delay == 0).count() as f64;
+        assert!((zeros / delays.len() as f64 - 0.4).abs() < 0.02);
+
+        params.deadline = Some(2);
+        let mut latency = Latency::new(&params, StdRng::seed_from_u64(1));
+        let expired = (0..20000)
+            .filter(|_| latency.next_delay().is_none())
+            .count() as f64;
+        // P(delay > 2) = 0.6^3
+        assert!((expired / 20000.0 - 0.216).abs() < 0.02);
+    }
+}
@@ -1,7 +1,27 @@
+pub mod arrivals;
+pub mod artifacts;
+pub mod batch;
+pub mod cli;
 pub mod grid;
+pub mod interference;
+pub mod latency;
+pub mod mac;
+pub mod metrics;
+pub mod mobility;
 mod obu;
 pub mod obu_manager;
+pub mod osm;
+pub mod propagation;
+pub mod report;
+pub mod road_map;
+pub mod routing;
 mod rsu;
 pub mod rsu_manager;
 mod comms;
-pub mod simulator;
\ No newline at end of file
+pub mod scenario;
+pub mod simulator;
+pub mod speed;
+pub mod sweep;
+pub mod trace;
+pub mod traffic_lights;
+mod xml;
\ No newline at end of file
@@ -0,0 +1,356 @@
+/**
+ * MAC
+ *
+ * Medium access control of the Ether, a carrier sense multiple access with
+ * collision avoidance (CSMA/CA) modeled on the channel access of 802.11p.
+ * Without it, all the messages of a round are sent at the same instant. With
+ * it, the round is split in time slots and each node draws a random backoff
+ * in the contention window when its message is ready. The backoff counts down
+ * in the slots where the node senses the channel idle, and freezes while the
+ * power of the ongoing transmissions reaches the carrier sense threshold; the
+ * node transmits when it reaches 0. Two nodes ending their backoff in the same
+ * slot, or hidden from each other, still transmit at the same time, and their
+ * messages collide at the receivers with the interference model. The messages
+ * that don't get the channel before the end of the round are lost.
+ */
+use crate::comms::Message;
+use crate::interference::to_linear;
+use crate::propagation::Propagation;
+use crate::scenario::ScenarioError;
+use crate::simulator::NodeType;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/**
+ * MacParams represents the time slots of a round and the channel access of
+ * the nodes.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacParams {
+    #[serde(default = "MacParams::default_slots")]
+    pub slots: u32, // Number of time slots of a round
+    #[serde(default = "MacParams::default_frame_slots")]
+    pub frame_slots: u32, // Number of slots a message takes on the channel
+    #[serde(default = "MacParams::default_contention_window")]
+    pub contention_window: u32, // Largest backoff of a node, in slots
+    #[serde(default = "MacParams::default_cca_threshold")]
+    pub cca_threshold: f64, // Lowest power a node senses the channel busy with, in dBm
+}
+
+/**
+ * MacStats represents the channel access of a node.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct MacStats {
+    pub accesses: u32,     // Messages sent on the channel
+    pub access_delay: u64, // Slots waited for the channel by the messages sent
+    pub losses: u32,       // Messages lost without getting the channel
+}
+
+/**
+ * Mac gives the nodes access to the channel, in the slots of a round.
+ */
+pub struct Mac {
+    params: MacParams,
+    stats: BTreeMap<u32, MacStats>, // Channel access of the OBUs, by OBU id
+    rng: StdRng,                    // Random number generator of the backoffs
+}
+
+/**
+ * MacParams implementation.
+ */
+impl MacParams {
+    /**
+     * By default a round has 1000 slots.
+     */
+    fn default_slots() -> u32 {
+        1000
+    }
+
+    /**
+     * By default a message takes 20 slots on the channel, so at most 50
+     * messages are sent in a round by nodes sensing each other.
+     */
+    fn default_frame_slots() -> u32 {
+        20
+    }
+
+    /**
+     * By default the backoff is drawn up to 15 slots, the smallest contention
+     * window of 802.11p.
+     */
+    fn default_contention_window() -> u32 {
+        15
+    }
+
+    /**
+     * By default the channel is busy from -85 dBm, the carrier sense threshold
+     * of the 10 MHz channels of 802.11p.
+     */
+    fn default_cca_threshold() -> f64 {
+        -85.0
+    }
+
+    /**
+     * Check that a message fits in a round and that the threshold is a
+     * number.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.slots == 0 || self.frame_slots == 0 || self.frame_slots > self.slots {
+            return Err(ScenarioError::Invalid(format!(
+                "mac.frame_slots must be between 1 and mac.slots ({}), got {}",
+                self.slots, self.frame_slots
+            )));
+        }
+
+        if self.cca_threshold.is_nan() {
+            return Err(ScenarioError::Invalid(
+                "mac.cca_threshold must be a number".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * MacStats implementation.
+ */
+impl MacStats {
+    /**
+     * Return the mean number of slots the sent messages waited for the
+     * channel, None without messages.
+     */
+    pub fn mean_access_delay(&self) -> Option<f32> {
+        (self.accesses > 0).then(|| self.access_delay as f32 / self.accesses as f32)
+    }
+}
+
+/**
+ * Mac implementation.
+ */
+impl Mac {
+    /**
+     * Create the MAC of the given params, which must be valid.
+     */
+    pub fn new(params: &MacParams, rng: StdRng) -> Mac {
+        Mac {
+            params: params.clone(),
+            stats: BTreeMap::new(),
+            rng,
+        }
+    }
+
+    /**
+     * Get the number of slots a message takes on the channel.
+     */
+    pub fn get_frame_slots(&self) -> u32 {
+        self.params.frame_slots
+    }
+
+    /**
+     * Get the channel access of an OBU, empty if it never sent a message.
+     */
+    pub fn get_stats(&self, obu_id: u32) -> MacStats {
+        self.stats.get(&obu_id).copied().unwrap_or_default()
+    }
+
+    /**
+     * Return the slot each message of a round is sent in, or None if its node
+     * doesn't get the channel before the end of the round. The nodes sense
+     * the power of the ongoing transmissions with the given propagation.
+     */
+    pub fn access_channel(
+        &mut self,
+        messages: &[Message],
+        propagation: &mut Propagation,
+    ) -> Vec<Option<u32>> {
+        // The power in mW each node senses the transmission of the others with
+        let mut sensed = vec![vec![0.0; messages.len()]; messages.len()];
+        for (sender, row) in sensed.iter_mut().enumerate() {
+            for (transmitter, power) in row.iter_mut().enumerate() {
+                if sender != transmitter {
+                    let message = &messages[transmitter];
+                    *power = to_linear(propagation.get_received_power(
+                        message.phy_coord,
+                        message.phy_range,
+                        messages[sender].phy_coord,
+                    ));
+                }
+            }
+        }
+        let cca_threshold = to_linear(self.params.cca_threshold);
+
+        // Every node draws its backoff when its message is ready
+        let mut backoffs: Vec<u32> = messages
+            .iter()
+            .map(|_| self.rng.gen_range(0..=self.params.contention_window))
+            .collect();
+        let mut slots: Vec<Option<u32>> = vec![None; messages.len()];
+        let mut pending: Vec<usize> = (0..messages.len()).collect();
+        let mut transmitting: Vec<usize> = Vec::new();
+
+        // A message must end before the end of the round
+        for slot in 0..=(self.params.slots - self.params.frame_slots) {
+            if pending.is_empty() {
+                break;
+            }
+
+            // The transmissions that ended free the channel
+            transmitting.retain(|transmitter| {
+                slots[*transmitter].is_some_and(|start| slot < start + self.params.frame_slots)
+            });
+
+            // The nodes sensing the channel idle count down, the others freeze
+            let mut starting = Vec::new();
+            for sender in pending.iter().copied() {
+                let power: f64 = transmitting
+                    .iter()
+                    .map(|transmitter| sensed[sender][*transmitter])
+                    .sum();
+                if power >= cca_threshold {
+                    continue;
+                }
+
+                if backoffs[sender] == 0 {
+                    starting.push(sender);
+                } else {
+                    backoffs[sender] -= 1;
+                }
+            }
+
+            // The nodes at the end of their backoff transmit together
+            for sender in starting {
+                slots[sender] = Some(slot);
+                transmitting.push(sender);
+            }
+            pending.retain(|sender| slots[*sender].is_none());
+        }
+
+        // Record the channel access of the OBUs
+        for (message, slot) in messages.iter().zip(&slots) {
+            if message.origin_type != NodeType::OBU {
+                continue;
+            }
+
+            let stats = self.stats.entry(message.origin_id).or_default();
+            match slot {
+                Some(slot) => {
+                    stats.accesses += 1;
+                    stats.access_delay += *slot as u64;
+                }
+                None => stats.losses += 1,
+            }
+        }
+
+        slots
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grid::Coordinate;
+    use rand::SeedableRng;
+
+    /**
+     * Return the params of a MAC with short rounds.
+     */
+    fn mac_params(contention_window: u32) -> MacParams {
+        MacParams {
+            slots: 20,
+            frame_slots: 4,
+            contention_window,
+            cca_threshold: MacParams::default_cca_threshold(),
+        }
+    }
+
+    /**
+     * Return the messages of OBUs on the first row of the grid, with a range
+     * of 3 cells.
+     */
+    fn messages(xs: &[u32]) -> Vec<Message> {
+        xs.iter()
+            .enumerate()
+            .map(|(id, x)| {
+                let coordinate = Coordinate { x: *x, y: 0 };
+                Message::new(id as u32, NodeType::OBU, coordinate, coordinate, 3)
+            })
+            .collect()
+    }
+
+    /**
+     * Test the validation of the params.
+     */
+    #[test]
+    fn test_validate() {
+        let mut params = mac_params(15);
+        assert!(params.validate().is_ok());
+        params.frame_slots = 21;
+        assert!(params.validate().is_err());
+        params.frame_slots = 0;
+        assert!(params.validate().is_err());
+        params.frame_slots = 20;
+        assert!(params.validate().is_ok());
+        params.cca_threshold = f64::NAN;
+        assert!(params.validate().is_err());
+    }
+
+    /**
+     * Test the carrier sensing: the nodes sensing each other take turns, the
+     * hidden ones transmit at the same time, and the messages that don't fit
+     * in the round are lost.
+     */
+    #[test]
+    fn test_access_channel() {
+        let mut propagation = Propagation::default();
+
+        // without backoff the first node takes the channel, the hidden one too
+        let mut mac = Mac::new(&mac_params(0), StdRng::seed_from_u64(1));
+        let slots = mac.access_channel(&messages(&[0, 6]), &mut propagation);
+        assert_eq!(slots, vec![Some(0), Some(0)]);
+
+        // the nodes sensing each other wait for the end of the transmissions,
+        // and the ones ending their backoff together collide
+        let slots = mac.access_channel(&messages(&[0, 1, 2]), &mut propagation);
+        assert_eq!(slots, vec![Some(0), Some(0), Some(0)]);
+        let mut mac = Mac::new(&mac_params(15), StdRng::seed_from_u64(1));
+        for _ in 0..20 {
+            let slots = mac.access_channel(&messages(&[0, 1]), &mut propagation);
+            if let [Some(first), Some(second)] = slots[..] {
+                assert!(first == second || first.abs_diff(second) >= 4);
+            }
+        }
+
+        // the nodes sensing each other send at most 5 messages of 4 slots in
+        // the 20 slots of a round
+        let mut mac = Mac::new(&mac_params(15), StdRng::seed_from_u64(2));
+        let mut lost = 0;
+        for _ in 0..20 {
+            let slots = mac.access_channel(&messages(&[0, 1, 2, 3, 0, 1, 2]), &mut propagation);
+            lost += slots.iter().filter(|slot| slot.is_none()).count() as u32;
+            let mut sent: Vec<u32> = slots.into_iter().flatten().collect();
+            sent.sort();
+            sent.dedup();
+            assert!(sent.len() <= 5);
+        }
+        assert!(lost > 0);
+        let stats: Vec<MacStats> = (0..7).map(|id| mac.get_stats(id)).collect();
+        assert_eq!(stats.iter().map(|s| s.losses).sum::<u32>(), lost);
+        assert_eq!(
+            stats.iter().map(|s| s.accesses + s.losses).sum::<u32>(),
+            7 * 20
+        );
+        assert!(stats
+            .iter()
+            .all(|s| s.mean_access_delay().is_some_and(|delay| delay > 0.0)));
+        assert_eq!(mac.get_stats(7).mean_access_delay(), None);
+    }
+}
@@ -0,0 +1,205 @@
+/**
+ * METRICS
+ *
+ * Per-round metrics of a simulation run, so transients, the warm-up and the
+ * OBU density over time can be plotted. The metrics are exported as CSV or
+ * JSON Lines, one record per round.
+ */
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/**
+ * MetricsFormat represents the file formats of the per-round metrics.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    Csv,   // One column per metric, and one per RSU for the deliveries
+    Jsonl, // One JSON object per line
+}
+
+/**
+ * RoundMetrics represents what happened in a round of the simulation. The
+ * messages delivered at the start of a round are the ones sent at the end of
+ * the previous round.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoundMetrics {
+    pub round: u32,
+    pub obu_count: u32,           // OBUs in the grid at the end of the round
+    pub obus_added: u32,          // OBUs added to the grid in the round
+    pub obus_removed: u32,        // OBUs that left the grid in the round
+    pub obus_waiting: u32, // Arrived vehicles waiting to enter the grid at the end of the round
+    pub obus_stopped: u32, // OBUs that did not move in the round, at a red light, behind another OBU or slowing down
+    pub messages_sent: u32, // OBU messages sent at the end of the round
+    pub messages_dropped: u32, // OBU messages not sent because of a TX failure
+    pub gps_falsified: u32, // Sent OBU messages reporting a falsified coordinate
+    pub mac_losses: u32,   // OBU messages not sent because the OBU didn't get the channel
+    pub messages_expired: u32, // Messages sent at the end of the round that expire before being delivered
+    pub messages_collided: u32, // Messages of the previous round lost to the interference at a receiver
+    pub messages_late: u32, // Messages delivered at the start of the round, sent before the previous round, once whatever the receivers
+    pub mean_neighbors: f32, // Mean number of OBUs heard by each OBU, 0 without OBUs
+    pub rsu_deliveries: Vec<u32>, // OBU messages delivered to each RSU, in RSU id order
+}
+
+/**
+ * MetricsFormat implementation.
+ */
+impl MetricsFormat {
+    /**
+     * Return the name of the metrics file in this format.
+     */
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            MetricsFormat::Csv => "metrics.csv",
+            MetricsFormat::Jsonl => "metrics.jsonl",
+        }
+    }
+}
+
+/**
+ * Write the metrics of every round to a file in the given format.
+ */
+pub fn write_metrics(
+    path: &Path,
+    metrics: &[RoundMetrics],
+    format: MetricsFormat,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    match format {
+        MetricsFormat::Csv => write_csv(&mut file, metrics)?,
+        MetricsFormat::Jsonl => {
+            for round_metrics in metrics {
+                serde_json::to_writer(&mut file, round_metrics)?;
+                writeln!(file)?;
+            }
+        }
+    }
+
+    file.flush()
+}
+
+/**
+ * Write the metrics as CSV. The deliveries are written as their total and as
+ * one column per RSU.
+ */
+fn write_csv<W: Write>(writer: &mut W, metrics: &[RoundMetrics]) -> io::Result<()> {
+    // The number of RSUs doesn't change during a run
+    let rsu_count = metrics.first().map_or(0, |m| m.rsu_deliveries.len());
+
+    write!(
+        writer,
+        "round,obu_count,obus_added,obus_removed,obus_waiting,obus_stopped,messages_sent,messages_dropped,gps_falsified,mac_losses,messages_expired,messages_collided,messages_late,mean_neighbors,rsu_deliveries"
+    )?;
+    for rsu_id in 0..rsu_count {
+        write!(writer, ",rsu_{:03}", rsu_id)?;
+    }
+    writeln!(writer)?;
+
+    for m in metrics {
+        write!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            m.round,
+            m.obu_count,
+            m.obus_added,
+            m.obus_removed,
+            m.obus_waiting,
+            m.obus_stopped,
+            m.messages_sent,
+            m.messages_dropped,
+            m.gps_falsified,
+            m.mac_losses,
+            m.messages_expired,
+            m.messages_collided,
+            m.messages_late,
+            m.mean_neighbors,
+            m.rsu_deliveries.iter().sum::<u32>()
+        )?;
+        for deliveries in &m.rsu_deliveries {
+            write!(writer, ",{}", deliveries)?;
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Return the metrics of a round for the tests.
+     */
+    fn round_metrics(round: u32) -> RoundMetrics {
+        RoundMetrics {
+            round,
+            obu_count: 10,
+            obus_added: 2,
+            obus_removed: 1,
+            obus_waiting: 0,
+            obus_stopped: 3,
+            messages_sent: 9,
+            messages_dropped: 1,
+            gps_falsified: 0,
+            mac_losses: 2,
+            messages_expired: 1,
+            messages_collided: 5,
+            messages_late: 4,
+            mean_neighbors: 1.5,
+            rsu_deliveries: vec![3, 4],
+        }
+    }
+
+    /**
+     * Test the CSV format.
+     */
+    #[test]
+    fn test_write_csv() {
+        let mut buffer = Vec::new();
+        write_csv(&mut buffer, &[round_metrics(0), round_metrics(1)]).unwrap();
+
+        let contents = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(",mean_neighbors,rsu_deliveries,rsu_000,rsu_001"));
+        assert_eq!(lines[2], "1,10,2,1,0,3,9,1,0,2,1,5,4,1.5,7,3,4");
+    }
+
+    /**
+     * Test the JSON Lines format.
+     */
+    #[test]
+    fn test_write_jsonl() {
+        let path = std::env::temp_dir().join(format!(
+            "vanet4j_test_write_jsonl_{}.jsonl",
+            std::process::id()
+        ));
+        write_metrics(
+            &path,
+            &[round_metrics(0), round_metrics(1)],
+            MetricsFormat::Jsonl,
+        )
+        .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["round"], 1);
+        assert_eq!(lines[1]["rsu_deliveries"][1], 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
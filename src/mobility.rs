@@ -0,0 +1,562 @@
+/**
+ * MOBILITY
+ *
+ * Drive the OBUs from an external floating car data trace instead of the
+ * random walk: the FCD XML output of SUMO or a `time,vehicle_id,x,y` CSV. The
+ * trace coordinates are scaled to grid cells and snapped to the nearest street
+ * cell. A vehicle gets an OBU when it enters the grid, if the maximum number of
+ * OBUs allows it, and its OBU leaves the grid with the vehicle.
+ */
+use crate::grid::{Coordinate, Grid};
+use crate::obu_manager::OnBoardUnitManager;
+use crate::scenario::ScenarioError;
+use crate::xml::XmlTags;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+// Tolerance when comparing the trace times
+const TIME_EPSILON: f64 = 1e-9;
+
+/**
+ * MobilityFormat represents the file formats of the mobility traces.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MobilityFormat {
+    Fcd, // SUMO floating car data XML output
+    Csv, // One time,vehicle_id,x,y record per line
+}
+
+/**
+ * MobilityParams represents the mobility trace driving the OBUs and how its
+ * coordinates are mapped to the grid.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MobilityParams {
+    pub file: PathBuf, // Trace file, relative to the working directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<MobilityFormat>, // Format of the trace, from the file extension if unset
+    #[serde(default = "MobilityParams::default_cell_size")]
+    pub cell_size: f64, // Length of a grid cell in the trace units (meters for SUMO)
+    #[serde(default = "MobilityParams::default_round_duration")]
+    pub round_duration: f64, // Trace time simulated by each round (seconds for SUMO)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<[f64; 2]>, // Trace position of the cell (0, 0), the trace minimum if unset
+}
+
+/**
+ * VehiclePosition represents the position of a vehicle in a trace step.
+ */
+#[derive(Clone, Debug, PartialEq)]
+struct VehiclePosition {
+    vehicle: usize, // Index of the vehicle id in the trace
+    x: f64,
+    y: f64,
+}
+
+/**
+ * MobilityStep represents the positions of the vehicles at a time of the trace.
+ */
+#[derive(Clone, Debug, PartialEq)]
+struct MobilityStep {
+    time: f64,
+    positions: Vec<VehiclePosition>,
+}
+
+/**
+ * MobilityTrace represents the positions of the vehicles over time, in time
+ * order.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct MobilityTrace {
+    vehicle_ids: Vec<String>,
+    steps: Vec<MobilityStep>,
+}
+
+/**
+ * Mobility moves the OBUs along a mobility trace, one round at a time.
+ */
+pub struct Mobility {
+    trace: MobilityTrace,
+    origin: [f64; 2],
+    cell_size: f64,
+    round_duration: f64,
+    step: usize,                   // Index of the last step reached
+    obu_ids: BTreeMap<usize, u32>, // OBU of each vehicle in the grid
+}
+
+/**
+ * MobilityParams implementation.
+ */
+impl MobilityParams {
+    /**
+     * Create the params of a trace file with the default mapping.
+     */
+    pub fn new(file: PathBuf) -> MobilityParams {
+        MobilityParams {
+            file,
+            format: None,
+            cell_size: MobilityParams::default_cell_size(),
+            round_duration: MobilityParams::default_round_duration(),
+            origin: None,
+        }
+    }
+
+    /**
+     * By default a trace unit is a cell.
+     */
+    fn default_cell_size() -> f64 {
+        1.0
+    }
+
+    /**
+     * By default a round simulates a trace time unit.
+     */
+    fn default_round_duration() -> f64 {
+        1.0
+    }
+
+    /**
+     * Return the format of the trace, given or from the file extension.
+     */
+    pub fn get_format(&self) -> Option<MobilityFormat> {
+        self.format.or_else(|| {
+            match self
+                .file
+                .extension()
+                .and_then(|extension| extension.to_str())
+            {
+                Some("xml") => Some(MobilityFormat::Fcd),
+                Some("csv") => Some(MobilityFormat::Csv),
+                _ => None,
+            }
+        })
+    }
+
+    /**
+     * Check that the trace can be mapped to the grid.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.get_format().is_none() {
+            return Err(ScenarioError::Invalid(format!(
+                "unknown format of mobility.file {}, set mobility.format to fcd or csv",
+                self.file.display()
+            )));
+        }
+
+        if !(self.cell_size > 0.0 && self.round_duration > 0.0) {
+            return Err(ScenarioError::Invalid(
+                "mobility.cell_size and mobility.round_duration must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Load the trace file.
+     */
+    pub fn load(&self) -> Result<MobilityTrace, ScenarioError> {
+        let contents =
+            fs::read_to_string(&self.file).map_err(|e| ScenarioError::Io(self.file.clone(), e))?;
+
+        match self.get_format() {
+            Some(MobilityFormat::Fcd) => MobilityTrace::from_fcd_str(&contents),
+            Some(MobilityFormat::Csv) => MobilityTrace::from_csv_str(&contents),
+            None => return Err(ScenarioError::UnsupportedFormat(self.file.clone())),
+        }
+        .map_err(|message| ScenarioError::Mobility(self.file.clone(), message))
+    }
+}
+
+/**
+ * MobilityTrace implementation.
+ */
+impl MobilityTrace {
+    /**
+     * Parse a SUMO FCD XML trace, the vehicles of each timestep element.
+     */
+    pub fn from_fcd_str(contents: &str) -> Result<MobilityTrace, String> {
+        let mut records = Vec::new();
+        let mut time = None;
+
+        for tag in XmlTags::new(contents) {
+            let (line, tag) = tag?;
+            match tag.name {
+                "timestep" if tag.closing => time = None,
+                "timestep" => time = Some(tag.number_attribute("time", line)?),
+                "vehicle" => {
+                    let time =
+                        time.ok_or_else(|| format!("line {}: vehicle outside a timestep", line))?;
+                    let id = tag
+                        .attribute("id")
+                        .ok_or_else(|| format!("line {}: vehicle without an id", line))?;
+                    let x = tag.number_attribute("x", line)?;
+                    let y = tag.number_attribute("y", line)?;
+                    records.push((time, id, x, y));
+                }
+                _ => {}
+            }
+        }
+
+        MobilityTrace::from_records(records)
+    }
+
+    /**
+     * Parse a CSV trace with a time,vehicle_id,x,y record per line. The
+     * header line is optional.
+     */
+    pub fn from_csv_str(contents: &str) -> Result<MobilityTrace, String> {
+        let mut records = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (index == 0 && line.starts_with("time")) {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() != 4 {
+                return Err(format!(
+                    "line {}: expected time,vehicle_id,x,y, got {}",
+                    index + 1,
+                    line
+                ));
+            }
+
+            let number = |field: &str| {
+                field
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .ok_or_else(|| format!("line {}: invalid number {}", index + 1, field))
+            };
+            records.push((
+                number(fields[0])?,
+                fields[1].to_string(),
+                number(fields[2])?,
+                number(fields[3])?,
+            ));
+        }
+
+        MobilityTrace::from_records(records)
+    }
+
+    /**
+     * Build a trace from time, vehicle id and position records, grouping the
+     * records by time.
+     */
+    fn from_records(mut records: Vec<(f64, String, f64, f64)>) -> Result<MobilityTrace, String> {
+        if records.is_empty() {
+            return Err("no vehicle positions".to_string());
+        }
+
+        // The sort is stable, the records of a time keep the file order
+        records.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut vehicles: HashMap<String, usize> = HashMap::new();
+        let mut trace = MobilityTrace {
+            vehicle_ids: Vec::new(),
+            steps: Vec::new(),
+        };
+
+        for (time, id, x, y) in records {
+            let vehicle = match vehicles.get(&id) {
+                Some(vehicle) => *vehicle,
+                None => {
+                    trace.vehicle_ids.push(id.clone());
+                    vehicles.insert(id, trace.vehicle_ids.len() - 1);
+                    trace.vehicle_ids.len() - 1
+                }
+            };
+
+            // Start a new step at each new time
+            if trace
+                .steps
+                .last()
+                .is_none_or(|step| time - step.time > TIME_EPSILON)
+            {
+                trace.steps.push(MobilityStep {
+                    time,
+                    positions: Vec::new(),
+                });
+            }
+            if let Some(step) = trace.steps.last_mut() {
+                step.positions.push(VehiclePosition { vehicle, x, y });
+            }
+        }
+
+        Ok(trace)
+    }
+
+    /**
+     * Return the number of distinct vehicles of the trace.
+     */
+    pub fn get_vehicle_count(&self) -> usize {
+        self.vehicle_ids.len()
+    }
+
+    /**
+     * Return the number of steps of the trace.
+     */
+    pub fn get_step_count(&self) -> usize {
+        self.steps.len()
+    }
+
+    /**
+     * Return the minimum x and y of the positions of the trace.
+     */
+    fn min_position(&self) -> [f64; 2] {
+        let positions = self.steps.iter().flat_map(|step| step.positions.iter());
+        positions.fold([f64::INFINITY, f64::INFINITY], |min, position| {
+            [min[0].min(position.x), min[1].min(position.y)]
+        })
+    }
+}
+
+/**
+ * Mobility implementation.
+ */
+impl Mobility {
+    /**
+     * Create the mobility of the OBUs from a trace.
+     */
+    pub fn new(trace: MobilityTrace, params: &MobilityParams) -> Mobility {
+        Mobility {
+            origin: params.origin.unwrap_or_else(|| trace.min_position()),
+            trace,
+            cell_size: params.cell_size,
+            round_duration: params.round_duration,
+            step: 0,
+            obu_ids: BTreeMap::new(),
+        }
+    }
+
+    /**
+     * Move the OBUs to the positions of their vehicle in the given round. The
+     * OBUs of the vehicles that left the grid are removed, and the vehicles
+     * that entered it get an OBU if the maximum number of OBUs allows it.
+     * Return the number of added OBUs.
+     */
+    pub fn move_obus(
+        &mut self,
+        round: u32,
+        grid: &Grid,
+        obu_manager: &mut OnBoardUnitManager,
+    ) -> u32 {
+        // Map the positions of the vehicles in the grid to street cells
+        let mut cells: BTreeMap<usize, Coordinate> = BTreeMap::new();
+        let mut vehicles = Vec::new();
+        if let Some(step) = self.step_at(round) {
+            for position in &self.trace.steps[step].positions {
+                let x = (position.x - self.origin[0]) / self.cell_size;
+                let y = (position.y - self.origin[1]) / self.cell_size;
+                if let Some(coordinate) = grid.snap_to_street(x, y) {
+                    cells.insert(position.vehicle, coordinate);
+                    vehicles.push(position.vehicle);
+                }
+            }
+        }
+
+        // Remove the OBUs of the vehicles that left the grid
+        self.obu_ids.retain(|vehicle, obu_id| {
+            let in_grid = cells.contains_key(vehicle);
+            if !in_grid {
+                obu_manager.remove_obu(*obu_id);
+            }
+            in_grid
+        });
+
+        // Move the OBUs and equip the vehicles that entered the grid
+        let mut added_obus = 0;
+        for vehicle in vehicles {
+            let coordinate = cells[&vehicle];
+            match self.obu_ids.get(&vehicle) {
+                Some(obu_id) => {
+                    if let Some(obu) = obu_manager.obus.get_mut(obu_id) {
+                        obu.set_coordinate(coordinate);
+                    }
+                }
+                None => {
+                    if let Some(obu_id) = obu_manager.create_obu(coordinate) {
+                        self.obu_ids.insert(vehicle, obu_id);
+                        added_obus += 1;
+                    }
+                }
+            }
+        }
+
+        added_obus
+    }
+
+    /**
+     * Return the index of the trace step of a round: the last step at or
+     * before the time of the round. Once the time of the round passes the
+     * last step, every vehicle has left.
+     */
+    fn step_at(&mut self, round: u32) -> Option<usize> {
+        let steps = &self.trace.steps;
+        let time = steps[0].time + round as f64 * self.round_duration;
+
+        while self.step + 1 < steps.len() && steps[self.step + 1].time <= time + TIME_EPSILON {
+            self.step += 1;
+        }
+
+        let ended = self.step + 1 == steps.len() && time > steps[self.step].time + TIME_EPSILON;
+        (!ended && steps[self.step].time <= time + TIME_EPSILON).then_some(self.step)
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grid::GridParams;
+    use crate::obu_manager::ObuManagerParams;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const FCD_TRACE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated by SUMO -->
+<fcd-export xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+    <timestep time="0.00">
+        <vehicle id="veh&amp;0" x="0.00" y="0.00" angle="90.00" type="DEFAULT_VEHTYPE" speed="0.00"/>
+    </timestep>
+    <timestep time="1.00">
+        <vehicle id="veh&amp;0" x="10.00" y="0.00" angle="90.00" speed="10.00"/>
+        <vehicle id="veh1" x="24.00" y="37.00" angle="0.00" speed="0.00"/>
+    </timestep>
+    <timestep time="2.00">
+        <vehicle id="veh1" x="24.00" y="41.00" angle="0.00" speed="10.00"/>
+        <vehicle id="veh2" x="60.00" y="0.00" angle="0.00" speed="10.00"/>
+    </timestep>
+</fcd-export>
+"#;
+
+    const CSV_TRACE: &str = "time,vehicle_id,x,y
+1,veh1,24,31
+0,veh&0,0,0
+1,veh&0,10,0
+2,veh1,24,41
+";
+
+    /**
+     * Test parsing the FCD and CSV traces.
+     */
+    #[test]
+    fn test_parse_traces() {
+        let fcd = MobilityTrace::from_fcd_str(FCD_TRACE).unwrap();
+        assert_eq!(fcd.get_vehicle_count(), 3);
+        assert_eq!(fcd.get_step_count(), 3);
+        assert_eq!(fcd.vehicle_ids[0], "veh&0");
+        assert_eq!(
+            fcd.steps[1].positions[1],
+            VehiclePosition {
+                vehicle: 1,
+                x: 24.0,
+                y: 37.0
+            }
+        );
+
+        // the records are grouped by time
+        let csv = MobilityTrace::from_csv_str(CSV_TRACE).unwrap();
+        assert_eq!(csv.get_step_count(), 3);
+        assert_eq!(csv.steps[1].positions.len(), 2);
+        assert_eq!(csv.steps[1].positions[0].vehicle, 1);
+
+        // errors report the line
+        let error = MobilityTrace::from_csv_str("0,veh0,1\n").unwrap_err();
+        assert!(error.starts_with("line 1"));
+        let error = MobilityTrace::from_fcd_str(
+            "<fcd-export>\n<timestep time=\"0\">\n<vehicle id=\"a\" x=\"1\"/>",
+        )
+        .unwrap_err();
+        assert!(error.starts_with("line 3"));
+        assert!(MobilityTrace::from_csv_str("time,vehicle_id,x,y\n").is_err());
+    }
+
+    /**
+     * Test moving the OBUs along a trace.
+     */
+    #[test]
+    fn test_move_obus() {
+        // a 10x10 grid with streets every 3 cells
+        let grid = Grid::new(GridParams {
+            blocks_per_street: 3,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        });
+        let mut obu_manager = OnBoardUnitManager::new(
+            ObuManagerParams {
+                max_obus: Some(10),
+                comms_range: 2,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.0,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 0,
+            },
+            grid.get_width(),
+            grid.get_height(),
+            StdRng::seed_from_u64(0),
+        );
+
+        let params = MobilityParams {
+            file: PathBuf::from("trace.xml"),
+            format: None,
+            cell_size: 5.0,
+            round_duration: 0.5,
+            origin: None,
+        };
+        assert_eq!(params.get_format(), Some(MobilityFormat::Fcd));
+        let trace = MobilityTrace::from_fcd_str(FCD_TRACE).unwrap();
+        let mut mobility = Mobility::new(trace, &params);
+
+        // the first vehicle enters the grid
+        assert_eq!(mobility.move_obus(0, &grid, &mut obu_manager), 1);
+        assert_eq!(
+            obu_manager.obus[&0].get_coordinate(),
+            Coordinate { x: 0, y: 0 }
+        );
+
+        // the step is kept until the next one
+        assert_eq!(mobility.move_obus(1, &grid, &mut obu_manager), 0);
+
+        // (4.8, 7.4) is snapped to the nearest street cell
+        assert_eq!(mobility.move_obus(2, &grid, &mut obu_manager), 1);
+        assert_eq!(
+            obu_manager.obus[&0].get_coordinate(),
+            Coordinate { x: 2, y: 0 }
+        );
+        assert_eq!(
+            obu_manager.obus[&1].get_coordinate(),
+            Coordinate { x: 6, y: 7 }
+        );
+
+        // the first vehicle left, the third one is outside the grid
+        assert_eq!(mobility.move_obus(4, &grid, &mut obu_manager), 0);
+        assert_eq!(obu_manager.get_obus_count(), 1);
+        assert_eq!(
+            obu_manager.obus[&1].get_coordinate(),
+            Coordinate { x: 5, y: 9 }
+        );
+
+        // the trace ended
+        mobility.move_obus(6, &grid, &mut obu_manager);
+        assert_eq!(obu_manager.get_obus_count(), 0);
+        assert_eq!(obu_manager.get_evaluated_obus().len(), 2);
+    }
+}
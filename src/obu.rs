@@ -1,6 +1,7 @@
 use crate::comms::{Message, NeighborEntry};
-use crate::grid::{Coordinate, Grid, SquareCoords};
+use crate::grid::Coordinate;
 use crate::simulator::{NodeType, Simulator};
+use rand::rngs::StdRng;
 use rand::Rng;
 
 pub struct OnBoardUnit {
@@ -11,7 +12,9 @@ pub struct OnBoardUnit {
     gps_failure_rate: f32,
     is_faulty: bool,
     pub neighbors: Vec<NeighborEntry>,
-    grid_dimension: u32,
+    grid_width: u32,  // Number of cells of the grid along the x axis
+    grid_height: u32, // Number of cells of the grid along the y axis
+    rng: StdRng,      // Random number generator used for fault injection
 }
 
 /**
@@ -21,6 +24,7 @@ impl OnBoardUnit {
     /**
      * Create a new OnBoardUnit
      */
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u32,
         coordinate: Coordinate,
@@ -28,7 +32,9 @@ impl OnBoardUnit {
         tx_failure_rate: f32,
         gps_failure_rate: f32,
         is_faulty: bool,
-        grid_dimension: u32,
+        grid_width: u32,
+        grid_height: u32,
+        rng: StdRng,
     ) -> OnBoardUnit {
         OnBoardUnit {
             id,
@@ -38,7 +44,9 @@ impl OnBoardUnit {
             gps_failure_rate,
             is_faulty,
             neighbors: Vec::new(),
-            grid_dimension,
+            grid_width,
+            grid_height,
+            rng,
         }
     }
 
@@ -60,7 +68,7 @@ impl OnBoardUnit {
      * Get the coordinate of the OnBoardUnit
      */
     pub fn get_coordinate(&self) -> Coordinate {
-        self.coordinate.clone()
+        self.coordinate
     }
 
     /**
@@ -87,18 +95,18 @@ impl OnBoardUnit {
     /**
      * Get a message from this obu
      */
-    pub fn get_message(&self) -> Option<Message> {
+    pub fn get_message(&mut self) -> Option<Message> {
         // Default value of the reported coordinate
-        let mut reported_coord = self.coordinate.clone();
+        let mut reported_coord = self.coordinate;
 
         // Check the tx failure rate
-        if Simulator::random_event(self.tx_failure_rate) {
+        if Simulator::random_event(&mut self.rng, self.tx_failure_rate) {
             // Don't send a message
             return None;
         }
 
         // Check the gps failure rate
-        if Simulator::random_event(self.gps_failure_rate) {
+        if Simulator::random_event(&mut self.rng, self.gps_failure_rate) {
             // Get a random coordinate outside the range of the OBU
             reported_coord = self.get_random_coordinate_outside_range();
         }
@@ -108,32 +116,30 @@ impl OnBoardUnit {
             self.id,
             NodeType::OBU,
             reported_coord,
-            self.coordinate.clone(),
+            self.coordinate,
             self.tx_range,
         ))
-    }fn get_message
+    }
 
     /**
      * Receive a message from the ether
      */
     pub fn receive_message(&mut self, message: Message) {
-        match message.origin_type {
-            NodeType::OBU => {
-                // ignore my own messages
-                if message.origin_id == self.id {
-                    return;
-                }
-
-                // create a neighbor entry
-                let neighbor = NeighborEntry {
-                    id: message.origin_id,
-                    coordinate: message.coordinate,
-                };
-
-                // add the neighbor to the list
-                self.neighbors.push(neighbor);
+        if let NodeType::OBU = message.origin_type {
+            // ignore my own messages
+            if message.origin_id == self.id {
+                return;
             }
-            _ => {}
+
+            // create a neighbor entry
+            let neighbor = NeighborEntry {
+                id: message.origin_id,
+                coordinate: message.coordinate,
+                send_round: message.send_round,
+            };
+
+            // add the neighbor to the list
+            self.neighbors.push(neighbor);
         }
     }
 
@@ -149,16 +155,13 @@ impl OnBoardUnit {
      * CAUTION: This function will loop infinitely if the OBU has relatively
      * large communication range in relation to the grid size.
      */
-    pub fn get_random_coordinate_outside_range(&self) -> Coordinate {
-        // Create a random number generator
-        let mut rng = rand::thread_rng();
-
+    pub fn get_random_coordinate_outside_range(&mut self) -> Coordinate {
         // Start an infinite loop
         loop {
             // Generate a random x coordinate within the grid
-            let x = rng.gen_range(0..self.grid_dimension);
+            let x = self.rng.gen_range(0..self.grid_width);
             // Generate a random y coordinate within the grid
-            let y = rng.gen_range(0..self.grid_dimension);
+            let y = self.rng.gen_range(0..self.grid_height);
 
             // Calculate the Euclidean distance between the OBU and the random coordinate
             let distance = (((self.coordinate.x as i32 - x as i32).pow(2)
@@ -173,5 +176,5 @@ impl OnBoardUnit {
             // If the distance is not greater than the communication range,
             // the loop will continue and generate a new random coordinate
         }
-    } fn get_random_coordinate_outside_range
-} impl OnBoardUnit
+    }
+}
@@ -1,11 +1,19 @@
 use crate::comms::Ether;
 use crate::comms::Message;
-use crate::grid::{Coordinate, Grid};
+use crate::grid::Coordinate;
 use crate::obu::OnBoardUnit;
-use std::collections::HashMap;
-
+use crate::simulator::NodeType;
+use crate::trace::{MessageStatus, TraceObu};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ObuManagerParams {
-    pub max_obus: u32,
+    #[serde(default)]
+    pub max_obus: Option<u32>, // Maximum number of OBUs in the grid, no cap if unset
     pub comms_range: u32,
     pub tx_base_failure_rate: f32,
     pub tx_faulty_obu_failure_rate: f32,
@@ -14,29 +22,36 @@ pub struct ObuManagerParams {
     pub faulty_obus: u32,
 }
 
-struct ObuManagerStats {
-    normal_obu_tx_count: u32,
-    normal_obu_tx_error_count: u32,
-    faulty_obu_tx_count: u32,
-    faulty_obu_tx_error_count: u32,
-    total_tx_count: u32,
-    total_tx_error_count: u32,
+/**
+ * ObuManagerStats represents the TX stats of the OBUs.
+ */
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ObuManagerStats {
+    pub normal_obu_tx_count: u32,
+    pub normal_obu_tx_error_count: u32,
+    pub faulty_obu_tx_count: u32,
+    pub faulty_obu_tx_error_count: u32,
+    pub total_tx_count: u32,
+    pub total_tx_error_count: u32,
 }
 
 pub struct OnBoardUnitManager {
     next_id: u32,
-    max_obus: u32,
+    max_obus: Option<u32>,
     comms_range: u32,
     tx_base_failure_rate: f32,
     tx_faulty_obu_failure_rate: f32,
     gps_failure_rate: f32,
     gps_faulty_obu_failure_rate: f32,
     faulty_obus: u32,
-    faulty_obus_added: u32,
-    pub obus: HashMap<u32, OnBoardUnit>, // FIXME: make private
+    faulty_obus_in_grid: u32, // Faulty OBUs in the grid, the departed ones are replaced
+    pub obus: BTreeMap<u32, OnBoardUnit>, // FIXME: make private
+    departed_obus: BTreeMap<u32, OnBoardUnit>, // OBUs that left the grid, kept for evaluation
     stats: ObuManagerStats,
     current_round: u32,
-    grid_dimension: u32,
+    grid_width: u32,
+    grid_height: u32,
+    rng: StdRng, // Seeds the random number generator of each new OBU
 }
 
 /**
@@ -44,9 +59,15 @@ pub struct OnBoardUnitManager {
  */
 impl OnBoardUnitManager {
     /**
-     * Creates a new OnBoardUnitManager.
+     * Creates a new OnBoardUnitManager. The given random number generator is
+     * used to derive the generators of the OBUs created by this manager.
      */
-    pub fn new(params: ObuManagerParams, grid_dimension: u32) -> OnBoardUnitManager {
+    pub fn new(
+        params: ObuManagerParams,
+        grid_width: u32,
+        grid_height: u32,
+        rng: StdRng,
+    ) -> OnBoardUnitManager {
         OnBoardUnitManager {
             next_id: 0,
             max_obus: params.max_obus,
@@ -56,18 +77,14 @@ impl OnBoardUnitManager {
             gps_failure_rate: params.gps_failure_rate,
             gps_faulty_obu_failure_rate: params.gps_faulty_obu_failure_rate,
             faulty_obus: params.faulty_obus,
-            faulty_obus_added: 0,
-            grid_dimension,
-            obus: HashMap::new(),
-            stats: ObuManagerStats {
-                normal_obu_tx_count: 0,
-                normal_obu_tx_error_count: 0,
-                faulty_obu_tx_count: 0,
-                faulty_obu_tx_error_count: 0,
-                total_tx_count: 0,
-                total_tx_error_count: 0,
-            },
+            faulty_obus_in_grid: 0,
+            grid_width,
+            grid_height,
+            obus: BTreeMap::new(),
+            departed_obus: BTreeMap::new(),
+            stats: ObuManagerStats::default(),
             current_round: 0,
+            rng,
         }
     }
 
@@ -75,32 +92,43 @@ impl OnBoardUnitManager {
      * Return the next available id.
      */
     pub fn get_next_id(&self) -> u32 {
-        let id = self.next_id;
-        id
+        self.next_id
     }
 
     /**
      * Return the comms range.
      */
     pub fn get_comms_range(&self) -> u32 {
-        let comms_range = self.comms_range;
-        comms_range
+        self.comms_range
     }
 
     /**
-     * Return max number of obus.
+     * Return max number of obus, none if there is no cap.
      */
-    pub fn get_max_obus(&self) -> u32 {
-        let max_obus = self.max_obus;
-        max_obus
+    pub fn get_max_obus(&self) -> Option<u32> {
+        self.max_obus
+    }
+
+    /**
+     * Return true if the maximum number of obus is in the grid.
+     */
+    pub fn is_full(&self) -> bool {
+        self.max_obus
+            .is_some_and(|max_obus| self.obus.len() >= max_obus as usize)
     }
 
     /**
      * Return number of obus.
      */
     pub fn get_obus_count(&self) -> u32 {
-        let num_obus = self.obus.len() as u32;
-        num_obus
+        self.obus.len() as u32
+    }
+
+    /**
+     * Return the TX stats.
+     */
+    pub fn get_stats(&self) -> ObuManagerStats {
+        self.stats.clone()
     }
 
     /**
@@ -118,7 +146,7 @@ impl OnBoardUnitManager {
         let id = self.next_id;
 
         // Check if the maximum number of OBUs has been reached.
-        if self.obus.len() >= self.max_obus as usize {
+        if self.is_full() {
             return None;
         }
 
@@ -129,18 +157,25 @@ impl OnBoardUnitManager {
         // By default, the OBU is not faulty.
         let mut is_faulty = false;
 
-        // if the number of faulty obus is greater than 0 and the number of faulty obus added is
-        // less than the number of faulty obus, then add a faulty obu
-        if self.faulty_obus > 0 && self.faulty_obus_added < self.faulty_obus {
-            if (self.obus.len() as u32 + 1) % (self.max_obus / self.faulty_obus) == 0 {
-                // adjust the failure rate
-                tx_failure_rate = self.tx_faulty_obu_failure_rate;
-                gps_failure_rate = self.gps_faulty_obu_failure_rate;
-                is_faulty = true;
-                self.faulty_obus_added += 1;
-            }
+        // if the number of faulty obus is greater than 0 and the number of faulty obus in the
+        // grid is less than the number of faulty obus, then add a faulty obu, spread among
+        // the normal ones up to the cap; without a cap, the first obus are the faulty ones
+        if self.faulty_obus > 0
+            && self.faulty_obus_in_grid < self.faulty_obus
+            && self.max_obus.is_none_or(|max_obus| {
+                (self.obus.len() as u32 + 1).is_multiple_of(max_obus / self.faulty_obus)
+            })
+        {
+            // adjust the failure rate
+            tx_failure_rate = self.tx_faulty_obu_failure_rate;
+            gps_failure_rate = self.gps_faulty_obu_failure_rate;
+            is_faulty = true;
+            self.faulty_obus_in_grid += 1;
         }
 
+        // derive the obu random number generator from the manager one
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+
         // create and insert obu in the hashmap
         self.obus.insert(
             id,
@@ -151,7 +186,9 @@ impl OnBoardUnitManager {
                 tx_failure_rate,
                 gps_failure_rate,
                 is_faulty,
-                self.grid_dimension,
+                self.grid_width,
+                self.grid_height,
+                rng,
             ),
         );
 
@@ -160,7 +197,34 @@ impl OnBoardUnitManager {
 
         // return the id of the created obu
         Some(id)
-    }fn create_obu
+    }
+
+    /**
+     * Remove an OBU that left the grid. The OBU is kept for the evaluation of
+     * the detectors.
+     */
+    pub fn remove_obu(&mut self, id: u32) {
+        if let Some(obu) = self.obus.remove(&id) {
+            if obu.is_faulty() {
+                self.faulty_obus_in_grid -= 1;
+            }
+            self.departed_obus.insert(id, obu);
+        }
+    }
+
+    /**
+     * Return the OBUs to evaluate, the ones in the grid and the ones that left
+     * it, in id order.
+     */
+    pub fn get_evaluated_obus(&self) -> Vec<&OnBoardUnit> {
+        let mut obus: Vec<&OnBoardUnit> = self
+            .obus
+            .values()
+            .chain(self.departed_obus.values())
+            .collect();
+        obus.sort_by_key(|obu| obu.get_id());
+        obus
+    }
 
     /**
      * Check if an obu is faulty.
@@ -181,54 +245,142 @@ impl OnBoardUnitManager {
 
         // Iterate over all obus
         for obu in self.obus.values_mut() {
+            // Try to get a message from the obu
+            let message = obu.get_message();
+
             // Update the stats
-            self.stats.total_tx_count += 1;
-            let is_faulty = obu.is_faulty();
-            if is_faulty {
-                self.stats.faulty_obu_tx_count += 1;
-            } else {
-                self.stats.normal_obu_tx_count += 1;
-            }
+            Self::count_tx(&mut self.stats, obu.is_faulty(), message.is_some());
 
-            // Try to get a message from the obu
-            if let Some(message) = obu.get_message() {
-                // If a message was returned, update the range and add it to the returned vector
+            // If a message was returned, add it to the returned vector
+            if let Some(message) = message {
                 messages.push(message);
-            } else {
-                // If no message was returned, update the stats accordingly
-                self.stats.total_tx_error_count += 1;
-                if is_faulty {
-                    self.stats.faulty_obu_tx_error_count += 1;
-                } else {
-                    self.stats.normal_obu_tx_error_count += 1;
-                }
             }
         }
 
         // Return the messages
         messages
-    }fn collect_messages
+    }
+
+    /**
+     * Place the OBUs as recorded in a round of a trace: the recorded OBUs are
+     * created or moved to their coordinate, and the others are removed. Return
+     * the number of created OBUs.
+     */
+    pub fn replay_obus(&mut self, recorded_obus: &[TraceObu]) -> u32 {
+        let recorded_ids: BTreeSet<u32> = recorded_obus.iter().map(|obu| obu.id).collect();
+        let departed_ids: Vec<u32> = self
+            .obus
+            .keys()
+            .filter(|id| !recorded_ids.contains(id))
+            .copied()
+            .collect();
+        for id in departed_ids {
+            self.remove_obu(id);
+        }
+
+        let mut added_obus = 0;
+        for recorded in recorded_obus {
+            if let Some(obu) = self.obus.get_mut(&recorded.id) {
+                obu.set_coordinate(recorded.coordinate);
+                continue;
+            }
+
+            // The failure rates are not used, the messages come from the trace
+            let (tx_failure_rate, gps_failure_rate) = if recorded.faulty {
+                self.faulty_obus_in_grid += 1;
+                (
+                    self.tx_faulty_obu_failure_rate,
+                    self.gps_faulty_obu_failure_rate,
+                )
+            } else {
+                (self.tx_base_failure_rate, self.gps_failure_rate)
+            };
+
+            let rng = StdRng::seed_from_u64(self.rng.gen());
+            self.obus.insert(
+                recorded.id,
+                OnBoardUnit::new(
+                    recorded.id,
+                    recorded.coordinate,
+                    self.comms_range,
+                    tx_failure_rate,
+                    gps_failure_rate,
+                    recorded.faulty,
+                    self.grid_width,
+                    self.grid_height,
+                    rng,
+                ),
+            );
+
+            self.next_id = self.next_id.max(recorded.id + 1);
+            added_obus += 1;
+        }
+
+        added_obus
+    }
+
+    /**
+     * Return the messages recorded in a round of a trace, instead of drawing
+     * the failures of the OBUs. The stats are updated as when collecting the
+     * messages.
+     */
+    pub fn replay_messages(&mut self, recorded_obus: &[TraceObu]) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        for recorded in recorded_obus {
+            let sent = recorded.status != MessageStatus::Dropped;
+            Self::count_tx(&mut self.stats, recorded.faulty, sent);
+
+            if sent {
+                messages.push(Message::new(
+                    recorded.id,
+                    NodeType::OBU,
+                    recorded.reported.unwrap_or(recorded.coordinate),
+                    recorded.coordinate,
+                    self.comms_range,
+                ));
+            }
+        }
+
+        messages
+    }
+
+    /**
+     * Count a transmission attempt of an OBU in the stats.
+     */
+    fn count_tx(stats: &mut ObuManagerStats, is_faulty: bool, sent: bool) {
+        stats.total_tx_count += 1;
+        if is_faulty {
+            stats.faulty_obu_tx_count += 1;
+        } else {
+            stats.normal_obu_tx_count += 1;
+        }
+
+        // If no message was sent, count the error
+        if !sent {
+            stats.total_tx_error_count += 1;
+            if is_faulty {
+                stats.faulty_obu_tx_error_count += 1;
+            } else {
+                stats.normal_obu_tx_error_count += 1;
+            }
+        }
+    }
 
     /**
      * Deliver messages to OBUs.
      */
-    pub fn deliver_messages(&mut self, messages: &Vec<Message>) {
+    pub fn deliver_messages(&mut self, ether: &mut Ether) {
         // Iterate over all obus
         for obu in self.obus.values_mut() {
             // Clear the obu neighbors
             obu.clear_neighbors();
 
-            // Iterate over all messages
-            for message in messages {
-                // Check if the message can reach the obu
-                if Ether::is_transmission_possible(
-                    message.phy_coord,
-                    message.phy_range,
-                    obu.get_coordinate(),
-                ) {
-                    // deliver the message to the obu
-                    obu.receive_message(message.clone());
-                }
+            // Iterate over the messages that reach the obu
+            for message in ether.receive_messages(NodeType::OBU, obu.get_id(), obu.get_coordinate())
+            {
+                // deliver the message to the obu
+                obu.receive_message(message);
             }
         }
     }
@@ -259,7 +411,7 @@ impl OnBoardUnitManager {
                 * 100.0
         );
     }
-}impl OnBoardUnitManager
+}
 
 /***
  * TESTS MODULE
@@ -268,7 +420,6 @@ impl OnBoardUnitManager {
 mod tests {
 
     use super::*;
-    use crate::grid::Coordinate;
 
     /**
      * Test the creation of an OnBoardUnitManager
@@ -276,7 +427,7 @@ mod tests {
     #[test]
     fn test_create_obu_manager() {
         let params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 1,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -285,7 +436,7 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let obu_manager = OnBoardUnitManager::new(params, 0);
+        let obu_manager = OnBoardUnitManager::new(params, 0, 0, StdRng::seed_from_u64(0));
         assert_eq!(obu_manager.next_id, 0);
         assert_eq!(obu_manager.obus.len(), 0);
     }
@@ -296,7 +447,7 @@ mod tests {
     #[test]
     fn test_create_obus_1() {
         let params = ObuManagerParams {
-            max_obus: 100,
+            max_obus: Some(100),
             comms_range: 1,
             tx_base_failure_rate: 0.01,
             tx_faulty_obu_failure_rate: 0.1,
@@ -305,7 +456,7 @@ mod tests {
             faulty_obus: 20,
         };
 
-        let mut obu_manager = OnBoardUnitManager::new(params, 0);
+        let mut obu_manager = OnBoardUnitManager::new(params, 0, 0, StdRng::seed_from_u64(0));
 
         // add 4 obus
         for _ in 0..4 {
@@ -320,7 +471,7 @@ mod tests {
         assert_eq!(obu.get_coordinate().x, 1);
         assert_eq!(obu.get_coordinate().y, 2);
         assert_eq!(obu.get_tx_failure_rate(), 0.01);
-        assert_eq!(obu.is_faulty(), false);
+        assert!(!obu.is_faulty());
 
         // add one more obu, this one should be faulty
         obu_manager.create_obu(Coordinate { x: 3, y: 4 });
@@ -332,10 +483,10 @@ mod tests {
         assert_eq!(obu.get_coordinate().x, 3);
         assert_eq!(obu.get_coordinate().y, 4);
         assert_eq!(obu.get_tx_failure_rate(), 0.1);
-        assert_eq!(obu.is_faulty(), true);
+        assert!(obu.is_faulty());
 
         // add the remaining obus
-        for _ in 5..obu_manager.max_obus {
+        for _ in 5..obu_manager.max_obus.unwrap() {
             obu_manager.create_obu(Coordinate { x: 1, y: 2 });
         }
 
@@ -360,7 +511,7 @@ mod tests {
     #[test]
     fn test_create_obus_2() {
         let params = ObuManagerParams {
-            max_obus: 100,
+            max_obus: Some(100),
             comms_range: 1,
             tx_base_failure_rate: 0.01,
             tx_faulty_obu_failure_rate: 0.1,
@@ -369,7 +520,7 @@ mod tests {
             faulty_obus: 13,
         };
 
-        let mut obu_manager = OnBoardUnitManager::new(params, 0);
+        let mut obu_manager = OnBoardUnitManager::new(params, 0, 0, StdRng::seed_from_u64(0));
 
         // add 6 obus
         for _ in 0..6 {
@@ -384,7 +535,7 @@ mod tests {
         assert_eq!(obu.get_coordinate().x, 1);
         assert_eq!(obu.get_coordinate().y, 2);
         assert_eq!(obu.get_tx_failure_rate(), 0.01);
-        assert_eq!(obu.is_faulty(), false);
+        assert!(!obu.is_faulty());
 
         // add one more obu, this one should be faulty
         obu_manager.create_obu(Coordinate { x: 3, y: 4 });
@@ -396,10 +547,10 @@ mod tests {
         assert_eq!(obu.get_coordinate().x, 3);
         assert_eq!(obu.get_coordinate().y, 4);
         assert_eq!(obu.get_tx_failure_rate(), 0.1);
-        assert_eq!(obu.is_faulty(), true);
+        assert!(obu.is_faulty());
 
         // add the remaining obus
-        for _ in 7..obu_manager.max_obus {
+        for _ in 7..obu_manager.max_obus.unwrap() {
             obu_manager.create_obu(Coordinate { x: 1, y: 2 });
         }
 
@@ -424,7 +575,7 @@ mod tests {
     #[test]
     fn test_obu_message_collection() {
         let params = ObuManagerParams {
-            max_obus: 3,
+            max_obus: Some(3),
             comms_range: 2,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -433,7 +584,7 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let mut obu_manager = OnBoardUnitManager::new(params, 0);
+        let mut obu_manager = OnBoardUnitManager::new(params, 0, 0, StdRng::seed_from_u64(0));
 
         obu_manager.create_obu(Coordinate { x: 1, y: 2 });
         obu_manager.create_obu(Coordinate { x: 1, y: 3 });
@@ -446,4 +597,4 @@ mod tests {
     }
 
     // TODO: Move message deliver tests from simulator.rs to here
-}mod tests
+}
@@ -0,0 +1,488 @@
+/**
+ * OSM
+ *
+ * Import the road network of an OpenStreetMap extract, a local .osm XML file.
+ * The drivable ways are rasterized onto the grid cells, with the north at the
+ * row 0, and each cell gets the headings of the ways going through it, so the
+ * one-way streets keep their flow direction. A way going out of the extract
+ * bounds starts or ends at the border of the grid.
+ */
+use crate::grid::{Coordinate, Heading};
+use crate::road_map::RoadMap;
+use crate::scenario::ScenarioError;
+use crate::xml::XmlTags;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// Mean radius of the Earth, in meters
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// Maximum number of cells of an imported grid
+const MAX_CELLS: f64 = 25_000_000.0;
+
+/**
+ * OsmParams represents the OSM extract of the road network and how it is
+ * rasterized onto the grid.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OsmParams {
+    pub file: PathBuf, // OSM XML extract, relative to the scenario file
+    #[serde(default = "OsmParams::default_cell_size")]
+    pub cell_size: f64, // Length of a grid cell in meters
+    #[serde(default = "OsmParams::default_highways")]
+    pub highways: Vec<String>, // Values of the highway tag of the drivable ways
+}
+
+/**
+ * OsmWay represents a drivable way of an OSM extract.
+ */
+struct OsmWay {
+    nodes: Vec<i64>, // Ids of the nodes of the way, in order
+    forward: bool,   // The vehicles drive from the first node to the last one
+    backward: bool,  // The vehicles drive from the last node to the first one
+}
+
+/**
+ * OsmParams implementation.
+ */
+impl OsmParams {
+    /**
+     * Create the params of an OSM extract with the default rasterization.
+     */
+    pub fn new(file: PathBuf) -> OsmParams {
+        OsmParams {
+            file,
+            cell_size: OsmParams::default_cell_size(),
+            highways: OsmParams::default_highways(),
+        }
+    }
+
+    /**
+     * By default a cell is the length of a vehicle and its gap, 7.5 meters.
+     */
+    fn default_cell_size() -> f64 {
+        7.5
+    }
+
+    /**
+     * By default the ways of the road types open to cars are drivable.
+     */
+    fn default_highways() -> Vec<String> {
+        [
+            "motorway",
+            "trunk",
+            "primary",
+            "secondary",
+            "tertiary",
+            "unclassified",
+            "residential",
+            "living_street",
+            "service",
+            "road",
+            "motorway_link",
+            "trunk_link",
+            "primary_link",
+            "secondary_link",
+            "tertiary_link",
+        ]
+        .iter()
+        .map(|highway| highway.to_string())
+        .collect()
+    }
+
+    /**
+     * Check that the extract can be rasterized.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if !(self.cell_size > 0.0 && self.cell_size.is_finite()) {
+            return Err(ScenarioError::Invalid(
+                "grid.osm.cell_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.highways.is_empty() {
+            return Err(ScenarioError::Invalid(
+                "grid.osm.highways must have at least one road type".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Load the extract file and rasterize its road network.
+     */
+    pub fn load(&self) -> Result<RoadMap, ScenarioError> {
+        let contents = fs::read_to_string(&self.file).map_err(|e| {
+            ScenarioError::Invalid(format!(
+                "can't read OSM file {}: {}",
+                self.file.display(),
+                e
+            ))
+        })?;
+
+        self.parse(&contents)
+            .map_err(|message| ScenarioError::Osm(self.file.clone(), message))
+    }
+
+    /**
+     * Parse an OSM XML extract and rasterize its drivable ways. The grid
+     * covers the bounds of the extract, or the drivable ways without bounds.
+     */
+    pub fn parse(&self, contents: &str) -> Result<RoadMap, String> {
+        let mut bounds = None;
+        let mut nodes: HashMap<i64, (f64, f64)> = HashMap::new();
+        let mut ways = Vec::new();
+        let mut way: Option<(Vec<i64>, HashMap<String, String>)> = None;
+
+        for tag in XmlTags::new(contents) {
+            let (line, tag) = tag?;
+            let id = |name: &str| {
+                tag.attribute(name)
+                    .and_then(|value| value.trim().parse::<i64>().ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "line {}: {} without a valid {} attribute",
+                            line, tag.name, name
+                        )
+                    })
+            };
+
+            match tag.name {
+                "bounds" => {
+                    bounds = Some([
+                        tag.number_attribute("minlat", line)?,
+                        tag.number_attribute("minlon", line)?,
+                        tag.number_attribute("maxlat", line)?,
+                        tag.number_attribute("maxlon", line)?,
+                    ])
+                }
+                "node" if !tag.closing => {
+                    let position = (
+                        tag.number_attribute("lat", line)?,
+                        tag.number_attribute("lon", line)?,
+                    );
+                    nodes.insert(id("id")?, position);
+                }
+                "way" if tag.closing => {
+                    if let Some((way_nodes, tags)) = way.take() {
+                        ways.extend(self.get_drivable_way(way_nodes, &tags));
+                    }
+                }
+                "way" => way = Some((Vec::new(), HashMap::new())),
+                "nd" => {
+                    if let Some((way_nodes, _)) = way.as_mut() {
+                        way_nodes.push(id("ref")?);
+                    }
+                }
+                "tag" => {
+                    if let (Some((_, tags)), Some(key), Some(value)) =
+                        (way.as_mut(), tag.attribute("k"), tag.attribute("v"))
+                    {
+                        tags.insert(key, value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // The bounds of the grid, from the ways without the extract bounds
+        let [min_lat, min_lon, max_lat, max_lon] = match bounds {
+            Some(bounds) => bounds,
+            None => ways
+                .iter()
+                .flat_map(|way| way.nodes.iter().filter_map(|node| nodes.get(node)))
+                .fold(
+                    [f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY],
+                    |bounds, (lat, lon)| {
+                        [
+                            bounds[0].min(*lat),
+                            bounds[1].min(*lon),
+                            bounds[2].max(*lat),
+                            bounds[3].max(*lon),
+                        ]
+                    },
+                ),
+        };
+        if !(min_lat <= max_lat && min_lon <= max_lon) {
+            return Err("no drivable way".to_string());
+        }
+
+        // Project the positions on a plane tangent to the center of the bounds
+        let scale = ((min_lat + max_lat) / 2.0).to_radians().cos();
+        let to_cell = |(lat, lon): (f64, f64)| {
+            (
+                (EARTH_RADIUS * (lon - min_lon).to_radians() * scale / self.cell_size).round()
+                    as i64,
+                (EARTH_RADIUS * (max_lat - lat).to_radians() / self.cell_size).round() as i64,
+            )
+        };
+        let (width, height) = to_cell((min_lat, max_lon));
+        if (width as f64 + 1.0) * (height as f64 + 1.0) > MAX_CELLS {
+            return Err(format!(
+                "the extract is too large for cells of {} meters",
+                self.cell_size
+            ));
+        }
+
+        let mut road_map = RoadMap::new(width as u32 + 1, height as u32 + 1);
+        for way in &ways {
+            // A way is split where its nodes are missing from the extract
+            let cells: Vec<Option<(i64, i64)>> = way
+                .nodes
+                .iter()
+                .map(|node| nodes.get(node).map(|position| to_cell(*position)))
+                .collect();
+            for pair in cells.windows(2) {
+                if let [Some(from), Some(to)] = pair {
+                    add_segment(&mut road_map, *from, *to, way.forward, way.backward);
+                }
+            }
+        }
+
+        Ok(road_map)
+    }
+
+    /**
+     * Return the way if it is drivable, with the directions its vehicles
+     * drive in. The motorways and roundabouts are one-way unless tagged
+     * otherwise.
+     */
+    fn get_drivable_way(&self, nodes: Vec<i64>, tags: &HashMap<String, String>) -> Option<OsmWay> {
+        let tag = |key: &str| tags.get(key).map(String::as_str);
+
+        let highway = tag("highway")?;
+        if !self.highways.iter().any(|drivable| drivable == highway)
+            || tag("area") == Some("yes")
+            || tag("access") == Some("no")
+        {
+            return None;
+        }
+
+        let implied_oneway = matches!(highway, "motorway" | "motorway_link")
+            || matches!(tag("junction"), Some("roundabout" | "circular"));
+        let (forward, backward) = match tag("oneway") {
+            Some("yes" | "true" | "1") => (true, false),
+            Some("-1" | "reverse") => (false, true),
+            Some("no" | "false" | "0") => (true, true),
+            _ => (true, !implied_oneway),
+        };
+
+        Some(OsmWay {
+            nodes,
+            forward,
+            backward,
+        })
+    }
+}
+
+/**
+ * Add the cells of a straight segment between two cells to the road map, with
+ * the headings of the directions the vehicles drive in. The segment goes
+ * from cell to cell through their sides, the cells out of the road map are
+ * skipped.
+ */
+fn add_segment(
+    road_map: &mut RoadMap,
+    from: (i64, i64),
+    to: (i64, i64),
+    forward: bool,
+    backward: bool,
+) {
+    let (dx, dy) = ((to.0 - from.0).abs(), (to.1 - from.1).abs());
+    let (step_x, step_y) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    let (heading_x, heading_y) = (
+        if step_x > 0 {
+            Heading::East
+        } else {
+            Heading::West
+        },
+        if step_y > 0 {
+            Heading::South
+        } else {
+            Heading::North
+        },
+    );
+
+    let mut add_headings = |cell: (i64, i64), heading: Heading| {
+        if cell.0 < 0
+            || cell.1 < 0
+            || cell.0 >= road_map.get_width() as i64
+            || cell.1 >= road_map.get_height() as i64
+        {
+            return;
+        }
+
+        let coordinate = Coordinate {
+            x: cell.0 as u32,
+            y: cell.1 as u32,
+        };
+        if forward {
+            road_map.add_heading(coordinate, heading);
+        }
+        if backward {
+            road_map.add_heading(coordinate, heading.opposite());
+        }
+    };
+
+    // Step along the axis that keeps the cells closest to the segment
+    let (mut cell, mut moved_x, mut moved_y) = (from, 0, 0);
+    while moved_x < dx || moved_y < dy {
+        let next;
+        let heading;
+        if (1 + 2 * moved_x) * dy < (1 + 2 * moved_y) * dx {
+            next = (cell.0 + step_x, cell.1);
+            heading = heading_x;
+            moved_x += 1;
+        } else {
+            next = (cell.0, cell.1 + step_y);
+            heading = heading_y;
+            moved_y += 1;
+        }
+
+        add_headings(cell, heading);
+        add_headings(next, heading);
+        cell = next;
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // 0.0001 degree of latitude is a cell, the bounds are 11x11 cells
+    const CELL_SIZE: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0 * 0.0001;
+
+    const OSM_EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="test">
+  <bounds minlat="-0.0005" minlon="0" maxlat="0.0005" maxlon="0.001"/>
+  <node id="1" lat="0" lon="0"/>
+  <node id="2" lat="0" lon="0.001"/>
+  <node id="3" lat="0.0005" lon="0.0005"/>
+  <node id="4" lat="-0.0005" lon="0.0005"/>
+  <node id="5" lat="-0.0005" lon="0.0002"/>
+  <node id="6" lat="0" lon="0.0002"/>
+  <node id="7" lat="0.0003" lon="-0.0005"/>
+  <node id="8" lat="0.0003" lon="0.0002">
+    <tag k="highway" v="traffic_signals"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Main Street"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="primary"/>
+    <tag k="oneway" v="yes"/>
+  </way>
+  <way id="12">
+    <nd ref="1"/>
+    <nd ref="3"/>
+    <tag k="highway" v="footway"/>
+  </way>
+  <way id="13">
+    <nd ref="5"/>
+    <nd ref="6"/>
+    <tag k="highway" v="service"/>
+    <tag k="oneway" v="-1"/>
+  </way>
+  <way id="14">
+    <nd ref="7"/>
+    <nd ref="8"/>
+    <tag k="highway" v="motorway"/>
+  </way>
+  <way id="15">
+    <nd ref="2"/>
+    <nd ref="99"/>
+    <nd ref="4"/>
+    <tag k="highway" v="tertiary"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role=""/>
+    <tag k="type" v="route"/>
+  </relation>
+</osm>
+"#;
+
+    /**
+     * Test the rasterization of the drivable ways.
+     */
+    #[test]
+    fn test_parse() {
+        let mut params = OsmParams::new(PathBuf::from("extract.osm"));
+        params.cell_size = CELL_SIZE;
+        let road_map = params.parse(OSM_EXTRACT).unwrap();
+        assert_eq!((road_map.get_width(), road_map.get_height()), (11, 11));
+
+        let headings = |x, y| road_map.get_headings(Coordinate { x, y }).to_vec();
+
+        // the two-way street goes both ways, the one-way ones keep their direction
+        assert_eq!(headings(3, 5), vec![Heading::East, Heading::West]);
+        assert_eq!(headings(5, 3), vec![Heading::South]);
+        assert_eq!(
+            headings(5, 5),
+            vec![Heading::South, Heading::East, Heading::West]
+        );
+        assert_eq!(headings(2, 7), vec![Heading::South]);
+
+        // the motorway is one-way, and starts at the border of the grid
+        assert_eq!(headings(0, 2), vec![Heading::East]);
+        assert_eq!(headings(2, 2), vec![Heading::East]);
+        assert!(!road_map.is_road(Coordinate { x: 3, y: 2 }));
+
+        // the footway and the way with a missing node are not drawn
+        assert!(!road_map.is_road(Coordinate { x: 1, y: 4 }));
+        assert!(!road_map.is_road(Coordinate { x: 8, y: 8 }));
+    }
+
+    /**
+     * Test the rasterization of a diagonal way.
+     */
+    #[test]
+    fn test_diagonal_way() {
+        let mut road_map = RoadMap::new(4, 4);
+        add_segment(&mut road_map, (0, 0), (3, 2), true, false);
+
+        // the cells of the segment share a side, the turns have both headings
+        assert_eq!(
+            road_map.to_ascii(),
+            "..##\n\
+             #..#\n\
+             ##..\n\
+             ####\n"
+        );
+        let headings = |x, y| road_map.get_headings(Coordinate { x, y }).to_vec();
+        assert_eq!(headings(0, 0), vec![Heading::East]);
+        assert_eq!(headings(1, 0), vec![Heading::South, Heading::East]);
+        assert_eq!(headings(3, 2), vec![Heading::East]);
+    }
+
+    /**
+     * Test the errors of the extracts.
+     */
+    #[test]
+    fn test_invalid_extracts() {
+        let params = OsmParams::new(PathBuf::from("extract.osm"));
+        assert!(params.parse("<osm></osm>").is_err());
+        assert!(params
+            .parse(r#"<osm><node id="x" lat="0" lon="0"/></osm>"#)
+            .is_err());
+        assert!(params
+            .parse(r#"<osm><bounds minlat="0" minlon="0" maxlat="90" maxlon="90"/></osm>"#)
+            .is_err());
+
+        let mut params = OsmParams::new(PathBuf::from("extract.osm"));
+        params.cell_size = 0.0;
+        assert!(params.validate().is_err());
+    }
+}
@@ -0,0 +1,892 @@
+/**
+ * PROPAGATION
+ *
+ * Radio propagation models of the Ether, so the delivery of a message depends
+ * on the power it is received with instead of a perfect disc around its
+ * transmitter. A model gives the power received at a distance from the
+ * transmit power, with a mean path loss and a random gain for the shadowing
+ * or the fading, and a message is received when its power reaches the
+ * sensitivity of the receiver. The unit disc keeps the nominal ranges of the
+ * nodes. The links also lose power for each building cell, a cell that is not
+ * a street, on their line of sight, so a node around a corner is not heard.
+ * The messages lost by the propagation sit next to the TX failures, and are
+ * the same for the normal and the faulty OBUs.
+ */
+use crate::grid::{Coordinate, Grid};
+use crate::scenario::ScenarioError;
+use crate::speed::sample_standard_normal;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+// Speed of light, in meters per second
+const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+// Distance of the reference path loss, in meters
+const REFERENCE_DISTANCE: f64 = 1.0;
+
+/**
+ * PropagationKind represents the propagation models.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PropagationKind {
+    UnitDisc,    // Every receiver within the range of the transmitter, and none beyond
+    LogDistance, // Path loss growing with the log of the distance
+    Shadowing,   // Log-distance path loss with a log-normal shadowing
+    Rayleigh,    // Log-distance path loss with a Rayleigh fading
+    Nakagami,    // Log-distance path loss with a Nakagami-m fading
+    TwoRay,      // Free space path loss, then the two-ray ground reflection one
+}
+
+/**
+ * PropagationParams represents the propagation model of the messages and the
+ * radios of the nodes.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PropagationParams {
+    pub model: PropagationKind,
+    #[serde(default = "PropagationParams::default_tx_power")]
+    pub tx_power: f64, // Transmit power of the nodes, in dBm
+    #[serde(default = "PropagationParams::default_sensitivity")]
+    pub sensitivity: f64, // Lowest power a message is received with, in dBm
+    #[serde(default = "PropagationParams::default_cell_size")]
+    pub cell_size: f64, // Length of a cell, in meters
+    #[serde(default = "PropagationParams::default_frequency")]
+    pub frequency: f64, // Carrier frequency, in GHz
+    #[serde(default)]
+    pub path_loss_exponent: Option<f64>, // Log-distance models: path loss exponent, 3 if unset
+    #[serde(default)]
+    pub std_dev: Option<f64>, // Shadowing: standard deviation of the shadowing, in dB
+    #[serde(default)]
+    pub nakagami_m: Option<f64>, // Nakagami: shape factor of the fading
+    #[serde(default)]
+    pub antenna_height: Option<f64>, // Two-ray: height of the antennas in meters, 1.5 if unset
+    #[serde(default)]
+    pub building_loss: Option<f64>, // Loss of a link for each building cell it crosses in dB, none if unset
+}
+
+/**
+ * PropagationModel gives the power a message is received with.
+ */
+pub trait PropagationModel: Send {
+    /**
+     * Return the power in dBm received at a distance from a transmitter, in
+     * meters, given its transmit power in dBm and its nominal range in
+     * meters. The power is random for the models with shadowing or fading.
+     */
+    fn received_power(&self, tx_power: f64, distance: f64, range: f64, rng: &mut StdRng) -> f64;
+}
+
+/**
+ * UnitDisc receives the messages at full power within the range of their
+ * transmitter, and not at all beyond.
+ */
+pub struct UnitDisc;
+
+/**
+ * LogDistance loses the free space reference loss at 1 meter, then 10 times
+ * the exponent dB for every tenfold distance.
+ */
+pub struct LogDistance {
+    reference_loss: f64, // Path loss at the reference distance, in dB
+    exponent: f64,
+}
+
+/**
+ * Shadowing adds a normal gain in dB to the log-distance path loss.
+ */
+pub struct Shadowing {
+    log_distance: LogDistance,
+    std_dev: f64, // Standard deviation of the gain, in dB
+}
+
+/**
+ * Rayleigh multiplies the power of the log-distance path loss by an
+ * exponential gain, the fading without a line of sight.
+ */
+pub struct Rayleigh {
+    log_distance: LogDistance,
+}
+
+/**
+ * Nakagami multiplies the power of the log-distance path loss by a gamma
+ * gain, from a Rayleigh fading with m = 1 to a weaker one as m grows.
+ */
+pub struct Nakagami {
+    log_distance: LogDistance,
+    m: f64, // Shape factor of the fading, at least 0.5
+}
+
+/**
+ * TwoRay loses the free space path loss up to the crossover distance, then
+ * the loss of the direct ray combined with the one reflected by the ground,
+ * growing with the fourth power of the distance.
+ */
+pub struct TwoRay {
+    wavelength: f64,     // Wavelength of the carrier, in meters
+    antenna_height: f64, // Height of the antennas of the transmitter and the receiver, in meters
+}
+
+/**
+ * Propagation decides which receivers get each message, with a propagation
+ * model and the radios of the nodes.
+ */
+pub struct Propagation {
+    model: Box<dyn PropagationModel>,
+    tx_power: f64,        // Transmit power of the nodes, in dBm
+    sensitivity: f64,     // Lowest power a message is received with, in dBm
+    cell_size: f64,       // Length of a cell, in meters
+    building_loss: f64,   // Loss of a link for each building cell it crosses, in dB
+    buildings: Vec<bool>, // Building cells of the grid by x * height + y, empty without a building loss
+    height: u32,          // Number of cells along the y axis of the grid
+    rng: StdRng,          // Random number generator of the shadowing and the fading
+}
+
+/**
+ * PropagationKind implementation.
+ */
+impl PropagationKind {
+    /**
+     * Return the name of the model, as in the scenario.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropagationKind::UnitDisc => "unit_disc",
+            PropagationKind::LogDistance => "log_distance",
+            PropagationKind::Shadowing => "shadowing",
+            PropagationKind::Rayleigh => "rayleigh",
+            PropagationKind::Nakagami => "nakagami",
+            PropagationKind::TwoRay => "two_ray",
+        }
+    }
+
+    /**
+     * Return true if the model has a log-distance path loss.
+     */
+    fn is_log_distance(&self) -> bool {
+        matches!(
+            self,
+            PropagationKind::LogDistance
+                | PropagationKind::Shadowing
+                | PropagationKind::Rayleigh
+                | PropagationKind::Nakagami
+        )
+    }
+}
+
+/**
+ * PropagationParams implementation.
+ */
+impl PropagationParams {
+    /**
+     * By default the nodes transmit at 10 dBm.
+     */
+    fn default_tx_power() -> f64 {
+        10.0
+    }
+
+    /**
+     * By default the messages are received down to -82 dBm.
+     */
+    fn default_sensitivity() -> f64 {
+        -82.0
+    }
+
+    /**
+     * By default a cell is 7.5 meters long, the space taken by a car.
+     */
+    fn default_cell_size() -> f64 {
+        7.5
+    }
+
+    /**
+     * By default the carrier is the 5.9 GHz of the vehicular networks.
+     */
+    fn default_frequency() -> f64 {
+        5.9
+    }
+
+    /**
+     * Check that the model is fully described and that its values are
+     * physical.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        // Each model only uses its own fields
+        let fields = [
+            (
+                "path_loss_exponent",
+                self.model.is_log_distance(),
+                self.path_loss_exponent.is_some(),
+            ),
+            (
+                "std_dev",
+                self.model == PropagationKind::Shadowing,
+                self.std_dev.is_some(),
+            ),
+            (
+                "nakagami_m",
+                self.model == PropagationKind::Nakagami,
+                self.nakagami_m.is_some(),
+            ),
+            (
+                "antenna_height",
+                self.model == PropagationKind::TwoRay,
+                self.antenna_height.is_some(),
+            ),
+        ];
+        for (name, is_used, is_set) in fields {
+            if is_set && !is_used {
+                return Err(ScenarioError::Invalid(format!(
+                    "propagation.{} is not used by the {} model",
+                    name,
+                    self.model.name()
+                )));
+            }
+        }
+
+        let required = [
+            ("std_dev", PropagationKind::Shadowing, self.std_dev),
+            ("nakagami_m", PropagationKind::Nakagami, self.nakagami_m),
+        ];
+        for (name, model, value) in required {
+            if model == self.model && value.is_none() {
+                return Err(ScenarioError::Invalid(format!(
+                    "propagation.{} must be set for the {} model",
+                    name,
+                    self.model.name()
+                )));
+            }
+        }
+
+        if !(self.tx_power.is_finite() && self.sensitivity.is_finite()) {
+            return Err(ScenarioError::Invalid(
+                "propagation.tx_power and propagation.sensitivity must be finite numbers"
+                    .to_string(),
+            ));
+        }
+
+        // The lengths, the frequency and the exponent are positive
+        let values = [
+            ("cell_size", Some(self.cell_size)),
+            ("frequency", Some(self.frequency)),
+            ("path_loss_exponent", self.path_loss_exponent),
+            ("antenna_height", self.antenna_height),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.filter(|value| !(value.is_finite() && *value > 0.0)) {
+                return Err(ScenarioError::Invalid(format!(
+                    "propagation.{} must be a number greater than 0, got {}",
+                    name, value
+                )));
+            }
+        }
+
+        if let Some(std_dev) = self
+            .std_dev
+            .filter(|std_dev| !(std_dev.is_finite() && *std_dev >= 0.0))
+        {
+            return Err(ScenarioError::Invalid(format!(
+                "propagation.std_dev must be a number not less than 0, got {}",
+                std_dev
+            )));
+        }
+
+        // An infinite building loss blocks the links through the buildings
+        if let Some(building_loss) = self
+            .building_loss
+            .filter(|building_loss| building_loss.is_nan() || *building_loss < 0.0)
+        {
+            return Err(ScenarioError::Invalid(format!(
+                "propagation.building_loss must be a number not less than 0, got {}",
+                building_loss
+            )));
+        }
+
+        if let Some(m) = self.nakagami_m.filter(|m| !(m.is_finite() && *m >= 0.5)) {
+            return Err(ScenarioError::Invalid(format!(
+                "propagation.nakagami_m must be a number not less than 0.5, got {}",
+                m
+            )));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Create the propagation model of the params, which must be valid.
+     */
+    pub fn create_model(&self) -> Box<dyn PropagationModel> {
+        let log_distance = LogDistance::new(self.frequency, self.path_loss_exponent.unwrap_or(3.0));
+        match self.model {
+            PropagationKind::UnitDisc => Box::new(UnitDisc),
+            PropagationKind::LogDistance => Box::new(log_distance),
+            PropagationKind::Shadowing => Box::new(Shadowing {
+                log_distance,
+                std_dev: self.std_dev.unwrap_or(0.0),
+            }),
+            PropagationKind::Rayleigh => Box::new(Rayleigh { log_distance }),
+            PropagationKind::Nakagami => Box::new(Nakagami {
+                log_distance,
+                m: self.nakagami_m.unwrap_or(1.0),
+            }),
+            PropagationKind::TwoRay => Box::new(TwoRay {
+                wavelength: wavelength(self.frequency),
+                antenna_height: self.antenna_height.unwrap_or(1.5),
+            }),
+        }
+    }
+}
+
+/**
+ * PropagationModel implementation of the unit disc.
+ */
+impl PropagationModel for UnitDisc {
+    fn received_power(&self, tx_power: f64, distance: f64, range: f64, _rng: &mut StdRng) -> f64 {
+        if distance <= range {
+            tx_power
+        } else {
+            f64::NEG_INFINITY
+        }
+    }
+}
+
+/**
+ * LogDistance implementation.
+ */
+impl LogDistance {
+    /**
+     * Create the log-distance path loss of a carrier frequency in GHz.
+     */
+    fn new(frequency: f64, exponent: f64) -> LogDistance {
+        LogDistance {
+            reference_loss: free_space_loss(wavelength(frequency), REFERENCE_DISTANCE),
+            exponent,
+        }
+    }
+
+    /**
+     * Return the path loss in dB over a distance in meters, the reference
+     * loss under the reference distance.
+     */
+    fn path_loss(&self, distance: f64) -> f64 {
+        self.reference_loss
+            + 10.0 * self.exponent * (distance.max(REFERENCE_DISTANCE) / REFERENCE_DISTANCE).log10()
+    }
+}
+
+/**
+ * PropagationModel implementation of the log-distance path loss.
+ */
+impl PropagationModel for LogDistance {
+    fn received_power(&self, tx_power: f64, distance: f64, _range: f64, _rng: &mut StdRng) -> f64 {
+        tx_power - self.path_loss(distance)
+    }
+}
+
+/**
+ * PropagationModel implementation of the log-normal shadowing.
+ */
+impl PropagationModel for Shadowing {
+    fn received_power(&self, tx_power: f64, distance: f64, _range: f64, rng: &mut StdRng) -> f64 {
+        tx_power - self.log_distance.path_loss(distance)
+            + self.std_dev * sample_standard_normal(rng)
+    }
+}
+
+/**
+ * PropagationModel implementation of the Rayleigh fading.
+ */
+impl PropagationModel for Rayleigh {
+    fn received_power(&self, tx_power: f64, distance: f64, _range: f64, rng: &mut StdRng) -> f64 {
+        // the power gain is exponential with a mean of 1
+        let gain = -(1.0 - rng.gen::<f64>()).ln();
+        tx_power - self.log_distance.path_loss(distance) + 10.0 * gain.log10()
+    }
+}
+
+/**
+ * PropagationModel implementation of the Nakagami-m fading.
+ */
+impl PropagationModel for Nakagami {
+    fn received_power(&self, tx_power: f64, distance: f64, _range: f64, rng: &mut StdRng) -> f64 {
+        // the power gain is gamma distributed with a shape m and a mean of 1
+        let gain = sample_gamma(rng, self.m) / self.m;
+        tx_power - self.log_distance.path_loss(distance) + 10.0 * gain.log10()
+    }
+}
+
+/**
+ * PropagationModel implementation of the two-ray ground reflection.
+ */
+impl PropagationModel for TwoRay {
+    fn received_power(&self, tx_power: f64, distance: f64, _range: f64, _rng: &mut StdRng) -> f64 {
+        let distance = distance.max(REFERENCE_DISTANCE);
+        let crossover = 4.0 * PI * self.antenna_height * self.antenna_height / self.wavelength;
+        let path_loss = if distance < crossover {
+            free_space_loss(self.wavelength, distance)
+        } else {
+            40.0 * distance.log10() - 40.0 * self.antenna_height.log10()
+        };
+        tx_power - path_loss
+    }
+}
+
+/**
+ * Propagation implementation.
+ */
+impl Propagation {
+    /**
+     * Create the propagation of the given params, which must be valid, over
+     * the buildings of a grid.
+     */
+    pub fn new(params: &PropagationParams, grid: &Grid, rng: StdRng) -> Propagation {
+        // the buildings are the cells that are not streets
+        let mut buildings = Vec::new();
+        if params
+            .building_loss
+            .is_some_and(|building_loss| building_loss > 0.0)
+        {
+            for x in 0..grid.get_width() {
+                for y in 0..grid.get_height() {
+                    buildings.push(!grid.is_street(Coordinate { x, y }));
+                }
+            }
+        }
+
+        Propagation {
+            model: params.create_model(),
+            tx_power: params.tx_power,
+            sensitivity: params.sensitivity,
+            cell_size: params.cell_size,
+            building_loss: params.building_loss.unwrap_or(0.0),
+            buildings,
+            height: grid.get_height(),
+            rng,
+        }
+    }
+
+    /**
+     * Get the lowest power a message is received with, in dBm.
+     */
+    pub fn get_sensitivity(&self) -> f64 {
+        self.sensitivity
+    }
+
+    /**
+     * Return the power in dBm a receiver gets a message with, from a
+     * transmitter with the given nominal range in cells. The link loses the
+     * building loss for each building cell on its line of sight.
+     */
+    pub fn get_received_power(
+        &mut self,
+        transmitter_coordinate: Coordinate,
+        transmitter_range: u32,
+        receiver_coordinate: Coordinate,
+    ) -> f64 {
+        let distance = ((transmitter_coordinate.x as f64 - receiver_coordinate.x as f64).powi(2)
+            + (transmitter_coordinate.y as f64 - receiver_coordinate.y as f64).powi(2))
+        .sqrt();
+
+        let power = self.model.received_power(
+            self.tx_power,
+            distance * self.cell_size,
+            transmitter_range as f64 * self.cell_size,
+            &mut self.rng,
+        );
+        if self.buildings.is_empty() || power == f64::NEG_INFINITY {
+            return power;
+        }
+
+        let buildings = self.count_buildings(transmitter_coordinate, receiver_coordinate);
+        if buildings == 0 {
+            power
+        } else {
+            power - self.building_loss * buildings as f64
+        }
+    }
+
+    /**
+     * Count the building cells on the line of sight between two cells, the
+     * cells of the line drawn between their centers with the Bresenham
+     * algorithm, the two cells excluded.
+     */
+    pub fn count_buildings(&self, from: Coordinate, to: Coordinate) -> u32 {
+        if self.buildings.is_empty() || from == to {
+            return 0;
+        }
+
+        let (x1, y1) = (to.x as i64, to.y as i64);
+        let (dx, dy) = ((x1 - from.x as i64).abs(), -(y1 - from.y as i64).abs());
+        let (sx, sy) = (
+            if (from.x as i64) < x1 { 1 } else { -1 },
+            if (from.y as i64) < y1 { 1 } else { -1 },
+        );
+        let (mut x, mut y) = (from.x as i64, from.y as i64);
+        let mut error = dx + dy;
+        let mut buildings = 0;
+
+        loop {
+            // step to the next cell of the line
+            let double_error = 2 * error;
+            if double_error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if double_error <= dx {
+                error += dx;
+                y += sy;
+            }
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            if self.buildings[(x as u32 * self.height + y as u32) as usize] {
+                buildings += 1;
+            }
+        }
+
+        buildings
+    }
+
+    /**
+     * Check if a receiver gets a message: its received power reaches the
+     * sensitivity.
+     */
+    pub fn is_received(
+        &mut self,
+        transmitter_coordinate: Coordinate,
+        transmitter_range: u32,
+        receiver_coordinate: Coordinate,
+    ) -> bool {
+        self.get_received_power(
+            transmitter_coordinate,
+            transmitter_range,
+            receiver_coordinate,
+        ) >= self.sensitivity
+    }
+}
+
+impl Default for Propagation {
+    /**
+     * The unit disc, which keeps the nominal ranges of the nodes.
+     */
+    fn default() -> Self {
+        Propagation {
+            model: Box::new(UnitDisc),
+            tx_power: PropagationParams::default_tx_power(),
+            sensitivity: PropagationParams::default_sensitivity(),
+            cell_size: 1.0,
+            building_loss: 0.0,
+            buildings: Vec::new(),
+            height: 0,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+/**
+ * Return the wavelength in meters of a carrier frequency in GHz.
+ */
+fn wavelength(frequency: f64) -> f64 {
+    SPEED_OF_LIGHT / (frequency * 1e9)
+}
+
+/**
+ * Return the free space path loss in dB over a distance in meters.
+ */
+fn free_space_loss(wavelength: f64, distance: f64) -> f64 {
+    20.0 * (4.0 * PI * distance / wavelength).log10()
+}
+
+/**
+ * Draw a number of the gamma distribution with the given shape and a scale
+ * of 1, with the Marsaglia-Tsang method. Shapes under 1 are boosted by 1 and
+ * scaled back.
+ */
+fn sample_gamma(rng: &mut StdRng, shape: f64) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        return sample_gamma(rng, shape + 1.0) * u.powf(1.0 / shape);
+    }
+
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_standard_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::comms::Ether;
+    use crate::grid::GridParams;
+
+    /**
+     * Return the params of a model with the default radios.
+     */
+    fn propagation_params(model: PropagationKind) -> PropagationParams {
+        PropagationParams {
+            model,
+            tx_power: PropagationParams::default_tx_power(),
+            sensitivity: PropagationParams::default_sensitivity(),
+            cell_size: PropagationParams::default_cell_size(),
+            frequency: PropagationParams::default_frequency(),
+            path_loss_exponent: None,
+            std_dev: None,
+            nakagami_m: None,
+            antenna_height: None,
+            building_loss: None,
+        }
+    }
+
+    /**
+     * Return a grid of 2x2 blocks of 2 cells.
+     */
+    fn grid() -> Grid {
+        Grid::new(GridParams {
+            blocks_per_street: 2,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        })
+    }
+
+    /**
+     * Return the share of the messages received at a distance in cells, out
+     * of the given number of messages.
+     */
+    fn received_share(propagation: &mut Propagation, distance: u32, messages: u32) -> f64 {
+        let received = (0..messages)
+            .filter(|_| {
+                propagation.is_received(
+                    Coordinate { x: 0, y: 0 },
+                    4,
+                    Coordinate { x: distance, y: 0 },
+                )
+            })
+            .count();
+        received as f64 / messages as f64
+    }
+
+    /**
+     * Test the validation of the models.
+     */
+    #[test]
+    fn test_validate() {
+        let mut params = propagation_params(PropagationKind::UnitDisc);
+        assert!(params.validate().is_ok());
+        params.path_loss_exponent = Some(2.0);
+        assert!(params.validate().is_err());
+
+        let mut params = propagation_params(PropagationKind::LogDistance);
+        params.path_loss_exponent = Some(2.0);
+        assert!(params.validate().is_ok());
+        params.path_loss_exponent = Some(0.0);
+        assert!(params.validate().is_err());
+        params.path_loss_exponent = None;
+        params.cell_size = -1.0;
+        assert!(params.validate().is_err());
+        params.cell_size = 5.0;
+        params.sensitivity = f64::NAN;
+        assert!(params.validate().is_err());
+
+        let mut params = propagation_params(PropagationKind::Shadowing);
+        assert!(params.validate().is_err());
+        params.std_dev = Some(-2.0);
+        assert!(params.validate().is_err());
+        params.std_dev = Some(4.0);
+        assert!(params.validate().is_ok());
+
+        let mut params = propagation_params(PropagationKind::Nakagami);
+        params.nakagami_m = Some(0.3);
+        assert!(params.validate().is_err());
+        params.nakagami_m = Some(1.5);
+        assert!(params.validate().is_ok());
+        params.antenna_height = Some(1.5);
+        assert!(params.validate().is_err());
+
+        // the buildings can't amplify the messages
+        let mut params = propagation_params(PropagationKind::LogDistance);
+        params.building_loss = Some(10.0);
+        assert!(params.validate().is_ok());
+        params.building_loss = Some(-1.0);
+        assert!(params.validate().is_err());
+    }
+
+    /**
+     * Test the deterministic models: the unit disc keeps the ranges of the
+     * nodes, the path losses give a range of their own.
+     */
+    #[test]
+    fn test_path_loss() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // the unit disc is the range check of the ether
+        let mut propagation = Propagation::default();
+        for x in 0..8 {
+            for y in 0..8 {
+                let receiver = Coordinate { x, y };
+                assert_eq!(
+                    propagation.is_received(Coordinate { x: 2, y: 1 }, 4, receiver),
+                    Ether::is_transmission_possible(Coordinate { x: 2, y: 1 }, 4, receiver)
+                );
+            }
+        }
+
+        // the free space loss at 1 meter of the 5.9 GHz carrier
+        let log_distance = LogDistance::new(5.9, 2.0);
+        assert!((log_distance.path_loss(1.0) - 47.86).abs() < 0.01);
+        assert!((log_distance.path_loss(100.0) - 87.86).abs() < 0.01);
+
+        // 10 dBm received down to -82 dBm: 92 dB over 29.7 meters, under 4 cells
+        let mut propagation = Propagation::new(
+            &propagation_params(PropagationKind::LogDistance),
+            &grid(),
+            StdRng::seed_from_u64(1),
+        );
+        assert_eq!(received_share(&mut propagation, 3, 10), 1.0);
+        assert_eq!(received_share(&mut propagation, 4, 10), 0.0);
+
+        // the two-ray ground loses 40 dB per decade beyond the crossover
+        let two_ray = TwoRay {
+            wavelength: wavelength(5.9),
+            antenna_height: 1.5,
+        };
+        let crossover = 4.0 * PI * 1.5 * 1.5 / wavelength(5.9);
+        let mut power = |distance| two_ray.received_power(0.0, distance, 0.0, &mut rng);
+        assert!((power(10.0) - power(100.0) - 20.0).abs() < 1e-9);
+        assert!((power(2.0 * crossover) - power(20.0 * crossover) - 40.0).abs() < 1e-9);
+        assert!((power(crossover * 0.999) - power(crossover)).abs() < 0.1);
+    }
+
+    /**
+     * Test the random models: the messages are lost at random around the
+     * range of the path loss, with a mean gain of 1.
+     */
+    #[test]
+    fn test_fading() {
+        let mut params = propagation_params(PropagationKind::Shadowing);
+        params.std_dev = Some(6.0);
+        let mut propagation = Propagation::new(&params, &grid(), StdRng::seed_from_u64(2));
+
+        // the messages are received at random around the range of the path loss
+        let share = received_share(&mut propagation, 3, 2000);
+        assert!(share > 0.6 && share < 0.85, "share {}", share);
+        let share = received_share(&mut propagation, 8, 2000);
+        assert!(share > 0.02 && share < 0.15, "share {}", share);
+
+        // the fading gains have a mean of 1, and vary less as m grows
+        let mut rng = StdRng::seed_from_u64(3);
+        let log_distance = || LogDistance::new(5.9, 3.0);
+        let models: [(Box<dyn PropagationModel>, f64); 3] = [
+            (
+                Box::new(Rayleigh {
+                    log_distance: log_distance(),
+                }),
+                1.0,
+            ),
+            (
+                Box::new(Nakagami {
+                    log_distance: log_distance(),
+                    m: 0.5,
+                }),
+                2.0,
+            ),
+            (
+                Box::new(Nakagami {
+                    log_distance: log_distance(),
+                    m: 4.0,
+                }),
+                0.25,
+            ),
+        ];
+        let mean_power = -log_distance().path_loss(50.0);
+        for (model, variance) in models {
+            let gains: Vec<f64> = (0..20000)
+                .map(|_| {
+                    10f64.powf((model.received_power(0.0, 50.0, 0.0, &mut rng) - mean_power) / 10.0)
+                })
+                .collect();
+            let mean = gains.iter().sum::<f64>() / gains.len() as f64;
+            let sample_variance =
+                gains.iter().map(|gain| (gain - mean).powi(2)).sum::<f64>() / gains.len() as f64;
+            assert!((mean - 1.0).abs() < 0.05, "mean {}", mean);
+            assert!(
+                (sample_variance - variance).abs() < variance * 0.15,
+                "variance {}",
+                sample_variance
+            );
+        }
+    }
+
+    /**
+     * Test the links crossing buildings, attenuated for each building cell
+     * on their line of sight.
+     */
+    #[test]
+    fn test_buildings() {
+        let mut params = propagation_params(PropagationKind::UnitDisc);
+        params.building_loss = Some(40.0);
+        let mut propagation = Propagation::new(&params, &grid(), StdRng::seed_from_u64(1));
+        let corner = Coordinate { x: 0, y: 0 };
+
+        // the streets are in line of sight, the blocks are in between
+        assert_eq!(
+            propagation.count_buildings(corner, Coordinate { x: 6, y: 0 }),
+            0
+        );
+        assert_eq!(
+            propagation.count_buildings(corner, Coordinate { x: 0, y: 3 }),
+            0
+        );
+        assert_eq!(
+            propagation.count_buildings(corner, Coordinate { x: 3, y: 3 }),
+            2
+        );
+        assert_eq!(
+            propagation.count_buildings(Coordinate { x: 3, y: 3 }, corner),
+            2
+        );
+        assert_eq!(
+            propagation.count_buildings(corner, Coordinate { x: 6, y: 3 }),
+            3
+        );
+        assert_eq!(propagation.count_buildings(corner, corner), 0);
+
+        // 2 building cells lose 80 dB, within the 92 dB between the transmit
+        // power and the sensitivity, 3 building cells lose too much
+        assert!(propagation.is_received(corner, 8, Coordinate { x: 3, y: 3 }));
+        assert!(!propagation.is_received(corner, 8, Coordinate { x: 6, y: 3 }));
+        assert!(propagation.is_received(corner, 8, Coordinate { x: 6, y: 0 }));
+
+        // an infinite loss blocks every link through a building
+        params.building_loss = Some(f64::INFINITY);
+        assert!(params.validate().is_ok());
+        let mut propagation = Propagation::new(&params, &grid(), StdRng::seed_from_u64(1));
+        assert!(!propagation.is_received(corner, 8, Coordinate { x: 3, y: 3 }));
+        assert!(propagation.is_received(corner, 8, Coordinate { x: 3, y: 0 }));
+        params.building_loss = Some(-1.0);
+        assert!(params.validate().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+/**
+ * REPORT
+ *
+ * The report gathers the results of a simulation run, so they can be checked
+ * and post-processed by the code using the simulator.
+ */
+use crate::grid::GridStats;
+use crate::mac::MacStats;
+use crate::metrics::RoundMetrics;
+use crate::obu_manager::ObuManagerStats;
+use crate::rsu_manager::DetectorVerdict;
+use serde::Serialize;
+
+/**
+ * ConfusionMatrix represents the RSUs predictions against the real state of
+ * the OBUs. Faulty OBUs are the positives.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ConfusionMatrix {
+    pub true_positive: u32,
+    pub false_positive: u32,
+    pub true_negative: u32,
+    pub false_negative: u32,
+}
+
+/**
+ * DetectionRates represents the rates derived from the confusion matrix.
+ * Rates without samples are NaN, serialized as null.
+ */
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct DetectionRates {
+    pub detection_rate: f32,
+    pub false_positive_rate: f32,
+    pub false_negative_rate: f32,
+}
+
+/**
+ * ObuVerdict represents the real state of an OBU and the detector verdict.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct ObuVerdict {
+    pub obu_id: u32,
+    pub is_faulty: bool,                   // Real state of the OBU
+    pub detected_faulty: bool,             // State predicted by the RSUs
+    pub detector: Option<DetectorVerdict>, // None if the RSUs never observed the OBU
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<MacStats>, // Channel access of the OBU, None without a MAC layer
+}
+
+/**
+ * SimulationReport represents the results of a simulation run.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub rounds: u32,
+    pub confusion_matrix: ConfusionMatrix,
+    pub rates: DetectionRates,
+    pub obu_tx_stats: ObuManagerStats,
+    pub grid_stats: GridStats,
+    pub verdicts: Vec<ObuVerdict>,
+    pub detector_skipped: bool, // The RSUs observed too few OBUs to run the detector
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub round_metrics: Vec<RoundMetrics>, // Empty unless the metrics are collected
+}
+
+/**
+ * ConfusionMatrix implementation.
+ */
+impl ConfusionMatrix {
+    /**
+     * Add a prediction to the matrix.
+     */
+    pub fn add(&mut self, is_faulty: bool, detected_faulty: bool) {
+        match (is_faulty, detected_faulty) {
+            (true, true) => self.true_positive += 1,
+            (true, false) => self.false_negative += 1,
+            (false, true) => self.false_positive += 1,
+            (false, false) => self.true_negative += 1,
+        }
+    }
+
+    /**
+     * Return the total number of predictions.
+     */
+    pub fn total(&self) -> u32 {
+        self.true_positive + self.true_negative + self.false_positive + self.false_negative
+    }
+
+    /**
+     * Calculate the detection rate, the false positive rate and the false
+     * negative rate.
+     */
+    pub fn rates(&self) -> DetectionRates {
+        // Calculate the totals
+        let total = self.total() as f32;
+        let total_negative = (self.false_positive + self.true_negative) as f32;
+        let total_positive = (self.false_negative + self.true_positive) as f32;
+
+        DetectionRates {
+            detection_rate: (self.true_positive + self.true_negative) as f32 / total,
+            false_positive_rate: self.false_positive as f32 / total_negative,
+            false_negative_rate: self.false_negative as f32 / total_positive,
+        }
+    }
+}
+
+/**
+ * SimulationReport implementation.
+ */
+impl SimulationReport {
+    /**
+     * Serialize the report to pretty printed JSON.
+     */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize report")
+    }
+
+    /**
+     * Print the final stats to the console.
+     */
+    pub fn print(&self) {
+        println!("--- FINAL STATS ---");
+        println!("True Positive: {}", self.confusion_matrix.true_positive);
+        println!("False Positive: {}", self.confusion_matrix.false_positive);
+        println!("True Negative: {}", self.confusion_matrix.true_negative);
+        println!("False Negative: {}", self.confusion_matrix.false_negative);
+        println!("Detection Rate: {}", self.rates.detection_rate);
+        println!("False Positive Rate: {}", self.rates.false_positive_rate);
+        println!("False Negative Rate: {}", self.rates.false_negative_rate);
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Test the confusion matrix and its rates.
+     */
+    #[test]
+    fn test_confusion_matrix() {
+        let mut confusion_matrix = ConfusionMatrix::default();
+
+        confusion_matrix.add(true, true);
+        confusion_matrix.add(true, false);
+        confusion_matrix.add(false, true);
+        confusion_matrix.add(false, false);
+        confusion_matrix.add(false, false);
+        confusion_matrix.add(false, false);
+
+        assert_eq!(confusion_matrix.true_positive, 1);
+        assert_eq!(confusion_matrix.false_negative, 1);
+        assert_eq!(confusion_matrix.false_positive, 1);
+        assert_eq!(confusion_matrix.true_negative, 3);
+        assert_eq!(confusion_matrix.total(), 6);
+
+        let rates = confusion_matrix.rates();
+        assert!((rates.detection_rate - 4.0 / 6.0).abs() < 0.0001);
+        assert!((rates.false_positive_rate - 0.25).abs() < 0.0001);
+        assert!((rates.false_negative_rate - 0.5).abs() < 0.0001);
+    }
+
+    /**
+     * Test the JSON serialization of a report.
+     */
+    #[test]
+    fn test_report_to_json() {
+        let confusion_matrix = ConfusionMatrix {
+            true_positive: 0,
+            false_positive: 0,
+            true_negative: 2,
+            false_negative: 0,
+        };
+
+        let report = SimulationReport {
+            seed: 1,
+            rounds: 10,
+            confusion_matrix,
+            rates: confusion_matrix.rates(),
+            obu_tx_stats: ObuManagerStats::default(),
+            grid_stats: GridStats {
+                width: 10,
+                height: 10,
+                street_cells: 64,
+                lane_cells: 64,
+            },
+            verdicts: vec![ObuVerdict {
+                obu_id: 0,
+                is_faulty: false,
+                detected_faulty: false,
+                detector: None,
+                mac: None,
+            }],
+            detector_skipped: false,
+            round_metrics: Vec::new(),
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["seed"], 1);
+        assert_eq!(json["confusion_matrix"]["true_negative"], 2);
+        assert_eq!(json["rates"]["detection_rate"], 1.0);
+
+        // no faulty obus, the false negative rate is undefined
+        assert!(json["rates"]["false_negative_rate"].is_null());
+
+        assert_eq!(json["grid_stats"]["street_cells"], 64);
+        assert_eq!(json["verdicts"][0]["obu_id"], 0);
+        assert!(json["verdicts"][0]["detector"].is_null());
+
+        // the metrics are omitted when not collected
+        assert!(json.get("round_metrics").is_none());
+    }
+}
@@ -0,0 +1,370 @@
+/**
+ * ROAD MAP
+ *
+ * The road map tells which cells of the grid are roads and which ones are
+ * buildings. It is built from rows and columns of blocks with one cell wide
+ * streets around them, or read from an ASCII map, and road segments can be
+ * removed from it. The grid derives its streets and lanes from the road map:
+ * the road cells next to each other are connected, unless the road map gives
+ * the headings of the lanes of a cell, as the imported road networks do.
+ */
+use crate::grid::{Coordinate, Heading};
+
+pub const ROAD: char = '.';
+pub const BUILDING: char = '#';
+
+/**
+ * RoadMap represents the roads and the buildings of a rectangular area.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoadMap {
+    width: u32,                  // Number of cells along the x axis
+    height: u32,                 // Number of cells along the y axis
+    roads: Vec<bool>,            // Is each cell a road? Indexed by x * height + y
+    headings: Vec<Vec<Heading>>, // Headings of the lanes of each cell, in heading order, from the streets if empty
+}
+
+/**
+ * RoadMap implementation.
+ */
+impl RoadMap {
+    /**
+     * Create a road map of the given size with only buildings.
+     */
+    pub fn new(width: u32, height: u32) -> RoadMap {
+        RoadMap {
+            width,
+            height,
+            roads: vec![false; (width * height) as usize],
+            headings: vec![Vec::new(); (width * height) as usize],
+        }
+    }
+
+    /**
+     * Create a road map of blocks with streets on all sides, given the width
+     * of each column of blocks from x = 0 and the height of each row of
+     * blocks from y = 0.
+     */
+    pub fn from_blocks(block_widths: &[u32], block_heights: &[u32]) -> RoadMap {
+        let street_ids = |block_sizes: &[u32]| {
+            let mut ids = vec![0];
+            for block_size in block_sizes {
+                ids.push(ids[ids.len() - 1] + block_size + 1);
+            }
+            ids
+        };
+        let columns = street_ids(block_widths);
+        let rows = street_ids(block_heights);
+
+        let mut road_map = RoadMap::new(columns[columns.len() - 1] + 1, rows[rows.len() - 1] + 1);
+        for x in 0..road_map.width {
+            for y in 0..road_map.height {
+                if columns.contains(&x) || rows.contains(&y) {
+                    road_map.set_road(Coordinate { x, y }, true);
+                }
+            }
+        }
+
+        road_map
+    }
+
+    /**
+     * Parse an ASCII map, one line per row from y = 0 and one character per
+     * cell from x = 0: '.' for a road and '#' for a building. All the rows
+     * must have the same length, the empty lines at the end are ignored.
+     */
+    pub fn parse(contents: &str) -> Result<RoadMap, String> {
+        let lines: Vec<&str> = contents
+            .trim_end()
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .collect();
+        let width = lines.first().map_or(0, |line| line.chars().count()) as u32;
+        if width == 0 {
+            return Err("the map is empty".to_string());
+        }
+
+        let mut road_map = RoadMap::new(width, lines.len() as u32);
+        for (y, line) in lines.iter().enumerate() {
+            if line.chars().count() as u32 != width {
+                return Err(format!(
+                    "line {} has {} cells instead of {}",
+                    y + 1,
+                    line.chars().count(),
+                    width
+                ));
+            }
+
+            for (x, cell) in line.chars().enumerate() {
+                let coordinate = Coordinate {
+                    x: x as u32,
+                    y: y as u32,
+                };
+                match cell {
+                    ROAD => road_map.set_road(coordinate, true),
+                    BUILDING => {}
+                    _ => {
+                        return Err(format!(
+                        "unknown cell '{}' at line {}, use '{}' for a road and '{}' for a building",
+                        cell,
+                        y + 1,
+                        ROAD,
+                        BUILDING
+                    ))
+                    }
+                }
+            }
+        }
+
+        Ok(road_map)
+    }
+
+    /**
+     * Get the width of the road map.
+     */
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    /**
+     * Get the height of the road map.
+     */
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+
+    /**
+     * Check if a cell is a road, the cells out of the map are not.
+     */
+    pub fn is_road(&self, coordinate: Coordinate) -> bool {
+        coordinate.x < self.width
+            && coordinate.y < self.height
+            && self.roads[(coordinate.x * self.height + coordinate.y) as usize]
+    }
+
+    /**
+     * Make a cell a road or a building. A building has no lanes.
+     */
+    pub fn set_road(&mut self, coordinate: Coordinate, is_road: bool) {
+        let index = self.get_index(coordinate);
+        self.roads[index] = is_road;
+        if !is_road {
+            self.headings[index].clear();
+        }
+    }
+
+    /**
+     * Add a lane heading to a cell, making it a road. The lanes of a cell
+     * with headings only come from its headings.
+     */
+    pub fn add_heading(&mut self, coordinate: Coordinate, heading: Heading) {
+        let index = self.get_index(coordinate);
+        self.roads[index] = true;
+        if let Err(position) = self.headings[index].binary_search(&heading) {
+            self.headings[index].insert(position, heading);
+        }
+    }
+
+    /**
+     * Get the lane headings given for a cell, in heading order. They are
+     * empty if the lanes come from the streets of the cell.
+     */
+    pub fn get_headings(&self, coordinate: Coordinate) -> &[Heading] {
+        &self.headings[self.get_index(coordinate)]
+    }
+
+    /**
+     * Get the index of a cell in the cell vectors.
+     */
+    fn get_index(&self, coordinate: Coordinate) -> usize {
+        assert!(
+            coordinate.x < self.width && coordinate.y < self.height,
+            "Coordinate out of the road map."
+        );
+        (coordinate.x * self.height + coordinate.y) as usize
+    }
+
+    /**
+     * Check if a road cell is part of a column street, a road along the y
+     * axis: the cell above or below it is a road too.
+     */
+    pub fn has_column_street(&self, coordinate: Coordinate) -> bool {
+        let Coordinate { x, y } = coordinate;
+        self.is_road(coordinate)
+            && ((y > 0 && self.is_road(Coordinate { x, y: y - 1 }))
+                || self.is_road(Coordinate { x, y: y + 1 }))
+    }
+
+    /**
+     * Check if a road cell is part of a row street, a road along the x axis:
+     * the cell on its left or right is a road too.
+     */
+    pub fn has_row_street(&self, coordinate: Coordinate) -> bool {
+        let Coordinate { x, y } = coordinate;
+        self.is_road(coordinate)
+            && ((x > 0 && self.is_road(Coordinate { x: x - 1, y }))
+                || self.is_road(Coordinate { x: x + 1, y }))
+    }
+
+    /**
+     * Remove the street segment of a road cell, the cells of its street
+     * between the intersections around it. The intersections are kept, so
+     * the streets crossing there may end in a dead end.
+     */
+    pub fn remove_segment(&mut self, coordinate: Coordinate) -> Result<(), String> {
+        let is_column = self.has_column_street(coordinate);
+        if is_column == self.has_row_street(coordinate) {
+            return Err(format!(
+                "({}, {}) is not a street cell between two intersections",
+                coordinate.x, coordinate.y
+            ));
+        }
+
+        // A cell of the segment is a road that doesn't cross another street
+        let in_segment = |road_map: &RoadMap, cell: Coordinate| {
+            road_map.is_road(cell)
+                && if is_column {
+                    !road_map.has_row_street(cell)
+                } else {
+                    !road_map.has_column_street(cell)
+                }
+        };
+        let step = |cell: Coordinate, forward: bool| {
+            let (value, other) = if is_column {
+                (cell.y, cell.x)
+            } else {
+                (cell.x, cell.y)
+            };
+            let value = if forward {
+                value + 1
+            } else {
+                value.checked_sub(1)?
+            };
+            Some(if is_column {
+                Coordinate { x: other, y: value }
+            } else {
+                Coordinate { x: value, y: other }
+            })
+        };
+
+        // Find the segment in both directions before removing it
+        let mut segment = vec![coordinate];
+        for forward in [false, true] {
+            let mut cell = coordinate;
+            while let Some(next) = step(cell, forward).filter(|next| in_segment(self, *next)) {
+                segment.push(next);
+                cell = next;
+            }
+        }
+
+        for cell in segment {
+            self.set_road(cell, false);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Return the ASCII map of the road map, as read by RoadMap::parse.
+     */
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_road(Coordinate { x, y }) {
+                    ascii.push(ROAD);
+                } else {
+                    ascii.push(BUILDING);
+                }
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /**
+     * Test the road map of blocks of different sizes.
+     */
+    #[test]
+    fn test_from_blocks() {
+        let road_map = RoadMap::from_blocks(&[1, 3], &[2]);
+        assert_eq!(road_map.get_width(), 7);
+        assert_eq!(road_map.get_height(), 4);
+        assert_eq!(
+            road_map.to_ascii(),
+            ".......\n\
+             .#.###.\n\
+             .#.###.\n\
+             .......\n"
+        );
+
+        // the street cells between two intersections are in a single street
+        assert!(road_map.has_column_street(Coordinate { x: 2, y: 1 }));
+        assert!(!road_map.has_row_street(Coordinate { x: 2, y: 1 }));
+        assert!(road_map.has_column_street(Coordinate { x: 2, y: 0 }));
+        assert!(road_map.has_row_street(Coordinate { x: 2, y: 0 }));
+        assert!(!road_map.has_column_street(Coordinate { x: 1, y: 1 }));
+    }
+
+    /**
+     * Test parsing an ASCII map.
+     */
+    #[test]
+    fn test_parse() {
+        let contents = "..#\r\n.##\n...\n\n";
+        let road_map = RoadMap::parse(contents).unwrap();
+        assert_eq!(road_map.get_width(), 3);
+        assert_eq!(road_map.get_height(), 3);
+        assert!(road_map.is_road(Coordinate { x: 1, y: 0 }));
+        assert!(!road_map.is_road(Coordinate { x: 2, y: 0 }));
+        assert!(!road_map.is_road(Coordinate { x: 3, y: 0 }));
+        assert_eq!(road_map.to_ascii(), "..#\n.##\n...\n");
+
+        assert!(RoadMap::parse("").is_err());
+        assert!(RoadMap::parse("...\n..\n").is_err());
+        assert!(RoadMap::parse("..R\n").is_err());
+    }
+
+    /**
+     * Test removing street segments.
+     */
+    #[test]
+    fn test_remove_segment() {
+        let mut road_map = RoadMap::from_blocks(&[2, 2], &[2, 2]);
+
+        // the segment between the intersections (3, 3) and (3, 6)
+        road_map.remove_segment(Coordinate { x: 3, y: 4 }).unwrap();
+        // the segment between the intersections (3, 3) and (6, 3), (3, 3) is now a corner
+        road_map.remove_segment(Coordinate { x: 5, y: 3 }).unwrap();
+        assert_eq!(
+            road_map.to_ascii(),
+            ".......\n\
+             .##.##.\n\
+             .##.##.\n\
+             ....##.\n\
+             .#####.\n\
+             .#####.\n\
+             .......\n"
+        );
+
+        // intersections, buildings and cells out of the map are not segments
+        assert!(road_map.remove_segment(Coordinate { x: 0, y: 0 }).is_err());
+        assert!(road_map.remove_segment(Coordinate { x: 1, y: 1 }).is_err());
+        assert!(road_map.remove_segment(Coordinate { x: 9, y: 1 }).is_err());
+
+        // removing another segment of the corner (3, 3) leaves a dead end
+        road_map.remove_segment(Coordinate { x: 3, y: 2 }).unwrap();
+        assert!(road_map.has_row_street(Coordinate { x: 3, y: 3 }));
+        assert!(!road_map.has_column_street(Coordinate { x: 3, y: 3 }));
+        assert!(!road_map.is_road(Coordinate { x: 4, y: 3 }));
+    }
+}
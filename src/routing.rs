@@ -0,0 +1,651 @@
+/**
+ * ROUTING
+ *
+ * Origin–destination routing of the vehicles, so the OBUs drive to a
+ * destination along a shortest path instead of choosing their moves at
+ * random. Each vehicle draws its destination when it enters the grid, from
+ * the OD matrix of its entry point or among the destinations of the grid, and
+ * leaves the grid when it reaches it. The shortest paths follow the lanes of
+ * the streets, with the turns the vehicles can make. A vehicle whose next cell on
+ * its path stays taken reroutes: after waiting a number of rounds, it takes
+ * the free cell closest to its destination, and its path goes on from there.
+ */
+use crate::grid::{Coordinate, Grid, Heading, Lane, Position};
+use crate::scenario::ScenarioError;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+// Distance of the cells the destination can't be reached from
+const UNREACHABLE: u32 = u32::MAX;
+
+// Headings of the lanes of a cell in the street graph
+const HEADINGS: [Heading; 4] = [Heading::South, Heading::North, Heading::East, Heading::West];
+
+/**
+ * OdPair represents an entry of the OD matrix: the vehicles entering the
+ * grid at the origin drive to the destination in proportion to the weight.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OdPair {
+    pub origin: Coordinate,      // Entry point of the vehicles
+    pub destination: Coordinate, // Street cell the vehicles drive to
+    #[serde(default = "OdPair::default_weight")]
+    pub weight: f64, // Share of the vehicles of the origin driving to the destination
+}
+
+/**
+ * RoutingParams represents the destinations of the vehicles and how they
+ * reroute.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingParams {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<Coordinate>, // Destinations of the origins out of the OD matrix, the exits if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub od_matrix: Vec<OdPair>, // Destinations of the vehicles of each origin, by weight
+    #[serde(default = "RoutingParams::default_patience")]
+    pub patience: u32, // Rounds a vehicle waits for its next cell before rerouting
+}
+
+/**
+ * StreetGraph represents the lanes of the street cells, by cell and heading,
+ * and the lanes each of them can be reached from, to calculate the distances
+ * to a destination.
+ */
+struct StreetGraph {
+    height: u32,               // Number of cells along the y axis of the grid
+    previous: Vec<Vec<usize>>, // Lanes leading to each lane, by cell and heading
+}
+
+/**
+ * Route represents the destination of a vehicle.
+ */
+#[derive(Clone, Copy, Debug)]
+struct Route {
+    destination: Option<Coordinate>, // None if no destination can be reached
+    blocked: u32,                    // Rounds the vehicle has waited for its next cell
+}
+
+/**
+ * Routing draws the destination of every vehicle and chooses its moves
+ * along a shortest path.
+ */
+pub struct Routing {
+    params: RoutingParams,
+    graph: StreetGraph,
+    destinations: Vec<Coordinate>, // Destinations of the origins out of the OD matrix
+    distances: BTreeMap<usize, Vec<u32>>, // Distances of the lanes to each destination, by cell index
+    routes: BTreeMap<u32, Route>,         // Routes of the vehicles, by OBU id
+    rng: StdRng,                          // Random number generator of the routes
+}
+
+/**
+ * OdPair implementation.
+ */
+impl OdPair {
+    /**
+     * By default every destination of an origin has the same weight.
+     */
+    fn default_weight() -> f64 {
+        1.0
+    }
+}
+
+/**
+ * RoutingParams implementation.
+ */
+impl RoutingParams {
+    /**
+     * By default a vehicle waits 2 rounds before rerouting.
+     */
+    fn default_patience() -> u32 {
+        2
+    }
+
+    /**
+     * Check that the destinations are street cells of the grid and that the
+     * OD matrix only has destinations its origins can reach.
+     */
+    pub fn validate(&self, grid: &Grid) -> Result<(), ScenarioError> {
+        let entry_points = grid.get_entry_points();
+        let graph = StreetGraph::new(grid);
+        let is_cell = |coordinate: &Coordinate| {
+            coordinate.x < grid.get_width()
+                && coordinate.y < grid.get_height()
+                && grid.is_street(*coordinate)
+        };
+
+        if let Some(destination) = self.destinations.iter().find(|c| !is_cell(c)) {
+            return Err(ScenarioError::Invalid(format!(
+                "routing.destinations: ({}, {}) is not a street cell of the grid",
+                destination.x, destination.y
+            )));
+        }
+
+        for od_pair in &self.od_matrix {
+            let (origin, destination) = (od_pair.origin, od_pair.destination);
+            if !entry_points.contains(&origin) {
+                return Err(ScenarioError::Invalid(format!(
+                    "routing.od_matrix: ({}, {}) is not an entry point of the grid",
+                    origin.x, origin.y
+                )));
+            }
+            if !is_cell(&destination) {
+                return Err(ScenarioError::Invalid(format!(
+                    "routing.od_matrix: ({}, {}) is not a street cell of the grid",
+                    destination.x, destination.y
+                )));
+            }
+            if !(od_pair.weight.is_finite() && od_pair.weight >= 0.0) {
+                return Err(ScenarioError::Invalid(format!(
+                    "routing.od_matrix weight of ({}, {}) to ({}, {}) must be a number not less than 0, got {}",
+                    origin.x, origin.y, destination.x, destination.y, od_pair.weight
+                )));
+            }
+            if origin == destination
+                || graph.get_cell_distance(&graph.get_distances(destination), origin) == UNREACHABLE
+            {
+                return Err(ScenarioError::Invalid(format!(
+                    "routing.od_matrix: ({}, {}) can't be reached from ({}, {})",
+                    destination.x, destination.y, origin.x, origin.y
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * StreetGraph implementation.
+ */
+impl StreetGraph {
+    /**
+     * Create the street graph of a grid from the moves of the vehicles in
+     * each lane of its street cells: ahead, into the crossing street at an
+     * intersection and back only at a dead end.
+     */
+    fn new(grid: &Grid) -> StreetGraph {
+        let height = grid.get_height();
+        let mut graph = StreetGraph {
+            height,
+            previous: vec![Vec::new(); (grid.get_width() * height) as usize * HEADINGS.len()],
+        };
+
+        for x in 0..grid.get_width() {
+            for y in 0..height {
+                let coordinate = Coordinate { x, y };
+                if !grid.is_street(coordinate) {
+                    continue;
+                }
+                for heading in grid.get_headings(coordinate) {
+                    let position = Position {
+                        coordinate,
+                        lane: Lane { heading, index: 0 },
+                    };
+                    let lane = graph.index(coordinate, heading);
+                    for (next, next_heading, _) in grid.get_next_cells(position) {
+                        let next_lane = graph.index(next, next_heading);
+                        graph.previous[next_lane].push(lane);
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    /**
+     * Get the index of a cell in the graph.
+     */
+    fn cell_index(&self, coordinate: Coordinate) -> usize {
+        (coordinate.x * self.height + coordinate.y) as usize
+    }
+
+    /**
+     * Get the index of the lane of a cell with a heading in the graph.
+     */
+    fn index(&self, coordinate: Coordinate, heading: Heading) -> usize {
+        self.cell_index(coordinate) * HEADINGS.len() + heading as usize
+    }
+
+    /**
+     * Calculate the number of cells of the shortest path from every lane to
+     * a destination, with a breadth first search from the lanes of the
+     * destination.
+     */
+    fn get_distances(&self, destination: Coordinate) -> Vec<u32> {
+        let mut distances = vec![UNREACHABLE; self.previous.len()];
+        let mut queue = VecDeque::new();
+
+        for heading in HEADINGS {
+            distances[self.index(destination, heading)] = 0;
+            queue.push_back(self.index(destination, heading));
+        }
+        while let Some(lane) = queue.pop_front() {
+            for previous in &self.previous[lane] {
+                if distances[*previous] == UNREACHABLE {
+                    distances[*previous] = distances[lane] + 1;
+                    queue.push_back(*previous);
+                }
+            }
+        }
+
+        distances
+    }
+
+    /**
+     * Get the distance of a cell to a destination, from the closest of its
+     * lanes, for a vehicle entering the grid at the cell.
+     */
+    fn get_cell_distance(&self, distances: &[u32], coordinate: Coordinate) -> u32 {
+        HEADINGS
+            .iter()
+            .map(|heading| distances[self.index(coordinate, *heading)])
+            .min()
+            .unwrap_or(UNREACHABLE)
+    }
+}
+
+/**
+ * Routing implementation.
+ */
+impl Routing {
+    /**
+     * Create the routing of the given params, which must be valid for the
+     * grid.
+     */
+    pub fn new(params: &RoutingParams, grid: &Grid, rng: StdRng) -> Routing {
+        let destinations = if params.destinations.is_empty() {
+            grid.get_exits()
+        } else {
+            params.destinations.clone()
+        };
+
+        Routing {
+            params: params.clone(),
+            graph: StreetGraph::new(grid),
+            destinations,
+            distances: BTreeMap::new(),
+            routes: BTreeMap::new(),
+            rng,
+        }
+    }
+
+    /**
+     * Return the destination of a vehicle, drawn from the cell it is in when
+     * it is first seen. None if it can't reach any destination.
+     */
+    pub fn get_destination(&mut self, obu_id: u32, coordinate: Coordinate) -> Option<Coordinate> {
+        if let Some(route) = self.routes.get(&obu_id) {
+            return route.destination;
+        }
+
+        let destination = self.draw_destination(coordinate);
+        self.routes.insert(
+            obu_id,
+            Route {
+                destination,
+                blocked: 0,
+            },
+        );
+        destination
+    }
+
+    /**
+     * Check if a vehicle has reached its destination.
+     */
+    pub fn is_arrived(&mut self, obu_id: u32, coordinate: Coordinate) -> bool {
+        self.get_destination(obu_id, coordinate) == Some(coordinate)
+    }
+
+    /**
+     * Choose the move of a vehicle among its possible moves, the free lanes
+     * of its next cells. The vehicle drives to a next cell on a shortest path
+     * to its destination. It waits while the cell is behind a red light, and
+     * while it is taken until it runs out of patience and reroutes through
+     * the free cell closest to its destination. A vehicle with no
+     * destination, or that can't reach it, drives at random. Return none if
+     * the vehicle waits.
+     */
+    pub fn choose_move(
+        &mut self,
+        obu_id: u32,
+        position: Position,
+        next_cells: &[(Coordinate, Heading, bool)],
+        possible_moves: &[Position],
+    ) -> Option<Position> {
+        let Some(destination) = self.get_destination(obu_id, position.coordinate) else {
+            return possible_moves.choose(&mut self.rng).copied();
+        };
+
+        let distances = self
+            .distances
+            .entry(self.graph.cell_index(destination))
+            .or_insert_with(|| self.graph.get_distances(destination));
+        let distance = |coordinate, heading| distances[self.graph.index(coordinate, heading)];
+
+        // get the next cells on a shortest path
+        let shortest = next_cells
+            .iter()
+            .map(|(next, heading, _)| distance(*next, *heading))
+            .min();
+        let Some(shortest) = shortest.filter(|shortest| *shortest != UNREACHABLE) else {
+            return possible_moves.choose(&mut self.rng).copied();
+        };
+        let is_red = next_cells
+            .iter()
+            .any(|(next, heading, is_red)| *is_red && distance(*next, *heading) == shortest);
+
+        let route = self.routes.get_mut(&obu_id).expect("Route not drawn");
+        let closest = possible_moves
+            .iter()
+            .map(|next| distance(next.coordinate, next.lane.heading))
+            .min()
+            .unwrap_or(UNREACHABLE);
+        if closest != shortest {
+            // the path is blocked, wait at the red or reroute once out of patience
+            if is_red {
+                return None;
+            }
+            if route.blocked < self.params.patience || closest == UNREACHABLE {
+                route.blocked += 1;
+                return None;
+            }
+        }
+        route.blocked = 0;
+
+        let moves: Vec<Position> = possible_moves
+            .iter()
+            .filter(|next| distance(next.coordinate, next.lane.heading) == closest)
+            .copied()
+            .collect();
+        moves.choose(&mut self.rng).copied()
+    }
+
+    /**
+     * Forget the route of a vehicle that left the grid.
+     */
+    pub fn remove_vehicle(&mut self, obu_id: u32) {
+        self.routes.remove(&obu_id);
+    }
+
+    /**
+     * Draw the destination of a vehicle entering the grid at the given cell:
+     * from the OD matrix if it has destinations for the cell, else among the
+     * destinations the vehicle can reach.
+     */
+    fn draw_destination(&mut self, origin: Coordinate) -> Option<Coordinate> {
+        let od_pairs: Vec<&OdPair> = self
+            .params
+            .od_matrix
+            .iter()
+            .filter(|od_pair| od_pair.origin == origin)
+            .collect();
+        if let Ok(weights) = WeightedIndex::new(od_pairs.iter().map(|od_pair| od_pair.weight)) {
+            return Some(od_pairs[weights.sample(&mut self.rng)].destination);
+        }
+
+        let mut reachable = Vec::new();
+        for destination in self.destinations.clone() {
+            let distances = self
+                .distances
+                .entry(self.graph.cell_index(destination))
+                .or_insert_with(|| self.graph.get_distances(destination));
+            if destination != origin
+                && self.graph.get_cell_distance(distances, origin) != UNREACHABLE
+            {
+                reachable.push(destination);
+            }
+        }
+        reachable.choose(&mut self.rng).copied()
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grid::{GridParams, Heading};
+    use rand::SeedableRng;
+
+    // Two rows of streets joined by three columns
+    const MAP: &str = ".......\n.##.##.\n.......\n";
+
+    /**
+     * Return a two-way grid of the given map.
+     */
+    fn grid(map: &str) -> Grid {
+        Grid::new(GridParams {
+            blocks_per_street: 0,
+            block_size: 0,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: Some(map.to_string()),
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: true,
+            lanes: 1,
+            streets: Vec::new(),
+        })
+    }
+
+    /**
+     * Return the params of a routing to the given destinations.
+     */
+    fn routing_params(destinations: Vec<Coordinate>) -> RoutingParams {
+        RoutingParams {
+            destinations,
+            od_matrix: Vec::new(),
+            patience: 2,
+        }
+    }
+
+    /**
+     * Drive an OBU with the routing until it waits or reaches its
+     * destination, return the cells it drove to.
+     */
+    fn drive(
+        grid: &mut Grid,
+        routing: &mut Routing,
+        obu_id: u32,
+        position: &mut Position,
+    ) -> Vec<Coordinate> {
+        let mut cells = Vec::new();
+        while !routing.is_arrived(obu_id, position.coordinate) {
+            let next_cells = grid.get_next_cells(*position);
+            let possible_moves = grid.get_possible_moves(*position);
+            let Some(next) = routing.choose_move(obu_id, *position, &next_cells, &possible_moves)
+            else {
+                break;
+            };
+            grid.move_obu(*position, next);
+            *position = next;
+            cells.push(next.coordinate);
+        }
+        cells
+    }
+
+    /**
+     * Test the validation of the destinations and of the OD matrix.
+     */
+    #[test]
+    fn test_validate() {
+        let grid = grid(MAP);
+        let cell = |x, y| Coordinate { x, y };
+
+        assert!(routing_params(vec![cell(3, 1), cell(6, 2)])
+            .validate(&grid)
+            .is_ok());
+        // a building and a cell out of the grid
+        assert!(routing_params(vec![cell(1, 1)]).validate(&grid).is_err());
+        assert!(routing_params(vec![cell(7, 0)]).validate(&grid).is_err());
+
+        let mut params = routing_params(Vec::new());
+        params.od_matrix = vec![OdPair {
+            origin: cell(0, 0),
+            destination: cell(3, 1),
+            weight: 1.0,
+        }];
+        assert!(params.validate(&grid).is_ok());
+        // the origin must be an entry point
+        params.od_matrix[0].origin = cell(1, 0);
+        assert!(params.validate(&grid).is_err());
+        params.od_matrix[0].origin = cell(0, 0);
+        params.od_matrix[0].weight = -1.0;
+        assert!(params.validate(&grid).is_err());
+        params.od_matrix[0].weight = 0.0;
+        assert!(params.validate(&grid).is_ok());
+        // the destination must be another cell the origin can reach
+        params.od_matrix[0].destination = cell(0, 0);
+        assert!(params.validate(&grid).is_err());
+        let grid = self::grid("...#...\n");
+        params.od_matrix[0].destination = cell(5, 0);
+        assert!(params.validate(&grid).is_err());
+    }
+
+    /**
+     * Test the vehicles driving along a shortest path to their destination.
+     */
+    #[test]
+    fn test_shortest_path() {
+        let mut grid = grid(MAP);
+        let destination = Coordinate { x: 3, y: 1 };
+        let mut routing = Routing::new(
+            &routing_params(vec![destination]),
+            &grid,
+            StdRng::seed_from_u64(1),
+        );
+
+        // the vehicle drives along the row 0, then turns into the column 3
+        assert!(grid.insert_obu_at(Coordinate { x: 0, y: 0 }, 0));
+        let mut position = grid.get_position(Coordinate { x: 0, y: 0 }, 0).unwrap();
+        let cells = drive(&mut grid, &mut routing, 0, &mut position);
+        assert_eq!(
+            cells,
+            vec![
+                Coordinate { x: 1, y: 0 },
+                Coordinate { x: 2, y: 0 },
+                Coordinate { x: 3, y: 0 },
+                destination,
+            ]
+        );
+        assert_eq!(
+            routing.get_destination(0, position.coordinate),
+            Some(destination)
+        );
+
+        // the destinations of the OD matrix come first, by weight
+        let mut params = routing_params(vec![destination]);
+        params.od_matrix = vec![
+            OdPair {
+                origin: Coordinate { x: 6, y: 0 },
+                destination: Coordinate { x: 0, y: 2 },
+                weight: 1.0,
+            },
+            OdPair {
+                origin: Coordinate { x: 6, y: 0 },
+                destination: Coordinate { x: 6, y: 2 },
+                weight: 0.0,
+            },
+        ];
+        let mut routing = Routing::new(&params, &grid, StdRng::seed_from_u64(1));
+        for obu_id in 0..10 {
+            assert_eq!(
+                routing.get_destination(obu_id, Coordinate { x: 6, y: 0 }),
+                Some(Coordinate { x: 0, y: 2 })
+            );
+            assert_eq!(
+                routing.get_destination(obu_id + 10, Coordinate { x: 0, y: 2 }),
+                Some(destination)
+            );
+        }
+
+        // by default the vehicles drive to an exit of the grid
+        let mut routing =
+            Routing::new(&routing_params(Vec::new()), &grid, StdRng::seed_from_u64(1));
+        let exits = grid.get_exits();
+        assert!(exits.contains(
+            &routing
+                .get_destination(0, Coordinate { x: 0, y: 0 })
+                .unwrap()
+        ));
+    }
+
+    /**
+     * Test a vehicle rerouting when its path is blocked.
+     */
+    #[test]
+    fn test_reroute() {
+        let mut grid = grid(MAP);
+        let destination = Coordinate { x: 3, y: 1 };
+        let mut routing = Routing::new(
+            &routing_params(vec![destination]),
+            &grid,
+            StdRng::seed_from_u64(1),
+        );
+
+        // an obu stops in the row 0, on the shortest path of the vehicle
+        let entry = Coordinate { x: 0, y: 0 };
+        assert!(grid.insert_obu_at(entry, 1));
+        let blocker = grid.get_position(entry, 1).unwrap();
+        let next = grid
+            .get_possible_moves(blocker)
+            .into_iter()
+            .find(|next| next.lane.heading == Heading::East)
+            .unwrap();
+        grid.move_obu(blocker, next);
+
+        // the vehicle waits 2 rounds, then drives along the row 2
+        assert!(grid.insert_obu_at(entry, 0));
+        let mut position = grid.get_position(entry, 0).unwrap();
+        for _ in 0..2 {
+            assert!(drive(&mut grid, &mut routing, 0, &mut position).is_empty());
+        }
+        let cells = drive(&mut grid, &mut routing, 0, &mut position);
+        assert_eq!(cells.len(), 6);
+        assert_eq!(cells[0], Coordinate { x: 0, y: 1 });
+        assert_eq!(position.coordinate, destination);
+    }
+
+    /**
+     * Test a vehicle whose destination is behind it on a two-way street: it
+     * drives on to the dead end of the street and makes a U-turn there.
+     */
+    #[test]
+    fn test_destination_behind() {
+        let mut grid = grid(".......\n");
+        let destination = Coordinate { x: 1, y: 0 };
+        let mut routing = Routing::new(
+            &routing_params(vec![destination]),
+            &grid,
+            StdRng::seed_from_u64(1),
+        );
+
+        // the vehicle heads east in the cell 3
+        let entry = Coordinate { x: 0, y: 0 };
+        assert!(grid.insert_obu_at(entry, 0));
+        let mut position = Position {
+            coordinate: Coordinate { x: 3, y: 0 },
+            lane: Lane {
+                heading: Heading::East,
+                index: 0,
+            },
+        };
+        grid.move_obu(grid.get_position(entry, 0).unwrap(), position);
+        let cells = drive(&mut grid, &mut routing, 0, &mut position);
+        assert_eq!(
+            cells.iter().map(|cell| cell.x).collect::<Vec<u32>>(),
+            vec![4, 5, 6, 5, 4, 3, 2, 1]
+        );
+        assert_eq!(position.coordinate, destination);
+    }
+}
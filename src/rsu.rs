@@ -34,7 +34,7 @@ impl RoadSideUnit {
      * Get the coordinate of the RoadSideUnit
      */
     pub fn get_coordinate(&self) -> Coordinate {
-        self.coordinate.clone()
+        self.coordinate
     }
 
     /**
@@ -48,21 +48,18 @@ impl RoadSideUnit {
      * Receive a message from the ether
      */
     pub fn receive_message(&mut self, message: Message) {
-        // Check the type of the `Message` instance.
-        match message.origin_type {
-            // If the `Message` instance was sent by an OBU node, add the sender to the `neighbors` vector.
-            NodeType::OBU => {
-                // Create a new `NeighborEntry` instance.
-                let neighbor = NeighborEntry {
-                    id: message.origin_id,
-                    coordinate: message.coordinate,
-                };
+        // If the `Message` instance was sent by an OBU node, add the sender to the `neighbors` vector.
+        // Messages sent by any other type of node are ignored.
+        if let NodeType::OBU = message.origin_type {
+            // Create a new `NeighborEntry` instance.
+            let neighbor = NeighborEntry {
+                id: message.origin_id,
+                coordinate: message.coordinate,
+                send_round: message.send_round,
+            };
 
-                // Add the `NeighborEntry` instance to the `neighbors` vector.
-                self.neighbors.push(neighbor);
-            }
-            // If the `Message` instance was sent by any other type of node, do nothing.
-            _ => {}
+            // Add the `NeighborEntry` instance to the `neighbors` vector.
+            self.neighbors.push(neighbor);
         }
     }
 
@@ -1,11 +1,12 @@
 use crate::comms::Ether;
-use crate::comms::Message;
 use crate::grid::Coordinate;
 use crate::rsu::RoadSideUnit;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
+use crate::simulator::NodeType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct RsuManagerParams {
     pub tx_range: u32,
     pub rx_range: u32,
@@ -13,23 +14,72 @@ pub struct RsuManagerParams {
     pub detect_obu_gps_failure: bool,
 }
 
-#[allow(dead_code)]
+/**
+ * Reputation represents the reputation class of an OBU. The classes are
+ * ordered from the worst to the best.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reputation {
+    Red = 0,    // error rate at or above the detection threshold
+    Yellow = 1, // error rate at or above 60% of the detection threshold
+    Green = 2,  // error rate below 60% of the detection threshold
+}
+
+/**
+ * DetectorVerdict represents the result of the faulty OBU detection for an OBU.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct DetectorVerdict {
+    pub obu_id: u32,
+    pub tx_error_count: u32,        // Number of messages missed by the RSUs
+    pub tx_error_rate: f32,         // Missed messages per round
+    pub tx_reputation: Reputation,  // Reputation based on the tx error rate
+    pub tx_faulty: bool,            // Detected as faulty by the tx failure detector
+    pub gps_error_count: u32,       // Number of rounds with an implausible coordinate
+    pub gps_error_rate: f32,        // Rounds with an implausible coordinate per observed round
+    pub gps_reputation: Reputation, // Reputation based on the gps error rate
+    pub gps_faulty: bool,           // Detected as faulty by the gps failure detector
+    pub reputation: Reputation,     // Worst of the tx and gps reputations
+    pub detected_faulty: bool,      // Detected as faulty by the enabled detectors
+}
+
 struct ObuData {
     coordinate: Coordinate,
     rsu_id: u32,
+    send_round: u32,    // Round the OBU sent the message for
+    arrival_round: u32, // Round the message reached the RSU, later with a latency
 }
 
 pub struct RoadSideUnitManager {
     next_id: u32,                                      // Next available id
     tx_range: u32,                                     // Transmission range
     rx_range: u32,                                     // Used to calculate the spacing between RSUs
-    pub rsus: HashMap<u32, RoadSideUnit>,              // FIXME: make private
+    pub rsus: BTreeMap<u32, RoadSideUnit>,             // FIXME: make private
     current_round: u32,                                // Current simulation round
     obu_observations: Vec<HashMap<u32, Vec<ObuData>>>, // A vector of HashMaps with the observations of the OBUs
     detect_obu_tx_failure: bool,                       // Detect OBU tx failures
     detect_obu_gps_failure: bool,                      // Detect OBU gps failures
 }
 
+/**
+ * Reputation implementation
+ */
+impl Reputation {
+    /**
+     * Classify the ratio between an error rate and its detection threshold.
+     */
+    fn from_ratio(ratio: f32) -> Reputation {
+        if ratio >= 1.0 {
+            Reputation::Red
+        } else if ratio >= 0.6 {
+            Reputation::Yellow
+        } else {
+            Reputation::Green
+        }
+    }
+}
+
 /**
  * RoadSideUnitManager implementation
  */
@@ -42,7 +92,7 @@ impl RoadSideUnitManager {
             next_id: 0,
             tx_range: params.tx_range,
             rx_range: params.rx_range,
-            rsus: HashMap::new(),
+            rsus: BTreeMap::new(),
             current_round: 0,
             obu_observations: Vec::new(),
             detect_obu_tx_failure: params.detect_obu_tx_failure,
@@ -54,24 +104,21 @@ impl RoadSideUnitManager {
      * Return the next available id.
      */
     pub fn get_next_id(&mut self) -> u32 {
-        let id = self.next_id;
-        id
+        self.next_id
     }
 
     /**
      * Return the comms range.
      */
     pub fn get_tx_range(&self) -> u32 {
-        let tx_range = self.tx_range;
-        tx_range
+        self.tx_range
     }
 
     /**
      * Return the rx range.
      */
     pub fn get_rx_range(&self) -> u32 {
-        let rx_range = self.rx_range;
-        rx_range
+        self.rx_range
     }
 
     /**
@@ -104,22 +151,16 @@ impl RoadSideUnitManager {
     /**
      * Deliver messages to RSUs.
      */
-    pub fn deliver_messages(&mut self, messages: &Vec<Message>) {
+    pub fn deliver_messages(&mut self, ether: &mut Ether) {
         // Iterate over all RSUs
         for rsu in self.rsus.values_mut() {
             // clear the neighbors
             rsu.clear_neighbors();
 
-            // Iterate over all messages
-            for message in messages {
-                // Check if the message can reach the rsu
-                if Ether::is_transmission_possible(
-                    message.phy_coord,
-                    message.phy_range,
-                    rsu.get_coordinate(),
-                ) {
-                    rsu.receive_message(message.clone());
-                }
+            // Iterate over the messages that reach the rsu
+            for message in ether.receive_messages(NodeType::RSU, rsu.get_id(), rsu.get_coordinate())
+            {
+                rsu.receive_message(message);
             }
         }
 
@@ -128,7 +169,9 @@ impl RoadSideUnitManager {
     }
 
     /**
-     * Update OBU observations.
+     * Update OBU observations. Each observation keeps the round the message
+     * was sent for apart from the round it arrived in, which is later with a
+     * latency, so the detectors count the stale reports in their round.
      */
     fn update_obu_observations(&mut self) {
         // Check if the current round is already in the vector
@@ -148,61 +191,71 @@ impl RoadSideUnitManager {
                 let obu_data = ObuData {
                     coordinate: neighbor.coordinate,
                     rsu_id: rsu.get_id(),
+                    send_round: neighbor.send_round,
+                    arrival_round: self.current_round,
                 };
 
-                // check if obu id is in the hashmap
-                if round_data.contains_key(&neighbor.id) {
-                    // add the obu data to the vector
-                    round_data.get_mut(&neighbor.id).unwrap().push(obu_data);
-                } else {
-                    // create a new vector and add the obu data
-                    round_data.insert(neighbor.id, vec![obu_data]);
-                }
+                // add the obu data to the vector, creating it if needed
+                round_data.entry(neighbor.id).or_default().push(obu_data);
             }
         }
 
         // add the round data to the vector
         self.obu_observations.push(round_data);
-    }fn update_obu_observations
+    }
 
     /**
-     * Check OBUs observations.
+     * Check OBUs observations and return the ids of the OBUs detected as faulty.
      */
     pub fn find_faulty_obus(&self) -> Vec<u32> {
+        self.evaluate_obus()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|verdict| verdict.detected_faulty)
+            .map(|verdict| verdict.obu_id)
+            .collect()
+    }
+
+    /**
+     * Check OBUs observations and return the detector verdict for every
+     * observed OBU, or None if the RSUs observed less than 2 OBUs, too few to
+     * set the detection thresholds. The detection has no side effects, the
+     * verdicts are written by the reporting layer.
+     */
+    pub fn evaluate_obus(&self) -> Option<Vec<DetectorVerdict>> {
         struct ObuErrorStats {
             tx_count: u32,
             tx_error_count: u32,
             tx_error_rate: f32,
             gps_error_count: u32,
             gps_error_rate: f32,
-            first_seen: u32,
         }
 
-        let mut error_stats: HashMap<u32, ObuErrorStats> = HashMap::new();
+        let mut error_stats: BTreeMap<u32, ObuErrorStats> = BTreeMap::new();
         let mut tx_errors: Vec<f32> = Vec::new();
         let mut gps_errors: Vec<f32> = Vec::new();
 
-        // Iterate over all rounds
-        for i in 0..self.obu_observations.len() {
-            // Get round data
-            let round_data = &self.obu_observations[i];
+        // The rounds each OBU sent a message for, and the ones with a gps
+        // error, whatever the round the messages arrived in
+        let mut sent_rounds: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+        let mut gps_error_rounds: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+
+        // The longest delay of a message, the ones sent in the last rounds up
+        // to it may still be in transit
+        let mut max_delay = 0;
 
+        // Iterate over all rounds
+        for round_data in self.obu_observations.iter() {
             // Iterate over round data
             for (obu_id, obu_data) in round_data.iter() {
-                // Create a new entry in the hashmap if it doesn't exist
-                let stats = error_stats.entry(*obu_id).or_insert_with(|| ObuErrorStats {
-                    tx_count: 0,
-                    tx_error_count: 0,
-                    tx_error_rate: 0.0,
-                    gps_error_count: 0,
-                    gps_error_rate: 0.0,
-                    first_seen: i as u32,
-                });
-
-                stats.tx_count += 1;
-
                 // Iterate over all obu data
                 for data in obu_data.iter() {
+                    max_delay = max_delay.max(data.arrival_round - data.send_round);
+                    sent_rounds
+                        .entry(*obu_id)
+                        .or_default()
+                        .insert(data.send_round);
+
                     let rsu_id = data.rsu_id;
                     let rsu_coordinate = self.rsus.get(&rsu_id).unwrap().get_coordinate();
 
@@ -212,25 +265,44 @@ impl RoadSideUnitManager {
                         self.rx_range + 3, // FIXME: i need a better value for this
                         rsu_coordinate,
                     ) {
-                        // Update the gps error count
-                        stats.gps_error_count += 1;
-
-                        // one error per round
-                        break;
+                        // one error per round the message was sent for
+                        gps_error_rounds
+                            .entry(*obu_id)
+                            .or_default()
+                            .insert(data.send_round);
                     }
                 }
             }
         }
 
-        // Get the number of rounds
+        // Get the number of rounds, the messages are only missing up to the
+        // rounds whose messages may still be in transit
         let rounds = self.obu_observations.len() as u32;
+        let horizon = rounds.saturating_sub(max_delay);
+
+        // An OBU is seen from the first round it sent a heard message for
+        for (obu_id, sent) in sent_rounds.iter() {
+            let first_seen = *sent.first().unwrap();
+            error_stats.insert(
+                *obu_id,
+                ObuErrorStats {
+                    tx_count: sent.len() as u32,
+                    tx_error_count: horizon.saturating_sub(first_seen)
+                        - sent.range(..horizon).count() as u32,
+                    tx_error_rate: 0.0,
+                    gps_error_count: gps_error_rounds.get(obu_id).map_or(0, |r| r.len() as u32),
+                    gps_error_rate: 0.0,
+                },
+            );
+        }
+
+        // The thresholds need the median of at least 2 OBUs
+        if error_stats.len() < 2 {
+            return None;
+        }
 
         // iterate over all rx stats
         for (_, stats) in error_stats.iter_mut() {
-            // Calculate the number of messages missing since the first round
-            // the OBU was seen
-            stats.tx_error_count = rounds - (stats.tx_count + stats.first_seen);
-
             // Calculate the error rates
             stats.tx_error_rate = stats.tx_error_count as f32 / self.current_round as f32;
             stats.gps_error_rate = stats.gps_error_count as f32 / stats.tx_count as f32;
@@ -255,106 +327,94 @@ impl RoadSideUnitManager {
         let median = gps_errors[gps_errors.len() / 2 - 1];
         let gps_threshold: f32 = median + ce * gps_error_mad;
 
-        // A vector to store the faulty obus
-        let mut faulty_obus: Vec<u32> = Vec::new();
-
-        // Create/Trunc reputation file
-        let mut file = File::create("reputation.csv").expect("Failed to create file");
-        file.write_all(b"OBU #,TX Error,TX Rep,GPS Error,GPS Rep,Reputation\n")
-            .expect("Failed to write to file");
+        // A vector to store the verdicts
+        let mut verdicts: Vec<DetectorVerdict> = Vec::new();
 
-        println!("--- Fauty OBUs identified by the RSUs ---");
-        println!("ID \ttx_error\tgps_error");
         for (obu_id, stats) in error_stats.iter() {
-            // Ignore OBUs with a rx error rate below the threshold
-
-            let tx_reputation = stats.tx_error_rate / tx_threshold;
-            let gps_reputation = stats.gps_error_rate / gps_threshold;
+            let tx_reputation = Reputation::from_ratio(stats.tx_error_rate / tx_threshold);
+            let gps_reputation = Reputation::from_ratio(stats.gps_error_rate / gps_threshold);
 
-            let tx_reputation_class = if tx_reputation >= 1.0 {
-                0 // red
-            } else if tx_reputation >= 0.6 {
-                1 // yellow
-            } else {
-                2 // green
-            };
+            // final reputation is the worst of the two
+            let reputation = tx_reputation.min(gps_reputation);
+
+            // Check the enabled detectors
+            let tx_faulty = self.detect_obu_tx_failure && stats.tx_error_rate >= tx_threshold;
+            let gps_faulty = self.detect_obu_gps_failure && stats.gps_error_rate >= gps_threshold;
+
+            // add the verdict to the vector
+            verdicts.push(DetectorVerdict {
+                obu_id: *obu_id,
+                tx_error_count: stats.tx_error_count,
+                tx_error_rate: stats.tx_error_rate,
+                tx_reputation,
+                tx_faulty,
+                gps_error_count: stats.gps_error_count,
+                gps_error_rate: stats.gps_error_rate,
+                gps_reputation,
+                gps_faulty,
+                reputation,
+                detected_faulty: tx_faulty || gps_faulty,
+            });
+        }
 
-            let gps_repuation_class = if gps_reputation >= 1.0 {
-                0 // red
-            } else if gps_reputation >= 0.6 {
-                1 // yellow
-            } else {
-                2 // green
-            };
+        // return the vector with the verdicts
+        Some(verdicts)
+    }
 
-            // final reputation is the worst of the two
-            let reputation = tx_reputation_class.min(gps_repuation_class);
-
-            write!(
-                file,
-                "{},{},{},{},{},{}\n",
-                obu_id,
-                stats.tx_error_rate,
-                tx_reputation_class,
-                stats.gps_error_rate,
-                gps_repuation_class,
-                reputation
-            )
-            .expect("Failed to write to file");
-
-            if (!self.detect_obu_tx_failure || stats.tx_error_rate < tx_threshold)
-                && (!self.detect_obu_gps_failure || stats.gps_error_rate < gps_threshold)
-            {
-                // not detected as faulty
-                continue;
-            }
+    /**
+     * Print the OBUs detected as faulty and the error rates that triggered
+     * the detection.
+     */
+    pub fn print_faulty_obus(verdicts: &[DetectorVerdict]) {
+        println!("--- Fauty OBUs identified by the RSUs ---");
+        println!("ID \ttx_error\tgps_error");
 
+        for verdict in verdicts.iter().filter(|verdict| verdict.detected_faulty) {
             // Print OBU id
-            print!("{:03}", obu_id);
+            print!("{:03}", verdict.obu_id);
 
-            // If obu tx failure detection is enabled
-            if self.detect_obu_tx_failure && stats.tx_error_rate >= tx_threshold {
+            // If the tx failure detector flagged the OBU
+            if verdict.tx_faulty {
                 // Print the detected tx error rate
                 print!(
                     "\t{:2} {:5.2}% ",
-                    stats.tx_error_count,
-                    stats.tx_error_rate * 100.0
+                    verdict.tx_error_count,
+                    verdict.tx_error_rate * 100.0
                 );
             } else {
                 print!("\t\t");
             }
 
-            // If obu gps failure detection is enabled
-            if self.detect_obu_gps_failure && stats.gps_error_rate >= gps_threshold {
+            // If the gps failure detector flagged the OBU
+            if verdict.gps_faulty {
                 // Print the detected gps error rate
                 print!(
                     "\t{:2} {:5.2}% ",
-                    stats.gps_error_count,
-                    stats.gps_error_rate * 100.0
+                    verdict.gps_error_count,
+                    verdict.gps_error_rate * 100.0
                 );
             }
 
             // Print new line
             println!();
-
-            // add the obu id to the vector
-            faulty_obus.push(*obu_id);
         }
-
-        // return the vector with the faulty obus
-        faulty_obus
-    }fn find_faulty_obus
+    }
 
     /**
-     * Calculate the Median Absolute Deviation (MAD) for a vector of f32 values.
+     * Calculate the Median Absolute Deviation (MAD) for a vector of f32 values,
+     * NaN without values.
      */
     pub fn calculate_mad(values: &[f32]) -> f32 {
+        if values.is_empty() {
+            return f32::NAN;
+        }
+
         // Clone and sort the values
         let mut sorted_values = values.to_vec();
         sorted_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
         // Calculate the median
-        let median = if sorted_values.len() % 2 == 0 {
+        let median = if sorted_values.len().is_multiple_of(2) {
             let mid1 = sorted_values.len() / 2 - 1;
             let mid2 = sorted_values.len() / 2;
             (f64::from(sorted_values[mid1]) + f64::from(sorted_values[mid2])) as f32 / 2.0
@@ -372,7 +432,7 @@ impl RoadSideUnitManager {
         abs_devs.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
         // Calculate the median absolute deviation
-        let mad = if abs_devs.len() % 2 == 0 {
+        let mad = if abs_devs.len().is_multiple_of(2) {
             let mid1 = abs_devs.len() / 2 - 1;
             let mid2 = abs_devs.len() / 2;
             (f64::from(abs_devs[mid1]) + f64::from(abs_devs[mid2])) as f32 / 2.0
@@ -382,8 +442,8 @@ impl RoadSideUnitManager {
 
         // Return MAD with the scaling factor (1 / phi(3/4)) for a normal distribution
         mad * 1.4826
-    }fn calculate_mad
-}impl RoadsideUnitManager
+    }
+}
 
 /***
  * TESTS MODULE
@@ -392,7 +452,7 @@ impl RoadSideUnitManager {
 mod tests {
 
     use super::*;
-    use crate::grid::Coordinate;
+    use crate::comms::Message;
 
     /**
      * Test the creation of an RoadSideUnitManager.
@@ -444,7 +504,7 @@ mod tests {
         assert_eq!(rsu.get_id(), 1);
         assert_eq!(rsu.get_coordinate().x, 3);
         assert_eq!(rsu.get_coordinate().y, 6);
-    }fn test_create_rsu
+    }
 
     /**
      * Test the calculation of the median absolute deviation.
@@ -499,5 +559,88 @@ mod tests {
         assert!((actual_mad - expected_mad).abs() < 0.001);
     }
 
+    /**
+     * Test the median absolute deviation of empty and one-element vectors.
+     */
+    #[test]
+    fn test_calculate_mad_small() {
+        assert!(RoadSideUnitManager::calculate_mad(&[]).is_nan());
+        assert_eq!(RoadSideUnitManager::calculate_mad(&[0.25]), 0.0);
+    }
+
+    /**
+     * Test that the detector gives no verdicts with less than 2 observed OBUs.
+     */
+    #[test]
+    fn test_evaluate_obus_small() {
+        let evaluate = |obu_count: u32| {
+            let mut rsu_manager = RoadSideUnitManager::new(RsuManagerParams {
+                tx_range: 5,
+                rx_range: 5,
+                detect_obu_gps_failure: true,
+                detect_obu_tx_failure: true,
+            });
+            rsu_manager.create_rsu(Coordinate { x: 0, y: 0 });
+
+            // every OBU next to the RSU sends a message in each round
+            let mut ether = Ether::new();
+            for round in 0..3 {
+                ether.clear();
+                for id in 0..obu_count {
+                    let coordinate = Coordinate { x: id, y: 0 };
+                    let mut message = Message::new(id, NodeType::OBU, coordinate, coordinate, 3);
+                    message.send_round = round;
+                    ether.send_message(message);
+                }
+                rsu_manager.set_current_round(round);
+                rsu_manager.deliver_messages(&mut ether);
+            }
+            rsu_manager.evaluate_obus()
+        };
+
+        assert!(evaluate(0).is_none());
+        assert!(evaluate(1).is_none());
+        assert_eq!(evaluate(2).unwrap().len(), 2);
+    }
+
+    /**
+     * Test that the messages still in transit at the end of the observations
+     * are not counted as missing.
+     */
+    #[test]
+    fn test_evaluate_obus_delayed() {
+        let mut rsu_manager = RoadSideUnitManager::new(RsuManagerParams {
+            tx_range: 5,
+            rx_range: 5,
+            detect_obu_gps_failure: true,
+            detect_obu_tx_failure: true,
+        });
+        rsu_manager.create_rsu(Coordinate { x: 0, y: 0 });
+        let data = |send_round, arrival_round| ObuData {
+            coordinate: Coordinate { x: 0, y: 0 },
+            rsu_id: 0,
+            send_round,
+            arrival_round,
+        };
+
+        // the messages of the OBU 0 arrive a round late, the OBU 1 sends no
+        // message in the round 1
+        for round in 0..4 {
+            let mut round_data = HashMap::new();
+            if round > 0 {
+                round_data.insert(0, vec![data(round - 1, round)]);
+            }
+            if round != 1 {
+                round_data.insert(1, vec![data(round, round)]);
+            }
+            rsu_manager.obu_observations.push(round_data);
+        }
+        rsu_manager.set_current_round(4);
+
+        let verdicts = rsu_manager.evaluate_obus().unwrap();
+        assert_eq!(verdicts[0].tx_error_count, 0);
+        assert_eq!(verdicts[1].tx_error_count, 1);
+    }
+
     // TODO: Move message deliver tests from simulator.rs to here
-}mod tests
+}
@@ -0,0 +1,732 @@
+/**
+ * SCENARIO
+ *
+ * A scenario gathers all the parameters of a simulation run, so experiments
+ * can be described in a TOML or JSON file instead of being hardcoded.
+ */
+use crate::arrivals::ArrivalParams;
+use crate::grid::GridParams;
+use crate::interference::InterferenceParams;
+use crate::latency::LatencyParams;
+use crate::mac::MacParams;
+use crate::metrics::MetricsFormat;
+use crate::mobility::{Mobility, MobilityParams};
+use crate::obu_manager::ObuManagerParams;
+use crate::propagation::PropagationParams;
+use crate::routing::RoutingParams;
+use crate::rsu_manager::RsuManagerParams;
+use crate::simulator::{Simulator, SimulatorParams};
+use crate::speed::SpeedParams;
+use crate::sweep::SweepParams;
+use crate::traffic_lights::TrafficLightParams;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+ * OutputParams represents where the artifacts of a run are written.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputParams {
+    #[serde(default = "OutputParams::default_dir")]
+    pub dir: PathBuf, // Directory where the run directories are created
+    #[serde(default = "OutputParams::default_artifacts")]
+    pub artifacts: bool, // Write the artifacts of every run and replication
+    #[serde(default)]
+    pub metrics: Option<MetricsFormat>, // Format of the per-round metrics artifact, none if unset
+    #[serde(default)]
+    pub trace: bool, // Write the trace of every run and replication as an artifact
+}
+
+/**
+ * Scenario represents the complete configuration of a simulation run.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub rounds: usize, // Number of rounds to simulate
+    pub simulator: SimulatorParams,
+    pub grid: GridParams,
+    pub rsu_manager: RsuManagerParams,
+    pub obu_manager: ObuManagerParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arrivals: Option<ArrivalParams>, // Arrival process of the vehicles, instead of keeping the max OBUs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic_lights: Option<TrafficLightParams>, // Signals of the intersections, none if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<SpeedParams>, // Speed model of the vehicles, one cell per round if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingParams>, // Destinations of the vehicles, random walk if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub propagation: Option<PropagationParams>, // Propagation model of the messages, a unit disc if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interference: Option<InterferenceParams>, // Interference of the concurrent messages, none if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<MacParams>, // Channel access of the nodes, all at once if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyParams>, // Delays of the messages, delivered in the next round if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mobility: Option<MobilityParams>, // Mobility trace driving the OBUs instead of the random walk
+    #[serde(default)]
+    pub output: OutputParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sweep: Option<SweepParams>, // Fields swept by the sweep subcommand
+}
+
+/**
+ * ScenarioError represents the errors found while loading a scenario.
+ */
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(PathBuf, std::io::Error), // The scenario file could not be read
+    UnsupportedFormat(PathBuf),  // The file extension is neither .toml nor .json
+    Parse(String),               // The scenario is not valid TOML/JSON or misses fields
+    Invalid(String),             // A field has a value the simulator can't work with
+    Mobility(PathBuf, String),   // The mobility trace file is not a valid trace
+    Osm(PathBuf, String),        // The OpenStreetMap file is not a valid extract
+}
+
+/**
+ * OutputParams implementation.
+ */
+impl OutputParams {
+    /**
+     * Default output directory.
+     */
+    fn default_dir() -> PathBuf {
+        PathBuf::from("results")
+    }
+
+    /**
+     * The artifacts are written by default.
+     */
+    fn default_artifacts() -> bool {
+        true
+    }
+}
+
+impl Default for OutputParams {
+    fn default() -> Self {
+        OutputParams {
+            dir: OutputParams::default_dir(),
+            artifacts: OutputParams::default_artifacts(),
+            metrics: None,
+            trace: false,
+        }
+    }
+}
+
+/**
+ * Scenario implementation.
+ */
+impl Scenario {
+    /**
+     * Load a scenario from a file. The format is chosen by the file extension.
+     * The OSM extract of the grid is relative to the directory of the file,
+     * it is made absolute so the copies of the scenario find it too.
+     */
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        let path = path.as_ref();
+
+        // Check the format before reading the file
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if extension != Some("toml") && extension != Some("json") {
+            return Err(ScenarioError::UnsupportedFormat(path.to_path_buf()));
+        }
+
+        // Read the whole file
+        let contents =
+            fs::read_to_string(path).map_err(|e| ScenarioError::Io(path.to_path_buf(), e))?;
+
+        // Parse it according to its extension
+        let mut scenario: Scenario = if extension == Some("toml") {
+            toml::from_str(&contents).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        } else {
+            serde_json::from_str(&contents).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        };
+
+        // Find the extract from the directory of the scenario file
+        if let Some(osm) = scenario.grid.osm.as_mut() {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            osm.file = std::path::absolute(dir.join(&osm.file))
+                .map_err(|e| ScenarioError::Io(path.to_path_buf(), e))?;
+        }
+
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /**
+     * Parse and validate a scenario in TOML format.
+     */
+    pub fn from_toml_str(contents: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario =
+            toml::from_str(contents).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /**
+     * Parse and validate a scenario in JSON format.
+     */
+    pub fn from_json_str(contents: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario =
+            serde_json::from_str(contents).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /**
+     * Check that the values of the scenario can be simulated.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.rounds == 0 {
+            return Err(ScenarioError::Invalid(
+                "rounds must be greater than 0".to_string(),
+            ));
+        }
+
+        // The seed is written to the scenario copies, TOML integers are signed
+        if self.simulator.seed > i64::MAX as u64 {
+            return Err(ScenarioError::Invalid(format!(
+                "simulator.seed must be at most {}",
+                i64::MAX
+            )));
+        }
+
+        // The blocks per street and block size are only used without a map,
+        // an OSM extract and the size of every block
+        let uses_blocks = self.grid.map.is_none()
+            && self.grid.osm.is_none()
+            && (self.grid.block_widths.is_empty() || self.grid.block_heights.is_empty());
+        if uses_blocks && (self.grid.blocks_per_street == 0 || self.grid.block_size == 0) {
+            return Err(ScenarioError::Invalid(
+                "grid.blocks_per_street and grid.block_size must be greater than 0".to_string(),
+            ));
+        }
+
+        // The grid is built once for the sections placed on it
+        let grid = self.grid.validate()?;
+
+        if self.rsu_manager.tx_range == 0 || self.rsu_manager.rx_range == 0 {
+            return Err(ScenarioError::Invalid(
+                "rsu_manager.tx_range and rsu_manager.rx_range must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(max_obus) = self.obu_manager.max_obus {
+            if max_obus == 0 {
+                return Err(ScenarioError::Invalid(
+                    "obu_manager.max_obus must be greater than 0".to_string(),
+                ));
+            }
+
+            if self.obu_manager.faulty_obus > max_obus {
+                return Err(ScenarioError::Invalid(format!(
+                    "obu_manager.faulty_obus ({}) can't be greater than obu_manager.max_obus ({})",
+                    self.obu_manager.faulty_obus, max_obus
+                )));
+            }
+        }
+
+        // All the failure rates are probabilities
+        let rates = [
+            (
+                "tx_base_failure_rate",
+                self.obu_manager.tx_base_failure_rate,
+            ),
+            (
+                "tx_faulty_obu_failure_rate",
+                self.obu_manager.tx_faulty_obu_failure_rate,
+            ),
+            ("gps_failure_rate", self.obu_manager.gps_failure_rate),
+            (
+                "gps_faulty_obu_failure_rate",
+                self.obu_manager.gps_faulty_obu_failure_rate,
+            ),
+        ];
+        for (name, rate) in rates {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ScenarioError::Invalid(format!(
+                    "obu_manager.{} must be between 0 and 1, got {}",
+                    name, rate
+                )));
+            }
+        }
+
+        if let Some(arrivals) = &self.arrivals {
+            if self.mobility.is_some() {
+                return Err(ScenarioError::Invalid(
+                    "arrivals can't be used with mobility, the vehicles come from the trace"
+                        .to_string(),
+                ));
+            }
+
+            arrivals.validate(&grid.get_entry_points())?;
+        }
+
+        if let Some(traffic_lights) = &self.traffic_lights {
+            if self.mobility.is_some() {
+                return Err(ScenarioError::Invalid(
+                    "traffic_lights can't be used with mobility, the vehicles come from the trace"
+                        .to_string(),
+                ));
+            }
+
+            traffic_lights.validate(&grid.get_intersections())?;
+        }
+
+        if let Some(speed) = &self.speed {
+            if self.mobility.is_some() {
+                return Err(ScenarioError::Invalid(
+                    "speed can't be used with mobility, the vehicles come from the trace"
+                        .to_string(),
+                ));
+            }
+
+            speed.validate()?;
+        }
+
+        if let Some(routing) = &self.routing {
+            if self.mobility.is_some() {
+                return Err(ScenarioError::Invalid(
+                    "routing can't be used with mobility, the vehicles come from the trace"
+                        .to_string(),
+                ));
+            }
+
+            routing.validate(&grid)?;
+        }
+
+        if let Some(propagation) = &self.propagation {
+            propagation.validate()?;
+        }
+
+        if let Some(interference) = &self.interference {
+            interference.validate()?;
+        }
+
+        if let Some(mac) = &self.mac {
+            mac.validate()?;
+        }
+
+        if let Some(latency) = &self.latency {
+            latency.validate()?;
+        }
+
+        if let Some(mobility) = &self.mobility {
+            mobility.validate()?;
+        }
+
+        // The sweep must expand to at least one combination
+        if let Some(sweep) = &self.sweep {
+            sweep.combinations()?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Set a field of the scenario from its textual value. The field is named
+     * by its section and name, as in `obu_manager.faulty_obus`, or only by its
+     * name when no other section has a field with the same name. The scenario
+     * is not validated, call validate() after setting all the fields.
+     */
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), ScenarioError> {
+        // Work on the serialized scenario, so every field can be set by name
+        let mut tree = serde_json::to_value(&*self).expect("Failed to serialize scenario");
+        let root = tree.as_object_mut().expect("Scenario must be an object");
+
+        // Find the section of the field
+        let (section, field) = match name.split_once('.') {
+            Some((section, field)) => (Some(section.to_string()), field),
+            None if root.get(name).is_some_and(|value| !value.is_object()) => (None, name),
+            None => {
+                let sections: Vec<String> = root
+                    .iter()
+                    .filter(|(_, value)| value.get(name).is_some())
+                    .map(|(section, _)| section.clone())
+                    .collect();
+
+                if sections.len() > 1 {
+                    return Err(ScenarioError::Invalid(format!(
+                        "ambiguous scenario field {}, use one of: {}",
+                        name,
+                        sections
+                            .iter()
+                            .map(|section| format!("{}.{}", section, name))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )));
+                }
+
+                (sections.into_iter().next(), name)
+            }
+        };
+
+        // Get the current value of the field
+        let slot = match section {
+            Some(section) => root
+                .get_mut(&section)
+                .and_then(|section| section.get_mut(field)),
+            None => root.get_mut(field),
+        }
+        .filter(|slot| !slot.is_object())
+        .ok_or_else(|| ScenarioError::Invalid(format!("unknown scenario field {}", name)))?;
+
+        // Keep textual fields as text, parse the others as JSON values
+        *slot = if slot.is_string() {
+            Value::String(value.to_string())
+        } else {
+            serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+        };
+
+        // Rebuild the scenario
+        *self = serde_json::from_value(tree)
+            .map_err(|e| ScenarioError::Parse(format!("{} = {}: {}", name, value, e)))?;
+
+        Ok(())
+    }
+
+    /**
+     * Create a simulator configured with this scenario. The mobility trace,
+     * if any, is loaded from its file.
+     */
+    pub fn create_simulator(&self) -> Result<Simulator, ScenarioError> {
+        let mut simulator = Simulator::new(
+            self.grid.clone(),
+            self.rsu_manager.clone(),
+            self.obu_manager.clone(),
+            self.simulator.clone(),
+        );
+
+        // The metrics are only collected to be written as an artifact
+        simulator.set_collect_metrics(self.output.artifacts && self.output.metrics.is_some());
+
+        if let Some(params) = &self.arrivals {
+            simulator.set_arrivals(params);
+        }
+
+        if let Some(params) = &self.traffic_lights {
+            simulator.set_traffic_lights(params);
+        }
+
+        if let Some(params) = &self.speed {
+            simulator.set_speed_model(params);
+        }
+
+        if let Some(params) = &self.routing {
+            simulator.set_routing(params);
+        }
+
+        if let Some(params) = &self.propagation {
+            simulator.set_propagation(params);
+        }
+
+        if let Some(params) = &self.interference {
+            simulator.set_interference(params);
+        }
+
+        if let Some(params) = &self.mac {
+            simulator.set_mac(params);
+        }
+
+        if let Some(params) = &self.latency {
+            simulator.set_latency(params);
+        }
+
+        if let Some(params) = &self.mobility {
+            simulator.set_mobility(Some(Mobility::new(params.load()?, params)));
+        }
+
+        Ok(simulator)
+    }
+}
+
+impl Default for Scenario {
+    /**
+     * The default scenario is the one in scenarios/default.toml.
+     */
+    fn default() -> Self {
+        Scenario::from_toml_str(include_str!("../scenarios/default.toml"))
+            .expect("Invalid default scenario")
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(path, e) => {
+                write!(f, "can't read scenario file {}: {}", path.display(), e)
+            }
+            ScenarioError::UnsupportedFormat(path) => write!(
+                f,
+                "unsupported scenario format {} (expected .toml or .json)",
+                path.display()
+            ),
+            ScenarioError::Parse(message) => write!(f, "invalid scenario: {}", message),
+            ScenarioError::Invalid(message) => write!(f, "invalid scenario: {}", message),
+            ScenarioError::Mobility(path, message) => {
+                write!(f, "invalid mobility trace {}: {}", path.display(), message)
+            }
+            ScenarioError::Osm(path, message) => {
+                write!(f, "invalid OSM file {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::arrivals::ArrivalProcess;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 10
+
+        [simulator]
+        seed = 1
+
+        [grid]
+        blocks_per_street = 3
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = false
+
+        [obu_manager]
+        max_obus = 10
+        comms_range = 2
+        tx_base_failure_rate = 0.02
+        tx_faulty_obu_failure_rate = 0.05
+        gps_failure_rate = 0.02
+        gps_faulty_obu_failure_rate = 0.05
+        faulty_obus = 2
+    "#;
+
+    /**
+     * Test loading a TOML scenario.
+     */
+    #[test]
+    fn test_toml_scenario() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        assert_eq!(scenario.rounds, 10);
+        assert_eq!(scenario.simulator.seed, 1);
+        assert_eq!(scenario.grid.blocks_per_street, 3);
+        assert_eq!(scenario.rsu_manager.rx_range, 3);
+        assert!(scenario.rsu_manager.detect_obu_tx_failure);
+        assert_eq!(scenario.obu_manager.faulty_obus, 2);
+        assert_eq!(scenario.output.dir, PathBuf::from("results"));
+        assert!(scenario.output.artifacts);
+    }
+
+    /**
+     * Test loading a JSON scenario.
+     */
+    #[test]
+    fn test_json_scenario() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        let json = serde_json::to_string(&scenario).unwrap();
+
+        let scenario = Scenario::from_json_str(&json).unwrap();
+        assert_eq!(scenario.rounds, 10);
+        assert_eq!(scenario.obu_manager.max_obus, Some(10));
+    }
+
+    /**
+     * Test missing and unknown fields.
+     */
+    #[test]
+    fn test_missing_and_unknown_fields() {
+        let contents = TOML_SCENARIO.replace("block_size = 2", "");
+        match Scenario::from_toml_str(&contents) {
+            Err(ScenarioError::Parse(message)) => assert!(message.contains("block_size")),
+            _ => panic!("a missing field must be reported"),
+        }
+
+        let contents = TOML_SCENARIO.replace("block_size = 2", "block_size = 2\nblock_sise = 2");
+        match Scenario::from_toml_str(&contents) {
+            Err(ScenarioError::Parse(message)) => assert!(message.contains("block_sise")),
+            _ => panic!("an unknown field must be reported"),
+        }
+    }
+
+    /**
+     * Test setting fields by name.
+     */
+    #[test]
+    fn test_set_field() {
+        let mut scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        scenario.set_field("obu_manager.faulty_obus", "4").unwrap();
+        assert_eq!(scenario.obu_manager.faulty_obus, 4);
+
+        scenario.set_field("rx_range", "7").unwrap();
+        assert_eq!(scenario.rsu_manager.rx_range, 7);
+
+        scenario
+            .set_field("detect_obu_gps_failure", "true")
+            .unwrap();
+        assert!(scenario.rsu_manager.detect_obu_gps_failure);
+
+        scenario.set_field("rounds", "20").unwrap();
+        assert_eq!(scenario.rounds, 20);
+
+        scenario.set_field("dir", "results/run").unwrap();
+        assert_eq!(scenario.output.dir, PathBuf::from("results/run"));
+
+        // the cap of the OBUs can be removed
+        scenario.set_field("max_obus", "null").unwrap();
+        assert_eq!(scenario.obu_manager.max_obus, None);
+
+        assert!(scenario.set_field("grid.unknown", "1").is_err());
+        assert!(scenario.set_field("grid", "1").is_err());
+        assert!(scenario.set_field("block_size", "two").is_err());
+    }
+
+    /**
+     * Test the default scenario.
+     */
+    #[test]
+    fn test_default_scenario() {
+        let scenario = Scenario::default();
+        assert_eq!(scenario.rounds, 180);
+        assert_eq!(scenario.obu_manager.max_obus, Some(120));
+    }
+
+    /**
+     * Test that every example scenario is valid.
+     */
+    #[test]
+    fn test_example_scenarios() {
+        for entry in fs::read_dir("scenarios").unwrap() {
+            let path = entry.unwrap().path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if extension != Some("toml") && extension != Some("json") {
+                continue;
+            }
+
+            let scenario =
+                Scenario::from_file(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            assert!(scenario.validate().is_ok(), "{}", path.display());
+        }
+    }
+
+    /**
+     * Test the rush hour example scenario.
+     */
+    #[test]
+    fn test_rush_hour_scenario() {
+        let mut scenario = Scenario::from_file("scenarios/rush_hour.toml").unwrap();
+        assert_eq!(scenario.obu_manager.max_obus, None);
+        let arrivals = scenario.arrivals.clone().unwrap();
+        assert_eq!(arrivals.process, ArrivalProcess::Profile);
+        assert_eq!(arrivals.profile[1], (120, 4.0));
+
+        // the vehicles come from either the arrivals or the mobility trace
+        scenario.mobility = Some(MobilityParams::new(PathBuf::from("trace.csv")));
+        assert!(matches!(
+            scenario.validate(),
+            Err(ScenarioError::Invalid(_))
+        ));
+    }
+
+    /**
+     * Test the town scenario, whose grid is an ASCII map.
+     */
+    #[test]
+    fn test_town_scenario() {
+        let mut scenario = Scenario::from_file("scenarios/town.toml").unwrap();
+        let road_map = scenario.grid.get_road_map().unwrap();
+        assert_eq!((road_map.get_width(), road_map.get_height()), (25, 17));
+
+        // the map is kept in the copies of the scenario
+        let copy = Scenario::from_toml_str(&toml::to_string(&scenario).unwrap()).unwrap();
+        assert_eq!(copy.grid.map, scenario.grid.map);
+
+        // without the map, the blocks are needed
+        scenario.set_field("grid.map", "null").unwrap();
+        assert!(matches!(
+            scenario.validate(),
+            Err(ScenarioError::Invalid(_))
+        ));
+    }
+
+    /**
+     * Test the village scenario, whose grid is an OpenStreetMap extract.
+     */
+    #[test]
+    fn test_village_scenario() {
+        let mut scenario = Scenario::from_file("scenarios/village.toml").unwrap();
+        let road_map = scenario.grid.get_road_map().unwrap();
+        assert_eq!((road_map.get_width(), road_map.get_height()), (46, 53));
+
+        // the extract is found from the directory of the scenario, and kept
+        // in the copies of the scenario
+        let copy = Scenario::from_toml_str(&toml::to_string(&scenario).unwrap()).unwrap();
+        assert_eq!(
+            copy.grid.osm.map(|osm| osm.file),
+            Some(std::path::absolute("scenarios/village.osm").unwrap())
+        );
+
+        // an extract that can't be read or parsed is rejected
+        let osm = scenario.grid.osm.as_mut().unwrap();
+        osm.file = PathBuf::from("scenarios/missing.osm");
+        assert!(matches!(
+            scenario.validate(),
+            Err(ScenarioError::Invalid(_))
+        ));
+        let osm = scenario.grid.osm.as_mut().unwrap();
+        osm.file = PathBuf::from("scenarios/town.toml");
+        assert!(matches!(scenario.validate(), Err(ScenarioError::Osm(_, _))));
+    }
+
+    /**
+     * Test scenario validation.
+     */
+    #[test]
+    fn test_invalid_scenario() {
+        let contents = TOML_SCENARIO.replace("faulty_obus = 2", "faulty_obus = 20");
+        assert!(matches!(
+            Scenario::from_toml_str(&contents),
+            Err(ScenarioError::Invalid(_))
+        ));
+
+        let contents = TOML_SCENARIO.replace("gps_failure_rate = 0.02", "gps_failure_rate = 2.0");
+        assert!(matches!(
+            Scenario::from_toml_str(&contents),
+            Err(ScenarioError::Invalid(_))
+        ));
+
+        // seeds written to a scenario copy must fit in a TOML integer
+        let mut scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        scenario.simulator.seed = u64::MAX;
+        assert!(matches!(
+            scenario.validate(),
+            Err(ScenarioError::Invalid(_))
+        ));
+
+        assert!(matches!(
+            Scenario::from_file("scenario.yaml"),
+            Err(ScenarioError::UnsupportedFormat(_))
+        ));
+
+        assert!(matches!(
+            Scenario::from_file("missing_scenario.toml"),
+            Err(ScenarioError::Io(_, _))
+        ));
+    }
+}
@@ -1,36 +1,102 @@
-use crate::comms::Ether;
-use crate::grid::{Coordinate, Grid, GridParams};
+use crate::arrivals::{ArrivalParams, Arrivals};
+use crate::comms::{Ether, Message};
+use crate::grid::{Coordinate, Grid, GridParams, Position};
+use crate::interference::{Interference, InterferenceParams};
+use crate::latency::{Latency, LatencyParams};
+use crate::mac::{Mac, MacParams, MacStats};
+use crate::metrics::RoundMetrics;
+use crate::mobility::Mobility;
 use crate::obu_manager::{ObuManagerParams, OnBoardUnitManager};
+use crate::propagation::{Propagation, PropagationParams};
+use crate::report::{ConfusionMatrix, ObuVerdict, SimulationReport};
+use crate::routing::{Routing, RoutingParams};
 use crate::rsu_manager::{RoadSideUnitManager, RsuManagerParams};
+use crate::speed::{SpeedModel, SpeedParams};
+use crate::trace::{
+    MessageStatus, TraceDelivery, TraceHeader, TraceObu, TraceReader, TraceRound, TraceWriter,
+    TRACE_VERSION,
+};
+use crate::traffic_lights::{TrafficLightParams, TrafficLights};
 use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NodeType {
     OBU,
     RSU,
 }
 
+/**
+ * SimulatorParams represents the parameters of the simulation itself.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatorParams {
+    pub seed: u64, // Seed of the random number generators, the same seed reproduces the same run
+    #[serde(default = "SimulatorParams::default_exit_at_border")]
+    pub exit_at_border: bool, // OBUs at the end of a street leave the grid and are replaced
+}
+
+/**
+ * SimulatorParams implementation.
+ */
+impl SimulatorParams {
+    /**
+     * The OBUs leave the grid at its border by default.
+     */
+    fn default_exit_at_border() -> bool {
+        true
+    }
+}
+
 pub struct Simulator {
     obu_manager: OnBoardUnitManager,
     rsu_manager: RoadSideUnitManager,
     grid: Grid,
     round: u32,
     ether: Ether,
+    seed: u64,
+    exit_at_border: bool, // OBUs at an exit cell leave the grid, instead of parking there
+    rng: StdRng,          // Random number generator used for the OBUs movement
+    quiet: bool,          // Do not print the simulation progress
+    collect_metrics: bool, // Collect the metrics of every round
+    metrics: Vec<RoundMetrics>, // Metrics of the rounds run so far
+    trace: Option<TraceWriter>, // Writer of the trace of the run, none if not traced
+    mobility: Option<Mobility>, // Mobility trace driving the OBUs, random walk if none
+    arrivals: Option<Arrivals>, // Arrival process of the vehicles, kept at the max OBUs if none
+    speed_model: Option<SpeedModel>, // Speeds of the vehicles, one cell per round if none
+    routing: Option<Routing>, // Destinations and paths of the vehicles, random walk if none
 }
 
 impl Simulator {
     /**
-     * Create a new Simulator
+     * Create a new Simulator. Every random choice of the simulation derives
+     * from the seed given in the simulator params: the optional models set
+     * afterwards each get their own random number generator, derived from
+     * it.
      */
     pub fn new(
         grid_params: GridParams,
         rsu_manager_params: RsuManagerParams,
         obu_manager_params: ObuManagerParams,
+        simulator_params: SimulatorParams,
     ) -> Simulator {
+        // create the root random number generator and derive the OBU manager one
+        let mut rng = StdRng::seed_from_u64(simulator_params.seed);
+        let obu_manager_rng = StdRng::seed_from_u64(rng.gen());
+
         let grid = Grid::new(grid_params);
-        let obu_manager = OnBoardUnitManager::new(obu_manager_params, grid.get_dimension());
+        let obu_manager = OnBoardUnitManager::new(
+            obu_manager_params,
+            grid.get_width(),
+            grid.get_height(),
+            obu_manager_rng,
+        );
         let rsu_manager = RoadSideUnitManager::new(rsu_manager_params);
 
         Simulator {
@@ -39,7 +105,148 @@ impl Simulator {
             grid,
             round: 0,
             ether: Ether::new(),
+            seed: simulator_params.seed,
+            exit_at_border: simulator_params.exit_at_border,
+            rng,
+            quiet: false,
+            collect_metrics: false,
+            metrics: Vec::new(),
+            trace: None,
+            mobility: None,
+            arrivals: None,
+            speed_model: None,
+            routing: None,
+        }
+    }
+
+    /**
+     * Enable or disable the quiet mode. In quiet mode only the final stats
+     * are printed.
+     */
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /**
+     * Enable or disable the collection of the per-round metrics, disabled by
+     * default. The metrics are added to the simulation report.
+     */
+    pub fn set_collect_metrics(&mut self, collect_metrics: bool) {
+        self.collect_metrics = collect_metrics;
+    }
+
+    /**
+     * Set the writer of the trace of the run, the run is not traced by default.
+     */
+    pub fn set_trace(&mut self, trace: Option<TraceWriter>) {
+        self.trace = trace;
+    }
+
+    /**
+     * Set the mobility trace driving the OBUs, instead of the random walk.
+     */
+    pub fn set_mobility(&mut self, mobility: Option<Mobility>) {
+        self.mobility = mobility;
+    }
+
+    /**
+     * Set the arrival process of the vehicles, instead of adding OBUs up to
+     * the maximum number of OBUs in every round.
+     */
+    pub fn set_arrivals(&mut self, params: &ArrivalParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.arrivals = Some(Arrivals::new(params, self.grid.get_entry_points(), rng));
+    }
+
+    /**
+     * Set the speed model of the vehicles, instead of driving one cell per
+     * round.
+     */
+    pub fn set_speed_model(&mut self, params: &SpeedParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.speed_model = Some(SpeedModel::new(params, rng));
+    }
+
+    /**
+     * Set the routing of the vehicles, so they drive to a destination along
+     * a shortest path instead of at random.
+     */
+    pub fn set_routing(&mut self, params: &RoutingParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.routing = Some(Routing::new(params, &self.grid, rng));
+    }
+
+    /**
+     * Set the propagation model of the messages, instead of delivering them
+     * to every node in the range of their transmitter, over the buildings of
+     * the grid.
+     */
+    pub fn set_propagation(&mut self, params: &PropagationParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.ether
+            .set_propagation(Propagation::new(params, &self.grid, rng));
+    }
+
+    /**
+     * Set the interference of the concurrent messages, so the messages sent in
+     * the same round collide at the receivers instead of all being delivered.
+     */
+    pub fn set_interference(&mut self, params: &InterferenceParams) {
+        self.ether.set_interference(Some(Interference::new(params)));
+    }
+
+    /**
+     * Set the channel access of the nodes, so the messages of a round are sent
+     * in time slots after a random backoff instead of all at once.
+     */
+    pub fn set_mac(&mut self, params: &MacParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.ether.set_mac(Some(Mac::new(params, rng)));
+    }
+
+    /**
+     * Set the latency of the messages, so they arrive after a random number
+     * of rounds instead of in the next round, or expire past a deadline.
+     */
+    pub fn set_latency(&mut self, params: &LatencyParams) {
+        let rng = StdRng::seed_from_u64(self.rng.gen());
+        self.ether.set_latency(Some(Latency::new(params, rng)));
+    }
+
+    /**
+     * Set the signals of the intersections of the grid, which has none by
+     * default.
+     */
+    pub fn set_traffic_lights(&mut self, params: &TrafficLightParams) {
+        let traffic_lights = TrafficLights::new(params, &self.grid);
+        self.grid.set_traffic_lights(Some(traffic_lights));
+    }
+
+    /**
+     * Print the grid stats, the RSUs coordinates and a map of the grid.
+     */
+    pub fn print_layout(&self) {
+        self.grid.print_stats(None);
+
+        println!("--- RSUs ---");
+        println!("Number of RSUs: {}", self.rsu_manager.rsus.len());
+        let mut rsu_coordinates = Vec::new();
+        for rsu in self.rsu_manager.rsus.values() {
+            let coordinate = rsu.get_coordinate();
+            println!("{:03}	({},{})", rsu.get_id(), coordinate.x, coordinate.y);
+            rsu_coordinates.push(coordinate);
+        }
+
+        if let Some(traffic_lights) = self.grid.get_traffic_lights() {
+            println!("--- Traffic lights ---");
+            println!(
+                "Number of traffic lights: {}",
+                traffic_lights.get_signal_count()
+            );
         }
+
+        println!("--- Map ---");
+        self.grid.print_map(&rsu_coordinates);
     }
 
     /**
@@ -64,43 +271,42 @@ impl Simulator {
             "RSUs already added to the grid."
         );
 
+        // place the rsus on a lattice covering the grid, skipping the ones with
+        // no street in their range
         let rx_range: u32 = self.rsu_manager.get_rx_range();
-        let mut next_coordinate = Coordinate {
-            x: rx_range - 1,
-            y: rx_range - 1,
-        };
-        let mut last_added_id = 0;
-
-        // add rsus to the grid
-        while next_coordinate.x < self.grid.get_dimension() {
-            while next_coordinate.y < self.grid.get_dimension() {
-                last_added_id = self.rsu_manager.create_rsu(next_coordinate.clone());
-                next_coordinate.y += (rx_range * 2) - 1;
-
-                // if the next coordinate is out of bounds
-                if next_coordinate.y >= self.grid.get_dimension() {
-                    let last_added = self.rsu_manager.rsus.get(&last_added_id).unwrap();
-
-                    // check if the last added rsu range do not cover the grid border
-                    if last_added.get_coordinate().y + rx_range < self.grid.get_dimension() {
-                        next_coordinate.y = self.grid.get_dimension() - 1;
-                    }
+        for x in Self::get_rsu_lattice(self.grid.get_width(), rx_range) {
+            for y in Self::get_rsu_lattice(self.grid.get_height(), rx_range) {
+                let coordinate = Coordinate { x, y };
+                if self
+                    .grid
+                    .has_street_in(self.grid.get_square_coords(coordinate, rx_range))
+                {
+                    self.rsu_manager.create_rsu(coordinate);
                 }
             }
+        }
+    }
 
-            next_coordinate.y = rx_range - 1;
-            next_coordinate.x += (rx_range * 2) - 1;
+    /**
+     * Get the coordinates of the RSUs along a side of the grid of the given
+     * length. The ranges of the RSUs touch each other, and an RSU is added at
+     * the end of the side if the last range doesn't reach it.
+     */
+    fn get_rsu_lattice(length: u32, rx_range: u32) -> Vec<u32> {
+        let mut lattice = Vec::new();
+        let mut next = rx_range - 1;
 
-            // if the next coordinate is out of bounds
-            if next_coordinate.x >= self.grid.get_dimension() {
-                let last_added = self.rsu_manager.rsus.get(&last_added_id).unwrap();
+        while next < length {
+            lattice.push(next);
+            next += (rx_range * 2) - 1;
 
-                // check if the last added rsu range do not cover the grid border
-                if last_added.get_coordinate().x + rx_range < self.grid.get_dimension() {
-                    next_coordinate.x = self.grid.get_dimension() - 1;
-                }
+            // check if the last added rsu range do not cover the grid border
+            if next >= length && lattice[lattice.len() - 1] + rx_range < length {
+                next = length - 1;
             }
         }
+
+        lattice
     }
 
     /**
@@ -114,139 +320,635 @@ impl Simulator {
         self.rsu_manager.set_current_round(0);
         self.obu_manager.set_current_round(0);
 
+        if self.quiet {
+            return;
+        }
+
         println!("--- SIMULATION INITIALIZED ---");
         println!("Number of RSUs: {}", self.rsu_manager.rsus.len());
-        println!("Number of OBUs: {}", self.obu_manager.get_max_obus());
+        match self.obu_manager.get_max_obus() {
+            Some(max_obus) => println!("Number of OBUs: {}", max_obus),
+            None => println!("Number of OBUs: no cap"),
+        }
+        if let Some(traffic_lights) = self.grid.get_traffic_lights() {
+            println!(
+                "Number of traffic lights: {}",
+                traffic_lights.get_signal_count()
+            );
+        }
 
-        self.grid.print_stats(Some(self.obu_manager.get_max_obus()));
+        self.grid.print_stats(self.obu_manager.get_max_obus());
     }
 
     /**
-     * Run the simulation
+     * Run the simulation and return its report.
      */
-    pub fn run(&mut self, rounds: usize) {
-        println!("--- SIMULATION RUNNING ---");
+    pub fn run(&mut self, rounds: usize) -> SimulationReport {
+        if !self.quiet {
+            println!("--- SIMULATION RUNNING ---");
+        }
 
         // Collect messages for the first round
         if self.round == 0 {
+            self.write_trace_header();
             self.collect_messages();
         }
 
         // Run the simulation for the given number of rounds
         for _ in 0..rounds {
-            // Deliver messages from the previous round
-            self.deliver_messages();
-
-            // Move obus
-            self.do_obus_moves();
-
-            // Add new obus if needed and possible
-            let mut added_obus = 0;
-            while self.obu_manager.obus.len() < self.obu_manager.get_max_obus() as usize {
-                match self.add_on_board_unit() {
-                    Some(_) => added_obus += 1,
-                    None => break,
-                }
+            self.step();
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush().expect("Failed to write the trace");
+        }
+
+        self.finish()
+    }
+
+    /**
+     * Replay a recorded trace and return the report of the replayed run. The
+     * OBUs movements and messages come from the trace instead of being drawn,
+     * so the RSUs observe exactly the same inputs as in the recorded run and
+     * only the detector settings can change the verdicts. The grid, the RSUs
+     * and the OBU range of the scenario must match the ones of the trace.
+     */
+    pub fn replay(&mut self, trace: TraceReader) -> io::Result<SimulationReport> {
+        assert_eq!(self.round, 0, "Simulation already started.");
+
+        // The recorded messages are only meaningful with the same layout
+        let header = trace.get_header();
+        let layout = (
+            self.grid.get_width(),
+            self.grid.get_height(),
+            self.rsu_manager.rsus.len() as u32,
+            self.obu_manager.get_comms_range(),
+        );
+        let recorded = (
+            header.grid_width,
+            header.grid_height,
+            header.rsu_count,
+            header.obu_comms_range,
+        );
+        if recorded != layout {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the trace doesn't match the scenario: (grid width, grid height, RSUs, OBU range) is {:?} in the trace and {:?} in the scenario",
+                    recorded, layout
+                ),
+            ));
+        }
+        self.seed = header.seed;
+
+        if !self.quiet {
+            println!("--- SIMULATION REPLAYING ---");
+        }
+
+        for round in trace {
+            self.replay_step(&round?)?;
+        }
+
+        Ok(self.finish())
+    }
+
+    /**
+     * Print the final stats and return the report of the run.
+     */
+    fn finish(&self) -> SimulationReport {
+        if !self.quiet {
+            println!("--- SIMULATION FINISHED ---");
+            self.obu_manager.print_stats();
+        }
+
+        let mut confusion_matrix = ConfusionMatrix::default();
+        let mut verdicts = Vec::new();
+
+        // Get the detector verdicts from the rsu manager, none if the RSUs
+        // observed too few OBUs
+        let evaluation = self.rsu_manager.evaluate_obus();
+        let detector_skipped = evaluation.is_none();
+        let mut detector_verdicts = evaluation.unwrap_or_default();
+        if !self.quiet {
+            if detector_skipped {
+                println!("--- Too few OBUs observed by the RSUs to detect faulty ones ---");
+            } else {
+                RoadSideUnitManager::print_faulty_obus(&detector_verdicts);
+            }
+        }
+
+        // Check RSU predictions, including the OBUs that left the grid
+        for obu in self.obu_manager.get_evaluated_obus() {
+            let is_faulty = obu.is_faulty();
+
+            // Find the verdict of the obu, if the RSUs ever observed it
+            let detector = detector_verdicts
+                .iter()
+                .position(|verdict| verdict.obu_id == obu.get_id())
+                .map(|index| detector_verdicts.swap_remove(index));
+            let detected_faulty = detector
+                .as_ref()
+                .is_some_and(|verdict| verdict.detected_faulty);
+
+            confusion_matrix.add(is_faulty, detected_faulty);
+
+            verdicts.push(ObuVerdict {
+                obu_id: obu.get_id(),
+                is_faulty,
+                detected_faulty,
+                detector,
+                mac: self.ether.get_mac_stats(obu.get_id()),
+            });
+        }
+
+        if !self.quiet {
+            Self::print_mac_stats(&verdicts);
+        }
+
+        SimulationReport {
+            seed: self.seed,
+            rounds: self.round,
+            confusion_matrix,
+            rates: confusion_matrix.rates(),
+            obu_tx_stats: self.obu_manager.get_stats(),
+            grid_stats: self.grid.get_stats(),
+            verdicts,
+            detector_skipped,
+            round_metrics: self.metrics.clone(),
+        }
+    }
+
+    /**
+     * Print the channel access of the normal and faulty OBUs, if there is a
+     * MAC layer.
+     */
+    fn print_mac_stats(verdicts: &[ObuVerdict]) {
+        if verdicts.iter().all(|verdict| verdict.mac.is_none()) {
+            return;
+        }
+
+        println!("--- MAC Stats ---");
+        for (name, is_faulty) in [("Normal", false), ("Faulty", true)] {
+            let mut stats = MacStats::default();
+            for mac in verdicts
+                .iter()
+                .filter(|verdict| verdict.is_faulty == is_faulty)
+                .filter_map(|verdict| verdict.mac)
+            {
+                stats.accesses += mac.accesses;
+                stats.access_delay += mac.access_delay;
+                stats.losses += mac.losses;
             }
 
-            // Print the number of added obus
-            if added_obus > 0 {
-                println!("Added {} new OBUs in round {}.", added_obus, self.round);
+            // A class without OBUs, or without messages, has no rates
+            let attempts = stats.accesses + stats.losses;
+            if attempts == 0 {
+                continue;
             }
 
-            // Update the round
-            self.round += 1;
+            println!(
+                "{} OBU TX: {} / losses {} ({:.2}%), mean access delay {:.2} slots",
+                name,
+                attempts,
+                stats.losses,
+                stats.losses as f32 / attempts as f32 * 100.0,
+                stats.mean_access_delay().unwrap_or(0.0)
+            );
+        }
+    }
 
-            // Update the current round for the managers
-            self.rsu_manager.set_current_round(self.round);
-            self.obu_manager.set_current_round(self.round);
+    /**
+     * Run a single round of the simulation.
+     */
+    fn step(&mut self) {
+        // Deliver messages from the previous round
+        self.deliver_messages();
+
+        // Count the deliveries before the OBUs move
+        let mut metrics = self.collect_metrics.then(|| self.delivery_metrics());
+        let deliveries = self.trace.is_some().then(|| self.trace_deliveries());
+
+        let obus_before = self.obu_manager.get_obus_count();
+        let mut added_obus = 0;
+        let mut stopped_obus = 0;
+        if let Some(mobility) = self.mobility.as_mut() {
+            // Move, add and remove obus along the mobility trace
+            added_obus = mobility.move_obus(self.round, &self.grid, &mut self.obu_manager);
+        } else {
+            // Move obus, then switch the traffic lights given the queues
+            stopped_obus = self.do_obus_moves();
+            self.grid.update_traffic_lights();
+
+            if let Some(arrivals) = self.arrivals.as_mut() {
+                // Add the obus of the arrived vehicles
+                added_obus = arrivals.add_obus(self.round, &mut self.grid, &mut self.obu_manager);
+            } else {
+                // Add new obus if needed and possible
+                while !self.obu_manager.is_full() {
+                    match self.add_on_board_unit() {
+                        Some(_) => added_obus += 1,
+                        None => break,
+                    }
+                }
+            }
+        }
 
-            // Collect messages for the next round delivery
-            self.collect_messages();
+        // Print the number of removed and added obus
+        let removed_obus = obus_before + added_obus - self.obu_manager.get_obus_count();
+        if removed_obus > 0 && !self.quiet {
+            println!(
+                "{} OBUs left the grid in round {}.",
+                removed_obus, self.round
+            );
+        }
+        if added_obus > 0 && !self.quiet {
+            println!("Added {} new OBUs in round {}.", added_obus, self.round);
         }
 
-        println!("--- SIMULATION FINISHED ---");
-        self.obu_manager.print_stats();
+        // Update the round
+        self.round += 1;
 
-        let mut true_positive = 0;
-        let mut false_positive = 0;
-        let mut true_negative = 0;
-        let mut false_negative = 0;
+        // Update the current round for the managers
+        self.rsu_manager.set_current_round(self.round);
+        self.obu_manager.set_current_round(self.round);
 
-        // Get the faulty obus ids from the rsu manager
-        let rsu_faulty_obs = self.rsu_manager.find_faulty_obus();
+        // Collect messages for the next round delivery
+        self.collect_messages();
 
-        // Check RSU predictions
-        for obu in self.obu_manager.obus.values() {
-            let is_faulty = obu.is_faulty();
-            let is_in_rsu_faulty = rsu_faulty_obs.contains(&obu.get_id());
+        // Complete the metrics with the OBUs and the sent messages
+        if let Some(mut metrics) = metrics.take() {
+            metrics.obus_added = added_obus;
+            metrics.obus_removed = removed_obus;
+            metrics.obus_waiting = self.arrivals.as_ref().map_or(0, Arrivals::get_waiting);
+            metrics.obus_stopped = stopped_obus;
+            self.add_message_metrics(&mut metrics);
+            self.metrics.push(metrics);
+        }
+
+        // Trace the round with the OBUs and the sent messages
+        if let Some(deliveries) = deliveries {
+            let round = TraceRound {
+                round: self.round - 1,
+                deliveries,
+                obus: self.trace_obus(),
+            };
+            if let Some(trace) = self.trace.as_mut() {
+                trace
+                    .write_round(&round)
+                    .expect("Failed to write the trace");
+            }
+        }
+    }
+
+    /**
+     * Write the header of the trace, if the run is traced.
+     */
+    fn write_trace_header(&mut self) {
+        let header = TraceHeader {
+            version: TRACE_VERSION,
+            seed: self.seed,
+            grid_width: self.grid.get_width(),
+            grid_height: self.grid.get_height(),
+            obu_comms_range: self.obu_manager.get_comms_range(),
+            rsu_count: self.rsu_manager.rsus.len() as u32,
+        };
+
+        if let Some(trace) = self.trace.as_mut() {
+            trace
+                .write_header(&header)
+                .expect("Failed to write the trace");
+        }
+    }
 
-            match (is_faulty, is_in_rsu_faulty) {
-                (true, true) => true_positive += 1,
-                (true, false) => false_negative += 1,
-                (false, true) => false_positive += 1,
-                (false, false) => true_negative += 1,
+    /**
+     * Return the receivers of every OBU message of the ether, found in the
+     * neighbors of the OBUs and RSUs after the delivery. With a latency, only
+     * the receivers the message arrived at without delay are traced.
+     */
+    fn trace_deliveries(&self) -> Vec<TraceDelivery> {
+        let mut deliveries: BTreeMap<u32, TraceDelivery> = BTreeMap::new();
+        for message in self.ether.get_messages() {
+            if let NodeType::OBU = message.origin_type {
+                deliveries.insert(
+                    message.origin_id,
+                    TraceDelivery {
+                        origin_id: message.origin_id,
+                        obus: Vec::new(),
+                        rsus: Vec::new(),
+                    },
+                );
+            }
+        }
+
+        // The OBUs and RSUs are iterated in id order
+        for obu in self.obu_manager.obus.values() {
+            for neighbor in obu.neighbors.iter().filter(|n| n.send_round == self.round) {
+                if let Some(delivery) = deliveries.get_mut(&neighbor.id) {
+                    delivery.obus.push(obu.get_id());
+                }
             }
         }
+        for rsu in self.rsu_manager.rsus.values() {
+            for neighbor in rsu
+                .get_neighbors()
+                .iter()
+                .filter(|n| n.send_round == self.round)
+            {
+                if let Some(delivery) = deliveries.get_mut(&neighbor.id) {
+                    delivery.rsus.push(rsu.get_id());
+                }
+            }
+        }
+
+        deliveries.into_values().collect()
+    }
+
+    /**
+     * Return the state of every OBU and the message it sent to the ether.
+     */
+    fn trace_obus(&self) -> Vec<TraceObu> {
+        let reported: BTreeMap<u32, Coordinate> = self
+            .ether
+            .get_messages()
+            .iter()
+            .filter(|message| matches!(message.origin_type, NodeType::OBU))
+            .map(|message| (message.origin_id, message.coordinate))
+            .collect();
+
+        self.obu_manager
+            .obus
+            .values()
+            .map(|obu| {
+                let coordinate = obu.get_coordinate();
+                let (status, reported) = match reported.get(&obu.get_id()) {
+                    None => (MessageStatus::Dropped, None),
+                    Some(reported) if *reported != coordinate => {
+                        (MessageStatus::GpsFalsified, Some(*reported))
+                    }
+                    Some(_) => (MessageStatus::Sent, None),
+                };
+
+                TraceObu {
+                    id: obu.get_id(),
+                    coordinate,
+                    faulty: obu.is_faulty(),
+                    status,
+                    reported,
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * Replay a round recorded in a trace.
+     */
+    fn replay_step(&mut self, recorded: &TraceRound) -> io::Result<()> {
+        if recorded.round != self.round {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expected round {} in the trace, got round {}",
+                    self.round, recorded.round
+                ),
+            ));
+        }
 
-        // Calculate the total
-        let total = (true_positive + true_negative + false_positive + false_negative) as f32;
-        let total_positive = (false_positive + true_negative) as f32;
-        let total_negative = (false_negative + true_positive) as f32;
+        // Deliver messages from the previous round
+        self.deliver_messages();
+
+        // Count the deliveries before the OBUs move
+        let mut metrics = self.collect_metrics.then(|| self.delivery_metrics());
+
+        // Move, add and remove the obus as recorded, the ones that keep their
+        // coordinate could not move
+        let coordinates: BTreeMap<u32, Coordinate> = self
+            .obu_manager
+            .obus
+            .values()
+            .map(|obu| (obu.get_id(), obu.get_coordinate()))
+            .collect();
+        let added_obus = self.obu_manager.replay_obus(&recorded.obus);
+        let stopped_obus = self
+            .obu_manager
+            .obus
+            .values()
+            .filter(|obu| coordinates.get(&obu.get_id()) == Some(&obu.get_coordinate()))
+            .count() as u32;
+
+        // The OBUs missing from the recorded round left the grid
+        let removed_obus =
+            coordinates.len() as u32 + added_obus - self.obu_manager.get_obus_count();
+        if removed_obus > 0 && !self.quiet {
+            println!(
+                "{} OBUs left the grid in round {}.",
+                removed_obus, self.round
+            );
+        }
+        if added_obus > 0 && !self.quiet {
+            println!("Added {} new OBUs in round {}.", added_obus, self.round);
+        }
 
-        // Calculate the detection rate
-        let detection_rate = (true_positive + true_negative) as f32 / total;
+        // Update the round
+        self.round += 1;
 
-        // Calculate the false positive rate
-        let false_positive_rate = false_positive as f32 / total_positive;
+        // Update the current round for the managers
+        self.rsu_manager.set_current_round(self.round);
+        self.obu_manager.set_current_round(self.round);
 
-        // Calculate the false negative rate
-        let false_negative_rate = false_negative as f32 / total_negative;
+        // Send the recorded messages for the next round delivery
+        let messages = self.obu_manager.replay_messages(&recorded.obus);
+        self.send_messages(messages);
+
+        // Complete the metrics with the OBUs and the sent messages
+        if let Some(mut metrics) = metrics.take() {
+            metrics.obus_added = added_obus;
+            metrics.obus_removed = removed_obus;
+            metrics.obus_stopped = stopped_obus;
+            self.add_message_metrics(&mut metrics);
+            self.metrics.push(metrics);
+        }
 
-        println!("--- FINAL STATS ---");
-        println!("True Positive: {}", true_positive);
-        println!("False Positive: {}", false_positive);
-        println!("True Negative: {}", true_negative);
-        println!("False Negative: {}", false_negative);
-        println!("Detection Rate: {}", detection_rate);
-        println!("False Positive Rate: {}", false_positive_rate);
-        println!("False Negative Rate: {}", false_negative_rate);
+        Ok(())
     }
 
     /**
-     * Move OnBoardUnits.
+     * Return the metrics of a round with the deliveries of the messages of the
+     * previous round.
      */
-    fn do_obus_moves(&mut self) {
-        for obu in self.obu_manager.obus.values_mut() {
-            // get the next possible coordinates for the obu
-            let possible_moves = self.grid.get_possible_moves(obu.get_coordinate());
-
-            // randomly select a coordinate
-            match possible_moves.choose(&mut thread_rng()) {
-                Some(coordinate) => {
-                    obu.set_coordinate(
-                        self.grid.move_obu(obu.get_coordinate(), coordinate.clone()),
-                    );
+    fn delivery_metrics(&self) -> RoundMetrics {
+        let obu_count = self.obu_manager.obus.len();
+        let neighbors: usize = self
+            .obu_manager
+            .obus
+            .values()
+            .map(|obu| obu.neighbors.len())
+            .sum();
+
+        RoundMetrics {
+            round: self.round,
+            obu_count: 0,
+            obus_added: 0,
+            obus_removed: 0,
+            obus_waiting: 0,
+            obus_stopped: 0,
+            messages_sent: 0,
+            messages_dropped: 0,
+            gps_falsified: 0,
+            mac_losses: 0,
+            messages_expired: 0,
+            messages_collided: self.ether.get_collision_count(),
+            messages_late: self.ether.get_late_count(),
+            mean_neighbors: match obu_count {
+                0 => 0.0,
+                _ => neighbors as f32 / obu_count as f32,
+            },
+            rsu_deliveries: self
+                .rsu_manager
+                .rsus
+                .values()
+                .map(|rsu| rsu.get_neighbors().len() as u32)
+                .collect(),
+        }
+    }
+
+    /**
+     * Add the OBUs count and the OBU messages sent to the ether to the metrics.
+     */
+    fn add_message_metrics(&self, metrics: &mut RoundMetrics) {
+        metrics.obu_count = self.obu_manager.get_obus_count();
+
+        // Every OBU tries to send a message in each round
+        for message in self.ether.get_messages() {
+            if let NodeType::OBU = message.origin_type {
+                metrics.messages_sent += 1;
+
+                // A falsified coordinate is always out of the OBU range
+                if message.coordinate != message.phy_coord {
+                    metrics.gps_falsified += 1;
                 }
-                None => (),
+            }
+        }
+        metrics.mac_losses = self.ether.get_mac_loss_count();
+        metrics.messages_expired = self.ether.get_expired_count();
+        metrics.messages_dropped = metrics.obu_count - metrics.messages_sent - metrics.mac_losses;
+    }
+
+    /**
+     * Move OnBoardUnits. Each OBU drives one cell, or the cells given by the
+     * speed model, one at a time, and stops when it can't move further. The
+     * OBUs drive at random, or along the path to their destination with a
+     * routing. The OBUs at an exit cell or at their destination leave the
+     * grid. Return the number of OBUs that did not move.
+     */
+    fn do_obus_moves(&mut self) -> u32 {
+        let mut exited_obus = Vec::new();
+        let mut stopped_obus = 0;
+
+        for obu in self.obu_manager.obus.values_mut() {
+            let id = obu.get_id();
+
+            // get the lane of the obu
+            let Some(mut position) = self.grid.get_position(obu.get_coordinate(), id) else {
+                continue;
+            };
+
+            // check if the obu drives out of the grid or reaches its destination
+            let exit_at_border = self.exit_at_border;
+            let leaves = |grid: &Grid, routing: &mut Option<Routing>, position: Position| {
+                (exit_at_border && grid.is_exit(position))
+                    || routing
+                        .as_mut()
+                        .is_some_and(|routing| routing.is_arrived(id, position.coordinate))
             };
+
+            // the obu drives out of the grid
+            if leaves(&self.grid, &mut self.routing, position) {
+                self.grid.remove_obu(position);
+                exited_obus.push(id);
+                continue;
+            }
+
+            // get the number of cells the obu tries to drive
+            let speed = self
+                .speed_model
+                .as_mut()
+                .map_or(1, |speed_model| speed_model.next_speed(id));
+
+            let mut moved = 0;
+            while moved < speed {
+                // the obu drives out of the grid on its way
+                if moved > 0 && leaves(&self.grid, &mut self.routing, position) {
+                    self.grid.remove_obu(position);
+                    exited_obus.push(id);
+                    break;
+                }
+
+                // get the next possible positions for the obu
+                let possible_moves = self.grid.get_possible_moves(position);
+
+                // select a position on the path of the obu or randomly, the
+                // obu brakes if there is none
+                let next_position = match self.routing.as_mut() {
+                    Some(routing) => routing.choose_move(
+                        id,
+                        position,
+                        &self.grid.get_next_cells(position),
+                        &possible_moves,
+                    ),
+                    None => possible_moves.choose(&mut self.rng).copied(),
+                };
+                let Some(next_position) = next_position else {
+                    break;
+                };
+                obu.set_coordinate(self.grid.move_obu(position, next_position));
+                position = next_position;
+                moved += 1;
+            }
+
+            if moved == 0 {
+                stopped_obus += 1;
+            }
+            if let Some(speed_model) = self.speed_model.as_mut() {
+                speed_model.set_speed(id, moved);
+            }
         }
+
+        // keep the exited obus for the evaluation
+        for id in exited_obus {
+            self.obu_manager.remove_obu(id);
+            if let Some(speed_model) = self.speed_model.as_mut() {
+                speed_model.remove_vehicle(id);
+            }
+            if let Some(routing) = self.routing.as_mut() {
+                routing.remove_vehicle(id);
+            }
+        }
+
+        stopped_obus
     }
 
     /**
      * Collect messages from OBUs and RSUs and send them to the Ether.
      */
     fn collect_messages(&mut self) {
+        let messages = self.obu_manager.collect_messages();
+        self.send_messages(messages);
+    }
+
+    /**
+     * Send the given OBU messages and the messages of the RSUs to the Ether.
+     */
+    fn send_messages(&mut self, messages: Vec<Message>) {
         // Clear the ether
         self.ether.clear();
 
-        // Collect messages from OBUs
-        let messages = self.obu_manager.collect_messages();
+        // Send the messages from OBUs
         for mut message in messages {
             // Calculate the physical area of the message
             message.phy_area = self
                 .grid
                 .get_square_coords(message.phy_coord, message.phy_range);
+            message.send_round = self.round;
 
             // Send the message to the ether
             self.ether.send_message(message);
@@ -255,38 +957,42 @@ impl Simulator {
         // Collect messages from RSUs
         let comms_range = self.rsu_manager.get_tx_range();
         for rsu in self.rsu_manager.rsus.values() {
-            match rsu.get_message() {
-                Some(mut message) => {
-                    message.phy_area = self.grid.get_square_coords(message.phy_coord, comms_range);
+            if let Some(mut message) = rsu.get_message() {
+                message.phy_area = self.grid.get_square_coords(message.phy_coord, comms_range);
+                message.send_round = self.round;
 
-                    self.ether.send_message(message);
-                }
-                None => (),
+                self.ether.send_message(message);
             }
         }
+
+        // Give the nodes access to the channel
+        self.ether.access_channel();
+
+        // Put the messages in transit with a latency
+        self.ether.delay_messages();
     }
 
     /**
      * Deliver messages from the ether to the OBUs and RSUs.
      */
     fn deliver_messages(&mut self) {
+        // Start the delivery of the messages arriving in the round
+        self.ether.start_delivery(self.round);
+
         // deliver messages to OBUs
-        self.obu_manager
-            .deliver_messages(&self.ether.get_messages());
+        self.obu_manager.deliver_messages(&mut self.ether);
 
         // deliver messages to RSUs
-        self.rsu_manager
-            .deliver_messages(&self.ether.get_messages());
+        self.rsu_manager.deliver_messages(&mut self.ether);
     }
 
     /**
      * Use uniform distribution to randomly select a number between 1 and 100,
      * and return true if the number is less than or equal to the given probability.
      */
-    pub fn random_event(probability: f32) -> bool {
+    pub fn random_event<R: Rng>(rng: &mut R, probability: f32) -> bool {
         let between = Uniform::from(1..=100);
-        let mut rng = rand::thread_rng();
-        let random_number = between.sample(&mut rng) as f32;
+        let random_number = between.sample(rng) as f32;
         random_number / 100.0 <= probability
     }
 }
@@ -298,6 +1004,8 @@ impl Simulator {
 mod tests {
 
     use super::*;
+    use crate::routing::OdPair;
+    use crate::speed::SpeedDistribution;
 
     /**
      * Test the creation of a Simulator.
@@ -307,6 +1015,14 @@ mod tests {
         let grid_params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let rsu_manager_params = RsuManagerParams {
@@ -317,7 +1033,7 @@ mod tests {
         };
 
         let obu_manager_params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 2,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -326,11 +1042,22 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let simulator = Simulator::new(grid_params, rsu_manager_params, obu_manager_params);
+        let simulator_params = SimulatorParams {
+            seed: 0,
+            exit_at_border: false,
+        };
+
+        let simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
 
         assert_eq!(simulator.obu_manager.obus.len(), 0);
         assert_eq!(simulator.rsu_manager.rsus.len(), 0);
-        assert_eq!(simulator.grid.get_dimension(), 10);
+        assert_eq!(simulator.grid.get_width(), 10);
+        assert_eq!(simulator.grid.get_height(), 10);
         assert_eq!(simulator.round, 0);
     }
 
@@ -342,6 +1069,14 @@ mod tests {
         let grid_params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let rsu_manager_params = RsuManagerParams {
@@ -352,7 +1087,7 @@ mod tests {
         };
 
         let obu_manager_params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 2,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -361,7 +1096,17 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let mut simulator = Simulator::new(grid_params, rsu_manager_params, obu_manager_params);
+        let simulator_params = SimulatorParams {
+            seed: 0,
+            exit_at_border: false,
+        };
+
+        let mut simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
 
         simulator.init();
 
@@ -392,6 +1137,14 @@ mod tests {
         let grid_params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let rsu_manager_params = RsuManagerParams {
@@ -402,7 +1155,7 @@ mod tests {
         };
 
         let obu_manager_params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 2,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -411,7 +1164,17 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let mut simulator = Simulator::new(grid_params, rsu_manager_params, obu_manager_params);
+        let simulator_params = SimulatorParams {
+            seed: 0,
+            exit_at_border: false,
+        };
+
+        let mut simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
 
         simulator.add_road_side_units();
 
@@ -442,6 +1205,14 @@ mod tests {
         let grid_params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let rsu_manager_params = RsuManagerParams {
@@ -452,7 +1223,7 @@ mod tests {
         };
 
         let obu_manager_params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 2,
             tx_base_failure_rate: 0.0,
             tx_faulty_obu_failure_rate: 0.0,
@@ -461,7 +1232,17 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let mut simulator = Simulator::new(grid_params, rsu_manager_params, obu_manager_params);
+        let simulator_params = SimulatorParams {
+            seed: 0,
+            exit_at_border: false,
+        };
+
+        let mut simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
 
         simulator.add_road_side_units();
 
@@ -480,6 +1261,14 @@ mod tests {
         let grid_params = GridParams {
             blocks_per_street: 3,
             block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
         };
 
         let rsu_manager_params = RsuManagerParams {
@@ -490,7 +1279,7 @@ mod tests {
         };
 
         let obu_manager_params = ObuManagerParams {
-            max_obus: 2,
+            max_obus: Some(2),
             comms_range: 2,
             tx_base_failure_rate: 0.01,
             tx_faulty_obu_failure_rate: 0.10,
@@ -499,7 +1288,17 @@ mod tests {
             faulty_obus: 0,
         };
 
-        let mut simulator = Simulator::new(grid_params, rsu_manager_params, obu_manager_params);
+        let simulator_params = SimulatorParams {
+            seed: 0,
+            exit_at_border: false,
+        };
+
+        let mut simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
         simulator.add_road_side_units();
 
         simulator.add_on_board_unit();
@@ -510,4 +1309,807 @@ mod tests {
         simulator.collect_messages();
         assert_eq!(simulator.ether.get_messages().len(), 2);
     }
+
+    /**
+     * Test that two simulations with the same seed produce the same run.
+     */
+    #[test]
+    fn test_same_seed_same_simulation() {
+        fn create_simulator(seed: u64) -> Simulator {
+            let grid_params = GridParams {
+                blocks_per_street: 4,
+                block_size: 2,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: None,
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: false,
+                lanes: 1,
+                streets: Vec::new(),
+            };
+
+            let rsu_manager_params = RsuManagerParams {
+                tx_range: 3,
+                rx_range: 3,
+                detect_obu_gps_failure: true,
+                detect_obu_tx_failure: true,
+            };
+
+            let obu_manager_params = ObuManagerParams {
+                max_obus: Some(20),
+                comms_range: 4,
+                tx_base_failure_rate: 0.1,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.1,
+                gps_faulty_obu_failure_rate: 0.5,
+                faulty_obus: 5,
+            };
+
+            let simulator_params = SimulatorParams {
+                seed,
+                exit_at_border: false,
+            };
+
+            Simulator::new(
+                grid_params,
+                rsu_manager_params,
+                obu_manager_params,
+                simulator_params,
+            )
+        }
+
+        let mut simulator_1 = create_simulator(7);
+        let mut simulator_2 = create_simulator(7);
+
+        simulator_1.init();
+        simulator_2.init();
+
+        for _ in 0..30 {
+            simulator_1.step();
+            simulator_2.step();
+
+            // the obus must be at the same coordinates
+            assert_eq!(
+                simulator_1.obu_manager.obus.len(),
+                simulator_2.obu_manager.obus.len()
+            );
+            for (obu_1, obu_2) in simulator_1
+                .obu_manager
+                .obus
+                .values()
+                .zip(simulator_2.obu_manager.obus.values())
+            {
+                assert_eq!(obu_1.get_id(), obu_2.get_id());
+                assert_eq!(obu_1.get_coordinate().x, obu_2.get_coordinate().x);
+                assert_eq!(obu_1.get_coordinate().y, obu_2.get_coordinate().y);
+            }
+
+            // the same messages must be in the ether
+            let messages_1 = simulator_1.ether.get_messages();
+            let messages_2 = simulator_2.ether.get_messages();
+            assert_eq!(messages_1.len(), messages_2.len());
+            for (message_1, message_2) in messages_1.iter().zip(messages_2.iter()) {
+                assert_eq!(message_1.origin_id, message_2.origin_id);
+                assert_eq!(message_1.coordinate.x, message_2.coordinate.x);
+                assert_eq!(message_1.coordinate.y, message_2.coordinate.y);
+            }
+        }
+    }
+
+    /**
+     * Test the report returned by a simulation run.
+     */
+    #[test]
+    fn test_run_report() {
+        let grid_params = GridParams {
+            blocks_per_street: 4,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        };
+
+        let rsu_manager_params = RsuManagerParams {
+            tx_range: 3,
+            rx_range: 3,
+            detect_obu_gps_failure: true,
+            detect_obu_tx_failure: true,
+        };
+
+        let obu_manager_params = ObuManagerParams {
+            max_obus: Some(20),
+            comms_range: 4,
+            tx_base_failure_rate: 0.0,
+            tx_faulty_obu_failure_rate: 0.5,
+            gps_failure_rate: 0.0,
+            gps_faulty_obu_failure_rate: 0.5,
+            faulty_obus: 5,
+        };
+
+        let simulator_params = SimulatorParams {
+            seed: 3,
+            exit_at_border: false,
+        };
+
+        let mut simulator = Simulator::new(
+            grid_params,
+            rsu_manager_params,
+            obu_manager_params,
+            simulator_params,
+        );
+        simulator.set_quiet(true);
+        simulator.init();
+
+        let report = simulator.run(50);
+
+        assert_eq!(report.seed, 3);
+        assert_eq!(report.rounds, 50);
+        assert_eq!(report.grid_stats.width, 13);
+
+        // there is a verdict and a prediction for each obu
+        assert_eq!(report.verdicts.len(), 20);
+        assert_eq!(report.confusion_matrix.total(), 20);
+        assert_eq!(
+            report.confusion_matrix.true_positive + report.confusion_matrix.false_negative,
+            5
+        );
+
+        // the confusion matrix matches the verdicts
+        let detected = report
+            .verdicts
+            .iter()
+            .filter(|verdict| verdict.detected_faulty)
+            .count() as u32;
+        assert_eq!(
+            report.confusion_matrix.true_positive + report.confusion_matrix.false_positive,
+            detected
+        );
+
+        // normal obus never fail to transmit
+        assert_eq!(report.obu_tx_stats.normal_obu_tx_error_count, 0);
+    }
+
+    /**
+     * Test the runs with too few OBUs observed by the RSUs for the detector,
+     * which are valid scenarios.
+     */
+    #[test]
+    fn test_run_few_obus() {
+        let run = |max_obus: u32, rounds: usize| {
+            let mut simulator = Simulator::new(
+                GridParams {
+                    blocks_per_street: 4,
+                    block_size: 2,
+                    block_widths: Vec::new(),
+                    block_heights: Vec::new(),
+                    map: None,
+                    osm: None,
+                    removed_segments: Vec::new(),
+                    two_way: false,
+                    lanes: 1,
+                    streets: Vec::new(),
+                },
+                RsuManagerParams {
+                    tx_range: 3,
+                    rx_range: 3,
+                    detect_obu_gps_failure: true,
+                    detect_obu_tx_failure: true,
+                },
+                ObuManagerParams {
+                    max_obus: Some(max_obus),
+                    comms_range: 4,
+                    tx_base_failure_rate: 0.0,
+                    tx_faulty_obu_failure_rate: 0.5,
+                    gps_failure_rate: 0.0,
+                    gps_faulty_obu_failure_rate: 0.5,
+                    faulty_obus: 0,
+                },
+                SimulatorParams {
+                    seed: 3,
+                    exit_at_border: false,
+                },
+            );
+            simulator.set_quiet(true);
+            simulator.init();
+            simulator.run(rounds)
+        };
+
+        // a single round delivers no message, a single OBU has no peer
+        for report in [run(20, 1), run(1, 5)] {
+            assert!(report.detector_skipped);
+            assert!(report.verdicts.iter().all(|v| v.detector.is_none()));
+            assert_eq!(report.confusion_matrix.false_positive, 0);
+        }
+        assert!(!run(20, 5).detector_skipped);
+    }
+
+    /**
+     * Test the per-round metrics.
+     */
+    #[test]
+    fn test_round_metrics() {
+        // Create a simulator with the same parameters as test_run_report
+        let create_simulator = || {
+            let mut simulator = Simulator::new(
+                GridParams {
+                    blocks_per_street: 4,
+                    block_size: 2,
+                    block_widths: Vec::new(),
+                    block_heights: Vec::new(),
+                    map: None,
+                    osm: None,
+                    removed_segments: Vec::new(),
+                    two_way: false,
+                    lanes: 1,
+                    streets: Vec::new(),
+                },
+                RsuManagerParams {
+                    tx_range: 3,
+                    rx_range: 3,
+                    detect_obu_gps_failure: true,
+                    detect_obu_tx_failure: true,
+                },
+                ObuManagerParams {
+                    max_obus: Some(20),
+                    comms_range: 4,
+                    tx_base_failure_rate: 0.0,
+                    tx_faulty_obu_failure_rate: 0.5,
+                    gps_failure_rate: 0.0,
+                    gps_faulty_obu_failure_rate: 0.5,
+                    faulty_obus: 5,
+                },
+                SimulatorParams {
+                    seed: 3,
+                    exit_at_border: false,
+                },
+            );
+            simulator.set_quiet(true);
+            simulator
+        };
+
+        // the metrics are not collected by default
+        let mut simulator = create_simulator();
+        simulator.init();
+        let report = simulator.run(50);
+        assert!(report.round_metrics.is_empty());
+
+        let mut simulator = create_simulator();
+        simulator.set_collect_metrics(true);
+        simulator.init();
+        let report_with_metrics = simulator.run(50);
+        let metrics = &report_with_metrics.round_metrics;
+
+        // collecting the metrics doesn't change the simulation
+        assert_eq!(
+            report.confusion_matrix,
+            report_with_metrics.confusion_matrix
+        );
+
+        assert_eq!(metrics.len(), 50);
+        assert_eq!(metrics[0].round, 0);
+        assert_eq!(metrics[49].round, 49);
+        assert_eq!(metrics.iter().map(|m| m.obus_added).sum::<u32>(), 20);
+        assert_eq!(metrics[49].obu_count, 20);
+
+        let mut dropped = 0;
+        for m in metrics {
+            // every obu tries to send a message
+            assert_eq!(m.messages_sent + m.messages_dropped, m.obu_count);
+            assert!(m.gps_falsified <= m.messages_sent);
+            assert_eq!(m.rsu_deliveries.len(), simulator.rsu_manager.rsus.len());
+            dropped += m.messages_dropped;
+        }
+
+        // the first round messages are collected before the first round
+        assert_eq!(
+            dropped,
+            report_with_metrics.obu_tx_stats.total_tx_error_count
+        );
+
+        // no obus in the grid before the first round, the empty round has no
+        // neighbors instead of a NaN mean
+        assert_eq!(metrics[0].mean_neighbors, 0.0);
+        let json = serde_json::to_value(&metrics[0]).unwrap();
+        assert_eq!(json["mean_neighbors"], 0.0);
+        assert!(metrics[1].mean_neighbors >= 0.0);
+    }
+
+    /**
+     * Test the replay of a recorded trace.
+     */
+    #[test]
+    fn test_replay() {
+        // Create a simulator with the given detectors
+        let create_simulator = |detect_obu_gps_failure: bool, rx_range: u32| {
+            let mut simulator = Simulator::new(
+                GridParams {
+                    blocks_per_street: 4,
+                    block_size: 2,
+                    block_widths: Vec::new(),
+                    block_heights: Vec::new(),
+                    map: None,
+                    osm: None,
+                    removed_segments: Vec::new(),
+                    two_way: false,
+                    lanes: 1,
+                    streets: Vec::new(),
+                },
+                RsuManagerParams {
+                    tx_range: 3,
+                    rx_range,
+                    detect_obu_gps_failure,
+                    detect_obu_tx_failure: true,
+                },
+                ObuManagerParams {
+                    max_obus: Some(20),
+                    comms_range: 4,
+                    tx_base_failure_rate: 0.1,
+                    tx_faulty_obu_failure_rate: 0.5,
+                    gps_failure_rate: 0.1,
+                    gps_faulty_obu_failure_rate: 0.5,
+                    faulty_obus: 5,
+                },
+                SimulatorParams {
+                    seed: 8,
+                    exit_at_border: false,
+                },
+            );
+            simulator.set_quiet(true);
+            simulator.set_collect_metrics(true);
+            simulator
+        };
+        let path =
+            std::env::temp_dir().join(format!("vanet4j_test_replay_{}.jsonl", std::process::id()));
+
+        // record a run
+        let mut simulator = create_simulator(true, 3);
+        simulator.set_trace(Some(TraceWriter::create(&path).unwrap()));
+        simulator.init();
+        let recorded = simulator.run(40);
+
+        // the replay reproduces the recorded run
+        let mut simulator = create_simulator(true, 3);
+        simulator.init();
+        let replayed = simulator.replay(TraceReader::open(&path).unwrap()).unwrap();
+        assert_eq!(replayed.to_json(), recorded.to_json());
+
+        // other detector settings give the same verdicts as a run with them
+        let mut simulator = create_simulator(false, 3);
+        simulator.init();
+        let expected = simulator.run(40);
+
+        let mut simulator = create_simulator(false, 3);
+        simulator.init();
+        let replayed = simulator.replay(TraceReader::open(&path).unwrap()).unwrap();
+        assert_eq!(replayed.to_json(), expected.to_json());
+        assert_ne!(
+            replayed.confusion_matrix, recorded.confusion_matrix,
+            "the detector settings must change the verdicts"
+        );
+
+        // the RSUs must be the recorded ones
+        let mut simulator = create_simulator(true, 5);
+        simulator.init();
+        assert!(simulator.replay(TraceReader::open(&path).unwrap()).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /**
+     * Test that the replay of a run with OBUs leaving the grid gives the
+     * per-round metrics of the recorded run.
+     */
+    #[test]
+    fn test_replay_metrics() {
+        let create_simulator = || {
+            let mut simulator = Simulator::new(
+                GridParams {
+                    blocks_per_street: 4,
+                    block_size: 2,
+                    block_widths: Vec::new(),
+                    block_heights: Vec::new(),
+                    map: None,
+                    osm: None,
+                    removed_segments: Vec::new(),
+                    two_way: false,
+                    lanes: 1,
+                    streets: Vec::new(),
+                },
+                RsuManagerParams {
+                    tx_range: 3,
+                    rx_range: 3,
+                    detect_obu_gps_failure: true,
+                    detect_obu_tx_failure: true,
+                },
+                ObuManagerParams {
+                    max_obus: Some(20),
+                    comms_range: 4,
+                    tx_base_failure_rate: 0.1,
+                    tx_faulty_obu_failure_rate: 0.5,
+                    gps_failure_rate: 0.1,
+                    gps_faulty_obu_failure_rate: 0.5,
+                    faulty_obus: 5,
+                },
+                SimulatorParams {
+                    seed: 8,
+                    exit_at_border: true,
+                },
+            );
+            simulator.set_quiet(true);
+            simulator.set_collect_metrics(true);
+            simulator
+        };
+        let path = std::env::temp_dir().join(format!(
+            "vanet4j_test_replay_metrics_{}.jsonl",
+            std::process::id()
+        ));
+
+        let mut simulator = create_simulator();
+        simulator.set_trace(Some(TraceWriter::create(&path).unwrap()));
+        simulator.init();
+        let recorded = simulator.run(40);
+        assert!(recorded.round_metrics.iter().any(|m| m.obus_removed > 0));
+
+        let mut simulator = create_simulator();
+        simulator.init();
+        let replayed = simulator.replay(TraceReader::open(&path).unwrap()).unwrap();
+        assert_eq!(replayed.round_metrics, recorded.round_metrics);
+        assert_eq!(replayed.to_json(), recorded.to_json());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /**
+     * Test the OBUs leaving the grid at its exits.
+     */
+    #[test]
+    fn test_exit_at_border() {
+        let mut simulator = Simulator::new(
+            GridParams {
+                blocks_per_street: 2,
+                block_size: 3,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: None,
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: false,
+                lanes: 1,
+                streets: Vec::new(),
+            },
+            RsuManagerParams {
+                tx_range: 3,
+                rx_range: 3,
+                detect_obu_gps_failure: false,
+                detect_obu_tx_failure: true,
+            },
+            ObuManagerParams {
+                max_obus: Some(10),
+                comms_range: 2,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 2,
+            },
+            SimulatorParams {
+                seed: 4,
+                exit_at_border: true,
+            },
+        );
+        simulator.set_quiet(true);
+        simulator.set_collect_metrics(true);
+        simulator.init();
+        let report = simulator.run(100);
+
+        // the obus left the grid and were replaced
+        let removed: u32 = report.round_metrics.iter().map(|m| m.obus_removed).sum();
+        let added: u32 = report.round_metrics.iter().map(|m| m.obus_added).sum();
+        assert!(removed > 0);
+        assert_eq!(added - removed, simulator.obu_manager.get_obus_count());
+
+        // the obus that left are evaluated
+        assert_eq!(report.verdicts.len() as u32, added);
+        assert_eq!(report.confusion_matrix.total(), added);
+
+        // the grid only holds the obus in it
+        let occupied = (0..9)
+            .flat_map(|x| (0..9).map(move |y| Coordinate { x, y }))
+            .map(|coordinate| {
+                simulator
+                    .grid
+                    .get_cell_state(coordinate)
+                    .get_obu_ids()
+                    .len()
+            })
+            .sum::<usize>();
+        assert_eq!(occupied, simulator.obu_manager.obus.len());
+
+        // the departed faulty obus are replaced by faulty ones
+        let faulty = simulator
+            .obu_manager
+            .obus
+            .values()
+            .filter(|obu| obu.is_faulty())
+            .count();
+        assert_eq!(faulty, 2);
+    }
+
+    /**
+     * Test a run on two-way streets with several lanes.
+     */
+    #[test]
+    fn test_two_way_lanes() {
+        let mut simulator = Simulator::new(
+            GridParams {
+                blocks_per_street: 2,
+                block_size: 3,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: None,
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: true,
+                lanes: 2,
+                streets: Vec::new(),
+            },
+            RsuManagerParams {
+                tx_range: 3,
+                rx_range: 3,
+                detect_obu_gps_failure: false,
+                detect_obu_tx_failure: true,
+            },
+            ObuManagerParams {
+                max_obus: Some(30),
+                comms_range: 2,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 3,
+            },
+            SimulatorParams {
+                seed: 8,
+                exit_at_border: true,
+            },
+        );
+        simulator.set_quiet(true);
+        simulator.set_collect_metrics(true);
+        simulator.init();
+
+        let mut shared_cells = 0;
+        for _ in 0..60 {
+            simulator.step();
+
+            // every obu is in a lane of its cell, some cells hold several obus
+            let mut occupied = 0;
+            for x in 0..9 {
+                for y in 0..9 {
+                    let obu_ids = simulator
+                        .grid
+                        .get_cell_state(Coordinate { x, y })
+                        .get_obu_ids();
+                    for obu_id in &obu_ids {
+                        let obu = &simulator.obu_manager.obus[obu_id];
+                        assert_eq!(obu.get_coordinate(), Coordinate { x, y });
+                    }
+                    occupied += obu_ids.len();
+                    shared_cells += (obu_ids.len() > 1) as u32;
+                }
+            }
+            assert_eq!(occupied, simulator.obu_manager.obus.len());
+        }
+        assert!(shared_cells > 0);
+
+        // the vehicles leave at the ends of the two-way streets
+        let removed: u32 = simulator.metrics.iter().map(|m| m.obus_removed).sum();
+        assert!(removed > 0);
+    }
+
+    /**
+     * Test the RSUs placement on a grid read from an ASCII map.
+     */
+    #[test]
+    fn test_rsus_on_map() {
+        // a ring road around a single building
+        let mut map = ".".repeat(10) + "\n";
+        for _ in 0..8 {
+            map += &(".".to_string() + &"#".repeat(8) + ".\n");
+        }
+        map += &".".repeat(10);
+
+        let mut simulator = Simulator::new(
+            GridParams {
+                blocks_per_street: 0,
+                block_size: 0,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: Some(map),
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: false,
+                lanes: 1,
+                streets: Vec::new(),
+            },
+            RsuManagerParams {
+                tx_range: 2,
+                rx_range: 2,
+                detect_obu_gps_failure: false,
+                detect_obu_tx_failure: true,
+            },
+            ObuManagerParams {
+                max_obus: Some(10),
+                comms_range: 3,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 2,
+            },
+            SimulatorParams {
+                seed: 5,
+                exit_at_border: true,
+            },
+        );
+        simulator.set_quiet(true);
+        simulator.init();
+
+        // the lattice is 4x4 RSUs, the ones with only the building in range are skipped
+        assert_eq!(simulator.rsu_manager.rsus.len(), 12);
+        for rsu in simulator.rsu_manager.rsus.values() {
+            let square = simulator.grid.get_square_coords(rsu.get_coordinate(), 2);
+            assert!(simulator.grid.has_street_in(square));
+        }
+
+        // the obus drive around the ring
+        simulator.run(30);
+        for obu in simulator.obu_manager.obus.values() {
+            assert!(simulator.grid.is_street(obu.get_coordinate()));
+        }
+    }
+
+    /**
+     * Test the OBUs driving several cells per round with a speed model.
+     */
+    #[test]
+    fn test_speed_model() {
+        let mut simulator = Simulator::new(
+            GridParams {
+                blocks_per_street: 0,
+                block_size: 0,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: Some(".".repeat(10)),
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: false,
+                lanes: 1,
+                streets: Vec::new(),
+            },
+            RsuManagerParams {
+                tx_range: 2,
+                rx_range: 2,
+                detect_obu_gps_failure: false,
+                detect_obu_tx_failure: true,
+            },
+            ObuManagerParams {
+                max_obus: Some(2),
+                comms_range: 3,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 1,
+            },
+            SimulatorParams {
+                seed: 6,
+                exit_at_border: true,
+            },
+        );
+        simulator.set_speed_model(&SpeedParams {
+            distribution: SpeedDistribution::Constant,
+            speed: Some(3),
+            min_speed: None,
+            max_speed: None,
+            mean_speed: None,
+            std_dev: None,
+            acceleration: 2,
+            slowdown: 0.0,
+        });
+        simulator.set_quiet(true);
+        simulator.set_collect_metrics(true);
+        simulator.init();
+
+        // the first obu enters at (0, 0), accelerates, then drives 3 cells per round
+        let mut coordinates = Vec::new();
+        for _ in 0..4 {
+            simulator.step();
+            coordinates.push(simulator.obu_manager.obus[&0].get_coordinate().x);
+        }
+        assert_eq!(coordinates, vec![0, 2, 5, 8]);
+
+        // the second obu entered behind it and follows it
+        assert_eq!(simulator.obu_manager.obus[&1].get_coordinate().x, 5);
+
+        // the first obu drives out of the grid on its way
+        simulator.step();
+        assert!(!simulator.obu_manager.obus.contains_key(&0));
+        assert_eq!(simulator.metrics[4].obus_removed, 1);
+    }
+
+    /**
+     * Test the OBUs driving to their destination and leaving the grid there.
+     */
+    #[test]
+    fn test_routing() {
+        let mut simulator = Simulator::new(
+            GridParams {
+                blocks_per_street: 0,
+                block_size: 0,
+                block_widths: Vec::new(),
+                block_heights: Vec::new(),
+                map: Some(".......\n.##.##.\n.......\n".to_string()),
+                osm: None,
+                removed_segments: Vec::new(),
+                two_way: true,
+                lanes: 1,
+                streets: Vec::new(),
+            },
+            RsuManagerParams {
+                tx_range: 2,
+                rx_range: 2,
+                detect_obu_gps_failure: false,
+                detect_obu_tx_failure: true,
+            },
+            ObuManagerParams {
+                max_obus: Some(4),
+                comms_range: 3,
+                tx_base_failure_rate: 0.0,
+                tx_faulty_obu_failure_rate: 0.5,
+                gps_failure_rate: 0.0,
+                gps_faulty_obu_failure_rate: 0.0,
+                faulty_obus: 1,
+            },
+            SimulatorParams {
+                seed: 3,
+                exit_at_border: false,
+            },
+        );
+        let destination = Coordinate { x: 3, y: 1 };
+        simulator.set_routing(&RoutingParams {
+            destinations: vec![destination],
+            od_matrix: vec![OdPair {
+                origin: Coordinate { x: 0, y: 0 },
+                destination: Coordinate { x: 6, y: 2 },
+                weight: 1.0,
+            }],
+            patience: 2,
+        });
+        simulator.set_quiet(true);
+        simulator.init();
+
+        for _ in 0..40 {
+            simulator.step();
+        }
+
+        // the obus only leave the grid at their destination
+        let departed: Vec<Coordinate> = simulator
+            .obu_manager
+            .get_evaluated_obus()
+            .into_iter()
+            .filter(|obu| !simulator.obu_manager.obus.contains_key(&obu.get_id()))
+            .map(|obu| obu.get_coordinate())
+            .collect();
+        assert!(departed.contains(&destination));
+        assert!(departed.contains(&Coordinate { x: 6, y: 2 }));
+        assert!(departed
+            .iter()
+            .all(|coordinate| [destination, Coordinate { x: 6, y: 2 }].contains(coordinate)));
+    }
 }
@@ -0,0 +1,401 @@
+/**
+ * SPEED
+ *
+ * Speed model of the vehicles, so the OBUs can drive several cells per round
+ * instead of one. Each vehicle gets a max speed drawn from a distribution when
+ * it enters the grid, and follows the Nagel–Schreckenberg cellular automaton:
+ * in every round it accelerates towards its max speed, slows down at random,
+ * and brakes when the vehicle ahead, a red light or the end of its street
+ * stops it, keeping the distance it could drive as its new speed.
+ */
+use crate::scenario::ScenarioError;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Fastest max speed of a vehicle, in cells per round
+const MAX_SPEED: u32 = 50;
+
+/**
+ * SpeedDistribution represents the distributions of the max speeds of the
+ * vehicles.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeedDistribution {
+    Constant, // Every vehicle has the same max speed
+    Uniform,  // Max speeds drawn uniformly between a min and a max
+    Normal,   // Max speeds drawn from a normal distribution, rounded
+}
+
+/**
+ * SpeedParams represents the distribution of the max speeds of the vehicles
+ * and how they accelerate and slow down, in cells per round.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeedParams {
+    pub distribution: SpeedDistribution,
+    #[serde(default)]
+    pub speed: Option<u32>, // Constant: max speed of every vehicle
+    #[serde(default)]
+    pub min_speed: Option<u32>, // Uniform: lowest max speed
+    #[serde(default)]
+    pub max_speed: Option<u32>, // Uniform: highest max speed
+    #[serde(default)]
+    pub mean_speed: Option<f64>, // Normal: mean of the max speeds
+    #[serde(default)]
+    pub std_dev: Option<f64>, // Normal: standard deviation of the max speeds
+    #[serde(default = "SpeedParams::default_acceleration")]
+    pub acceleration: u32, // Speed gained in each round up to the max speed
+    #[serde(default)]
+    pub slowdown: f64, // Probability of a vehicle to slow down by 1 in a round
+}
+
+/**
+ * VehicleSpeed represents the speeds of a vehicle.
+ */
+#[derive(Clone, Copy, Debug)]
+struct VehicleSpeed {
+    max: u32,     // Max speed of the vehicle
+    current: u32, // Distance driven in the last round
+}
+
+/**
+ * SpeedModel draws the max speed of every vehicle and the distance it can
+ * drive in each round.
+ */
+pub struct SpeedModel {
+    params: SpeedParams,
+    speeds: BTreeMap<u32, VehicleSpeed>, // Speeds of the vehicles, by OBU id
+    rng: StdRng,                         // Random number generator of the speeds
+}
+
+/**
+ * SpeedDistribution implementation.
+ */
+impl SpeedDistribution {
+    /**
+     * Return the name of the distribution, as in the scenario.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedDistribution::Constant => "constant",
+            SpeedDistribution::Uniform => "uniform",
+            SpeedDistribution::Normal => "normal",
+        }
+    }
+}
+
+/**
+ * SpeedParams implementation.
+ */
+impl SpeedParams {
+    /**
+     * By default the vehicles accelerate by one cell per round.
+     */
+    fn default_acceleration() -> u32 {
+        1
+    }
+
+    /**
+     * Check that the distribution is fully described and that the speeds can
+     * be simulated.
+     */
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        // Each distribution only uses its own fields
+        let fields = [
+            ("speed", SpeedDistribution::Constant, self.speed.is_some()),
+            (
+                "min_speed",
+                SpeedDistribution::Uniform,
+                self.min_speed.is_some(),
+            ),
+            (
+                "max_speed",
+                SpeedDistribution::Uniform,
+                self.max_speed.is_some(),
+            ),
+            (
+                "mean_speed",
+                SpeedDistribution::Normal,
+                self.mean_speed.is_some(),
+            ),
+            ("std_dev", SpeedDistribution::Normal, self.std_dev.is_some()),
+        ];
+        for (name, distribution, is_set) in fields {
+            if is_set && distribution != self.distribution {
+                return Err(ScenarioError::Invalid(format!(
+                    "speed.{} is not used by the {} distribution",
+                    name,
+                    self.distribution.name()
+                )));
+            }
+        }
+
+        let speed_range = format!("between 1 and {}", MAX_SPEED);
+        match self.distribution {
+            SpeedDistribution::Constant => match self.speed {
+                Some(speed) if (1..=MAX_SPEED).contains(&speed) => {}
+                _ => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "speed.speed must be set {} for the constant distribution",
+                        speed_range
+                    )))
+                }
+            },
+            SpeedDistribution::Uniform => match (self.min_speed, self.max_speed) {
+                (Some(min_speed), Some(max_speed))
+                    if min_speed >= 1 && min_speed <= max_speed && max_speed <= MAX_SPEED => {}
+                _ => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "speed.min_speed and speed.max_speed must be set {}, the min not greater than the max, for the uniform distribution",
+                        speed_range
+                    )))
+                }
+            },
+            SpeedDistribution::Normal => match (self.mean_speed, self.std_dev) {
+                (Some(mean_speed), Some(std_dev))
+                    if (1.0..=MAX_SPEED as f64).contains(&mean_speed)
+                        && std_dev.is_finite()
+                        && std_dev >= 0.0 => {}
+                _ => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "speed.mean_speed must be set {} and speed.std_dev not less than 0 for the normal distribution",
+                        speed_range
+                    )))
+                }
+            },
+        }
+
+        if self.acceleration == 0 {
+            return Err(ScenarioError::Invalid(
+                "speed.acceleration must be greater than 0".to_string(),
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.slowdown) {
+            return Err(ScenarioError::Invalid(format!(
+                "speed.slowdown must be between 0 and 1, got {}",
+                self.slowdown
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * SpeedModel implementation.
+ */
+impl SpeedModel {
+    /**
+     * Create the speed model of the given params, which must be valid.
+     */
+    pub fn new(params: &SpeedParams, rng: StdRng) -> SpeedModel {
+        SpeedModel {
+            params: params.clone(),
+            speeds: BTreeMap::new(),
+            rng,
+        }
+    }
+
+    /**
+     * Return the max speed of a vehicle, drawn when the vehicle is first seen.
+     */
+    pub fn get_max_speed(&mut self, obu_id: u32) -> u32 {
+        self.get_vehicle_speed(obu_id).max
+    }
+
+    /**
+     * Return the distance a vehicle tries to drive in the round: its speed
+     * accelerates towards its max speed, then slows down by 1 at random.
+     */
+    pub fn next_speed(&mut self, obu_id: u32) -> u32 {
+        let acceleration = self.params.acceleration;
+        let vehicle = self.get_vehicle_speed(obu_id);
+        let speed = (vehicle.current + acceleration).min(vehicle.max);
+
+        if self.params.slowdown > 0.0 && self.rng.gen_bool(self.params.slowdown) {
+            speed.saturating_sub(1)
+        } else {
+            speed
+        }
+    }
+
+    /**
+     * Set the distance a vehicle drove in the round, its speed for the next
+     * one.
+     */
+    pub fn set_speed(&mut self, obu_id: u32, speed: u32) {
+        if let Some(vehicle) = self.speeds.get_mut(&obu_id) {
+            vehicle.current = speed;
+        }
+    }
+
+    /**
+     * Forget the speeds of a vehicle that left the grid.
+     */
+    pub fn remove_vehicle(&mut self, obu_id: u32) {
+        self.speeds.remove(&obu_id);
+    }
+
+    /**
+     * Return the speeds of a vehicle, drawing its max speed if it is new. A
+     * new vehicle enters the grid stopped.
+     */
+    fn get_vehicle_speed(&mut self, obu_id: u32) -> VehicleSpeed {
+        if let Some(vehicle) = self.speeds.get(&obu_id) {
+            return *vehicle;
+        }
+
+        let max = match self.params.distribution {
+            SpeedDistribution::Constant => self.params.speed.unwrap_or(1),
+            SpeedDistribution::Uniform => self
+                .rng
+                .gen_range(self.params.min_speed.unwrap_or(1)..=self.params.max_speed.unwrap_or(1)),
+            SpeedDistribution::Normal => {
+                let speed = self.params.mean_speed.unwrap_or(1.0)
+                    + self.params.std_dev.unwrap_or(0.0) * sample_standard_normal(&mut self.rng);
+                (speed.round() as u32).clamp(1, MAX_SPEED)
+            }
+        };
+        let vehicle = VehicleSpeed { max, current: 0 };
+        self.speeds.insert(obu_id, vehicle);
+
+        vehicle
+    }
+}
+
+/**
+ * Draw a number of the standard normal distribution, with the Box-Muller
+ * transform.
+ */
+pub fn sample_standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>(); // in (0, 1], so its log is finite
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+
+    /**
+     * Return the params of a distribution with no speed.
+     */
+    fn speed_params(distribution: SpeedDistribution) -> SpeedParams {
+        SpeedParams {
+            distribution,
+            speed: None,
+            min_speed: None,
+            max_speed: None,
+            mean_speed: None,
+            std_dev: None,
+            acceleration: 1,
+            slowdown: 0.0,
+        }
+    }
+
+    /**
+     * Test the validation of the distributions.
+     */
+    #[test]
+    fn test_validate() {
+        let mut params = speed_params(SpeedDistribution::Constant);
+        assert!(params.validate().is_err());
+        params.speed = Some(0);
+        assert!(params.validate().is_err());
+        params.speed = Some(3);
+        assert!(params.validate().is_ok());
+        params.max_speed = Some(5);
+        assert!(params.validate().is_err());
+
+        let mut params = speed_params(SpeedDistribution::Uniform);
+        params.min_speed = Some(4);
+        params.max_speed = Some(2);
+        assert!(params.validate().is_err());
+        params.max_speed = Some(MAX_SPEED + 1);
+        assert!(params.validate().is_err());
+        params.max_speed = Some(6);
+        assert!(params.validate().is_ok());
+        params.slowdown = 1.5;
+        assert!(params.validate().is_err());
+        params.slowdown = 0.3;
+        params.acceleration = 0;
+        assert!(params.validate().is_err());
+
+        let mut params = speed_params(SpeedDistribution::Normal);
+        params.mean_speed = Some(3.0);
+        assert!(params.validate().is_err());
+        params.std_dev = Some(-1.0);
+        assert!(params.validate().is_err());
+        params.std_dev = Some(1.0);
+        assert!(params.validate().is_ok());
+    }
+
+    /**
+     * Test the acceleration, braking and random slowdown of a vehicle.
+     */
+    #[test]
+    fn test_next_speed() {
+        let mut params = speed_params(SpeedDistribution::Constant);
+        params.speed = Some(4);
+        params.acceleration = 2;
+        let mut speed_model = SpeedModel::new(&params, StdRng::seed_from_u64(1));
+
+        // the vehicle enters stopped and accelerates up to its max speed
+        assert_eq!(speed_model.next_speed(0), 2);
+        speed_model.set_speed(0, 2);
+        assert_eq!(speed_model.next_speed(0), 4);
+        speed_model.set_speed(0, 4);
+        assert_eq!(speed_model.next_speed(0), 4);
+
+        // it brakes behind a vehicle, then accelerates again
+        speed_model.set_speed(0, 1);
+        assert_eq!(speed_model.next_speed(0), 3);
+
+        // a vehicle that always slows down never reaches its max speed
+        params.slowdown = 1.0;
+        let mut speed_model = SpeedModel::new(&params, StdRng::seed_from_u64(1));
+        assert_eq!(speed_model.next_speed(0), 1);
+        speed_model.set_speed(0, 4);
+        assert_eq!(speed_model.next_speed(0), 3);
+
+        // a vehicle that left the grid enters again stopped
+        speed_model.remove_vehicle(0);
+        assert_eq!(speed_model.next_speed(0), 1);
+    }
+
+    /**
+     * Test the distributions of the max speeds.
+     */
+    #[test]
+    fn test_max_speeds() {
+        let mut params = speed_params(SpeedDistribution::Uniform);
+        params.min_speed = Some(2);
+        params.max_speed = Some(5);
+        let mut speed_model = SpeedModel::new(&params, StdRng::seed_from_u64(1));
+        let speeds: Vec<u32> = (0..200).map(|id| speed_model.get_max_speed(id)).collect();
+        assert!(speeds.iter().all(|speed| (2..=5).contains(speed)));
+        assert!((2..=5).all(|speed| speeds.contains(&speed)));
+
+        // the max speed of a vehicle is drawn once
+        assert_eq!(speed_model.get_max_speed(7), speeds[7]);
+
+        let mut params = speed_params(SpeedDistribution::Normal);
+        params.mean_speed = Some(4.0);
+        params.std_dev = Some(1.0);
+        let mut speed_model = SpeedModel::new(&params, StdRng::seed_from_u64(1));
+        let speeds: Vec<u32> = (0..1000).map(|id| speed_model.get_max_speed(id)).collect();
+        let mean = speeds.iter().sum::<u32>() as f64 / speeds.len() as f64;
+        assert!((mean - 4.0).abs() < 0.2, "mean max speed {}", mean);
+        assert!(speeds.iter().all(|speed| *speed >= 1));
+    }
+}
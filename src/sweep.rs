@@ -0,0 +1,513 @@
+/**
+ * SWEEP
+ *
+ * A sweep runs a batch of replications for every combination of values of one
+ * or more scenario fields, and gathers the results in a tidy table with one
+ * record per replication keyed by the values of the swept fields.
+ */
+use crate::artifacts;
+use crate::batch::{self, BatchSummary, MetricSummary, ReplicationResult};
+use crate::scenario::{Scenario, ScenarioError};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/**
+ * SweepMode represents how the values of the swept fields are combined.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SweepMode {
+    #[default]
+    Cartesian, // Every combination of the values of all the fields
+    Zip, // The i-th values of all the fields, all the lists have the same length
+}
+
+/**
+ * SweepValues represents the values taken by a swept field, either an
+ * explicit list or an inclusive range.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SweepValues {
+    List(Vec<Value>),
+    Range { start: f64, stop: f64, step: f64 },
+}
+
+/**
+ * SweepParam represents a swept scenario field and its values.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepParam {
+    pub name: String, // Scenario field, as accepted by Scenario::set_field
+    pub values: SweepValues,
+}
+
+/**
+ * SweepParams represents the swept fields and how they are combined.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepParams {
+    #[serde(default)]
+    pub mode: SweepMode,
+    #[serde(default = "SweepParams::default_replications")]
+    pub replications: u32, // Replications run for each combination of values
+    pub params: Vec<SweepParam>,
+}
+
+/**
+ * SweepPoint represents the results of the replications run for a
+ * combination of values of the swept fields.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct SweepPoint {
+    pub values: Vec<String>, // Values of the swept fields, in the order of the params
+    pub summary: BatchSummary,
+}
+
+/**
+ * SweepResults represents the results of a sweep.
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct SweepResults {
+    pub params: Vec<String>, // Names of the swept fields
+    pub points: Vec<SweepPoint>,
+}
+
+/**
+ * SweepValues implementation.
+ */
+impl SweepValues {
+    /**
+     * Parse the values from the command line, either a comma separated list
+     * or a start:stop:step range.
+     */
+    pub fn parse(text: &str) -> Result<SweepValues, ScenarioError> {
+        if !text.contains(':') {
+            return Ok(SweepValues::List(
+                text.split(',')
+                    .map(|value| Value::String(value.trim().to_string()))
+                    .collect(),
+            ));
+        }
+
+        let bounds: Vec<f64> = text
+            .split(':')
+            .map(|bound| bound.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                ScenarioError::Invalid(format!("expected start:stop:step range, got {}", text))
+            })?;
+
+        match bounds[..] {
+            [start, stop, step] => Ok(SweepValues::Range { start, stop, step }),
+            _ => Err(ScenarioError::Invalid(format!(
+                "expected start:stop:step range, got {}",
+                text
+            ))),
+        }
+    }
+
+    /**
+     * Expand the values to their textual form, as accepted by
+     * Scenario::set_field.
+     */
+    pub fn expand(&self) -> Result<Vec<String>, ScenarioError> {
+        match self {
+            SweepValues::List(values) => Ok(values
+                .iter()
+                .map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    _ => value.to_string(),
+                })
+                .collect()),
+            SweepValues::Range { start, stop, step } => {
+                // Written so that NaN bounds are rejected too
+                let valid = *step > 0.0 && stop >= start;
+                if !valid {
+                    return Err(ScenarioError::Invalid(format!(
+                        "invalid sweep range {}:{}:{}, expected start <= stop and step > 0",
+                        start, stop, step
+                    )));
+                }
+
+                // Tolerate the rounding errors so the stop value is included
+                let count = ((stop - start) / step + 1e-9).floor() as u64 + 1;
+                let integral = start.fract() == 0.0 && step.fract() == 0.0;
+
+                Ok((0..count)
+                    .map(|i| {
+                        let value = start + i as f64 * step;
+                        if integral {
+                            format!("{}", value as i64)
+                        } else {
+                            // Drop the noise of the floating point steps
+                            format!("{}", (value * 1e9).round() / 1e9)
+                        }
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/**
+ * SweepParams implementation.
+ */
+impl SweepParams {
+    /**
+     * Default number of replications for each combination of values.
+     */
+    fn default_replications() -> u32 {
+        1
+    }
+
+    /**
+     * Return the combinations of values of the swept fields. In cartesian
+     * mode the first field varies the slowest.
+     */
+    pub fn combinations(&self) -> Result<Vec<Vec<String>>, ScenarioError> {
+        if self.params.is_empty() {
+            return Err(ScenarioError::Invalid(
+                "a sweep needs at least one param".to_string(),
+            ));
+        }
+        if self.replications == 0 {
+            return Err(ScenarioError::Invalid(
+                "sweep.replications must be greater than 0".to_string(),
+            ));
+        }
+
+        // Expand the values of each field
+        let mut values = Vec::new();
+        for param in &self.params {
+            let param_values = param.values.expand()?;
+            if param_values.is_empty() {
+                return Err(ScenarioError::Invalid(format!(
+                    "no values to sweep for {}",
+                    param.name
+                )));
+            }
+            values.push(param_values);
+        }
+
+        match self.mode {
+            SweepMode::Cartesian => {
+                let mut combinations = vec![Vec::new()];
+                for param_values in &values {
+                    combinations = combinations
+                        .into_iter()
+                        .flat_map(|combination: Vec<String>| {
+                            param_values.iter().map(move |value| {
+                                let mut combination = combination.clone();
+                                combination.push(value.clone());
+                                combination
+                            })
+                        })
+                        .collect();
+                }
+                Ok(combinations)
+            }
+            SweepMode::Zip => {
+                let length = values[0].len();
+                if values
+                    .iter()
+                    .any(|param_values| param_values.len() != length)
+                {
+                    return Err(ScenarioError::Invalid(
+                        "all the params of a zip sweep must have the same number of values"
+                            .to_string(),
+                    ));
+                }
+                Ok((0..length)
+                    .map(|i| {
+                        values
+                            .iter()
+                            .map(|param_values| param_values[i].clone())
+                            .collect()
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /**
+     * Create the scenario of each combination of values. All the scenarios
+     * are validated, so no simulation is run if any combination is invalid.
+     */
+    pub fn scenarios(
+        &self,
+        scenario: &Scenario,
+    ) -> Result<Vec<(Vec<String>, Scenario)>, ScenarioError> {
+        let mut scenarios = Vec::new();
+        for values in self.combinations()? {
+            let mut point_scenario = scenario.clone();
+            for (param, value) in self.params.iter().zip(&values) {
+                point_scenario.set_field(&param.name, value)?;
+            }
+            point_scenario.validate()?;
+            scenarios.push((values, point_scenario));
+        }
+
+        Ok(scenarios)
+    }
+}
+
+/**
+ * Run the sweep over the scenario. The replications of every combination use
+ * the same derived seeds, so the combinations are compared on the same
+ * random streams unless the seed itself is swept. The combinations run in
+ * parallel, as the replications of each combination. When an artifacts
+ * directory is given, the artifacts of each combination are written to its
+ * own point-NNNN directory in it.
+ */
+pub fn run_sweep(
+    scenario: &Scenario,
+    sweep: &SweepParams,
+    artifacts_dir: Option<&Path>,
+) -> Result<SweepResults, Box<dyn Error>> {
+    let points = sweep
+        .scenarios(scenario)?
+        .into_par_iter()
+        .enumerate()
+        .map(|(point, (values, point_scenario))| {
+            let point_dir = artifacts_dir.map(|dir| dir.join(artifacts::point_dir_name(point)));
+            let summary =
+                batch::run_batch(&point_scenario, sweep.replications, point_dir.as_deref())?;
+
+            Ok(SweepPoint { values, summary })
+        })
+        .collect::<io::Result<Vec<SweepPoint>>>()?;
+
+    Ok(SweepResults {
+        params: sweep
+            .params
+            .iter()
+            .map(|param| param.name.clone())
+            .collect(),
+        points,
+    })
+}
+
+/**
+ * SweepResults implementation.
+ */
+impl SweepResults {
+    /**
+     * Print the mean and the 95% confidence interval of the detection rates
+     * of each combination of values.
+     */
+    pub fn print(&self) {
+        // Format a summary as its mean and confidence interval
+        let format = |summary: &MetricSummary| {
+            format!(
+                "{:.4} [{:.4}, {:.4}]",
+                summary.mean, summary.ci95_low, summary.ci95_high
+            )
+        };
+
+        println!("--- SWEEP RESULTS ---");
+        println!(
+            "{}\tReplications\tTP\tFP\tTN\tFN\tDetection Rate\tFPR\tFNR",
+            self.params.join("\t")
+        );
+        for point in &self.points {
+            let summary = &point.summary;
+            println!(
+                "{}\t{}\t{:.2}\t{:.2}\t{:.2}\t{:.2}\t{}\t{}\t{}",
+                point.values.join("\t"),
+                summary.replications.len(),
+                summary.true_positive.mean,
+                summary.false_positive.mean,
+                summary.true_negative.mean,
+                summary.false_negative.mean,
+                format(&summary.detection_rate),
+                format(&summary.false_positive_rate),
+                format(&summary.false_negative_rate)
+            );
+        }
+    }
+
+    /**
+     * Write a CSV record for each replication, prefixed by the values of the
+     * swept fields.
+     */
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+
+        writeln!(
+            file,
+            "{},{}",
+            self.params.join(","),
+            ReplicationResult::CSV_HEADER
+        )?;
+        for point in &self.points {
+            for result in &point.summary.replications {
+                writeln!(
+                    file,
+                    "{},{}",
+                    point.values.join(","),
+                    result.to_csv_record()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 20
+
+        [simulator]
+        seed = 5
+
+        [grid]
+        blocks_per_street = 4
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = false
+
+        [obu_manager]
+        max_obus = 10
+        comms_range = 4
+        tx_base_failure_rate = 0.02
+        tx_faulty_obu_failure_rate = 0.05
+        gps_failure_rate = 0.02
+        gps_faulty_obu_failure_rate = 0.05
+        faulty_obus = 2
+
+        [sweep]
+        mode = "cartesian"
+        replications = 2
+
+        [[sweep.params]]
+        name = "faulty_obus"
+        values = [1, 3]
+
+        [[sweep.params]]
+        name = "obu_manager.tx_faulty_obu_failure_rate"
+        values = { start = 0.1, stop = 0.3, step = 0.1 }
+    "#;
+
+    /**
+     * Test the expansion of the values of a field.
+     */
+    #[test]
+    fn test_sweep_values() {
+        let values = SweepValues::parse("10, 20,30").unwrap();
+        assert_eq!(values.expand().unwrap(), vec!["10", "20", "30"]);
+
+        let values = SweepValues::parse("0.05:0.2:0.05").unwrap();
+        assert_eq!(values.expand().unwrap(), vec!["0.05", "0.1", "0.15", "0.2"]);
+
+        let values = SweepValues::parse("2:10:4").unwrap();
+        assert_eq!(values.expand().unwrap(), vec!["2", "6", "10"]);
+
+        assert!(SweepValues::parse("1:2").is_err());
+        assert!(SweepValues::parse("a:2:1").is_err());
+        assert!(SweepValues::parse("2:1:1").unwrap().expand().is_err());
+        assert!(SweepValues::parse("1:2:0").unwrap().expand().is_err());
+    }
+
+    /**
+     * Test the combinations of values of the cartesian and zip modes.
+     */
+    #[test]
+    fn test_combinations() {
+        let mut sweep = SweepParams {
+            mode: SweepMode::Cartesian,
+            replications: 1,
+            params: vec![
+                SweepParam {
+                    name: "a".to_string(),
+                    values: SweepValues::parse("1,2").unwrap(),
+                },
+                SweepParam {
+                    name: "b".to_string(),
+                    values: SweepValues::parse("x,y").unwrap(),
+                },
+            ],
+        };
+
+        let combinations = sweep.combinations().unwrap();
+        assert_eq!(
+            combinations,
+            vec![
+                vec!["1", "x"],
+                vec!["1", "y"],
+                vec!["2", "x"],
+                vec!["2", "y"]
+            ]
+        );
+
+        sweep.mode = SweepMode::Zip;
+        let combinations = sweep.combinations().unwrap();
+        assert_eq!(combinations, vec![vec!["1", "x"], vec!["2", "y"]]);
+
+        // zipped lists must have the same length
+        sweep.params[1].values = SweepValues::parse("x").unwrap();
+        assert!(sweep.combinations().is_err());
+
+        // at least one param is needed
+        sweep.params.clear();
+        assert!(sweep.combinations().is_err());
+    }
+
+    /**
+     * Test a sweep declared in a scenario file.
+     */
+    #[test]
+    fn test_run_sweep() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        let sweep = scenario.sweep.clone().unwrap();
+
+        let results = run_sweep(&scenario, &sweep, None).unwrap();
+
+        assert_eq!(
+            results.params,
+            vec!["faulty_obus", "obu_manager.tx_faulty_obu_failure_rate"]
+        );
+        assert_eq!(results.points.len(), 6);
+        assert_eq!(results.points[0].values, vec!["1", "0.1"]);
+        assert_eq!(results.points[5].values, vec!["3", "0.3"]);
+
+        for point in &results.points {
+            assert_eq!(point.summary.replications.len(), 2);
+
+            // every combination uses the same seeds
+            assert_eq!(point.summary.replications[1].seed, batch::derive_seed(5, 1));
+        }
+
+        // the swept values are applied
+        let faulty = |point: &SweepPoint| {
+            let matrix = point.summary.replications[0].confusion_matrix;
+            matrix.true_positive + matrix.false_negative
+        };
+        assert_eq!(faulty(&results.points[0]), 1);
+        assert_eq!(faulty(&results.points[5]), 3);
+
+        // invalid combinations are reported before running anything
+        let mut sweep = sweep;
+        sweep.params[0].values = SweepValues::parse("1,50").unwrap();
+        assert!(run_sweep(&scenario, &sweep, None).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+/**
+ * TRACE
+ *
+ * The trace records what actually happened in a simulation run, so detector
+ * decisions can be debugged: the state of every OBU and the message it sent in
+ * each round, and the OBUs and RSUs that received every message of the Ether.
+ * The trace is written as JSON Lines, a header line followed by one line per
+ * round, and read back with a TraceReader to replay the run. The simulator
+ * only builds the records when a TraceWriter is set, so untraced runs pay
+ * nothing for it.
+ */
+use crate::grid::Coordinate;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Lines, Write};
+use std::path::Path;
+
+pub const TRACE_VERSION: u32 = 2;
+
+/**
+ * TraceHeader represents the first line of a trace, the parameters of the run
+ * needed to interpret the rounds.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceHeader {
+    pub version: u32,         // Version of the trace format
+    pub seed: u64,            // Seed of the traced run
+    pub grid_width: u32,      // Number of cells of the grid along the x axis
+    pub grid_height: u32,     // Number of cells of the grid along the y axis
+    pub obu_comms_range: u32, // Physical communication range of the OBUs
+    pub rsu_count: u32,       // Number of RSUs, the RSU ids go from 0 to rsu_count - 1
+}
+
+/**
+ * MessageStatus represents what happened to the message of an OBU in a round.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    Sent,         // The message reports the physical coordinate of the OBU
+    Dropped,      // The message was not sent because of a TX failure or of the MAC layer
+    GpsFalsified, // The message reports a falsified coordinate
+}
+
+/**
+ * TraceObu represents the state of an OBU at the end of a round and the
+ * message it sent.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceObu {
+    pub id: u32,
+    pub coordinate: Coordinate, // Physical coordinate of the OBU
+    pub faulty: bool,
+    pub status: MessageStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reported: Option<Coordinate>, // Falsified coordinate, only for GPS-falsified messages
+}
+
+/**
+ * TraceDelivery represents the receivers of a message of the Ether.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceDelivery {
+    pub origin_id: u32, // ID of the OBU that sent the message
+    pub obus: Vec<u32>, // OBUs that received the message, in id order
+    pub rsus: Vec<u32>, // RSUs that received the message, in id order
+}
+
+/**
+ * TraceRound represents a round of the simulation. As for the round metrics,
+ * the messages delivered at the start of a round are the ones sent at the end
+ * of the previous round.
+ */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceRound {
+    pub round: u32,
+    pub deliveries: Vec<TraceDelivery>, // Messages delivered at the start of the round
+    pub obus: Vec<TraceObu>,            // OBUs at the end of the round, in id order
+}
+
+/**
+ * TraceWriter writes a trace to a file.
+ */
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+}
+
+/**
+ * TraceReader reads a trace from a file, one round at a time.
+ */
+pub struct TraceReader {
+    header: TraceHeader,
+    lines: Lines<BufReader<File>>,
+}
+
+/**
+ * TraceWriter implementation.
+ */
+impl TraceWriter {
+    /**
+     * Create the trace file, the header is written when the run starts.
+     */
+    pub fn create(path: &Path) -> io::Result<TraceWriter> {
+        Ok(TraceWriter {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /**
+     * Write the header of the trace.
+     */
+    pub fn write_header(&mut self, header: &TraceHeader) -> io::Result<()> {
+        self.write_line(header)
+    }
+
+    /**
+     * Write a round of the trace.
+     */
+    pub fn write_round(&mut self, round: &TraceRound) -> io::Result<()> {
+        self.write_line(round)
+    }
+
+    /**
+     * Write the buffered lines to the file.
+     */
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /**
+     * Write a value as a JSON line.
+     */
+    fn write_line<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, value)?;
+        writeln!(self.writer)
+    }
+}
+
+/**
+ * TraceReader implementation.
+ */
+impl TraceReader {
+    /**
+     * Open a trace file and read its header.
+     */
+    pub fn open(path: &Path) -> io::Result<TraceReader> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let header: TraceHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(io::Error::new(ErrorKind::InvalidData, "empty trace")),
+        };
+        if header.version != TRACE_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported trace version {}", header.version),
+            ));
+        }
+
+        Ok(TraceReader { header, lines })
+    }
+
+    /**
+     * Get the header of the trace.
+     */
+    pub fn get_header(&self) -> &TraceHeader {
+        &self.header
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = io::Result<TraceRound>;
+
+    /**
+     * Read the next round of the trace.
+     */
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        Some(line.and_then(|line| Ok(serde_json::from_str(&line)?)))
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::scenario::Scenario;
+
+    const TOML_SCENARIO: &str = r#"
+        rounds = 30
+
+        [simulator]
+        seed = 11
+
+        [grid]
+        blocks_per_street = 4
+        block_size = 2
+
+        [rsu_manager]
+        tx_range = 3
+        rx_range = 3
+        detect_obu_tx_failure = true
+        detect_obu_gps_failure = true
+
+        [obu_manager]
+        max_obus = 15
+        comms_range = 4
+        tx_base_failure_rate = 0.1
+        tx_faulty_obu_failure_rate = 0.5
+        gps_failure_rate = 0.1
+        gps_faulty_obu_failure_rate = 0.5
+        faulty_obus = 4
+    "#;
+
+    /**
+     * Test writing the trace of a run and reading it back.
+     */
+    #[test]
+    fn test_write_and_read_trace() {
+        let path = std::env::temp_dir().join(format!(
+            "vanet4j_test_write_and_read_trace_{}.jsonl",
+            std::process::id()
+        ));
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+
+        // run the same simulation with and without the trace
+        let mut simulator = scenario.create_simulator().unwrap();
+        simulator.set_quiet(true);
+        simulator.init();
+        let report = simulator.run(scenario.rounds);
+
+        let mut simulator = scenario.create_simulator().unwrap();
+        simulator.set_quiet(true);
+        simulator.set_trace(Some(TraceWriter::create(&path).unwrap()));
+        simulator.init();
+        let traced_report = simulator.run(scenario.rounds);
+        drop(simulator);
+
+        // tracing doesn't change the simulation
+        assert_eq!(report.confusion_matrix, traced_report.confusion_matrix);
+
+        let mut reader = TraceReader::open(&path).unwrap();
+        assert_eq!(reader.get_header().seed, 11);
+        assert_eq!(reader.get_header().obu_comms_range, 4);
+        let rsu_count = reader.get_header().rsu_count;
+        assert_eq!(rsu_count, 9);
+
+        let rounds: Vec<TraceRound> = reader.by_ref().map(|round| round.unwrap()).collect();
+        assert_eq!(rounds.len(), 30);
+        assert_eq!(rounds[29].round, 29);
+        assert_eq!(rounds[29].obus.len(), 15);
+
+        let mut dropped = 0;
+        for (index, round) in rounds.iter().enumerate() {
+            for obu in &round.obus {
+                // only the falsified coordinates are written
+                match obu.status {
+                    MessageStatus::GpsFalsified => {
+                        assert!(obu.reported.is_some_and(|c| c != obu.coordinate))
+                    }
+                    _ => assert_eq!(obu.reported, None),
+                }
+                if obu.status == MessageStatus::Dropped {
+                    dropped += 1;
+                }
+
+                // the faulty flag doesn't change
+                let verdict = traced_report
+                    .verdicts
+                    .iter()
+                    .find(|verdict| verdict.obu_id == obu.id)
+                    .unwrap();
+                assert_eq!(obu.faulty, verdict.is_faulty);
+            }
+
+            // the messages sent in a round are delivered at the start of the next one
+            if index > 0 {
+                let sent: Vec<u32> = rounds[index - 1]
+                    .obus
+                    .iter()
+                    .filter(|obu| obu.status != MessageStatus::Dropped)
+                    .map(|obu| obu.id)
+                    .collect();
+                let delivered: Vec<u32> = round.deliveries.iter().map(|d| d.origin_id).collect();
+                assert_eq!(sent, delivered);
+            }
+
+            for delivery in &round.deliveries {
+                assert!(!delivery.obus.contains(&delivery.origin_id));
+                assert!(delivery.rsus.iter().all(|rsu_id| *rsu_id < rsu_count));
+            }
+        }
+        assert_eq!(dropped, traced_report.obu_tx_stats.total_tx_error_count);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,512 @@
+/**
+ * TRAFFIC LIGHTS
+ *
+ * Signalized intersections, so the vehicles queue and drive in platoons
+ * instead of crossing the intersections freely. A signal gives the green to
+ * the column streets of its intersection, then to its row streets, with an
+ * optional all red clearance in between. Its phases follow a fixed cycle, or
+ * an actuated timing that extends the green while vehicles approach and
+ * switches early when the green streets are empty and vehicles wait on the
+ * red ones. The vehicles can't enter an intersection on the red, the ones
+ * already in it leave it freely.
+ */
+use crate::grid::{Coordinate, Grid, Heading};
+use crate::scenario::ScenarioError;
+use serde::{Deserialize, Serialize};
+
+/**
+ * SignalPlan represents the timings of the signal phases.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignalPlan {
+    Fixed,    // Each phase lasts a fixed number of rounds
+    Actuated, // Each phase lasts between a min and a max number of rounds, given the vehicles
+}
+
+/**
+ * TrafficLightParams represents the phase plan of the signals and the
+ * intersections they are placed at.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrafficLightParams {
+    pub plan: SignalPlan,
+    #[serde(default)]
+    pub green: Option<(u32, u32)>, // Fixed: rounds of green of the column streets and of the row streets
+    #[serde(default)]
+    pub min_green: Option<u32>, // Actuated: rounds of green before the phase can switch
+    #[serde(default)]
+    pub max_green: Option<u32>, // Actuated: rounds of green after which the phase switches if vehicles wait
+    #[serde(default)]
+    pub all_red: u32, // Rounds of red for every street between two phases
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intersections: Vec<Coordinate>, // Signalized intersections, all of them if empty
+}
+
+/**
+ * SignalPhase represents the streets that have the green at an intersection.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalPhase {
+    ColumnGreen, // The column streets have the green, the row streets the red
+    RowGreen,    // The row streets have the green, the column streets the red
+    AllRed,      // Every street has the red while the intersection clears
+}
+
+/**
+ * Signal represents the traffic light of an intersection.
+ */
+#[derive(Clone, Debug)]
+struct Signal {
+    coordinate: Coordinate,
+    phase: SignalPhase,
+    next_column_green: bool, // The column streets get the green after the all red
+    elapsed: u32,            // Rounds since the phase started
+}
+
+/**
+ * TrafficLights represents the signals of the grid and updates their phases
+ * every round.
+ */
+#[derive(Clone, Debug)]
+pub struct TrafficLights {
+    params: TrafficLightParams,
+    signals: Vec<Signal>,
+    signal_ids: Vec<Option<usize>>, // Index of the signal of each cell, by x * height + y
+    height: u32,                    // Number of cells along the y axis of the grid
+}
+
+/**
+ * SignalPlan implementation.
+ */
+impl SignalPlan {
+    /**
+     * Return the name of the plan, as in the scenario.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignalPlan::Fixed => "fixed",
+            SignalPlan::Actuated => "actuated",
+        }
+    }
+}
+
+/**
+ * TrafficLightParams implementation.
+ */
+impl TrafficLightParams {
+    /**
+     * Check that the plan is fully described and that the signals are placed
+     * at intersections of the grid.
+     */
+    pub fn validate(&self, intersections: &[Coordinate]) -> Result<(), ScenarioError> {
+        // Each plan only uses its own fields
+        let fields = [
+            ("green", SignalPlan::Fixed, self.green.is_some()),
+            ("min_green", SignalPlan::Actuated, self.min_green.is_some()),
+            ("max_green", SignalPlan::Actuated, self.max_green.is_some()),
+        ];
+        for (name, plan, is_set) in fields {
+            if is_set && plan != self.plan {
+                return Err(ScenarioError::Invalid(format!(
+                    "traffic_lights.{} is not used by the {} plan",
+                    name,
+                    self.plan.name()
+                )));
+            }
+        }
+
+        match self.plan {
+            SignalPlan::Fixed => match self.green {
+                Some((column_green, row_green)) if column_green > 0 && row_green > 0 => {}
+                Some(_) => {
+                    return Err(ScenarioError::Invalid(
+                        "traffic_lights.green must be greater than 0".to_string(),
+                    ))
+                }
+                None => {
+                    return Err(ScenarioError::Invalid(
+                        "traffic_lights.green must be set for the fixed plan".to_string(),
+                    ))
+                }
+            },
+            SignalPlan::Actuated => match (self.min_green, self.max_green) {
+                (Some(min_green), Some(max_green)) => {
+                    if min_green == 0 || max_green < min_green {
+                        return Err(ScenarioError::Invalid(format!(
+                            "traffic_lights: min_green ({}) must be greater than 0 and not greater than max_green ({})",
+                            min_green, max_green
+                        )));
+                    }
+                }
+                _ => {
+                    return Err(ScenarioError::Invalid(
+                        "traffic_lights.min_green and traffic_lights.max_green must be set for the actuated plan"
+                            .to_string(),
+                    ))
+                }
+            },
+        }
+
+        // The signals are placed at distinct intersections
+        if intersections.is_empty() {
+            return Err(ScenarioError::Invalid(
+                "traffic_lights: the grid has no intersection".to_string(),
+            ));
+        }
+        for (index, coordinate) in self.intersections.iter().enumerate() {
+            if !intersections.contains(coordinate) {
+                return Err(ScenarioError::Invalid(format!(
+                    "traffic_lights.intersections: ({}, {}) is not an intersection of the grid",
+                    coordinate.x, coordinate.y
+                )));
+            }
+            if self.intersections[..index].contains(coordinate) {
+                return Err(ScenarioError::Invalid(format!(
+                    "traffic_lights.intersections: ({}, {}) is listed more than once",
+                    coordinate.x, coordinate.y
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * TrafficLights implementation.
+ */
+impl TrafficLights {
+    /**
+     * Create the signals of the given params at the intersections of the
+     * grid. Every signal starts with the green for the column streets. The
+     * params must be valid for the grid.
+     */
+    pub fn new(params: &TrafficLightParams, grid: &Grid) -> TrafficLights {
+        let coordinates = if params.intersections.is_empty() {
+            grid.get_intersections()
+        } else {
+            params.intersections.clone()
+        };
+
+        let height = grid.get_height();
+        let mut signal_ids = vec![None; (grid.get_width() * height) as usize];
+        let mut signals = Vec::new();
+        for coordinate in coordinates {
+            signal_ids[(coordinate.x * height + coordinate.y) as usize] = Some(signals.len());
+            signals.push(Signal {
+                coordinate,
+                phase: SignalPhase::ColumnGreen,
+                next_column_green: false,
+                elapsed: 0,
+            });
+        }
+
+        TrafficLights {
+            params: params.clone(),
+            signals,
+            signal_ids,
+            height,
+        }
+    }
+
+    /**
+     * Return the number of signals.
+     */
+    pub fn get_signal_count(&self) -> usize {
+        self.signals.len()
+    }
+
+    /**
+     * Return the phase of the signal of a cell, none if the cell has no
+     * signal.
+     */
+    pub fn get_phase(&self, coordinate: Coordinate) -> Option<SignalPhase> {
+        self.signal_ids
+            .get((coordinate.x * self.height + coordinate.y) as usize)
+            .copied()
+            .flatten()
+            .map(|id| self.signals[id].phase)
+    }
+
+    /**
+     * Check if the vehicles driving in a heading can't enter a cell, because
+     * its signal is red for their street.
+     */
+    pub fn is_red(&self, coordinate: Coordinate, heading: Heading) -> bool {
+        match self.get_phase(coordinate) {
+            Some(SignalPhase::ColumnGreen) => !heading.is_column(),
+            Some(SignalPhase::RowGreen) => heading.is_column(),
+            Some(SignalPhase::AllRed) => true,
+            None => false,
+        }
+    }
+
+    /**
+     * Move every signal to the next round, switching the phases whose time is
+     * over. The actuated signals look at the vehicles approaching their
+     * intersection in the grid.
+     */
+    pub fn update(&mut self, grid: &Grid) {
+        for signal in self.signals.iter_mut() {
+            signal.elapsed += 1;
+
+            let column_green = match signal.phase {
+                SignalPhase::ColumnGreen => true,
+                SignalPhase::RowGreen => false,
+                SignalPhase::AllRed => {
+                    // The intersection is cleared, the other streets get the green
+                    if signal.elapsed >= self.params.all_red {
+                        signal.phase = if signal.next_column_green {
+                            SignalPhase::ColumnGreen
+                        } else {
+                            SignalPhase::RowGreen
+                        };
+                        signal.elapsed = 0;
+                    }
+                    continue;
+                }
+            };
+
+            let switch = match self.params.plan {
+                SignalPlan::Fixed => {
+                    let (column_green_rounds, row_green_rounds) =
+                        self.params.green.unwrap_or((1, 1));
+                    signal.elapsed
+                        >= if column_green {
+                            column_green_rounds
+                        } else {
+                            row_green_rounds
+                        }
+                }
+                SignalPlan::Actuated => {
+                    // The green is kept while no vehicle waits on the red
+                    signal.elapsed >= self.params.min_green.unwrap_or(1)
+                        && grid.has_approaching_obu(signal.coordinate, !column_green)
+                        && (signal.elapsed >= self.params.max_green.unwrap_or(1)
+                            || !grid.has_approaching_obu(signal.coordinate, column_green))
+                }
+            };
+
+            if switch {
+                signal.elapsed = 0;
+                signal.phase = if self.params.all_red > 0 {
+                    signal.next_column_green = !column_green;
+                    SignalPhase::AllRed
+                } else if column_green {
+                    SignalPhase::RowGreen
+                } else {
+                    SignalPhase::ColumnGreen
+                };
+            }
+        }
+    }
+}
+
+/***
+ * TESTS MODULE
+ */
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grid::{GridParams, Position};
+
+    /**
+     * Return the params of a plan with no timing.
+     */
+    fn traffic_light_params(plan: SignalPlan) -> TrafficLightParams {
+        TrafficLightParams {
+            plan,
+            green: None,
+            min_green: None,
+            max_green: None,
+            all_red: 0,
+            intersections: Vec::new(),
+        }
+    }
+
+    /**
+     * Return a grid of 2x2 blocks of 2 cells, with one-way streets.
+     */
+    fn grid() -> Grid {
+        Grid::new(GridParams {
+            blocks_per_street: 2,
+            block_size: 2,
+            block_widths: Vec::new(),
+            block_heights: Vec::new(),
+            map: None,
+            osm: None,
+            removed_segments: Vec::new(),
+            two_way: false,
+            lanes: 1,
+            streets: Vec::new(),
+        })
+    }
+
+    /**
+     * Drive an OBU from a cell in the given headings, return its position.
+     */
+    fn drive(
+        grid: &mut Grid,
+        coordinate: Coordinate,
+        obu_id: u32,
+        headings: &[Heading],
+    ) -> Position {
+        let mut position = grid.get_position(coordinate, obu_id).unwrap();
+        for heading in headings {
+            let next = grid
+                .get_possible_moves(position)
+                .into_iter()
+                .find(|next| next.lane.heading == *heading)
+                .unwrap();
+            grid.move_obu(position, next);
+            position = next;
+        }
+        position
+    }
+
+    /**
+     * Test the validation of the plans.
+     */
+    #[test]
+    fn test_validate() {
+        let intersections = grid().get_intersections();
+
+        let mut params = traffic_light_params(SignalPlan::Fixed);
+        assert!(params.validate(&intersections).is_err());
+        params.green = Some((10, 0));
+        assert!(params.validate(&intersections).is_err());
+        params.green = Some((10, 5));
+        assert!(params.validate(&intersections).is_ok());
+        params.min_green = Some(3);
+        assert!(params.validate(&intersections).is_err());
+
+        let mut params = traffic_light_params(SignalPlan::Actuated);
+        params.min_green = Some(5);
+        assert!(params.validate(&intersections).is_err());
+        params.max_green = Some(4);
+        assert!(params.validate(&intersections).is_err());
+        params.max_green = Some(20);
+        assert!(params.validate(&intersections).is_ok());
+
+        // the signals are placed at intersections
+        params.intersections = vec![Coordinate { x: 3, y: 3 }, Coordinate { x: 6, y: 0 }];
+        assert!(params.validate(&intersections).is_ok());
+        params.intersections.push(Coordinate { x: 3, y: 3 });
+        assert!(params.validate(&intersections).is_err());
+        params.intersections = vec![Coordinate { x: 1, y: 0 }];
+        assert!(params.validate(&intersections).is_err());
+
+        // the corners and the street starts are only approached by one street
+        assert_eq!(
+            intersections,
+            vec![
+                Coordinate { x: 0, y: 3 },
+                Coordinate { x: 3, y: 3 },
+                Coordinate { x: 3, y: 6 },
+                Coordinate { x: 6, y: 0 },
+            ]
+        );
+    }
+
+    /**
+     * Test the fixed cycle, with an all red clearance.
+     */
+    #[test]
+    fn test_fixed_cycle() {
+        let grid = grid();
+        let mut params = traffic_light_params(SignalPlan::Fixed);
+        params.green = Some((3, 2));
+        params.all_red = 1;
+        params.intersections = vec![Coordinate { x: 3, y: 3 }];
+        let mut traffic_lights = TrafficLights::new(&params, &grid);
+        assert_eq!(traffic_lights.get_signal_count(), 1);
+
+        let mut phases = Vec::new();
+        for _ in 0..8 {
+            phases.push(traffic_lights.get_phase(Coordinate { x: 3, y: 3 }).unwrap());
+            traffic_lights.update(&grid);
+        }
+        assert_eq!(
+            phases,
+            vec![
+                SignalPhase::ColumnGreen,
+                SignalPhase::ColumnGreen,
+                SignalPhase::ColumnGreen,
+                SignalPhase::AllRed,
+                SignalPhase::RowGreen,
+                SignalPhase::RowGreen,
+                SignalPhase::AllRed,
+                SignalPhase::ColumnGreen,
+            ]
+        );
+
+        // the other cells have no signal
+        assert_eq!(traffic_lights.get_phase(Coordinate { x: 0, y: 3 }), None);
+        assert!(!traffic_lights.is_red(Coordinate { x: 0, y: 3 }, Heading::East));
+    }
+
+    /**
+     * Test the actuated timing and the red blocking the vehicles.
+     */
+    #[test]
+    fn test_actuated_timing() {
+        let mut grid = grid();
+        let mut params = traffic_light_params(SignalPlan::Actuated);
+        params.min_green = Some(2);
+        params.max_green = Some(4);
+        params.intersections = vec![Coordinate { x: 3, y: 3 }];
+        grid.set_traffic_lights(Some(TrafficLights::new(&params, &grid)));
+
+        // without vehicles on the red, the green is kept
+        for _ in 0..6 {
+            grid.update_traffic_lights();
+        }
+        assert_eq!(
+            grid.get_traffic_lights()
+                .unwrap()
+                .get_phase(Coordinate { x: 3, y: 3 }),
+            Some(SignalPhase::ColumnGreen)
+        );
+
+        // row 3 flows West, a vehicle waits on the red next to the intersection
+        grid.insert_obu_at(Coordinate { x: 6, y: 3 }, 0);
+        let waiting = drive(&mut grid, Coordinate { x: 6, y: 3 }, 0, &[Heading::West; 2]);
+        assert!(grid.get_possible_moves(waiting).is_empty());
+
+        // the column streets are empty, the green switches at once
+        grid.update_traffic_lights();
+        assert_eq!(grid.get_possible_moves(waiting).len(), 1);
+
+        // column 3 flows South, a vehicle approaching keeps the green up to the max
+        grid.insert_obu_at(Coordinate { x: 3, y: 0 }, 1);
+        drive(
+            &mut grid,
+            Coordinate { x: 3, y: 0 },
+            1,
+            &[Heading::South; 2],
+        );
+        let mut phases = Vec::new();
+        for _ in 0..5 {
+            grid.update_traffic_lights();
+            phases.push(
+                grid.get_traffic_lights()
+                    .unwrap()
+                    .get_phase(Coordinate { x: 3, y: 3 })
+                    .unwrap(),
+            );
+        }
+        assert_eq!(
+            phases,
+            vec![
+                SignalPhase::RowGreen,
+                SignalPhase::RowGreen,
+                SignalPhase::RowGreen,
+                SignalPhase::ColumnGreen,
+                SignalPhase::ColumnGreen,
+            ]
+        );
+    }
+}
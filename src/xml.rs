@@ -0,0 +1,165 @@
+/*!
+ * XML
+ *
+ * A minimal reader of the element tags of XML documents, enough for the
+ * files written by SUMO and OpenStreetMap. Only the tags and their attributes
+ * are read: the text content is ignored, and comments, declarations and
+ * processing instructions are skipped.
+ */
+
+/**
+ * XmlTag represents a start, end or empty element tag of an XML document.
+ */
+pub struct XmlTag<'a> {
+    pub name: &'a str,
+    pub closing: bool,
+    attributes: &'a str,
+}
+
+/**
+ * XmlTags iterates over the element tags of an XML document, with their line
+ * number.
+ */
+pub struct XmlTags<'a> {
+    contents: &'a str,
+    position: usize,      // Position of the next tag search
+    line: usize,          // Line number at the counted position
+    line_position: usize, // Position up to which the lines are counted
+}
+
+/**
+ * XmlTag implementation.
+ */
+impl<'a> XmlTag<'a> {
+    /**
+     * Return the unescaped value of an attribute.
+     */
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        let mut rest = self.attributes;
+        loop {
+            let (attribute_name, value) = rest.split_once('=')?;
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote != '"' && quote != '\'' {
+                return None;
+            }
+            let (value, next) = value[1..].split_once(quote)?;
+
+            if attribute_name.trim() == name {
+                return Some(
+                    value
+                        .replace("&lt;", "<")
+                        .replace("&gt;", ">")
+                        .replace("&quot;", "\"")
+                        .replace("&apos;", "'")
+                        .replace("&amp;", "&"),
+                );
+            }
+            rest = next;
+        }
+    }
+
+    /**
+     * Return the value of a numeric attribute.
+     */
+    pub fn number_attribute(&self, name: &str, line: usize) -> Result<f64, String> {
+        self.attribute(name)
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|number| number.is_finite())
+            .ok_or_else(|| {
+                format!(
+                    "line {}: {} without a valid {} attribute",
+                    line, self.name, name
+                )
+            })
+    }
+}
+
+/**
+ * XmlTags implementation.
+ */
+impl<'a> XmlTags<'a> {
+    /**
+     * Iterate over the tags of the given document.
+     */
+    pub fn new(contents: &'a str) -> XmlTags<'a> {
+        XmlTags {
+            contents,
+            position: 0,
+            line: 1,
+            line_position: 0,
+        }
+    }
+
+    /**
+     * Return the line number of a position of the document, counting the
+     * lines since the previous position.
+     */
+    fn line(&mut self, position: usize) -> usize {
+        self.line += self.contents[self.line_position..position]
+            .matches('\n')
+            .count();
+        self.line_position = position;
+        self.line
+    }
+}
+
+impl<'a> Iterator for XmlTags<'a> {
+    type Item = Result<(usize, XmlTag<'a>), String>;
+
+    /**
+     * Return the next element tag.
+     */
+    fn next(&mut self) -> Option<Self::Item> {
+        let contents = self.contents;
+        loop {
+            let start = self.position + contents[self.position..].find('<')?;
+            let rest = &contents[start..];
+
+            // Skip the comments, declarations and processing instructions
+            let terminator = if rest.starts_with("<!--") {
+                "-->"
+            } else if rest.starts_with("<?") {
+                "?>"
+            } else if rest.starts_with("<!") {
+                ">"
+            } else {
+                ""
+            };
+            if !terminator.is_empty() {
+                match rest.find(terminator) {
+                    Some(end) => self.position = start + end + terminator.len(),
+                    None => {
+                        self.position = contents.len();
+                        return Some(Err(format!("line {}: unclosed markup", self.line(start))));
+                    }
+                }
+                continue;
+            }
+
+            let line = self.line(start);
+            let Some(end) = rest.find('>') else {
+                self.position = contents.len();
+                return Some(Err(format!("line {}: unclosed tag", line)));
+            };
+            self.position = start + end + 1;
+
+            // Split the tag in its name and attributes
+            let body = rest[1..end].trim_end_matches('/');
+            let (closing, body) = match body.strip_prefix('/') {
+                Some(body) => (true, body),
+                None => (false, body),
+            };
+            let (name, attributes) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+
+            return Some(Ok((
+                line,
+                XmlTag {
+                    name,
+                    closing,
+                    attributes,
+                },
+            )));
+        }
+    }
+}
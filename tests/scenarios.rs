@@ -0,0 +1,199 @@
+/**
+ * SCENARIOS
+ *
+ * Runs of the example scenarios, checking that each of them shows the effect
+ * of its feature on the simulation.
+ */
+use vanet4j::mac::MacStats;
+use vanet4j::report::SimulationReport;
+use vanet4j::scenario::Scenario;
+
+/**
+ * Run a scenario for the given number of rounds, quietly and with the
+ * per-round metrics.
+ */
+fn run_quiet(scenario: &Scenario, rounds: usize) -> SimulationReport {
+    let mut simulator = scenario.create_simulator().unwrap();
+    simulator.set_quiet(true);
+    simulator.set_collect_metrics(true);
+    simulator.init();
+    simulator.run(rounds)
+}
+
+/**
+ * Test the vehicles of the downtown scenario, which queue at the red
+ * lights.
+ */
+#[test]
+fn test_downtown_queues() {
+    let mut scenario = Scenario::from_file("scenarios/downtown.toml").unwrap();
+    let stopped = |scenario: &Scenario| {
+        run_quiet(scenario, 60)
+            .round_metrics
+            .iter()
+            .map(|metrics| metrics.obus_stopped)
+            .sum::<u32>()
+    };
+
+    let with_lights = stopped(&scenario);
+    scenario.traffic_lights = None;
+    assert!(with_lights > stopped(&scenario));
+}
+
+/**
+ * Test the vehicles of the commute scenario, which leave the grid at
+ * their destination even when the exits of the streets are closed.
+ */
+#[test]
+fn test_commute_departures() {
+    let mut scenario = Scenario::from_file("scenarios/commute.toml").unwrap();
+    scenario.simulator.exit_at_border = false;
+    let removed = |scenario: &Scenario| {
+        run_quiet(scenario, 60)
+            .round_metrics
+            .iter()
+            .map(|metrics| metrics.obus_removed)
+            .sum::<u32>()
+    };
+
+    assert!(removed(&scenario) > 0);
+    scenario.routing = None;
+    assert_eq!(removed(&scenario), 0);
+}
+
+/**
+ * Test the fading scenario, whose shadowing loses the same messages with
+ * the same seed.
+ */
+#[test]
+fn test_fading_reproducible() {
+    let scenario = Scenario::from_file("scenarios/fading.toml").unwrap();
+    let deliveries = |scenario: &Scenario| {
+        run_quiet(scenario, 30)
+            .round_metrics
+            .into_iter()
+            .map(|metrics| metrics.rsu_deliveries)
+            .collect::<Vec<Vec<u32>>>()
+    };
+
+    assert_eq!(deliveries(&scenario), deliveries(&scenario));
+}
+
+/**
+ * Test the urban scenario, whose RSUs get less messages through the
+ * buildings.
+ */
+#[test]
+fn test_urban_obstruction() {
+    let mut scenario = Scenario::from_file("scenarios/urban.toml").unwrap();
+    let deliveries = |scenario: &Scenario| {
+        run_quiet(scenario, 30)
+            .round_metrics
+            .iter()
+            .flat_map(|metrics| metrics.rsu_deliveries.clone())
+            .sum::<u32>()
+    };
+
+    let obstructed = deliveries(&scenario);
+    scenario
+        .set_field("propagation.building_loss", "null")
+        .unwrap();
+    assert!(obstructed < deliveries(&scenario));
+}
+
+/**
+ * Test the congestion scenario, whose concurrent messages collide more as
+ * the traffic gets denser.
+ */
+#[test]
+fn test_congestion_collisions() {
+    let mut scenario = Scenario::from_file("scenarios/congestion.toml").unwrap();
+    let run = |scenario: &Scenario| {
+        let metrics = run_quiet(scenario, 20).round_metrics;
+        (
+            metrics.iter().map(|m| m.messages_collided).sum::<u32>(),
+            metrics
+                .iter()
+                .flat_map(|m| m.rsu_deliveries.clone())
+                .sum::<u32>(),
+        )
+    };
+
+    // the dense traffic loses messages to the collisions
+    let (collisions, deliveries) = run(&scenario);
+    assert!(collisions > 0);
+
+    // without interference every message above the sensitivity is delivered
+    let mut without_interference = scenario.clone();
+    without_interference.interference = None;
+    let (no_collisions, all_deliveries) = run(&without_interference);
+    assert_eq!(no_collisions, 0);
+    assert!(all_deliveries > deliveries);
+
+    // less vehicles collide less
+    scenario.set_field("obu_manager.max_obus", "60").unwrap();
+    scenario.set_field("obu_manager.faulty_obus", "10").unwrap();
+    assert!(run(&scenario).0 < collisions);
+}
+
+/**
+ * Test the contention scenario, whose vehicles take turns on the channel
+ * and collide less than when sending all at once.
+ */
+#[test]
+fn test_contention_access() {
+    let mut scenario = Scenario::from_file("scenarios/contention.toml").unwrap();
+    let deliveries = |report: &SimulationReport| {
+        report
+            .round_metrics
+            .iter()
+            .flat_map(|m| m.rsu_deliveries.clone())
+            .sum::<u32>()
+    };
+
+    // the OBUs wait for the channel, and every message is accounted for
+    let report = run_quiet(&scenario, 20);
+    let mac: Vec<MacStats> = report.verdicts.iter().filter_map(|v| v.mac).collect();
+    assert_eq!(mac.len(), report.verdicts.len());
+    assert!(mac.iter().any(|stats| stats.access_delay > 0));
+    for m in &report.round_metrics {
+        assert_eq!(
+            m.messages_sent + m.messages_dropped + m.mac_losses,
+            m.obu_count
+        );
+    }
+
+    // the messages sent all at once collide more
+    scenario.mac = None;
+    let all_at_once = run_quiet(&scenario, 20);
+    assert!(all_at_once.verdicts.iter().all(|v| v.mac.is_none()));
+    assert!(deliveries(&all_at_once) < deliveries(&report));
+}
+
+/**
+ * Test the latency scenario, whose messages arrive late or expire past
+ * the deadline.
+ */
+#[test]
+fn test_latency_deliveries() {
+    let mut scenario = Scenario::from_file("scenarios/latency.toml").unwrap();
+
+    // some messages arrive late, the ones past the deadline expire
+    let metrics = run_quiet(&scenario, 30).round_metrics;
+    assert!(metrics.iter().any(|m| m.messages_late > 0));
+    assert!(metrics.iter().any(|m| m.messages_expired > 0));
+    let expired = metrics.iter().map(|m| m.messages_expired).sum::<u32>();
+    assert!(expired < metrics.iter().map(|m| m.messages_sent).sum::<u32>() / 10);
+
+    // without a deadline no message expires
+    scenario.set_field("latency.deadline", "null").unwrap();
+    let metrics = run_quiet(&scenario, 30).round_metrics;
+    assert!(metrics.iter().all(|m| m.messages_expired == 0));
+
+    // without latency every message is delivered in the next round
+    scenario.latency = None;
+    let metrics = run_quiet(&scenario, 30).round_metrics;
+    assert!(metrics
+        .iter()
+        .all(|m| m.messages_late == 0 && m.messages_expired == 0));
+}